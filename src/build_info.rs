@@ -0,0 +1,149 @@
+//! Information about the build of the running application
+//!
+//! Call [emit] from your `build.rs`:
+//!
+//! ```no_run
+//! // build.rs
+//! fn main() {
+//!     actix_toolbox::build_info::emit();
+//! }
+//! ```
+//!
+//! and register the collected [BuildInfo] together with the [version] handler:
+//!
+//! ```no_run
+//! use actix_toolbox::build_info::{self, BuildInfo};
+//! use actix_web::web::{get, Data};
+//! use actix_web::App;
+//!
+//! let info: BuildInfo = actix_toolbox::build_info!();
+//! let app = App::new()
+//!     .app_data(Data::new(info))
+//!     .route("/version", get().to(build_info::version));
+//! ```
+
+use std::process::Command;
+
+use actix_web::web::{Data, Json};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+/// Version of actix-toolbox
+pub const TOOLBOX_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Name of the environment variable holding the git commit hash
+pub const GIT_HASH_ENV: &str = "ACTIX_TOOLBOX_GIT_HASH";
+/// Name of the environment variable holding the build time
+pub const BUILD_TIME_ENV: &str = "ACTIX_TOOLBOX_BUILD_TIME";
+/// Name of the environment variable holding the rustc version
+pub const RUSTC_VERSION_ENV: &str = "ACTIX_TOOLBOX_RUSTC_VERSION";
+
+/**
+Information about the build of the running application
+
+Use the [build_info](crate::build_info!) macro to collect it.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BuildInfo {
+    /// Name of the application's crate
+    pub name: String,
+    /// Version of the application's crate
+    pub version: String,
+    /// Version of actix-toolbox
+    pub toolbox_version: String,
+    /// Hash of the git commit the application was built from
+    pub git_hash: Option<String>,
+    /// Time the application was built at in RFC 3339 format
+    pub build_time: Option<String>,
+    /// Version of the compiler used
+    pub rustc_version: Option<String>,
+}
+
+impl BuildInfo {
+    /// Identifier of the release in the form `name@version+git_hash`
+    ///
+    /// This matches the format expected by error reporting services like Sentry.
+    pub fn release(&self) -> String {
+        match &self.git_hash {
+            Some(git_hash) => format!("{}@{}+{}", self.name, self.version, git_hash),
+            None => format!("{}@{}", self.name, self.version),
+        }
+    }
+}
+
+impl std::fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} v{}", self.name, self.version)?;
+        if let Some(git_hash) = &self.git_hash {
+            write!(f, " ({git_hash})")?;
+        }
+        if let Some(build_time) = &self.build_time {
+            write!(f, " built at {build_time}")?;
+        }
+        if let Some(rustc_version) = &self.rustc_version {
+            write!(f, " with {rustc_version}")?;
+        }
+        Ok(())
+    }
+}
+
+/**
+Collect the [BuildInfo] of the crate this macro is called in
+
+The optional fields are only populated if [emit] has been called in the crate's build script.
+*/
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::build_info::BuildInfo {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            toolbox_version: $crate::build_info::TOOLBOX_VERSION.to_string(),
+            git_hash: option_env!("ACTIX_TOOLBOX_GIT_HASH").map(str::to_string),
+            build_time: option_env!("ACTIX_TOOLBOX_BUILD_TIME").map(str::to_string),
+            rustc_version: option_env!("ACTIX_TOOLBOX_RUSTC_VERSION").map(str::to_string),
+        }
+    };
+}
+
+/**
+Build script helper exposing the git hash, build time and rustc version to the crate
+
+Values which can't be determined (e.g. when not building from a git checkout) are left out.
+*/
+pub fn emit() {
+    if let Some(git_hash) = command_output("git", &["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env={GIT_HASH_ENV}={git_hash}");
+    }
+    println!(
+        "cargo:rustc-env={BUILD_TIME_ENV}={}",
+        Utc::now().to_rfc3339()
+    );
+
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    if let Some(rustc_version) = command_output(&rustc, &["--version"]) {
+        println!("cargo:rustc-env={RUSTC_VERSION_ENV}={rustc_version}");
+    }
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
+
+/// Run a command and return its trimmed stdout if it succeeded
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_string()).filter(|output| !output.is_empty())
+}
+
+/**
+Handler responding with the [BuildInfo] registered as app data
+
+Route it to e.g. `/version`.
+*/
+pub async fn version(info: Data<BuildInfo>) -> Json<BuildInfo> {
+    Json(info.as_ref().clone())
+}
@@ -0,0 +1,126 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Length of the nonce prepended to the ciphertext
+const NONCE_LEN: usize = 12;
+
+/**
+Key used to encrypt data before storing it
+
+The oidc module uses it to encrypt the `UserData` stored in the user's session,
+the `DBSessionStore` to encrypt the sessions' state.
+This protects the data from leaking together with the database, e.g. the `DBSession` table.
+
+It is (de)serialized as 32 base64 encoded bytes.
+Generate one using `openssl rand -base64 32`.
+Changing the key logs out every user.
+
+The data is encrypted with AES-256-GCM using a random 96 bit nonce each time.
+As nonces may collide after about 2^32 encryptions with the same key,
+which would break the encryption, rotate the key well before reaching that many.
+*/
+#[derive(Clone)]
+pub struct SessionEncryptionKey([u8; 32]);
+
+impl SessionEncryptionKey {
+    /// Create a key from its raw bytes
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Encrypt `plaintext` and encode it with a random nonce
+    pub(crate) fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<String, aes_gcm::Error> {
+        Ok(base64::encode_config(
+            self.seal(plaintext, aad)?,
+            base64::URL_SAFE_NO_PAD,
+        ))
+    }
+
+    /// Decrypt data produced by [`SessionEncryptionKey::encrypt`]
+    pub(crate) fn decrypt(&self, data: &str, aad: &[u8]) -> Option<Vec<u8>> {
+        self.open(
+            &base64::decode_config(data, base64::URL_SAFE_NO_PAD).ok()?,
+            aad,
+        )
+    }
+
+    /// Encrypt `plaintext` and prepend a random nonce
+    ///
+    /// The `aad` isn't encrypted, but has to be passed again to decrypt the data.
+    /// It binds the data to where it is stored, e.g. its session,
+    /// so it can't be moved somewhere else.
+    pub(crate) fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, aes_gcm::Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut data = nonce.to_vec();
+        data.extend(Aes256Gcm::new(&self.0.into()).encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )?);
+        Ok(data)
+    }
+
+    /// Decrypt data produced by [`SessionEncryptionKey::seal`] with the same `aad`
+    pub(crate) fn open(&self, data: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::from(<[u8; NONCE_LEN]>::try_from(nonce).ok()?);
+        Aes256Gcm::new(&self.0.into())
+            .decrypt(
+                &nonce,
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .ok()
+    }
+}
+
+impl std::fmt::Debug for SessionEncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SessionEncryptionKey(..)")
+    }
+}
+
+impl Serialize for SessionEncryptionKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for SessionEncryptionKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let key = String::deserialize(deserializer)?;
+        let key = base64::decode(key.trim()).map_err(D::Error::custom)?;
+        let key = <[u8; 32]>::try_from(key)
+            .map_err(|key| D::Error::invalid_length(key.len(), &"32 bytes"))?;
+        Ok(Self(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_requires_the_same_aad() {
+        let key = SessionEncryptionKey::new([7; 32]);
+        let data = key.seal(b"state", b"session").unwrap();
+        assert_eq!(key.open(&data, b"session").as_deref(), Some(&b"state"[..]));
+        assert_eq!(key.open(&data, b"other session"), None);
+
+        let data = key.encrypt(b"state", b"session").unwrap();
+        assert_eq!(
+            key.decrypt(&data, b"session").as_deref(),
+            Some(&b"state"[..])
+        );
+        assert_eq!(key.decrypt(&data, b""), None);
+    }
+}
@@ -4,11 +4,29 @@
 //! This also includes an ORM. [rorm](https://github.com/rorm-orm/rorm) is used for this
 #![warn(missing_docs)]
 
+/// Provides information about the application's build and a handler exposing it
+#[cfg(feature = "build-info")]
+pub mod build_info;
+/// Provides an abstraction over the current time
+#[cfg(any(feature = "__session", feature = "memory-session", feature = "oidc"))]
+pub mod clock;
+#[cfg(any(feature = "__session", feature = "oidc"))]
+mod encryption;
 /// Provides logging functionality e.g. sets up a configured logger
 #[cfg(feature = "logging")]
 pub mod logging;
+/// Provides an extractor parsing large json arrays incrementally
+#[cfg(feature = "streaming-json")]
+pub mod streaming_json;
 /// Provides a variety of different middlewares
 pub mod tb_middleware;
+/// Provides tracing functionality e.g. sets up a configured subscriber
+#[cfg(feature = "tracing")]
+pub mod tracing;
+
+/// Provides a coordinator gating traffic until warmup tasks have completed
+#[cfg(feature = "warmup")]
+pub mod warmup;
 
 /// Provides a sender-receiver based websocket interface
 #[cfg(feature = "ws")]
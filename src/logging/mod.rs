@@ -1,4 +1,8 @@
-use log::LevelFilter;
+use std::fmt;
+
+use chrono::{SecondsFormat, Utc};
+use log::kv::{Key, Value, VisitSource};
+use log::{LevelFilter, Record};
 use log4rs::append::console::ConsoleAppender;
 use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
 use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
@@ -6,6 +10,7 @@ use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
 use log4rs::append::rolling_file::RollingFileAppender;
 use log4rs::config::{Appender, Logger, Root};
 use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::{Encode, Write};
 use log4rs::{Config, Handle};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -27,6 +32,105 @@ pub const LOG_PATTERN_WITHOUT_TARGET: &str = "{h([{d(%Y-%m-%d %H:%M:%S)} | {({l}
 /// Log pattern for actix-web's logging tb_middleware.
 pub const LOG_PATTERN_ACTIX_NGINX_LIKE: &str = r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#;
 
+/**
+Format of the log entries
+
+Selected in the [LoggingConfig] and [AdditionalFileLogger] using `"text"` or `"json"`.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Plain text using the configured pattern
+    #[default]
+    Text,
+    /// One json object per line, see [JsonLinesEncoder]
+    ///
+    /// The configured pattern is ignored.
+    Json,
+}
+
+impl LogFormat {
+    /// Create the encoder writing entries in this format
+    fn encoder(self, pattern: &str) -> Box<dyn Encode> {
+        match self {
+            LogFormat::Text => Box::new(PatternEncoder::new(pattern)),
+            LogFormat::Json => Box::new(JsonLinesEncoder),
+        }
+    }
+}
+
+/**
+Encoder writing each log entry as a single line of json
+
+This can be shipped to log aggregators like Loki or Elasticsearch without parsing the text.
+The key-value pairs of the entry and the entries of log4rs' mapped diagnostic context,
+e.g. the request id set by [SetRequestId](crate::tb_middleware::SetRequestId),
+are collected in `fields`, which is omitted if there are none.
+
+**Example log**:
+```log
+{"timestamp":"2022-11-06T23:54:17.042Z","level":"INFO","target":"actix_server::builder","message":"Starting 8 workers"}
+{"timestamp":"2022-11-06T23:54:18.513Z","level":"WARN","target":"app","message":"Login failed","fields":{"attempt":3,"user":"alice"}}
+```
+
+The second entry was logged using `warn!(user = "alice", attempt = 3; "Login failed")`.
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonLinesEncoder;
+
+/// A log entry written by the [JsonLinesEncoder]
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: String,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+    #[serde(skip_serializing_if = "serde_json::Map::is_empty")]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Collects the key-value pairs of a log entry as json
+struct JsonFields(serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = if let Some(value) = value.to_bool() {
+            value.into()
+        } else if let Some(value) = value.to_i64() {
+            value.into()
+        } else if let Some(value) = value.to_u64() {
+            value.into()
+        } else if let Some(value) = value.to_f64() {
+            value.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+impl Encode for JsonLinesEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        let mut fields = JsonFields(serde_json::Map::new());
+        log_mdc::iter(|key, value| {
+            fields.0.insert(key.to_string(), value.into());
+        });
+        record.key_values().visit(&mut fields)?;
+
+        let line = JsonLine {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            level: record.level().as_str(),
+            target: record.target(),
+            message: fmt::format(*record.args()),
+            fields: fields.0,
+        };
+        serde_json::to_writer(&mut *w, &line)?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
 /**
 Representation of a file logger
 */
@@ -67,6 +171,11 @@ pub struct AdditionalFileLogger {
     ///
     /// See [log4rs::encode::pattern] for more information
     pub alternative_pattern: Option<String>,
+    /// Optional format of the entries
+    ///
+    /// If None, the main format will be used.
+    #[serde(default)]
+    pub format: Option<LogFormat>,
 }
 
 /**
@@ -90,6 +199,11 @@ pub struct LoggingConfig {
     ///
     /// Defaults to [LOG_PATTERN]
     pub alternative_pattern: Option<String>,
+    /// Format of the entries written to stdout and the log file
+    ///
+    /// Defaults to [LogFormat::Text]
+    #[serde(default)]
+    pub format: LogFormat,
     /// Additional list of file loggers
     pub additional_file_loggers: Vec<AdditionalFileLogger>,
 }
@@ -109,7 +223,7 @@ pub fn setup_logging(config: &LoggingConfig) -> Result<Handle, String> {
         .as_ref()
         .map_or(LOG_PATTERN, |x| x.as_str());
     let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(main_pattern)))
+        .encoder(config.format.encoder(main_pattern))
         .build();
 
     let file_logger_uuid = Uuid::new_v4().to_string();
@@ -121,7 +235,7 @@ pub fn setup_logging(config: &LoggingConfig) -> Result<Handle, String> {
             .map_err(|e| e.to_string())?,
     );
     let file_logger = RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(main_pattern)))
+        .encoder(config.format.encoder(main_pattern))
         .build(
             &config.path,
             Box::new(CompoundPolicy::new(
@@ -150,7 +264,7 @@ pub fn setup_logging(config: &LoggingConfig) -> Result<Handle, String> {
 
         let ap = Box::new(
             RollingFileAppender::builder()
-                .encoder(Box::new(PatternEncoder::new(pattern)))
+                .encoder(x.format.unwrap_or(config.format).encoder(pattern))
                 .build(
                     &x.path,
                     Box::new(CompoundPolicy::new(
@@ -0,0 +1,345 @@
+use actix_web::dev::Payload;
+use actix_web::http::{header, StatusCode};
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError};
+use futures::future::LocalBoxFuture;
+use openidconnect::core::{CoreJsonWebKeySet, CoreJsonWebKeyUse, CoreJwsSigningAlgorithm};
+use openidconnect::{
+    AdditionalClaims, ClaimsVerificationError, EmptyAdditionalClaims, JsonWebKey, JsonWebKeyId,
+    JwsSigningAlgorithm, SignatureVerificationError,
+};
+use serde::de::Error as _;
+use serde::Deserialize;
+
+use crate::oidc::config::not_in_future;
+use crate::oidc::{Client, OidcIdTokenClaims};
+
+/// Media type of JWT access tokens, see RFC 9068 section 2.1
+const ACCESS_TOKEN_TYPE: &str = "at+jwt";
+
+/// The parts of a JWT's header needed to verify it
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: CoreJwsSigningAlgorithm,
+    #[serde(default)]
+    kid: Option<JsonWebKeyId>,
+    #[serde(default)]
+    typ: Option<String>,
+}
+
+/// A JWT split into its parts, whose signature hasn't been verified yet
+struct UnverifiedJwt {
+    header: JwtHeader,
+    /// The encoded header and payload the signature was created over
+    signing_input: String,
+    payload: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl UnverifiedJwt {
+    /// Split and decode a JWT in compact serialization
+    fn parse(token: &str) -> Result<Self, serde_json::Error> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(serde_json::Error::custom("expected three parts"));
+        };
+        let decode = |part: &str| {
+            base64::decode_config(part, base64::URL_SAFE_NO_PAD).map_err(serde_json::Error::custom)
+        };
+
+        Ok(Self {
+            header: serde_json::from_slice(&decode(header)?)?,
+            signing_input: format!("{header}.{payload}"),
+            payload: decode(payload)?,
+            signature: decode(signature)?,
+        })
+    }
+
+    /// Check whether the header marks the token as access token
+    ///
+    /// The `application/` prefix may be omitted and media types are case-insensitive.
+    fn is_access_token(&self) -> bool {
+        self.header.typ.as_deref().is_some_and(|typ| {
+            let typ = typ.to_ascii_lowercase();
+            typ.strip_prefix("application/").unwrap_or(&typ) == ACCESS_TOKEN_TYPE
+        })
+    }
+
+    /// Verify the signature using one of the provider's keys
+    fn verify_signature(
+        &self,
+        keys: &CoreJsonWebKeySet,
+        allowed_algs: &[CoreJwsSigningAlgorithm],
+    ) -> Result<(), ClaimsVerificationError> {
+        let alg = &self.header.alg;
+        // Tokens signed with the client secret could have been issued by any holder of the secret
+        if !allowed_algs.contains(alg) || alg.uses_shared_secret() {
+            return Err(ClaimsVerificationError::SignatureVerification(
+                SignatureVerificationError::DisallowedAlg(format!("{alg:?}")),
+            ));
+        }
+
+        let mut result = Err(SignatureVerificationError::NoMatchingKey);
+        for key in keys.keys().iter().filter(|key| {
+            (self.header.kid.is_none() || self.header.kid.as_ref() == key.key_id())
+                && key
+                    .key_use()
+                    .is_none_or(|key_use| *key_use == CoreJsonWebKeyUse::Signature)
+        }) {
+            result = key.verify_signature(alg, self.signing_input.as_bytes(), &self.signature);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result.map_err(ClaimsVerificationError::SignatureVerification)
+    }
+}
+
+/// Extract and validate a JWT access token from the `Authorization: Bearer` header
+///
+/// This allows APIs protected by the same provider to authenticate requests without sessions.
+///
+/// Only access tokens following RFC 9068 are accepted, i.e. their header's `typ` has to be `at+jwt`.
+/// This keeps id tokens, which may pass through the browser, from being used as access tokens.
+///
+/// The token's signature is checked against the provider's keys.
+/// Its issuer has to match the provider, it must not be expired
+/// and its audience has to contain one of the [`Config::bearer_audiences`](crate::oidc::Config::bearer_audiences).
+/// Without any configured audiences, every token is rejected.
+///
+/// ```no_run
+/// use actix_toolbox::oidc::BearerClaims;
+/// use actix_web::HttpResponse;
+///
+/// async fn api(claims: BearerClaims) -> HttpResponse {
+///     HttpResponse::Ok().body(format!("Hello {}", claims.0.subject().as_str()))
+/// }
+/// ```
+pub struct BearerClaims<AC: AdditionalClaims = EmptyAdditionalClaims>(pub OidcIdTokenClaims<AC>);
+
+impl<AC: AdditionalClaims + Clone> FromRequest for BearerClaims<AC> {
+    type Error = BearerError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let client = req.app_data::<Data<Client<AC>>>().cloned();
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                let (scheme, token) = value.split_once(' ')?;
+                scheme
+                    .eq_ignore_ascii_case("bearer")
+                    .then(|| token.trim().to_string())
+            });
+
+        Box::pin(async move {
+            let client = client.ok_or(BearerError::MissingClient)?;
+            let token = token.ok_or(BearerError::MissingToken)?;
+            verify_bearer(&client, &token).await.map(BearerClaims)
+        })
+    }
+}
+
+/// Verify the bearer token
+async fn verify_bearer<AC: AdditionalClaims + Clone>(
+    client: &Client<AC>,
+    token: &str,
+) -> Result<OidcIdTokenClaims<AC>, BearerError> {
+    if client.bearer_audiences.is_empty() {
+        return Err(BearerError::MissingAudiences);
+    }
+
+    let jwt = UnverifiedJwt::parse(token).map_err(BearerError::MalformedToken)?;
+    if !jwt.is_access_token() {
+        return Err(BearerError::InvalidTokenType);
+    }
+    client
+        .keys
+        .verify_with_keys(|keys| jwt.verify_signature(keys, &client.signing_algs))
+        .await
+        .map_err(BearerError::InvalidToken)?;
+
+    let claims: OidcIdTokenClaims<AC> =
+        serde_json::from_slice(&jwt.payload).map_err(BearerError::MalformedToken)?;
+    if claims.issuer() != client.keys.issuer() {
+        return Err(BearerError::InvalidToken(
+            ClaimsVerificationError::InvalidIssuer(format!(
+                "expected `{}` (found `{}`)",
+                client.keys.issuer().as_str(),
+                claims.issuer().as_str()
+            )),
+        ));
+    }
+    let now = client.clock.now();
+    if claims.expiration() <= now - client.clock_skew {
+        return Err(BearerError::InvalidToken(ClaimsVerificationError::Expired(
+            format!("token expired at {}", claims.expiration()),
+        )));
+    }
+    not_in_future("iat", claims.issue_time(), &client.clock, client.clock_skew)
+        .map_err(|err| BearerError::InvalidToken(ClaimsVerificationError::Other(err)))?;
+
+    if !claims
+        .audiences()
+        .iter()
+        .any(|audience| client.bearer_audiences.contains(audience))
+    {
+        return Err(BearerError::InvalidAudience);
+    }
+
+    Ok(claims)
+}
+
+/// Error returned by the [`BearerClaims`] extractor
+#[derive(Debug)]
+pub enum BearerError {
+    /// No [`Client`] has been registered as app data
+    MissingClient,
+
+    /// The request has no `Authorization: Bearer` header
+    MissingToken,
+
+    /// The token is not a valid JWT
+    MalformedToken(serde_json::Error),
+
+    /// No [`Config::bearer_audiences`](crate::oidc::Config::bearer_audiences) have been configured
+    MissingAudiences,
+
+    /// The token is not a JWT access token, e.g. an id token
+    InvalidTokenType,
+
+    /// The token's signature, issuer or expiry is invalid
+    InvalidToken(ClaimsVerificationError),
+
+    /// The token wasn't issued for this application
+    InvalidAudience,
+}
+impl std::fmt::Display for BearerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BearerError::MissingClient => write!(f, "No oidc client has been registered"),
+            BearerError::MissingToken => write!(f, "Missing bearer token"),
+            BearerError::MissingAudiences => {
+                write!(f, "No audiences for bearer tokens have been configured")
+            }
+            BearerError::MalformedToken(err) => write!(f, "Malformed bearer token: {err}"),
+            BearerError::InvalidTokenType => write!(f, "The bearer token is no access token"),
+            BearerError::InvalidToken(err) => {
+                write!(f, "The bearer token didn't pass the verification: {err}")
+            }
+            BearerError::InvalidAudience => {
+                write!(f, "The bearer token wasn't issued for this audience")
+            }
+        }
+    }
+}
+impl std::error::Error for BearerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BearerError::MissingClient => None,
+            BearerError::MissingToken => None,
+            BearerError::MissingAudiences => None,
+            BearerError::MalformedToken(err) => Some(err),
+            BearerError::InvalidTokenType => None,
+            BearerError::InvalidToken(err) => Some(err),
+            BearerError::InvalidAudience => None,
+        }
+    }
+}
+impl ResponseError for BearerError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            BearerError::MissingClient | BearerError::MissingAudiences => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            BearerError::MissingClient | BearerError::MissingAudiences => {}
+            BearerError::MissingToken => {
+                response.append_header((header::WWW_AUTHENTICATE, "Bearer"));
+            }
+            _ => {
+                response
+                    .append_header((header::WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#));
+            }
+        }
+        response.finish()
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use openidconnect::RedirectUrl;
+
+    use super::*;
+    use crate::oidc::test::{MockProvider, MockServer, MockUser};
+
+    async fn client(server: &MockServer) -> Data<Client> {
+        server
+            .config(RedirectUrl::new("http://localhost/finish_login".to_string()).unwrap())
+            .discover()
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    async fn accepts_access_token() {
+        let server = MockProvider::new(vec![MockUser::new("alice")])
+            .start()
+            .await
+            .unwrap();
+        let client = client(&server).await;
+
+        let token = server.access_token("alice").unwrap();
+        let claims = verify_bearer(&client, &token).await.unwrap();
+        assert_eq!(claims.subject().as_str(), "alice");
+
+        server.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn refuses_id_token() {
+        let server = MockProvider::new(vec![MockUser::new("alice")])
+            .start()
+            .await
+            .unwrap();
+        let client = client(&server).await;
+
+        let token = server.id_token("alice").unwrap();
+        assert!(matches!(
+            verify_bearer(&client, &token).await,
+            Err(BearerError::InvalidTokenType)
+        ));
+
+        server.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn refuses_tokens_without_audiences() {
+        let server = MockProvider::new(vec![MockUser::new("alice")])
+            .start()
+            .await
+            .unwrap();
+        let mut config =
+            server.config(RedirectUrl::new("http://localhost/finish_login".to_string()).unwrap());
+        config.bearer_audiences.clear();
+        let client = config.discover().await.unwrap();
+
+        let token = server.access_token("alice").unwrap();
+        assert!(matches!(
+            verify_bearer(&client, &token).await,
+            Err(BearerError::MissingAudiences)
+        ));
+
+        server.stop().await;
+    }
+}
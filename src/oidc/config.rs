@@ -1,12 +1,33 @@
 use std::collections::HashSet;
+use std::future::Future;
 use std::ops::Deref;
+use std::time::Duration;
 
 use actix_web::web::Data;
-use openidconnect::core::{CoreClient, CoreProviderMetadata};
-use openidconnect::reqwest::{async_http_client, HttpClientError};
-use openidconnect::{ClientId, ClientSecret, DiscoveryError, IssuerUrl, RedirectUrl, Scope};
+use chrono::{DateTime, Utc};
+use log::warn;
+use openidconnect::core::{
+    CoreAuthPrompt, CoreIdTokenVerifier, CoreJsonWebKeySet, CoreJwsSigningAlgorithm,
+    CoreProviderMetadata, CoreResponseMode, CoreResponseType,
+};
+use openidconnect::reqwest::HttpClientError;
+use openidconnect::url::Url;
+use openidconnect::{
+    AdditionalClaims, Audience, AuthType, AuthUrl, AuthenticationContextClass,
+    ClaimsVerificationError, ClientId, ClientSecret, DeviceAuthorizationUrl, DiscoveryError,
+    EmptyAdditionalClaims, IntrospectionUrl, IssuerUrl, JsonWebKeySetUrl, LanguageTag, LoginHint,
+    RedirectUrl, RegistrationUrl, Scope, TokenUrl, UserInfoUrl,
+};
 use serde::{Deserialize, Serialize};
 
+use crate::clock::SharedClock;
+use crate::oidc::assertion::AssertionSigner;
+use crate::oidc::keys::{KeyRotation, KeySource, KeyStore};
+use crate::oidc::{
+    AuthStateStore, ClaimPolicy, ClientAuthMethod, FrontChannelLogout, HttpClient,
+    LoginErrorHandler, OidcClient, PostLoginHook, RoleMapping, SessionEncryptionKey, TokenStore,
+};
+
 /// Configuration for Open ID Connect
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -14,18 +35,183 @@ pub struct Config {
     pub finish_login_url: RedirectUrl,
 
     /// Url [`finish_login`] will redirect to
+    ///
+    /// This is used if [`login`] wasn't called with a valid `next` parameter.
     pub post_auth_url: String,
 
+    /// Origins (e.g. `https://app.example.com`) [`login`]'s `next` parameter may redirect to
+    ///
+    /// Paths like `/some/page` and urls on the origin of [`Config::finish_login_url`] are always allowed.
+    /// Defaults to none
+    #[serde(default)]
+    pub allowed_return_origins: Vec<String>,
+
     /// Data about the oidc provider
     pub provider: Provider,
 
     /// List of scopes to request from oidc provider
     pub scopes: HashSet<Scope>,
 
+    /// Additional parameters sent with every authorization request
+    ///
+    /// They can be overwritten per request using [`login`]'s query parameters.
+    #[serde(default)]
+    pub auth_params: AuthParams,
+
+    /// Additional response types requested besides `code`
+    ///
+    /// Setting any selects the hybrid flow, e.g. `["id_token"]` requests `code id_token`.
+    /// [`finish_login`] still exchanges the code and verifies the id token returned by the token
+    /// endpoint, additional tokens in the authorization response are ignored.
+    ///
+    /// Providers return hybrid responses in the url's fragment by default, which never reaches
+    /// the server. [`Config::discover`] therefore fails unless the [`Config::response_mode`]
+    /// is `form_post`.
+    #[serde(default)]
+    pub response_types: Vec<CoreResponseType>,
+
+    /// How the provider returns the authorization response to [`finish_login`]
+    ///
+    /// `form_post` requires [`finish_login`] to be registered for `POST` requests as well.
+    /// The provider's page submits the form cross-site, so the session cookie has to be set
+    /// with `SameSite=None`, e.g. by the
+    /// [`CrossSite`](crate::tb_middleware::SessionCookiePreset::CrossSite) preset.
+    /// Browsers don't send `SameSite=Lax` cookies with it, so [`finish_login`] can't find
+    /// the login's state and fails with
+    /// [`MissingState`](crate::oidc::FinishLoginError::MissingState).
+    ///
+    /// `fragment` is rejected by [`Config::discover`], as the response would never reach the server.
+    ///
+    /// Defaults to the provider's default for the selected flow
+    #[serde(default)]
+    pub response_mode: Option<CoreResponseMode>,
+
+    /// APIs the access token should be usable for (RFC 8707 resource indicators)
+    ///
+    /// Sent as `resource` parameters in the authorization and token requests.
+    #[serde(default)]
+    pub resources: Vec<Url>,
+
+    /// The API the access token should be issued for
+    ///
+    /// Sent as `audience` parameter in the authorization request.
+    /// This isn't standardized, but required by providers like Auth0
+    /// to issue access tokens for an API instead of opaque ones.
+    #[serde(default)]
+    pub audience: Option<String>,
+
+    /// Fetch the provider's UserInfo endpoint in [`finish_login`]
+    /// and store its claims in [`UserData::user_info`](crate::oidc::UserData::user_info)
+    ///
+    /// Some providers only return claims like `groups` from this endpoint.
+    /// Defaults to `false`
+    #[serde(default)]
+    pub fetch_user_info: bool,
+
+    /// Audiences accepted in tokens extracted by [`BearerClaims`](crate::oidc::BearerClaims)
+    ///
+    /// Usually the identifier of your API at the provider, not the [`Provider::client_id`].
+    ///
+    /// Defaults to none, which rejects every bearer token
+    #[serde(default)]
+    pub bearer_audiences: Vec<Audience>,
+
+    /// Mapping from the provider's claims to the [`UserData::roles`](crate::oidc::UserData::roles)
+    ///
+    /// Defaults to not assigning any roles
+    #[serde(default)]
+    pub roles: RoleMapping,
+
+    /// Requirements the user's claims have to meet to log in
+    ///
+    /// Defaults to accepting every user
+    #[serde(default)]
+    pub claim_policy: ClaimPolicy,
+
+    /// Names of the claims the [`me`](crate::oidc::me) handler returns
+    ///
+    /// Defaults to `sub`, `name`, `preferred_username`, `email` and `picture`
+    #[serde(default = "default_me_claims")]
+    pub me_claims: Vec<String>,
+
+    /// How the provider's signing keys are refreshed
+    ///
+    /// Provides a [`Default::default`]
+    #[serde(default)]
+    pub key_rotation: KeyRotation,
+
+    /// How often [`Config::discover`] retries reaching the provider
+    ///
+    /// Provides a [`Default::default`]
+    #[serde(default)]
+    pub discovery_retry: DiscoveryRetry,
+
     /// Set of keys (strings) under which this modules stores its data in the user's session
     ///
     /// Provides a [`Default::default`]
     pub session_keys: SessionKeys,
+
+    /// Key to encrypt the [`UserData`](crate::oidc::UserData) with before storing it in the session
+    ///
+    /// Defaults to storing it unencrypted
+    #[serde(default)]
+    pub session_encryption_key: Option<SessionEncryptionKey>,
+
+    /// Renew the session's key after a successful login to prevent session fixation
+    ///
+    /// The session's data is kept.
+    /// Defaults to `true`
+    #[serde(default = "default_renew_session")]
+    pub renew_session: bool,
+
+    /// Validation of the parameters sent to [`front_channel_logout`](crate::oidc::front_channel_logout)
+    ///
+    /// Provides a [`Default::default`]
+    #[serde(default)]
+    pub front_channel_logout: FrontChannelLogout,
+
+    /// Seconds the provider's clock may be off when verifying the `exp`, `iat`
+    /// and `auth_time` claims
+    ///
+    /// Defaults to 60 seconds
+    #[serde(default = "default_clock_skew")]
+    pub clock_skew: u64,
+
+    /// Clock used to verify the tokens' timestamps
+    ///
+    /// This is not (de)serialized and defaults to the system's time.
+    #[serde(skip)]
+    pub clock: SharedClock,
+
+    /// Http client used for all requests to the oidc provider
+    ///
+    /// This is not (de)serialized and defaults to a fresh client for every request.
+    #[serde(skip)]
+    pub http_client: HttpClient,
+
+    /// Renders the errors occurring in [`finish_login`]
+    ///
+    /// This is not (de)serialized and defaults to the errors' [`ResponseError`](actix_web::ResponseError) implementation.
+    #[serde(skip)]
+    pub error_handler: LoginErrorHandler,
+
+    /// Where [`login`] stores the state [`finish_login`] verifies the provider's response with
+    ///
+    /// This is not (de)serialized and defaults to the user's session.
+    #[serde(skip)]
+    pub state_store: AuthStateStore,
+
+    /// Where [`finish_login`] stores the user's tokens
+    ///
+    /// This is not (de)serialized and defaults to the user's session.
+    #[serde(skip)]
+    pub token_store: TokenStore,
+
+    /// Called by [`finish_login`] after the claims have been verified
+    ///
+    /// This is not (de)serialized and defaults to accepting every login.
+    #[serde(skip)]
+    pub post_login: PostLoginHook,
 }
 
 /// Set of keys (strings) under which this modules stores its data in the user's session
@@ -48,6 +234,116 @@ impl Default for SessionKeys {
     }
 }
 
+fn default_renew_session() -> bool {
+    true
+}
+
+pub(crate) fn default_clock_skew() -> u64 {
+    60
+}
+
+pub(crate) fn default_me_claims() -> Vec<String> {
+    ["sub", "name", "preferred_username", "email", "picture"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+/// Standard parameters of the authorization request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthParams {
+    /// Whether the provider should prompt the user, e.g. `login` to enforce reauthentication
+    pub prompt: Vec<CoreAuthPrompt>,
+
+    /// Seconds since the user's last authentication after which they have to reauthenticate
+    pub max_age: Option<u64>,
+
+    /// Hint about the user's identifier, e.g. their email address
+    pub login_hint: Option<LoginHint>,
+
+    /// Requested authentication context classes in order of preference
+    pub acr_values: Vec<AuthenticationContextClass>,
+
+    /// Preferred languages for the provider's user interface in order of preference
+    pub ui_locales: Vec<LanguageTag>,
+}
+
+/// Configuration of how often discovery is retried if the provider can't be reached
+///
+/// Only network errors and `5xx` responses are retried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveryRetry {
+    /// Number of attempts before giving up
+    ///
+    /// Defaults to 1, i.e. no retries
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+
+    /// Seconds to wait before the first retry
+    ///
+    /// The delay is doubled for every further retry.
+    ///
+    /// Defaults to 1 second
+    #[serde(default = "default_initial_delay")]
+    pub initial_delay: u64,
+
+    /// Maximum seconds to wait between two attempts
+    ///
+    /// Defaults to 30 seconds
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+}
+fn default_attempts() -> u32 {
+    1
+}
+fn default_initial_delay() -> u64 {
+    1
+}
+fn default_max_delay() -> u64 {
+    30
+}
+impl Default for DiscoveryRetry {
+    fn default() -> Self {
+        Self {
+            attempts: default_attempts(),
+            initial_delay: default_initial_delay(),
+            max_delay: default_max_delay(),
+        }
+    }
+}
+
+impl DiscoveryRetry {
+    /// Run a request to the provider until it succeeds or the attempts are exhausted
+    pub(crate) async fn run<T, F: Future<Output = Result<T, DiscoveryError<HttpClientError>>>>(
+        &self,
+        mut request: impl FnMut() -> F,
+    ) -> Result<T, DiscoveryError<HttpClientError>> {
+        let mut delay = Duration::from_secs(self.initial_delay);
+        let mut attempt = 1;
+        loop {
+            match request().await {
+                Err(err) if attempt < self.attempts && is_transient(&err) => {
+                    warn!("Couldn't reach the oidc provider (attempt {attempt}): {err}");
+                    actix_web::rt::time::sleep(delay).await;
+                    delay = (delay * 2).min(Duration::from_secs(self.max_delay));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Check whether retrying a failed request might succeed
+fn is_transient(err: &DiscoveryError<HttpClientError>) -> bool {
+    match err {
+        DiscoveryError::Request(_) => true,
+        DiscoveryError::Response(status, _, _) => status.is_server_error(),
+        _ => false,
+    }
+}
+
 /// Data about the oidc provider
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Provider {
@@ -58,54 +354,572 @@ pub struct Provider {
     pub client_secret: Option<ClientSecret>,
 
     /// The oidc provider's auth url
+    ///
+    /// This is also the issuer tokens are checked against, if [`Provider::metadata`] is set.
     pub discover_url: IssuerUrl,
+
+    /// Explicit endpoints of the oidc provider
+    ///
+    /// If set, [`Config::discover`] won't use discovery.
+    /// This is useful for deployments which can't reach the discovery url.
+    #[serde(default)]
+    pub metadata: Option<ProviderMetadata>,
+
+    /// The oidc provider's device authorization endpoint
+    ///
+    /// Required for [`start_device_login`](crate::oidc::start_device_login),
+    /// because it isn't part of the discovered metadata.
+    #[serde(default)]
+    pub device_authorization_url: Option<DeviceAuthorizationUrl>,
+
+    /// The oidc provider's token introspection endpoint
+    ///
+    /// Required for [`Client::introspect_token`], because it isn't part of the discovered metadata.
+    #[serde(default)]
+    pub introspection_url: Option<IntrospectionUrl>,
+
+    /// The oidc provider's dynamic client registration endpoint
+    ///
+    /// Used by [`Config::register`] instead of discovering it.
+    #[serde(default)]
+    pub registration_url: Option<RegistrationUrl>,
+
+    /// Don't use PKCE when logging in
+    ///
+    /// **Only** set this for legacy providers which reject PKCE parameters.
+    /// Without PKCE a leaked authorization code can be exchanged for tokens by an attacker.
+    ///
+    /// Defaults to `false`
+    #[serde(default)]
+    pub disable_pkce: bool,
+
+    /// Don't verify the id token's `nonce` claim when logging in
+    ///
+    /// **Only** set this for legacy providers which don't return the nonce in the id token.
+    /// Without it an attacker could replay an intercepted id token.
+    ///
+    /// Defaults to `false`
+    #[serde(default)]
+    pub disable_nonce: bool,
+
+    /// Algorithms the provider's tokens may be signed with
+    ///
+    /// Tokens signed with any other algorithm are rejected.
+    /// Unsigned tokens (`none`) are never accepted.
+    ///
+    /// Defaults to `["RS256"]`
+    #[serde(default = "default_signing_algs")]
+    pub signing_algs: Vec<CoreJwsSigningAlgorithm>,
+
+    /// How your application authenticates itself at the provider's token endpoint
+    ///
+    /// Provides a [`Default::default`]
+    #[serde(default)]
+    pub client_auth: ClientAuthMethod,
+}
+fn default_signing_algs() -> Vec<CoreJwsSigningAlgorithm> {
+    vec![CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256]
+}
+
+/// Explicit endpoints of the oidc provider used instead of discovery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderMetadata {
+    /// The provider's authorization endpoint
+    pub authorization_url: AuthUrl,
+
+    /// The provider's token endpoint
+    pub token_url: TokenUrl,
+
+    /// The provider's UserInfo endpoint
+    #[serde(default)]
+    pub userinfo_url: Option<UserInfoUrl>,
+
+    /// The provider's endpoint serving its signing keys
+    ///
+    /// The keys are fetched once in [`Config::discover`].
+    /// Use [`ProviderMetadata::jwks`] if the endpoint can't be reached either.
+    #[serde(default)]
+    pub jwks_url: Option<JsonWebKeySetUrl>,
+
+    /// The provider's signing keys
+    ///
+    /// Either these or the [`ProviderMetadata::jwks_url`] are required to verify tokens,
+    /// [`Config::discover`] fails if neither is set.
+    #[serde(default)]
+    pub jwks: Option<CoreJsonWebKeySet>,
+
+    /// The provider's endpoint to log the user out
+    #[serde(default)]
+    pub end_session_url: Option<Url>,
 }
 
 impl Config {
     /// Fetch the provider's metadata using discovery and create a client
     ///
+    /// Discovery is skipped if [`Provider::metadata`] is set.
+    ///
     /// The [`Ok`] value should be passed to [`App::app_data`](actix_web::App::app_data)
     pub async fn discover(self) -> Result<Data<Client>, DiscoveryError<HttpClientError>> {
+        self.discover_with_claims().await
+    }
+
+    /// Fetch the provider's metadata using discovery and create a client
+    /// which deserializes additional claims into `AC`
+    ///
+    /// The [`Ok`] value should be passed to [`App::app_data`](actix_web::App::app_data)
+    /// and the handlers have to be instantiated with the same `AC`:
+    ///
+    /// ```no_run
+    /// use actix_toolbox::oidc::openidconnect::AdditionalClaims;
+    /// use actix_toolbox::oidc::{finish_login, login, Config};
+    /// use actix_web::web::get;
+    /// use actix_web::App;
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Debug, Clone, Serialize, Deserialize)]
+    /// struct GroupClaims {
+    ///     groups: Vec<String>,
+    /// }
+    /// impl AdditionalClaims for GroupClaims {}
+    ///
+    /// # async fn setup(config: Config) {
+    /// let client = config
+    ///     .discover_with_claims::<GroupClaims>()
+    ///     .await
+    ///     .expect("Failed openid discover");
+    /// let app = App::new()
+    ///     .app_data(client)
+    ///     .route("/login", get().to(login::<GroupClaims>))
+    ///     .route("/finish_login", get().to(finish_login::<GroupClaims>));
+    /// # }
+    /// ```
+    pub async fn discover_with_claims<AC: AdditionalClaims>(
+        self,
+    ) -> Result<Data<Client<AC>>, DiscoveryError<HttpClientError>> {
         let Config {
             finish_login_url,
             post_auth_url,
+            allowed_return_origins,
             provider:
                 Provider {
                     client_id,
                     client_secret,
                     discover_url,
+                    device_authorization_url,
+                    introspection_url,
+                    registration_url: _,
+                    metadata,
+                    disable_pkce,
+                    disable_nonce,
+                    mut signing_algs,
+                    client_auth,
                 },
             scopes,
+            auth_params,
+            response_types,
+            response_mode,
+            resources,
+            audience,
+            fetch_user_info,
+            bearer_audiences,
+            roles,
+            claim_policy,
+            me_claims,
+            key_rotation,
+            discovery_retry,
             session_keys,
+            session_encryption_key,
+            renew_session,
+            front_channel_logout,
+            clock_skew,
+            clock,
+            http_client,
+            error_handler,
+            state_store,
+            token_store,
+            post_login,
         } = self;
+        let clock_skew = chrono::Duration::seconds(clock_skew as i64);
+
+        if response_mode == Some(CoreResponseMode::Fragment) {
+            return Err(DiscoveryError::Other(
+                "The fragment response mode never reaches finish_login".to_string(),
+            ));
+        }
+        if response_types
+            .iter()
+            .any(|response_type| *response_type != CoreResponseType::Code)
+            && response_mode != Some(CoreResponseMode::FormPost)
+        {
+            return Err(DiscoveryError::Other(
+                "Additional response types require the form_post response mode".to_string(),
+            ));
+        }
+
+        signing_algs.retain(|alg| *alg != CoreJwsSigningAlgorithm::None);
+        let assertion_key = client_auth
+            .key(client_secret.as_ref())
+            .map_err(DiscoveryError::Other)?;
+        let assertion_client_id = client_id.clone();
+        // The secret must not be sent when authenticating with a JWT
+        let request_secret = client_secret.clone().filter(|_| client_auth.sends_secret());
 
-        let provider_metadata =
-            CoreProviderMetadata::discover_async(discover_url, async_http_client).await?;
-        let client =
-            CoreClient::from_provider_metadata(provider_metadata, client_id, client_secret)
-                .set_redirect_uri(finish_login_url);
+        let end_session_url;
+        let keys;
+        let token_endpoint;
+        let mut client = match metadata {
+            Some(ProviderMetadata {
+                authorization_url,
+                token_url,
+                userinfo_url,
+                jwks_url,
+                jwks,
+                end_session_url: url,
+            }) => {
+                let jwks = match (jwks, &jwks_url) {
+                    (Some(jwks), _) => jwks,
+                    (None, Some(jwks_url)) => {
+                        discovery_retry
+                            .run(|| {
+                                CoreJsonWebKeySet::fetch_async(jwks_url, |request| {
+                                    http_client.request(request)
+                                })
+                            })
+                            .await?
+                    }
+                    (None, None) => {
+                        return Err(DiscoveryError::Other(
+                            "The provider's metadata contains neither jwks nor a jwks_url"
+                                .to_string(),
+                        ))
+                    }
+                };
+                end_session_url = url;
+                token_endpoint = Some(token_url.clone());
+                keys = KeyStore::new(
+                    client_id.clone(),
+                    client_secret.clone(),
+                    discover_url.clone(),
+                    jwks_url.map_or(KeySource::Static, |url| {
+                        KeySource::Url(url, http_client.clone())
+                    }),
+                    key_rotation,
+                    clock.clone(),
+                    jwks.clone(),
+                );
+                OidcClient::new(
+                    client_id,
+                    request_secret,
+                    discover_url,
+                    authorization_url,
+                    Some(token_url),
+                    userinfo_url,
+                    jwks,
+                )
+            }
+            None => {
+                let provider_metadata = discovery_retry
+                    .run(|| {
+                        CoreProviderMetadata::discover_async(discover_url.clone(), |request| {
+                            http_client.request(request)
+                        })
+                    })
+                    .await?;
+                end_session_url = None;
+                token_endpoint = provider_metadata.token_endpoint().cloned();
+                keys = KeyStore::new(
+                    client_id.clone(),
+                    client_secret.clone(),
+                    provider_metadata.issuer().clone(),
+                    KeySource::Discovery(http_client.clone()),
+                    key_rotation,
+                    clock.clone(),
+                    provider_metadata.jwks().clone(),
+                );
+                OidcClient::from_provider_metadata(provider_metadata, client_id, request_secret)
+            }
+        }
+        .set_redirect_uri(finish_login_url.clone());
+        if !matches!(client_auth, ClientAuthMethod::ClientSecretBasic) {
+            client = client.set_auth_type(AuthType::RequestBody);
+        }
+        let assertion_signer = assertion_key.map(|key| AssertionSigner {
+            key,
+            client_id: assertion_client_id,
+            audience: token_endpoint.map_or_else(
+                || keys.issuer().to_string(),
+                |token_url| token_url.to_string(),
+            ),
+        });
+        if let Some(device_authorization_url) = device_authorization_url {
+            client = client.set_device_authorization_uri(device_authorization_url);
+        }
+        if let Some(introspection_url) = introspection_url {
+            client = client.set_introspection_uri(introspection_url);
+        }
 
         Ok(Data::new(Client {
             client,
+            finish_login_url,
             post_auth_url,
+            allowed_return_origins,
             scopes,
+            auth_params,
+            response_types,
+            response_mode,
+            resources,
+            audience,
+            fetch_user_info,
+            bearer_audiences,
+            roles,
+            claim_policy,
+            me_claims,
+            disable_pkce,
+            disable_nonce,
+            signing_algs,
+            assertion_signer,
+            end_session_url,
+            keys,
             session_keys,
+            session_encryption_key,
+            renew_session,
+            front_channel_logout,
+            clock_skew,
+            clock,
+            http_client,
+            error_handler,
+            state_store,
+            token_store,
+            post_login,
         }))
     }
 }
 
 /// Client the [`handler`] depend on
-pub struct Client {
-    pub(crate) client: CoreClient,
+pub struct Client<AC: AdditionalClaims = EmptyAdditionalClaims> {
+    pub(crate) client: OidcClient<AC>,
+    pub(crate) finish_login_url: RedirectUrl,
     pub(crate) post_auth_url: String,
+    pub(crate) allowed_return_origins: Vec<String>,
     pub(crate) scopes: HashSet<Scope>,
+    pub(crate) auth_params: AuthParams,
+    pub(crate) response_types: Vec<CoreResponseType>,
+    pub(crate) response_mode: Option<CoreResponseMode>,
+    pub(crate) resources: Vec<Url>,
+    pub(crate) audience: Option<String>,
+    pub(crate) fetch_user_info: bool,
+    pub(crate) bearer_audiences: Vec<Audience>,
+    pub(crate) roles: RoleMapping,
+    pub(crate) claim_policy: ClaimPolicy,
+    pub(crate) me_claims: Vec<String>,
+    pub(crate) disable_pkce: bool,
+    pub(crate) disable_nonce: bool,
+    pub(crate) signing_algs: Vec<CoreJwsSigningAlgorithm>,
+    pub(crate) assertion_signer: Option<AssertionSigner>,
+    pub(crate) end_session_url: Option<Url>,
+    pub(crate) keys: KeyStore,
     pub(crate) session_keys: SessionKeys,
+    pub(crate) session_encryption_key: Option<SessionEncryptionKey>,
+    pub(crate) renew_session: bool,
+    pub(crate) front_channel_logout: FrontChannelLogout,
+    pub(crate) clock_skew: chrono::Duration,
+    pub(crate) clock: SharedClock,
+    pub(crate) http_client: HttpClient,
+    pub(crate) error_handler: LoginErrorHandler,
+    pub(crate) state_store: AuthStateStore,
+    pub(crate) token_store: TokenStore,
+    pub(crate) post_login: PostLoginHook,
 }
 
-impl Deref for Client {
-    type Target = CoreClient;
+impl<AC: AdditionalClaims> Client<AC> {
+    /// The provider's endpoint to log the user out
+    ///
+    /// This is only known if it has been set in [`ProviderMetadata::end_session_url`].
+    pub fn end_session_url(&self) -> Option<&Url> {
+        self.end_session_url.as_ref()
+    }
+
+    /// The clock used to check the expiry of tokens
+    ///
+    /// Configured in [`Config::clock`].
+    pub fn clock(&self) -> &SharedClock {
+        &self.clock
+    }
+
+    /// Fetch the provider's current signing keys
+    ///
+    /// This is done automatically as configured in [`Config::key_rotation`]
+    /// and whenever a token is signed by an unknown key.
+    /// Call this, if you know the provider has rotated its keys.
+    pub async fn refresh_keys(&self) -> Result<(), DiscoveryError<HttpClientError>> {
+        self.keys.refresh().await
+    }
+
+    /// Verify a token using the provider's current keys and respecting the [`Config`]
+    ///
+    /// `verify` may be called twice, if the keys had to be refreshed.
+    pub(crate) async fn verify<T>(
+        &self,
+        verify: impl Fn(CoreIdTokenVerifier<'static>) -> Result<T, ClaimsVerificationError>,
+    ) -> Result<T, ClaimsVerificationError> {
+        self.keys
+            .verify(|verifier| {
+                // Tolerate the provider's clock being off by the skew in either direction
+                let skew = self.clock_skew;
+                let clock = self.clock.clone();
+                let iat_clock = self.clock.clone();
+                let auth_time_clock = self.clock.clone();
+                verify(
+                    verifier
+                        .set_allowed_algs(self.signing_algs.clone())
+                        .set_time_fn(move || clock.now() - skew)
+                        .set_issue_time_verifier_fn(move |iat| {
+                            not_in_future("iat", iat, &iat_clock, skew)
+                        })
+                        .set_auth_time_verifier_fn(move |auth_time| {
+                            auth_time.map_or(Ok(()), |auth_time| {
+                                not_in_future("auth_time", auth_time, &auth_time_clock, skew)
+                            })
+                        }),
+                )
+            })
+            .await
+    }
+
+    /// Get the path [`configure`](crate::oidc::configure) mounts the handlers below
+    ///
+    /// This is the parent of [`Config::finish_login_url`]'s path.
+    pub(crate) fn handler_base_path(&self) -> &str {
+        self.finish_login_url
+            .url()
+            .path()
+            .rsplit_once('/')
+            .map_or("", |(base, _)| base)
+    }
+
+    /// Check whether the user may be redirected to `url` after logging in
+    ///
+    /// Allows absolute paths and absolute urls whose origin is this application's
+    /// or in [`Config::allowed_return_origins`].
+    /// Urls containing credentials are rejected, as they are mostly used to disguise the host.
+    pub(crate) fn is_allowed_return_url(&self, url: &str) -> bool {
+        // Browsers strip tabs and newlines and treat backslashes as slashes,
+        // which would turn e.g. `/\t/evil.com` into the protocol relative `//evil.com`
+        if url
+            .chars()
+            .any(|c| c.is_control() || c.is_whitespace() || c == '\\')
+        {
+            return false;
+        }
+
+        let base = self.finish_login_url.url();
+        let resolved = if url.starts_with('/') {
+            base.join(url)
+        } else {
+            Url::parse(url)
+        };
+        let Ok(resolved) = resolved else {
+            return false;
+        };
+        if !resolved.username().is_empty() || resolved.password().is_some() {
+            return false;
+        }
+        let origin = resolved.origin();
+        origin.is_tuple()
+            && (origin == base.origin()
+                || self
+                    .allowed_return_origins
+                    .iter()
+                    .filter_map(|allowed| Url::parse(allowed).ok())
+                    .any(|allowed| allowed.origin() == origin))
+    }
+}
+
+impl<AC: AdditionalClaims> Deref for Client<AC> {
+    type Target = OidcClient<AC>;
 
     fn deref(&self) -> &Self::Target {
         &self.client
     }
 }
+
+/// Reject timestamps which lie further in the future than the clock skew
+pub(crate) fn not_in_future(
+    claim: &str,
+    time: DateTime<Utc>,
+    clock: &SharedClock,
+    skew: chrono::Duration,
+) -> Result<(), String> {
+    let now = clock.now();
+    if time > now + skew {
+        Err(format!(
+            "{claim} {time} is in the future (current time is {now})"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use openidconnect::core::{CoreResponseMode, CoreResponseType};
+    use openidconnect::RedirectUrl;
+
+    use crate::oidc::test::{MockProvider, MockUser};
+
+    #[actix_web::test]
+    async fn return_urls() {
+        let server = MockProvider::new(vec![MockUser::new("alice")])
+            .start()
+            .await
+            .unwrap();
+        let mut config =
+            server.config(RedirectUrl::new("http://localhost/finish_login".to_string()).unwrap());
+        config.allowed_return_origins = vec!["https://allowed.com".to_string()];
+        let client = config.discover().await.unwrap();
+
+        for url in [
+            "/",
+            "/dashboard?tab=1#top",
+            "http://localhost/dashboard",
+            "https://allowed.com/dashboard",
+        ] {
+            assert!(client.is_allowed_return_url(url), "{url}");
+        }
+        for url in [
+            "//evil.com",
+            "/\\evil.com",
+            "/\t/evil.com",
+            "/\n/evil.com",
+            "javascript:alert(1)",
+            "data:text/html,evil",
+            "https://evil.com",
+            "https://evil.com@allowed.com",
+            "https://allowed.com@evil.com",
+            "http://allowed.com",
+            "dashboard",
+            "",
+        ] {
+            assert!(!client.is_allowed_return_url(url), "{url:?}");
+        }
+
+        server.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn hybrid_flow_requires_form_post() {
+        let server = MockProvider::new(vec![MockUser::new("alice")])
+            .start()
+            .await
+            .unwrap();
+        let mut config =
+            server.config(RedirectUrl::new("http://localhost/finish_login".to_string()).unwrap());
+        config.response_types = vec![CoreResponseType::IdToken];
+        assert!(config.clone().discover().await.is_err());
+
+        config.response_mode = Some(CoreResponseMode::FormPost);
+        assert!(config.discover().await.is_ok());
+
+        server.stop().await;
+    }
+}
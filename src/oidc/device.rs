@@ -0,0 +1,342 @@
+use std::time::Duration;
+
+use actix_session::{Session, SessionGetError, SessionInsertError};
+use actix_web::http::{header, StatusCode};
+use actix_web::web::{Data, Json};
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use openidconnect::core::{CoreDeviceAuthorizationResponse, CoreRequestTokenError};
+use openidconnect::reqwest::HttpClientError;
+use openidconnect::{
+    AdditionalClaims, ClaimsVerificationError, ConfigurationError, DeviceCodeErrorResponse,
+    DeviceCodeErrorResponseType, Nonce, RequestTokenError, SigningError, TokenResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::oidc::logout::session_id;
+use crate::oidc::refresh::expires_at;
+use crate::oidc::{Client, LoginClaims, PolicyViolation, TokenStoreError, UserData};
+
+/// Maximum time [`poll_device_login`] waits for the user before responding
+const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Response of [`start_device_login`]
+///
+/// The companion (e.g. a CLI) has to show the `user_code` and `verification_uri` to the user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeviceLogin {
+    /// The code the user has to enter at the `verification_uri`
+    pub user_code: String,
+
+    /// The url the user has to visit
+    pub verification_uri: String,
+
+    /// The url the user has to visit including the `user_code`, if the provider supports it
+    pub verification_uri_complete: Option<String>,
+
+    /// Seconds until the device flow expires
+    pub expires_in: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DeviceState {
+    details: CoreDeviceAuthorizationResponse,
+    expires_at: DateTime<Utc>,
+}
+
+/// Handler starting the device authorization grant for CLI or headless companions of your app
+///
+/// The pending flow is stored in the session, so the companion has to keep the session cookie
+/// and call [`poll_device_login`] afterwards.
+///
+/// This requires [`Provider::device_authorization_url`](crate::oidc::Provider::device_authorization_url).
+///
+/// `AC` has to match the [`Client`]'s additional claims.
+pub async fn start_device_login<AC: AdditionalClaims>(
+    client: Data<Client<AC>>,
+    session: Session,
+) -> Result<Json<DeviceLogin>, DeviceLoginError> {
+    let mut request = client
+        .exchange_device_code()
+        .map_err(DeviceLoginError::MissingDeviceEndpoint)?;
+    for scope in &client.scopes {
+        request = request.add_scope(scope.clone());
+    }
+    for resource in &client.resources {
+        request = request.add_extra_param("resource", resource.as_str());
+    }
+    if let Some(audience) = &client.audience {
+        request = request.add_extra_param("audience", audience);
+    }
+    for (name, value) in client
+        .client_assertion()
+        .map_err(DeviceLoginError::ClientAssertion)?
+    {
+        request = request.add_extra_param(name, value);
+    }
+    let details: CoreDeviceAuthorizationResponse = request
+        .request_async(|request| client.http_client.request(request))
+        .await
+        .map_err(DeviceLoginError::FailedRequestDeviceCode)?;
+
+    let expires_in = details.expires_in();
+    let response = DeviceLogin {
+        user_code: details.user_code().secret().clone(),
+        verification_uri: details.verification_uri().to_string(),
+        verification_uri_complete: details
+            .verification_uri_complete()
+            .map(|uri| uri.secret().clone()),
+        expires_in: expires_in.as_secs(),
+    };
+
+    session
+        .insert(
+            &client.session_keys.request,
+            DeviceState {
+                expires_at: client.clock.now()
+                    + chrono::Duration::from_std(expires_in).unwrap_or(chrono::Duration::zero()),
+                details,
+            },
+        )
+        .map_err(DeviceLoginError::SessionInsert)?;
+
+    Ok(Json(response))
+}
+
+/// Handler waiting for the user to finish the device flow started by [`start_device_login`]
+///
+/// This long-polls the provider:
+/// - `200 Ok` is returned once the user logged in. The [`UserData`] is stored in the session.
+/// - `202 Accepted` is returned if the user hasn't logged in yet. Call this handler again.
+/// - [`DeviceLoginError::Expired`] is returned if the device flow has expired.
+///
+/// `AC` has to match the [`Client`]'s additional claims.
+pub async fn poll_device_login<AC: AdditionalClaims + Clone>(
+    request: HttpRequest,
+    client: Data<Client<AC>>,
+    session: Session,
+) -> Result<HttpResponse, DeviceLoginError> {
+    let DeviceState {
+        details,
+        expires_at: flow_expires_at,
+    } = session
+        .get(&client.session_keys.request)
+        .map_err(DeviceLoginError::SessionGet)?
+        .ok_or(DeviceLoginError::MissingState)?;
+
+    let remaining = (flow_expires_at - client.clock.now())
+        .to_std()
+        .map_err(|_| DeviceLoginError::Expired)?;
+
+    let mut token_request = client.exchange_device_access_token(&details);
+    for resource in &client.resources {
+        token_request = token_request.add_extra_param("resource", resource.as_str());
+    }
+    for (name, value) in client
+        .client_assertion()
+        .map_err(DeviceLoginError::ClientAssertion)?
+    {
+        token_request = token_request.add_extra_param(name, value);
+    }
+    let result = token_request
+        .request_async(
+            |request| client.http_client.request(request),
+            actix_web::rt::time::sleep,
+            Some(remaining.min(LONG_POLL_TIMEOUT)),
+        )
+        .await;
+    let token = match result {
+        Ok(token) => token,
+        Err(RequestTokenError::ServerResponse(err))
+            if *err.error() == DeviceCodeErrorResponseType::ExpiredToken =>
+        {
+            // Also reported when the long poll times out
+            return if remaining > LONG_POLL_TIMEOUT {
+                Ok(HttpResponse::Accepted().finish())
+            } else {
+                session.remove(&client.session_keys.request);
+                Err(DeviceLoginError::Expired)
+            };
+        }
+        Err(err) => {
+            session.remove(&client.session_keys.request);
+            return Err(DeviceLoginError::FailedRequestToken(err));
+        }
+    };
+    session.remove(&client.session_keys.request);
+
+    // The device flow doesn't use a nonce
+    let id_token = token.id_token().ok_or(DeviceLoginError::MissingIdToken)?;
+    let claims = client
+        .verify(|verifier| {
+            id_token
+                .claims(&verifier, |_: Option<&Nonce>| Ok(()))
+                .cloned()
+        })
+        .await
+        .map_err(DeviceLoginError::InvalidIdToken)?;
+    client
+        .claim_policy
+        .check(&claims, None::<&()>)
+        .map_err(DeviceLoginError::PolicyViolation)?;
+
+    let roles = client.roles.roles(&claims, None::<&()>);
+
+    if let Some(hook) = client.post_login.hook() {
+        let login_claims = LoginClaims {
+            subject: claims.subject().to_string(),
+            claims: serde_json::to_value(&claims).unwrap_or_default(),
+            user_info: None,
+            roles: roles.clone(),
+        };
+        hook(request, login_claims)
+            .await
+            .map_err(DeviceLoginError::Rejected)?;
+    }
+
+    if client.renew_session {
+        session.renew();
+    }
+    client
+        .insert_user_data(
+            &session,
+            &UserData {
+                roles,
+                session_id: session_id(id_token),
+                claims,
+                user_info: None,
+                expires_at: expires_at(&token, client.clock.now()),
+                token,
+            },
+            client.clock.now(),
+        )
+        .await
+        .map_err(DeviceLoginError::TokenStore)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Error returned by [`start_device_login`] and [`poll_device_login`]
+#[derive(Debug)]
+pub enum DeviceLoginError {
+    /// The provider's device authorization endpoint isn't configured
+    MissingDeviceEndpoint(ConfigurationError),
+
+    /// Failed to request a device code from the oidc provider
+    FailedRequestDeviceCode(CoreRequestTokenError<HttpClientError>),
+
+    /// There is no pending device flow in the user's session
+    MissingState,
+
+    /// The device flow has expired before the user logged in
+    Expired,
+
+    /// Failed to sign the JWT authenticating your application
+    ClientAssertion(SigningError),
+
+    /// Failed to request the actual token from the oidc provider
+    FailedRequestToken(RequestTokenError<HttpClientError, DeviceCodeErrorResponse>),
+
+    /// The provider didn't send a id token
+    MissingIdToken,
+
+    /// Failed to verify the id token while reading claims
+    InvalidIdToken(ClaimsVerificationError),
+
+    /// The user's claims don't meet the [`Config::claim_policy`](crate::oidc::Config::claim_policy)
+    PolicyViolation(PolicyViolation),
+
+    /// The [`PostLoginHook`](crate::oidc::PostLoginHook) rejected the login
+    Rejected(actix_web::Error),
+
+    /// Error from [`Session::get`]
+    SessionGet(SessionGetError),
+
+    /// Error from [`Session::insert`]
+    SessionInsert(SessionInsertError),
+
+    /// Failed to store the user data
+    TokenStore(TokenStoreError),
+}
+impl std::fmt::Display for DeviceLoginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceLoginError::MissingDeviceEndpoint(err) => {
+                write!(f, "Can't start the device flow: {err}")
+            }
+            DeviceLoginError::FailedRequestDeviceCode(err) => {
+                write!(f, "Failed to request device code: {err}")
+            }
+            DeviceLoginError::MissingState => {
+                write!(f, "Device flow is missing from user session")
+            }
+            DeviceLoginError::Expired => write!(f, "The device flow has expired"),
+            DeviceLoginError::ClientAssertion(err) => {
+                write!(f, "Failed to sign the client assertion: {err}")
+            }
+            DeviceLoginError::FailedRequestToken(err) => {
+                write!(f, "Failed to request token: {err}")
+            }
+            DeviceLoginError::MissingIdToken => {
+                write!(f, "Provider didn't respond with an ID token")
+            }
+            DeviceLoginError::InvalidIdToken(err) => {
+                write!(f, "The ID token didn't pass the verification: {err}")
+            }
+            DeviceLoginError::PolicyViolation(err) => write!(f, "The login was rejected: {err}"),
+            DeviceLoginError::Rejected(err) => write!(f, "The login was rejected: {err}"),
+            DeviceLoginError::SessionGet(err) => {
+                write!(f, "Failed to get device flow from user session: {err}")
+            }
+            DeviceLoginError::SessionInsert(err) => {
+                write!(f, "Failed to set data in user session: {err}")
+            }
+            DeviceLoginError::TokenStore(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl std::error::Error for DeviceLoginError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DeviceLoginError::MissingDeviceEndpoint(err) => Some(err),
+            DeviceLoginError::FailedRequestDeviceCode(err) => Some(err),
+            DeviceLoginError::MissingState => None,
+            DeviceLoginError::Expired => None,
+            DeviceLoginError::ClientAssertion(err) => Some(err),
+            DeviceLoginError::FailedRequestToken(err) => Some(err),
+            DeviceLoginError::MissingIdToken => None,
+            DeviceLoginError::InvalidIdToken(err) => Some(err),
+            DeviceLoginError::PolicyViolation(err) => Some(err),
+            DeviceLoginError::Rejected(_) => None,
+            DeviceLoginError::SessionGet(err) => Some(err),
+            DeviceLoginError::SessionInsert(err) => Some(err),
+            DeviceLoginError::TokenStore(err) => Some(err),
+        }
+    }
+}
+impl ResponseError for DeviceLoginError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DeviceLoginError::MissingState => StatusCode::BAD_REQUEST,
+            DeviceLoginError::Expired => StatusCode::GONE,
+            // The user denied the authorization request
+            DeviceLoginError::FailedRequestToken(RequestTokenError::ServerResponse(response))
+                if *response.error() == DeviceCodeErrorResponseType::AccessDenied =>
+            {
+                StatusCode::FORBIDDEN
+            }
+            DeviceLoginError::PolicyViolation(err) => err.status_code(),
+            DeviceLoginError::Rejected(err) => err.as_response_error().status_code(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            DeviceLoginError::Rejected(err) => err.error_response(),
+            _ => HttpResponse::build(self.status_code())
+                .insert_header(header::ContentType::plaintext())
+                .body(self.to_string()),
+        }
+    }
+}
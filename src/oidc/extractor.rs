@@ -0,0 +1,105 @@
+use actix_session::SessionExt;
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::web::Data;
+use actix_web::{FromRequest, HttpRequest, ResponseError};
+use futures::future::LocalBoxFuture;
+use openidconnect::{AdditionalClaims, EmptyAdditionalClaims};
+
+use crate::oidc::{Client, SessionKeys, TokenStoreError, UserData};
+
+/// Read the [`UserData`] from the request's session
+///
+/// The session key and [`TokenStore`](crate::oidc::TokenStore) are taken from the [`Client`]
+/// registered as app data and fall back to [`SessionKeys::default`].
+pub(crate) async fn get_user_data<AC: AdditionalClaims>(
+    req: &HttpRequest,
+) -> Result<Option<UserData<AC>>, TokenStoreError> {
+    let session = req.get_session();
+    match req.app_data::<Data<Client<AC>>>() {
+        Some(client) => client.get_user_data(&session, client.clock.now()).await,
+        None => session
+            .get(&SessionKeys::default().data)
+            .map_err(TokenStoreError::SessionGet),
+    }
+}
+
+/// Extract the logged-in user from the session
+///
+/// Responds with `401 Unauthorized` if the user isn't logged in.
+/// Use [`OptionalUserData`] to handle this case yourself.
+///
+/// ```no_run
+/// use actix_toolbox::oidc::UserData;
+/// use actix_web::HttpResponse;
+///
+/// async fn index(user: UserData) -> HttpResponse {
+///     HttpResponse::Ok().body(format!("Hello {}", user.claims.subject().as_str()))
+/// }
+/// ```
+impl<AC: AdditionalClaims> FromRequest for UserData<AC> {
+    type Error = UserDataError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            get_user_data(&req)
+                .await
+                .map_err(UserDataError::TokenStore)?
+                .ok_or(UserDataError::NotLoggedIn)
+        })
+    }
+}
+
+/// Extract the logged-in user from the session, if there is one
+pub struct OptionalUserData<AC: AdditionalClaims = EmptyAdditionalClaims>(pub Option<UserData<AC>>);
+
+impl<AC: AdditionalClaims> FromRequest for OptionalUserData<AC> {
+    type Error = UserDataError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            get_user_data(&req)
+                .await
+                .map(OptionalUserData)
+                .map_err(UserDataError::TokenStore)
+        })
+    }
+}
+
+/// Error returned by the [`UserData`] and [`OptionalUserData`] extractors
+#[derive(Debug)]
+pub enum UserDataError {
+    /// The user hasn't logged in
+    NotLoggedIn,
+
+    /// Failed to load the user data
+    TokenStore(TokenStoreError),
+}
+impl std::fmt::Display for UserDataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserDataError::NotLoggedIn => write!(f, "The user isn't logged in"),
+            UserDataError::TokenStore(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl std::error::Error for UserDataError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UserDataError::NotLoggedIn => None,
+            UserDataError::TokenStore(err) => Some(err),
+        }
+    }
+}
+impl ResponseError for UserDataError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UserDataError::NotLoggedIn => StatusCode::UNAUTHORIZED,
+            UserDataError::TokenStore(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
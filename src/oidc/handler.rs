@@ -1,96 +1,290 @@
-use actix_session::{Session, SessionInsertError};
-use actix_web::http::header;
-use actix_web::web::{Data, Query, Redirect};
-use actix_web::{HttpResponse, ResponseError};
-use openidconnect::core::{CoreAuthenticationFlow, CoreRequestTokenError};
-use openidconnect::reqwest::{async_http_client, HttpClientError};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_session::Session;
+use actix_web::http::{header, StatusCode};
+use actix_web::web::{Data, Form, Query, Redirect};
+use actix_web::{Either, HttpRequest, HttpResponse, ResponseError};
+use futures::future::LocalBoxFuture;
+use openidconnect::core::{
+    CoreAuthErrorResponseType, CoreAuthPrompt, CoreAuthenticationFlow, CoreErrorResponseType,
+    CoreRequestTokenError, CoreResponseType,
+};
+use openidconnect::reqwest::HttpClientError;
 use openidconnect::{
-    AccessTokenHash, AuthorizationCode, ClaimsVerificationError, CsrfToken, Nonce,
-    OAuth2TokenResponse, PkceCodeChallenge, PkceCodeVerifier, SigningError, TokenResponse,
+    AccessTokenHash, AdditionalClaims, AuthenticationContextClass, AuthorizationCode,
+    ClaimsVerificationError, ConfigurationError, CsrfToken, LanguageTag, LoginHint, Nonce,
+    NonceVerifier, OAuth2TokenResponse, PkceCodeChallenge, RequestTokenError, SigningError,
+    TokenResponse, UserInfoError,
 };
-use serde::{Deserialize, Serialize};
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
 
-use crate::oidc::{Client, UserData};
+use crate::oidc::logout::session_id;
+use crate::oidc::refresh::expires_at;
+use crate::oidc::state::AuthState;
+use crate::oidc::{AuthParams, AuthStateError, Client, PolicyViolation, TokenStoreError, UserData};
 
 /// Handler for OIDC's login endpoint
-pub async fn login(client: Data<Client>, session: Session) -> Result<Redirect, SessionInsertError> {
-    // Create a PKCE code verifier and SHA-256 encode it as a code challenge.
-    let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
+///
+/// The optional `next` query parameter (e.g. `/login?next=/some/page`) is the url [`finish_login`]
+/// will redirect to instead of [`Config::post_auth_url`](crate::oidc::Config::post_auth_url).
+/// It has to be a relative path or match one of the
+/// [`Config::allowed_return_origins`](crate::oidc::Config::allowed_return_origins),
+/// otherwise it is ignored.
+///
+/// The [`Config::auth_params`](crate::oidc::Config::auth_params) can be overwritten using the
+/// query parameters `prompt`, `max_age`, `login_hint`, `acr_values` and `ui_locales`.
+/// Lists are separated by spaces like in the authorization request itself.
+///
+/// `AC` has to match the [`Client`]'s additional claims.
+/// Use [`EmptyAdditionalClaims`](openidconnect::EmptyAdditionalClaims) for a client created by
+/// [`Config::discover`](crate::oidc::Config::discover).
+pub async fn login<AC: AdditionalClaims>(
+    client: Data<Client<AC>>,
+    params: Query<LoginRequest>,
+    session: Session,
+) -> Result<Redirect, AuthStateError> {
+    let LoginRequest {
+        next,
+        prompt,
+        max_age,
+        login_hint,
+        acr_values,
+        ui_locales,
+    } = params.into_inner();
+    let next = next.filter(|next| client.is_allowed_return_url(next));
+
+    let defaults = &client.auth_params;
+    let auth_params = AuthParams {
+        prompt: prompt.map_or_else(
+            || defaults.prompt.clone(),
+            |prompt| prompt.split_whitespace().map(parse_prompt).collect(),
+        ),
+        max_age: max_age.or(defaults.max_age),
+        login_hint: login_hint.or_else(|| defaults.login_hint.clone()),
+        acr_values: acr_values.map_or_else(
+            || defaults.acr_values.clone(),
+            |values| {
+                values
+                    .split_whitespace()
+                    .map(|value| AuthenticationContextClass::new(value.to_string()))
+                    .collect()
+            },
+        ),
+        ui_locales: ui_locales.map_or_else(
+            || defaults.ui_locales.clone(),
+            |locales| {
+                locales
+                    .split_whitespace()
+                    .map(|locale| LanguageTag::new(locale.to_string()))
+                    .collect()
+            },
+        ),
+    };
+
+    // The code is always requested, because finish_login exchanges it for the tokens
+    let mut response_types = vec![CoreResponseType::Code];
+    for response_type in &client.response_types {
+        if !response_types.contains(response_type) {
+            response_types.push(response_type.clone());
+        }
+    }
+    let flow = if response_types.len() > 1 {
+        CoreAuthenticationFlow::Hybrid(response_types)
+    } else {
+        CoreAuthenticationFlow::AuthorizationCode
+    };
 
     // Generate the authorization URL to which we'll redirect the user.
-    let mut request = client
-        .authorize_url(
-            CoreAuthenticationFlow::AuthorizationCode,
-            CsrfToken::new_random,
-            Nonce::new_random,
-        )
-        .set_pkce_challenge(pkce_code_challenge);
+    let mut request = client.authorize_url(flow, CsrfToken::new_random, Nonce::new_random);
+    if let Some(response_mode) = &client.response_mode {
+        request = request.add_extra_param("response_mode", response_mode.as_ref());
+    }
+    for resource in &client.resources {
+        request = request.add_extra_param("resource", resource.as_str());
+    }
+    if let Some(audience) = &client.audience {
+        request = request.add_extra_param("audience", audience);
+    }
+
+    // Create a PKCE code verifier and SHA-256 encode it as a code challenge.
+    let mut pkce_code_verifier = None;
+    if !client.disable_pkce {
+        let (pkce_code_challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+        request = request.set_pkce_challenge(pkce_code_challenge);
+        pkce_code_verifier = Some(verifier);
+    }
     for scope in &client.scopes {
         request = request.add_scope(scope.clone());
     }
+    for prompt in auth_params.prompt {
+        request = request.add_prompt(prompt);
+    }
+    if let Some(max_age) = auth_params.max_age {
+        request = request.set_max_age(Duration::from_secs(max_age));
+    }
+    if let Some(login_hint) = auth_params.login_hint {
+        request = request.set_login_hint(login_hint);
+    }
+    for acr_value in auth_params.acr_values {
+        request = request.add_auth_context_value(acr_value);
+    }
+    for ui_locale in auth_params.ui_locales {
+        request = request.add_ui_locale(ui_locale);
+    }
     let (auth_url, csrf_token, nonce) = request.url();
 
     // Store the csrf_token to verify it in finish_login
-    session.insert(
-        &client.session_keys.request,
-        AuthState {
-            csrf_token,
-            pkce_code_verifier,
-            nonce,
-        },
-    )?;
+    client
+        .state_store
+        .save(
+            &session,
+            &client.session_keys.request,
+            AuthState {
+                csrf_token,
+                pkce_code_verifier,
+                nonce,
+                next,
+            },
+            client.clock.now(),
+        )
+        .await?;
 
     Ok(Redirect::to(auth_url.to_string()).temporary())
 }
 
-#[derive(Serialize, Deserialize)]
-struct AuthState {
-    csrf_token: CsrfToken,
-    pkce_code_verifier: PkceCodeVerifier,
-    nonce: Nonce,
+/// Parse a single value of the `prompt` parameter
+fn parse_prompt(prompt: &str) -> CoreAuthPrompt {
+    // Unknown values are kept as extension, so deserializing from a string can't fail
+    CoreAuthPrompt::deserialize(prompt.into_deserializer())
+        .unwrap_or_else(|_: serde::de::value::Error| CoreAuthPrompt::Extension(prompt.to_string()))
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    next: Option<String>,
+    prompt: Option<String>,
+    max_age: Option<u64>,
+    login_hint: Option<LoginHint>,
+    acr_values: Option<String>,
+    ui_locales: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct AuthRequest {
-    code: AuthorizationCode,
+    code: Option<AuthorizationCode>,
     state: CsrfToken,
+    error: Option<CoreAuthErrorResponseType>,
+    error_description: Option<String>,
 }
 
 /// Handler for the OIDC endpoint the user will be redirected to from the OIDC provider
-pub async fn finish_login(
-    client: Data<Client>,
-    params: Query<AuthRequest>,
+///
+/// The provider's response is read from the query or,
+/// for the `form_post` [`Config::response_mode`](crate::oidc::Config::response_mode),
+/// from the form.
+///
+/// Errors are rendered by the [`Config::error_handler`](crate::oidc::Config::error_handler)
+/// if one is set.
+/// Otherwise errors caused by the user respond with `4xx`
+/// and those caused by the provider with `502` or `503`.
+///
+/// `AC` has to match the [`Client`]'s additional claims.
+pub async fn finish_login<AC: AdditionalClaims + Clone>(
+    client: Data<Client<AC>>,
+    params: Either<Form<AuthRequest>, Query<AuthRequest>>,
+    session: Session,
+    request: HttpRequest,
+) -> Result<HttpResponse, FinishLoginError> {
+    let params = match params {
+        Either::Left(form) => form.into_inner(),
+        Either::Right(query) => query.into_inner(),
+    };
+    match finish_login_inner(&client, params, session, &request).await {
+        Ok(response) => Ok(response),
+        Err(err) => match &client.error_handler.0 {
+            Some(handler) => Ok(handler(&request, &err)),
+            None => Err(err),
+        },
+    }
+}
+
+async fn finish_login_inner<AC: AdditionalClaims + Clone>(
+    client: &Client<AC>,
+    params: AuthRequest,
     session: Session,
+    request: &HttpRequest,
 ) -> Result<HttpResponse, FinishLoginError> {
-    let AuthRequest { code, state } = params.into_inner();
+    let AuthRequest {
+        code,
+        state,
+        error,
+        error_description,
+    } = params;
 
     // Get and remove the state generated in login
     let AuthState {
         csrf_token,
         pkce_code_verifier,
         nonce,
-    } = session
-        .remove_as(&client.session_keys.request)
-        .ok_or(FinishLoginError::MissingState)?
-        .map_err(|_| FinishLoginError::MissingState)?;
+        next,
+    } = client
+        .state_store
+        .take(&session, &client.session_keys.request, client.clock.now())
+        .await
+        .map_err(FinishLoginError::LoadState)?
+        .ok_or(FinishLoginError::MissingState)?;
 
     // Check the states to match
     if state.secret() != csrf_token.secret() {
         return Err(FinishLoginError::InvalidState);
     }
 
+    // The provider redirects with an error instead of a code, e.g. if the user denied the access
+    if let Some(error) = error {
+        return Err(FinishLoginError::ProviderError {
+            error,
+            description: error_description,
+        });
+    }
+    let code = code.ok_or(FinishLoginError::MissingCode)?;
+
     // Exchange the code with a token.
-    let token = client
-        .exchange_code(code)
-        .set_pkce_verifier(pkce_code_verifier)
-        .request_async(async_http_client)
+    let mut token_request = client.exchange_code(code);
+    for resource in &client.resources {
+        token_request = token_request.add_extra_param("resource", resource.as_str());
+    }
+    for (name, value) in client
+        .client_assertion()
+        .map_err(FinishLoginError::ClientAssertion)?
+    {
+        token_request = token_request.add_extra_param(name, value);
+    }
+    if let Some(pkce_code_verifier) = pkce_code_verifier {
+        token_request = token_request.set_pkce_verifier(pkce_code_verifier);
+    }
+    let token = token_request
+        .request_async(|request| client.http_client.request(request))
         .await
         .map_err(FinishLoginError::FailedRequestToken)?;
 
     // Extract the ID token claims after verifying its authenticity and nonce.
     let id_token = token.id_token().ok_or(FinishLoginError::MissingIdToken)?;
-    let claims = id_token
-        .claims(&client.id_token_verifier(), &nonce)
+    let claims = client
+        .verify(|verifier| {
+            id_token
+                .claims(&verifier, |claim: Option<&Nonce>| {
+                    if client.disable_nonce {
+                        Ok(())
+                    } else {
+                        (&nonce).verify(claim)
+                    }
+                })
+                .cloned()
+        })
+        .await
         .map_err(FinishLoginError::InvalidIdToken)?;
 
     // Verify the access token hash to ensure that the access token hasn't been substituted for
@@ -108,22 +302,181 @@ pub async fn finish_login(
         }
     }
 
+    // Fetch additional claims from the UserInfo endpoint
+    let user_info = if client.fetch_user_info {
+        Some(
+            client
+                .user_info(token.access_token().clone(), Some(claims.subject().clone()))
+                .map_err(FinishLoginError::MissingUserInfoEndpoint)?
+                .request_async(|request| client.http_client.request(request))
+                .await
+                .map_err(FinishLoginError::FailedRequestUserInfo)?,
+        )
+    } else {
+        None
+    };
+
+    client
+        .claim_policy
+        .check(&claims, user_info.as_ref())
+        .map_err(FinishLoginError::PolicyViolation)?;
+
+    let roles = client.roles.roles(&claims, user_info.as_ref());
+
+    if let Some(hook) = client.post_login.hook() {
+        let login_claims = LoginClaims {
+            subject: claims.subject().to_string(),
+            claims: serde_json::to_value(&claims).unwrap_or_default(),
+            user_info: user_info
+                .as_ref()
+                .and_then(|user_info| serde_json::to_value(user_info).ok()),
+            roles: roles.clone(),
+        };
+        hook(request.clone(), login_claims)
+            .await
+            .map_err(FinishLoginError::Rejected)?;
+    }
+
     // Store in session
-    session
-        .insert(
-            &client.session_keys.data,
-            UserData {
-                claims: claims.clone(),
+    if client.renew_session {
+        session.renew();
+    }
+    client
+        .insert_user_data(
+            &session,
+            &UserData {
+                roles,
+                session_id: session_id(id_token),
+                claims,
+                user_info,
+                expires_at: expires_at(&token, client.clock.now()),
                 token,
             },
+            client.clock.now(),
         )
-        .map_err(FinishLoginError::SessionInsert)?;
+        .await
+        .map_err(FinishLoginError::TokenStore)?;
 
     Ok(HttpResponse::Found()
-        .append_header((header::LOCATION, client.post_auth_url.as_str()))
+        .append_header((
+            header::LOCATION,
+            next.as_deref().unwrap_or(&client.post_auth_url),
+        ))
         .finish())
 }
 
+/// Renders a [`FinishLoginError`] into a response
+///
+/// Use it to render branded error pages, redirect back to [`login`] or respond with json:
+///
+/// ```no_run
+/// use actix_toolbox::oidc::{FinishLoginError, LoginErrorHandler};
+/// use actix_web::http::header;
+/// use actix_web::HttpResponse;
+///
+/// let handler = LoginErrorHandler::new(|_request, error| match error {
+///     FinishLoginError::MissingState | FinishLoginError::InvalidState => HttpResponse::Found()
+///         .append_header((header::LOCATION, "/login"))
+///         .finish(),
+///     _ => HttpResponse::InternalServerError().body("<h1>Login failed</h1>"),
+/// });
+/// ```
+///
+/// Defaults to the [`ResponseError`] implementation of [`FinishLoginError`].
+#[derive(Clone, Default)]
+pub struct LoginErrorHandler(Option<Arc<ErrorHandlerFn>>);
+type ErrorHandlerFn = dyn Fn(&HttpRequest, &FinishLoginError) -> HttpResponse + Send + Sync;
+impl LoginErrorHandler {
+    /// Wrap a function rendering the errors
+    pub fn new(
+        handler: impl Fn(&HttpRequest, &FinishLoginError) -> HttpResponse + Send + Sync + 'static,
+    ) -> Self {
+        Self(Some(Arc::new(handler)))
+    }
+}
+impl std::fmt::Debug for LoginErrorHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("LoginErrorHandler")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// The verified claims passed to the [`PostLoginHook`]
+///
+/// The claims are passed as json, because the hook doesn't know the [`Client`]'s additional claims.
+#[derive(Debug, Clone)]
+pub struct LoginClaims {
+    /// The user's subject identifier
+    pub subject: String,
+
+    /// The id token's claims
+    pub claims: serde_json::Value,
+
+    /// The claims returned by the provider's UserInfo endpoint
+    ///
+    /// This is only fetched if [`Config::fetch_user_info`](crate::oidc::Config::fetch_user_info) is set.
+    pub user_info: Option<serde_json::Value>,
+
+    /// The application roles mapped from the claims
+    pub roles: HashSet<String>,
+}
+
+/// Async function called by [`finish_login`] and [`poll_device_login`](crate::oidc::poll_device_login)
+/// after the claims have been verified
+///
+/// Use it to provision a local user or to reject logins.
+/// Returning an error aborts the login and responds with it,
+/// unless a [`LoginErrorHandler`] renders [`FinishLoginError::Rejected`].
+///
+/// Resources like a database can be taken from the request's app data:
+///
+/// ```no_run
+/// use actix_toolbox::oidc::PostLoginHook;
+/// use actix_web::error::ErrorForbidden;
+/// use actix_web::web::Data;
+///
+/// struct Allowlist(Vec<String>);
+///
+/// let hook = PostLoginHook::new(|request, claims| {
+///     Box::pin(async move {
+///         let allowlist = request.app_data::<Data<Allowlist>>().expect("Missing allowlist");
+///         if allowlist.0.contains(&claims.subject) {
+///             Ok(())
+///         } else {
+///             Err(ErrorForbidden("You're not allowed to use this app"))
+///         }
+///     })
+/// });
+/// ```
+///
+/// Defaults to accepting every login.
+#[derive(Clone, Default)]
+pub struct PostLoginHook(Option<Arc<PostLoginFn>>);
+type PostLoginFn = dyn Fn(HttpRequest, LoginClaims) -> PostLoginFuture + Send + Sync;
+type PostLoginFuture = LocalBoxFuture<'static, Result<(), actix_web::Error>>;
+impl PostLoginHook {
+    /// Wrap an async function
+    pub fn new(
+        hook: impl Fn(HttpRequest, LoginClaims) -> PostLoginFuture + Send + Sync + 'static,
+    ) -> Self {
+        Self(Some(Arc::new(hook)))
+    }
+
+    /// Get the wrapped function, `None` if every login is accepted
+    pub(crate) fn hook(&self) -> Option<&PostLoginFn> {
+        self.0.as_deref()
+    }
+}
+impl std::fmt::Debug for PostLoginHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PostLoginHook")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// Error returned by [`finish_login`]
 #[derive(Debug)]
 pub enum FinishLoginError {
     /// There is no `state` in the user's session
@@ -133,6 +486,23 @@ pub enum FinishLoginError {
     /// The `state` in the user's session doesn't match the `state` the oidc provider responded with.
     InvalidState,
 
+    /// Failed to load the state from the [`AuthStateStore`](crate::oidc::AuthStateStore)
+    LoadState(AuthStateError),
+
+    /// The provider responded with an error instead of an authorization code
+    ProviderError {
+        /// The error's code
+        error: CoreAuthErrorResponseType,
+        /// The error's human-readable description
+        description: Option<String>,
+    },
+
+    /// The provider responded with neither an authorization code nor an error
+    MissingCode,
+
+    /// Failed to sign the JWT authenticating your application
+    ClientAssertion(SigningError),
+
     /// Failed to request the actual token from the oidc provider
     FailedRequestToken(CoreRequestTokenError<HttpClientError>),
 
@@ -148,14 +518,43 @@ pub enum FinishLoginError {
     /// The claims' access token doesn't match the oidc's
     InvalidAccessTokenHash,
 
-    /// Error from [`Session::insert`]
-    SessionInsert(SessionInsertError),
+    /// The provider doesn't have a UserInfo endpoint, but [`Config::fetch_user_info`](crate::oidc::Config::fetch_user_info) is set
+    MissingUserInfoEndpoint(ConfigurationError),
+
+    /// Failed to request the UserInfo endpoint
+    FailedRequestUserInfo(UserInfoError<HttpClientError>),
+
+    /// The user's claims don't meet the [`Config::claim_policy`](crate::oidc::Config::claim_policy)
+    PolicyViolation(PolicyViolation),
+
+    /// The [`PostLoginHook`] rejected the login
+    Rejected(actix_web::Error),
+
+    /// Failed to store the user data
+    TokenStore(TokenStoreError),
 }
 impl std::fmt::Display for FinishLoginError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             FinishLoginError::MissingState => write!(f, "State is missing from user session"),
             FinishLoginError::InvalidState => write!(f, "State in user session is invalid"),
+            FinishLoginError::LoadState(err) => write!(f, "Failed to load the state: {err}"),
+            FinishLoginError::ProviderError { error, description } => match description {
+                Some(description) => {
+                    write!(
+                        f,
+                        "Provider responded with {}: {description}",
+                        error.as_ref()
+                    )
+                }
+                None => write!(f, "Provider responded with {}", error.as_ref()),
+            },
+            FinishLoginError::MissingCode => {
+                write!(f, "Provider didn't respond with an authorization code")
+            }
+            FinishLoginError::ClientAssertion(err) => {
+                write!(f, "Failed to sign the client assertion: {err}")
+            }
             FinishLoginError::FailedRequestToken(err) => {
                 write!(f, "Failed to request token: {err}")
             }
@@ -171,9 +570,15 @@ impl std::fmt::Display for FinishLoginError {
             FinishLoginError::InvalidAccessTokenHash => {
                 write!(f, "The access token's hash doesn't match")
             }
-            FinishLoginError::SessionInsert(err) => {
-                write!(f, "Failed to set token in user session: {err}")
+            FinishLoginError::MissingUserInfoEndpoint(err) => {
+                write!(f, "Can't fetch the UserInfo endpoint: {err}")
             }
+            FinishLoginError::FailedRequestUserInfo(err) => {
+                write!(f, "Failed to request UserInfo: {err}")
+            }
+            FinishLoginError::PolicyViolation(err) => write!(f, "The login was rejected: {err}"),
+            FinishLoginError::Rejected(err) => write!(f, "The login was rejected: {err}"),
+            FinishLoginError::TokenStore(err) => write!(f, "{err}"),
         }
     }
 }
@@ -182,13 +587,69 @@ impl std::error::Error for FinishLoginError {
         match self {
             FinishLoginError::MissingState => None,
             FinishLoginError::InvalidState => None,
+            FinishLoginError::LoadState(err) => Some(err),
+            FinishLoginError::ProviderError { .. } => None,
+            FinishLoginError::MissingCode => None,
+            FinishLoginError::ClientAssertion(err) => Some(err),
             FinishLoginError::FailedRequestToken(err) => Some(err),
-            FinishLoginError::SessionInsert(err) => Some(err),
+            FinishLoginError::TokenStore(err) => Some(err),
             FinishLoginError::MissingIdToken => None,
             FinishLoginError::CreateAccessTokenHash(err) => Some(err),
             FinishLoginError::InvalidAccessTokenHash => None,
             FinishLoginError::InvalidIdToken(err) => Some(err),
+            FinishLoginError::MissingUserInfoEndpoint(err) => Some(err),
+            FinishLoginError::FailedRequestUserInfo(err) => Some(err),
+            FinishLoginError::PolicyViolation(err) => Some(err),
+            FinishLoginError::Rejected(_) => None,
+        }
+    }
+}
+impl ResponseError for FinishLoginError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            // The user's session expired or the callback was opened directly
+            FinishLoginError::MissingState => StatusCode::BAD_REQUEST,
+            FinishLoginError::InvalidState => StatusCode::BAD_REQUEST,
+            FinishLoginError::LoadState(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            FinishLoginError::ProviderError { error, .. } => match error {
+                CoreAuthErrorResponseType::AccessDenied
+                | CoreAuthErrorResponseType::AccountSelectionRequired
+                | CoreAuthErrorResponseType::ConsentRequired
+                | CoreAuthErrorResponseType::InteractionRequired
+                | CoreAuthErrorResponseType::LoginRequired => StatusCode::UNAUTHORIZED,
+                CoreAuthErrorResponseType::ServerError => StatusCode::BAD_GATEWAY,
+                CoreAuthErrorResponseType::TemporarilyUnavailable => {
+                    StatusCode::SERVICE_UNAVAILABLE
+                }
+                _ => StatusCode::BAD_REQUEST,
+            },
+            FinishLoginError::MissingCode => StatusCode::BAD_REQUEST,
+            // The code expired or has already been used, e.g. by reloading the page
+            FinishLoginError::FailedRequestToken(RequestTokenError::ServerResponse(response))
+                if *response.error() == CoreErrorResponseType::InvalidGrant =>
+            {
+                StatusCode::BAD_REQUEST
+            }
+            FinishLoginError::FailedRequestToken(_) => StatusCode::BAD_GATEWAY,
+            FinishLoginError::MissingIdToken => StatusCode::BAD_GATEWAY,
+            FinishLoginError::InvalidIdToken(_) => StatusCode::BAD_GATEWAY,
+            FinishLoginError::ClientAssertion(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            FinishLoginError::CreateAccessTokenHash(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            FinishLoginError::InvalidAccessTokenHash => StatusCode::BAD_GATEWAY,
+            FinishLoginError::MissingUserInfoEndpoint(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            FinishLoginError::FailedRequestUserInfo(_) => StatusCode::BAD_GATEWAY,
+            FinishLoginError::PolicyViolation(err) => err.status_code(),
+            FinishLoginError::Rejected(err) => err.as_response_error().status_code(),
+            FinishLoginError::TokenStore(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            FinishLoginError::Rejected(err) => err.error_response(),
+            _ => HttpResponse::build(self.status_code())
+                .insert_header(header::ContentType::plaintext())
+                .body(self.to_string()),
         }
     }
 }
-impl ResponseError for FinishLoginError {}
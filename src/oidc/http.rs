@@ -0,0 +1,61 @@
+use openidconnect::reqwest::{async_http_client, Error, HttpClientError};
+use openidconnect::{HttpRequest, HttpResponse};
+
+/// The http client used for all requests to the oidc provider
+///
+/// Provides a [`Default::default`] which creates a fresh client for every request
+/// like [`async_http_client`] does.
+///
+/// Use [`HttpClient::new`] to configure a proxy, custom CAs, timeouts and the like:
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use actix_toolbox::oidc::reqwest::{redirect, Client, Proxy};
+/// use actix_toolbox::oidc::HttpClient;
+///
+/// let client = Client::builder()
+///     .proxy(Proxy::all("http://proxy.corp:3128").unwrap())
+///     .timeout(Duration::from_secs(10))
+///     // Following redirects opens the client up to SSRF vulnerabilities
+///     .redirect(redirect::Policy::none())
+///     .build()
+///     .unwrap();
+/// let http_client = HttpClient::new(client);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HttpClient(Option<reqwest::Client>);
+
+impl HttpClient {
+    /// Use a custom reqwest client
+    ///
+    /// The client shouldn't follow redirects.
+    pub fn new(client: reqwest::Client) -> Self {
+        Self(Some(client))
+    }
+
+    /// Send a request to the oidc provider
+    pub(crate) async fn request(
+        &self,
+        request: HttpRequest,
+    ) -> Result<HttpResponse, HttpClientError> {
+        let Some(client) = &self.0 else {
+            return async_http_client(request).await;
+        };
+
+        let mut request_builder = client
+            .request(request.method, request.url.as_str())
+            .body(request.body);
+        for (name, value) in &request.headers {
+            request_builder = request_builder.header(name.as_str(), value.as_bytes());
+        }
+        let request = request_builder.build().map_err(Error::Reqwest)?;
+
+        let response = client.execute(request).await.map_err(Error::Reqwest)?;
+        Ok(HttpResponse {
+            status_code: response.status(),
+            headers: response.headers().to_owned(),
+            body: response.bytes().await.map_err(Error::Reqwest)?.to_vec(),
+        })
+    }
+}
@@ -0,0 +1,75 @@
+use openidconnect::core::{CoreErrorResponseType, CoreTokenIntrospectionResponse};
+use openidconnect::reqwest::HttpClientError;
+use openidconnect::{
+    AccessToken, AdditionalClaims, ConfigurationError, RequestTokenError, SigningError,
+    StandardErrorResponse,
+};
+
+use crate::oidc::Client;
+
+impl<AC: AdditionalClaims> Client<AC> {
+    /// Ask the provider's introspection endpoint (RFC 7662) about an access token
+    ///
+    /// Use this to validate opaque access tokens which can't be verified locally
+    /// like [`BearerClaims`](crate::oidc::BearerClaims) does for JWTs.
+    /// The response has to be checked for
+    /// [`active`](openidconnect::TokenIntrospectionResponse::active) before trusting the token.
+    ///
+    /// This requires [`Provider::introspection_url`](crate::oidc::Provider::introspection_url).
+    pub async fn introspect_token(
+        &self,
+        token: &AccessToken,
+    ) -> Result<CoreTokenIntrospectionResponse, IntrospectionError> {
+        let mut request = self
+            .client
+            .introspect(token)
+            .map_err(IntrospectionError::MissingIntrospectionEndpoint)?;
+        for (name, value) in self
+            .client_assertion()
+            .map_err(IntrospectionError::ClientAssertion)?
+        {
+            request = request.add_extra_param(name, value);
+        }
+        request
+            .request_async(|request| self.http_client.request(request))
+            .await
+            .map_err(IntrospectionError::FailedRequest)
+    }
+}
+
+/// Error returned by [`Client::introspect_token`]
+#[derive(Debug)]
+pub enum IntrospectionError {
+    /// The provider's introspection endpoint isn't configured
+    MissingIntrospectionEndpoint(ConfigurationError),
+
+    /// Failed to sign the JWT authenticating your application
+    ClientAssertion(SigningError),
+
+    /// Failed to request the token's metadata from the oidc provider
+    FailedRequest(RequestTokenError<HttpClientError, StandardErrorResponse<CoreErrorResponseType>>),
+}
+impl std::fmt::Display for IntrospectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntrospectionError::MissingIntrospectionEndpoint(err) => {
+                write!(f, "Can't introspect token: {err}")
+            }
+            IntrospectionError::ClientAssertion(err) => {
+                write!(f, "Failed to sign the client assertion: {err}")
+            }
+            IntrospectionError::FailedRequest(err) => {
+                write!(f, "Failed to introspect token: {err}")
+            }
+        }
+    }
+}
+impl std::error::Error for IntrospectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IntrospectionError::MissingIntrospectionEndpoint(err) => Some(err),
+            IntrospectionError::ClientAssertion(err) => Some(err),
+            IntrospectionError::FailedRequest(err) => Some(err),
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::sync::RwLock;
+
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use openidconnect::core::{CoreIdTokenVerifier, CoreJsonWebKeySet, CoreProviderMetadata};
+use openidconnect::reqwest::HttpClientError;
+use openidconnect::{
+    ClaimsVerificationError, ClientId, ClientSecret, DiscoveryError, IssuerUrl, JsonWebKeySetUrl,
+    SignatureVerificationError,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::SharedClock;
+use crate::oidc::HttpClient;
+
+/// Minimum time between two refreshes triggered by an unknown key
+///
+/// This prevents tokens with made up key ids from flooding the provider with requests.
+const MIN_ON_DEMAND_INTERVAL: Duration = Duration::seconds(60);
+
+/// Configuration of how the provider's signing keys are refreshed
+///
+/// Independent of this configuration, the keys are refreshed on demand
+/// whenever a token is signed by an unknown key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyRotation {
+    /// Seconds after which the keys are refreshed
+    ///
+    /// Defaults to none, i.e. only refreshing on demand
+    #[serde(default)]
+    pub refresh_interval: Option<u64>,
+
+    /// Seconds replaced keys are still accepted after a refresh
+    ///
+    /// Defaults to 5 minutes
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u64,
+}
+fn default_grace_period() -> u64 {
+    5 * 60
+}
+impl Default for KeyRotation {
+    fn default() -> Self {
+        Self {
+            refresh_interval: None,
+            grace_period: default_grace_period(),
+        }
+    }
+}
+
+/// Where the signing keys are fetched from
+pub(crate) enum KeySource {
+    /// Use discovery to find the current keys
+    Discovery(HttpClient),
+    /// Fetch the keys from a fixed url
+    Url(JsonWebKeySetUrl, HttpClient),
+    /// The keys have been configured explicitly and can't be refreshed
+    Static,
+}
+
+/// The provider's signing keys which are refreshed when the provider rotates them
+pub(crate) struct KeyStore {
+    client_id: ClientId,
+    client_secret: Option<ClientSecret>,
+    issuer: IssuerUrl,
+    source: KeySource,
+    rotation: KeyRotation,
+    clock: SharedClock,
+    state: RwLock<KeyState>,
+}
+
+struct KeyState {
+    current: CoreJsonWebKeySet,
+    /// Replaced keys and until when they are still accepted
+    previous: Option<(CoreJsonWebKeySet, DateTime<Utc>)>,
+    fetched_at: DateTime<Utc>,
+}
+
+impl KeyStore {
+    pub(crate) fn new(
+        client_id: ClientId,
+        client_secret: Option<ClientSecret>,
+        issuer: IssuerUrl,
+        source: KeySource,
+        rotation: KeyRotation,
+        clock: SharedClock,
+        keys: CoreJsonWebKeySet,
+    ) -> Self {
+        let fetched_at = clock.now();
+        Self {
+            client_id,
+            client_secret,
+            issuer,
+            source,
+            rotation,
+            clock,
+            state: RwLock::new(KeyState {
+                current: keys,
+                previous: None,
+                fetched_at,
+            }),
+        }
+    }
+
+    /// Fetch the provider's current keys
+    ///
+    /// The replaced keys are still accepted for [`KeyRotation::grace_period`].
+    pub(crate) async fn refresh(&self) -> Result<(), DiscoveryError<HttpClientError>> {
+        let keys = match &self.source {
+            KeySource::Discovery(http_client) => {
+                CoreProviderMetadata::discover_async(self.issuer.clone(), |request| {
+                    http_client.request(request)
+                })
+                .await?
+                .jwks()
+                .clone()
+            }
+            KeySource::Url(url, http_client) => {
+                CoreJsonWebKeySet::fetch_async(url, |request| http_client.request(request)).await?
+            }
+            KeySource::Static => return Ok(()),
+        };
+
+        let now = self.clock.now();
+        let mut state = self
+            .state
+            .write()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let previous = std::mem::replace(&mut state.current, keys);
+        state.previous = Some((
+            previous,
+            now + Duration::seconds(self.rotation.grace_period as i64),
+        ));
+        state.fetched_at = now;
+        Ok(())
+    }
+
+    /// Verify a token using the current keys
+    ///
+    /// The keys are refreshed before verifying, if they are older than the
+    /// [`KeyRotation::refresh_interval`], and after a failed verification, if the token was
+    /// signed by an unknown key.
+    pub(crate) async fn verify<T>(
+        &self,
+        verify: impl Fn(CoreIdTokenVerifier<'static>) -> Result<T, ClaimsVerificationError>,
+    ) -> Result<T, ClaimsVerificationError> {
+        self.verify_with_keys(|keys| verify(self.verifier(keys.clone())))
+            .await
+    }
+
+    /// Verify a token using the current keys directly
+    ///
+    /// This is used for tokens which aren't id tokens.
+    /// The keys are refreshed like in [`KeyStore::verify`].
+    pub(crate) async fn verify_with_keys<T>(
+        &self,
+        verify: impl Fn(&CoreJsonWebKeySet) -> Result<T, ClaimsVerificationError>,
+    ) -> Result<T, ClaimsVerificationError> {
+        if let Some(interval) = self.rotation.refresh_interval {
+            if self.fetched_at() + Duration::seconds(interval as i64) <= self.clock.now() {
+                self.refresh_logged().await;
+            }
+        }
+
+        match verify(&self.keys()) {
+            Err(ClaimsVerificationError::SignatureVerification(
+                SignatureVerificationError::NoMatchingKey,
+            )) if self.fetched_at() + MIN_ON_DEMAND_INTERVAL <= self.clock.now() => {
+                self.refresh_logged().await;
+                verify(&self.keys())
+            }
+            result => result,
+        }
+    }
+
+    async fn refresh_logged(&self) {
+        if let Err(err) = self.refresh().await {
+            warn!("Failed to refresh the oidc provider's signing keys: {err}");
+        }
+    }
+
+    /// The issuer tokens are verified against
+    pub(crate) fn issuer(&self) -> &IssuerUrl {
+        &self.issuer
+    }
+
+    fn fetched_at(&self) -> DateTime<Utc> {
+        self.state
+            .read()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .fetched_at
+    }
+
+    /// Get the current and the not yet expired previous keys
+    fn keys(&self) -> CoreJsonWebKeySet {
+        let state = self
+            .state
+            .read()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let mut keys = state.current.keys().clone();
+        if let Some((previous, until)) = &state.previous {
+            if *until > self.clock.now() {
+                keys.extend(previous.keys().iter().cloned());
+            }
+        }
+        CoreJsonWebKeySet::new(keys)
+    }
+
+    /// Create a verifier accepting the keys
+    fn verifier(&self, jwks: CoreJsonWebKeySet) -> CoreIdTokenVerifier<'static> {
+        let verifier = match &self.client_secret {
+            Some(client_secret) => CoreIdTokenVerifier::new_confidential_client(
+                self.client_id.clone(),
+                client_secret.clone(),
+                self.issuer.clone(),
+                jwks,
+            ),
+            None => CoreIdTokenVerifier::new_public_client(
+                self.client_id.clone(),
+                self.issuer.clone(),
+                jwks,
+            ),
+        };
+        let clock = self.clock.clone();
+        verifier.set_time_fn(move || clock.now())
+    }
+}
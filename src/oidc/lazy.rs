@@ -0,0 +1,163 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{
+    forward_ready, Extensions, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::error::ErrorServiceUnavailable;
+use actix_web::web::Data;
+use actix_web::Error;
+use futures::future::LocalBoxFuture;
+use futures::lock::Mutex;
+use log::error;
+use openidconnect::{AdditionalClaims, EmptyAdditionalClaims};
+
+use crate::oidc::{Client, Config};
+
+/// Middleware discovering the provider on the first request instead of at startup
+///
+/// This lets your application start while the provider is unreachable.
+/// Until discovery succeeded, requests are answered with `503 Service Unavailable`
+/// and the next request tries again.
+/// Afterwards the [`Client`] is provided to the handlers as if it had been registered
+/// using [`App::app_data`](actix_web::App::app_data).
+///
+/// Create it once outside of [`HttpServer::new`](actix_web::HttpServer::new)
+/// and clone it into every worker, so the provider is only discovered once:
+///
+/// ```no_run
+/// use actix_toolbox::oidc::openidconnect::EmptyAdditionalClaims;
+/// use actix_toolbox::oidc::{finish_login, login, Config, LazyDiscovery};
+/// use actix_web::web::get;
+/// use actix_web::{App, HttpServer};
+///
+/// # async fn run(config: Config) -> std::io::Result<()> {
+/// let discovery = LazyDiscovery::new(config);
+/// HttpServer::new(move || {
+///     App::new()
+///         .route("/login", get().to(login::<EmptyAdditionalClaims>))
+///         .route("/finish_login", get().to(finish_login::<EmptyAdditionalClaims>))
+///         .wrap(discovery.clone())
+/// })
+/// .bind(("127.0.0.1", 8080))?
+/// .run()
+/// .await
+/// # }
+/// ```
+pub struct LazyDiscovery<AC: AdditionalClaims = EmptyAdditionalClaims> {
+    config: Arc<Config>,
+    client: Arc<OnceLock<Data<Client<AC>>>>,
+    discovering: Arc<Mutex<()>>,
+}
+
+impl LazyDiscovery {
+    /// Create the middleware discovering the provider configured in `config`
+    pub fn new(config: Config) -> Self {
+        Self::with_claims(config)
+    }
+}
+
+impl<AC: AdditionalClaims> LazyDiscovery<AC> {
+    /// Create the middleware for a [`Client`] with additional claims
+    ///
+    /// See [`Config::discover_with_claims`].
+    pub fn with_claims(config: Config) -> Self {
+        Self {
+            config: Arc::new(config),
+            client: Arc::new(OnceLock::new()),
+            discovering: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Get the client, if the provider has already been discovered
+    pub fn client(&self) -> Option<Data<Client<AC>>> {
+        self.client.get().cloned()
+    }
+
+    /// Get the client, discovering the provider if that hasn't been done yet
+    async fn discover(&self) -> Result<Data<Client<AC>>, Error> {
+        if let Some(client) = self.client() {
+            return Ok(client);
+        }
+
+        // Only one request discovers the provider, the others wait for it
+        let _guard = self.discovering.lock().await;
+        if let Some(client) = self.client() {
+            return Ok(client);
+        }
+
+        let client = Config::clone(&self.config)
+            .discover_with_claims()
+            .await
+            .map_err(|err| {
+                error!("Couldn't discover the oidc provider: {err}");
+                ErrorServiceUnavailable("The identity provider is unavailable")
+            })?;
+        Ok(self.client.get_or_init(|| client).clone())
+    }
+}
+
+impl<AC: AdditionalClaims> Clone for LazyDiscovery<AC> {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            client: self.client.clone(),
+            discovering: self.discovering.clone(),
+        }
+    }
+}
+
+impl<S, B, AC> Transform<S, ServiceRequest> for LazyDiscovery<AC>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+    AC: AdditionalClaims,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = LazyDiscoveryMiddleware<S, AC>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LazyDiscoveryMiddleware {
+            service: Rc::new(service),
+            discovery: self.clone(),
+        }))
+    }
+}
+
+/// Service created by [LazyDiscovery]
+pub struct LazyDiscoveryMiddleware<S, AC: AdditionalClaims> {
+    service: Rc<S>,
+    discovery: LazyDiscovery<AC>,
+}
+
+impl<S, B, AC> Service<ServiceRequest> for LazyDiscoveryMiddleware<S, AC>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+    AC: AdditionalClaims,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let discovery = self.discovery.clone();
+
+        Box::pin(async move {
+            let client = discovery.discover().await?;
+
+            let mut app_data = Extensions::new();
+            app_data.insert(client);
+            req.add_data_container(Rc::new(app_data));
+            service.call(req).await
+        })
+    }
+}
@@ -0,0 +1,165 @@
+use actix_session::Session;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::http::StatusCode;
+use actix_web::web::{Data, Query};
+use actix_web::{HttpResponse, ResponseError};
+use openidconnect::core::{
+    CoreGenderClaim, CoreJsonWebKeyType, CoreJweContentEncryptionAlgorithm, CoreJwsSigningAlgorithm,
+};
+use openidconnect::{AdditionalClaims, IdToken, IssuerUrl};
+use serde::{Deserialize, Serialize};
+
+use crate::oidc::{Client, TokenStoreError};
+
+/// Configuration of the [`front_channel_logout`] handler
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrontChannelLogout {
+    /// Require the provider to send the `iss` and `sid` parameters
+    ///
+    /// This has to match the `frontchannel_logout_session_required` your application
+    /// is registered with.
+    /// If unset, the parameters are still validated if present.
+    /// Without `sid` the user's session is cleared regardless of which session the provider
+    /// logged out.
+    ///
+    /// Defaults to `true`
+    #[serde(default = "default_session_required")]
+    pub session_required: bool,
+}
+fn default_session_required() -> bool {
+    true
+}
+impl Default for FrontChannelLogout {
+    fn default() -> Self {
+        Self {
+            session_required: default_session_required(),
+        }
+    }
+}
+
+/// Read the `sid` claim from an already verified id token
+///
+/// `openidconnect` doesn't expose this claim, so the token's payload is decoded again.
+pub(crate) fn session_id<AC: AdditionalClaims>(
+    id_token: &IdToken<
+        AC,
+        CoreGenderClaim,
+        CoreJweContentEncryptionAlgorithm,
+        CoreJwsSigningAlgorithm,
+        CoreJsonWebKeyType,
+    >,
+) -> Option<String> {
+    #[derive(Deserialize)]
+    struct SessionIdClaim {
+        sid: Option<String>,
+    }
+
+    let jwt = id_token.to_string();
+    let payload = jwt.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice::<SessionIdClaim>(&payload).ok()?.sid
+}
+
+/// Parameters sent by the provider to [`front_channel_logout`]
+#[derive(Deserialize)]
+pub struct FrontChannelLogoutRequest {
+    iss: Option<IssuerUrl>,
+    sid: Option<String>,
+}
+
+/// Handler for OIDC Front-Channel Logout
+///
+/// Register its url as `frontchannel_logout_uri` with your provider.
+/// The provider loads it in an iframe when the user logs out somewhere else
+/// and this handler clears the user's session.
+///
+/// The `iss` and `sid` parameters are validated as configured in
+/// [`Config::front_channel_logout`](crate::oidc::Config::front_channel_logout).
+///
+/// Browsers only send the session cookie to the iframe, if it is set with `SameSite=None`
+/// and `Secure`.
+///
+/// `AC` has to match the [`Client`]'s additional claims.
+pub async fn front_channel_logout<AC: AdditionalClaims>(
+    client: Data<Client<AC>>,
+    params: Query<FrontChannelLogoutRequest>,
+    session: Session,
+) -> Result<HttpResponse, FrontChannelLogoutError> {
+    let FrontChannelLogoutRequest { iss, sid } = params.into_inner();
+
+    if client.front_channel_logout.session_required && (iss.is_none() || sid.is_none()) {
+        return Err(FrontChannelLogoutError::MissingParameters);
+    }
+    if let Some(iss) = &iss {
+        if iss != client.keys.issuer() {
+            return Err(FrontChannelLogoutError::InvalidIssuer);
+        }
+    }
+
+    let user_data = client
+        .get_user_data(&session, client.clock.now())
+        .await
+        .map_err(FrontChannelLogoutError::TokenStore)?;
+    if let Some(user_data) = user_data {
+        // Another session of the user might have been logged out
+        let matches = match &sid {
+            Some(sid) => user_data.session_id.as_ref() == Some(sid),
+            None => true,
+        };
+        if matches {
+            client
+                .remove_tokens(&session)
+                .await
+                .map_err(FrontChannelLogoutError::TokenStore)?;
+            session.purge();
+        }
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .finish())
+}
+
+/// Error returned by [`front_channel_logout`]
+#[derive(Debug)]
+pub enum FrontChannelLogoutError {
+    /// The provider didn't send the required `iss` and `sid` parameters
+    MissingParameters,
+
+    /// The `iss` parameter doesn't match the provider
+    InvalidIssuer,
+
+    /// Failed to load or remove the user data
+    TokenStore(TokenStoreError),
+}
+impl std::fmt::Display for FrontChannelLogoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrontChannelLogoutError::MissingParameters => {
+                write!(f, "The iss and sid parameters are missing")
+            }
+            FrontChannelLogoutError::InvalidIssuer => {
+                write!(f, "The iss parameter doesn't match the provider")
+            }
+            FrontChannelLogoutError::TokenStore(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl std::error::Error for FrontChannelLogoutError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FrontChannelLogoutError::MissingParameters => None,
+            FrontChannelLogoutError::InvalidIssuer => None,
+            FrontChannelLogoutError::TokenStore(err) => Some(err),
+        }
+    }
+}
+impl ResponseError for FrontChannelLogoutError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            FrontChannelLogoutError::MissingParameters => StatusCode::BAD_REQUEST,
+            FrontChannelLogoutError::InvalidIssuer => StatusCode::BAD_REQUEST,
+            FrontChannelLogoutError::TokenStore(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::BTreeSet;
+
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use chrono::{DateTime, Utc};
+use openidconnect::AdditionalClaims;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::oidc::{Client, UserData};
+
+/// Response of the [`me`] handler
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurrentUser {
+    /// The user's claims listed in [`Config::me_claims`](crate::oidc::Config::me_claims)
+    ///
+    /// Claims are taken from the id token first and from the UserInfo response second.
+    pub claims: Map<String, Value>,
+
+    /// The user's application roles
+    pub roles: BTreeSet<String>,
+
+    /// Point in time the access token expires at
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CurrentUser {
+    /// Collect the allowed claims from the user's data
+    fn new<AC: AdditionalClaims>(user_data: UserData<AC>, allowed: &[String]) -> Self {
+        let sources = std::iter::once(serde_json::to_value(&user_data.claims).ok())
+            .chain(
+                user_data
+                    .user_info
+                    .as_ref()
+                    .map(|user_info| serde_json::to_value(user_info).ok()),
+            )
+            .flatten();
+
+        let mut claims = Map::new();
+        for source in sources {
+            let Value::Object(source) = source else {
+                continue;
+            };
+            for (name, value) in source {
+                if !value.is_null() && !claims.contains_key(&name) && allowed.contains(&name) {
+                    claims.insert(name, value);
+                }
+            }
+        }
+
+        Self {
+            claims,
+            roles: user_data.roles.into_iter().collect(),
+            expires_at: user_data.expires_at,
+        }
+    }
+}
+
+/// Handler returning the logged-in user as [`CurrentUser`] json
+///
+/// Single page applications can use it to query whether the user is logged in
+/// and display their name.
+/// Only the claims listed in [`Config::me_claims`](crate::oidc::Config::me_claims) are returned.
+///
+/// Responds with `401 Unauthorized` if the user isn't logged in.
+///
+/// `AC` has to match the [`Client`]'s additional claims.
+pub async fn me<AC: AdditionalClaims>(
+    client: Data<Client<AC>>,
+    user_data: UserData<AC>,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(CurrentUser::new(user_data, &client.me_claims))
+}
@@ -1,20 +1,211 @@
+mod assertion;
+mod bearer;
 mod config;
+mod device;
+mod extractor;
 mod handler;
+mod http;
+mod introspection;
+mod keys;
+mod lazy;
+mod logout;
+mod me;
+mod policy;
+mod refresh;
+mod registration;
+mod require_login;
+mod roles;
+mod routes;
+mod state;
+mod step_up;
+mod tenant;
+#[cfg(feature = "test-util")]
+pub mod test;
+mod tokens;
 
+use std::collections::HashSet;
+
+use chrono::{DateTime, Duration, Utc};
 /// Re-export the wrapped Open ID Connect implementation
 pub use openidconnect;
-use openidconnect::core::{CoreIdTokenClaims, CoreTokenResponse};
+use openidconnect::core::{
+    CoreAuthDisplay, CoreAuthPrompt, CoreErrorResponseType, CoreGenderClaim, CoreJsonWebKey,
+    CoreJsonWebKeyType, CoreJsonWebKeyUse, CoreJweContentEncryptionAlgorithm,
+    CoreJwsSigningAlgorithm, CoreRevocableToken, CoreRevocationErrorResponse,
+    CoreTokenIntrospectionResponse, CoreTokenType,
+};
+use openidconnect::{
+    AdditionalClaims, EmptyAdditionalClaims, EmptyExtraTokenFields, IdTokenClaims, IdTokenFields,
+    StandardErrorResponse, StandardTokenResponse, UserInfoClaims,
+};
+/// Re-export the http client used by [`HttpClient`]
+pub use reqwest;
 use serde::{Deserialize, Serialize};
 
-pub use crate::oidc::config::{Client, Config, Provider, SessionKeys};
-pub use crate::oidc::handler::{finish_login, login};
+pub use crate::encryption::SessionEncryptionKey;
+pub use crate::oidc::assertion::ClientAuthMethod;
+pub use crate::oidc::bearer::{BearerClaims, BearerError};
+pub use crate::oidc::config::{
+    AuthParams, Client, Config, DiscoveryRetry, Provider, ProviderMetadata, SessionKeys,
+};
+pub use crate::oidc::device::{
+    poll_device_login, start_device_login, DeviceLogin, DeviceLoginError,
+};
+pub use crate::oidc::extractor::{OptionalUserData, UserDataError};
+pub use crate::oidc::handler::{
+    finish_login, login, FinishLoginError, LoginClaims, LoginErrorHandler, PostLoginHook,
+};
+pub use crate::oidc::http::HttpClient;
+pub use crate::oidc::introspection::IntrospectionError;
+pub use crate::oidc::keys::KeyRotation;
+pub use crate::oidc::lazy::{LazyDiscovery, LazyDiscoveryMiddleware};
+pub use crate::oidc::logout::{front_channel_logout, FrontChannelLogout, FrontChannelLogoutError};
+pub use crate::oidc::me::{me, CurrentUser};
+pub use crate::oidc::policy::{ClaimPolicy, PolicyViolation};
+pub use crate::oidc::refresh::{refresh, RefreshError, EXPIRY_LEEWAY};
+pub use crate::oidc::registration::{ClientRegistration, RegistrationError};
+pub use crate::oidc::require_login::{RequireLogin, RequireLoginMiddleware};
+pub use crate::oidc::roles::{RequireRole, RequireRoleMiddleware, RoleError, RoleMapping};
+pub use crate::oidc::routes::configure;
+#[cfg(feature = "__session")]
+pub use crate::oidc::state::DBAuthState;
+pub use crate::oidc::state::{AuthStateError, AuthStateStore, AUTH_STATE_LIFETIME};
+pub use crate::oidc::step_up::{StepUpError, StepUpPolicy, SteppedUp};
+pub use crate::oidc::tenant::{TenantDiscovery, TenantDiscoveryMiddleware, TenantResolver};
+#[cfg(feature = "__session")]
+pub use crate::oidc::tokens::DBToken;
+pub use crate::oidc::tokens::{TokenStore, TokenStoreError, TOKEN_STORE_LIFETIME};
+
+/// [`CoreClient`](openidconnect::core::CoreClient) generic over the id token's additional claims
+pub type OidcClient<AC = EmptyAdditionalClaims> = openidconnect::Client<
+    AC,
+    CoreAuthDisplay,
+    CoreGenderClaim,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJwsSigningAlgorithm,
+    CoreJsonWebKeyType,
+    CoreJsonWebKeyUse,
+    CoreJsonWebKey,
+    CoreAuthPrompt,
+    StandardErrorResponse<CoreErrorResponseType>,
+    OidcTokenResponse<AC>,
+    CoreTokenType,
+    CoreTokenIntrospectionResponse,
+    CoreRevocableToken,
+    CoreRevocationErrorResponse,
+>;
+
+/// [`CoreTokenResponse`](openidconnect::core::CoreTokenResponse) generic over the id token's additional claims
+pub type OidcTokenResponse<AC = EmptyAdditionalClaims> = StandardTokenResponse<
+    IdTokenFields<
+        AC,
+        EmptyExtraTokenFields,
+        CoreGenderClaim,
+        CoreJweContentEncryptionAlgorithm,
+        CoreJwsSigningAlgorithm,
+        CoreJsonWebKeyType,
+    >,
+    CoreTokenType,
+>;
+
+/// [`CoreIdTokenClaims`](openidconnect::core::CoreIdTokenClaims) generic over the additional claims
+pub type OidcIdTokenClaims<AC = EmptyAdditionalClaims> = IdTokenClaims<AC, CoreGenderClaim>;
+
+/// [`CoreUserInfoClaims`](openidconnect::core::CoreUserInfoClaims) generic over the additional claims
+pub type OidcUserInfoClaims<AC = EmptyAdditionalClaims> = UserInfoClaims<AC, CoreGenderClaim>;
 
 /// Data the [`finish_login`] handler will store in the user's session
+///
+/// Custom claims sent by the provider (like `groups`) can be deserialized into `AC`.
+/// See [`Config::discover_with_claims`] for how to choose them.
 #[derive(Serialize, Deserialize)]
-pub struct UserData {
+#[serde(bound = "")]
+pub struct UserData<AC: AdditionalClaims = EmptyAdditionalClaims> {
     /// The oidc token
-    pub token: CoreTokenResponse,
+    pub token: OidcTokenResponse<AC>,
 
     /// The OIDC claims
-    pub claims: CoreIdTokenClaims,
+    pub claims: OidcIdTokenClaims<AC>,
+
+    /// The claims returned by the provider's UserInfo endpoint
+    ///
+    /// This is only fetched if [`Config::fetch_user_info`] is set.
+    #[serde(default, with = "user_info_serde")]
+    pub user_info: Option<OidcUserInfoClaims<AC>>,
+
+    /// Point in time the access token expires at
+    ///
+    /// `None` if the provider didn't specify an expiry.
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+
+    /// The application roles mapped from the claims using [`Config::roles`]
+    #[serde(default)]
+    pub roles: HashSet<String>,
+
+    /// The provider's session id from the id token's `sid` claim
+    ///
+    /// Used by [`front_channel_logout`] to find the session the provider logged out.
+    #[serde(default)]
+    pub session_id: Option<String>,
+}
+
+impl<AC: AdditionalClaims> UserData<AC> {
+    /// Check whether the user has an application role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+
+    /// Get the time left until the access token expires
+    ///
+    /// Pass the current time as returned by [`Client::clock`].
+    ///
+    /// Returns `None` if the provider didn't specify an expiry
+    /// and a negative duration if the token has already expired.
+    pub fn expires_in(&self, now: DateTime<Utc>) -> Option<Duration> {
+        self.expires_at.map(|expires_at| expires_at - now)
+    }
+
+    /// Check whether the access token has expired and should be refreshed
+    ///
+    /// Pass the current time as returned by [`Client::clock`].
+    ///
+    /// Like [`refresh`] this considers tokens expiring within the next [`EXPIRY_LEEWAY`] expired.
+    /// Tokens without expiry never expire.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_in(now)
+            .is_some_and(|expires_in| expires_in <= EXPIRY_LEEWAY)
+    }
+}
+
+/// (De)serialize the optional [`OidcUserInfoClaims`]
+///
+/// `openidconnect` only implements `Serialize` for them, parsing is done through
+/// [`UserInfoClaims::from_json`].
+mod user_info_serde {
+    use openidconnect::reqwest::HttpClientError;
+    use openidconnect::AdditionalClaims;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::oidc::OidcUserInfoClaims;
+
+    pub fn serialize<S: Serializer, AC: AdditionalClaims>(
+        user_info: &Option<OidcUserInfoClaims<AC>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        user_info.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, AC: AdditionalClaims>(
+        deserializer: D,
+    ) -> Result<Option<OidcUserInfoClaims<AC>>, D::Error> {
+        let Some(json) = Option::<serde_json::Value>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+        let json = serde_json::to_vec(&json).map_err(D::Error::custom)?;
+        OidcUserInfoClaims::from_json::<HttpClientError>(&json, None)
+            .map(Some)
+            .map_err(D::Error::custom)
+    }
 }
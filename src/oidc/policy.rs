@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use actix_web::http::StatusCode;
+use actix_web::ResponseError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/**
+Requirements the user's claims have to meet to log in
+
+```yaml
+email_verified: true
+email_domains:
+  - example.com
+amr:
+  - mfa
+acr:
+  - urn:example:loa:high
+claims:
+  realm_access.roles: staff
+```
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClaimPolicy {
+    /// Require the `email_verified` claim to be `true`
+    pub email_verified: bool,
+
+    /// Domains the `email` claim has to belong to
+    ///
+    /// If it is empty, every domain is allowed.
+    pub email_domains: Vec<String>,
+
+    /// Authentication methods which all have to be listed in the `amr` claim, e.g. `mfa`
+    pub amr: Vec<String>,
+
+    /// Values the `acr` claim may have
+    ///
+    /// If it is empty, any value is allowed.
+    pub acr: Vec<String>,
+
+    /// Claims which have to have a certain value
+    ///
+    /// Nested claims are addressed using dots, e.g. `realm_access.roles`.
+    /// If the claim is a list, it has to contain the value.
+    pub claims: HashMap<String, Value>,
+}
+
+impl ClaimPolicy {
+    /// Check the id token's and UserInfo response's claims against the policy
+    ///
+    /// Claims are looked up in the id token first and in the UserInfo response second.
+    pub(crate) fn check(
+        &self,
+        claims: &impl Serialize,
+        user_info: Option<&impl Serialize>,
+    ) -> Result<(), PolicyViolation> {
+        let sources: Vec<Value> = std::iter::once(serde_json::to_value(claims).ok())
+            .chain(user_info.map(|user_info| serde_json::to_value(user_info).ok()))
+            .flatten()
+            .collect();
+        let lookup = |path: &str| {
+            sources.iter().find_map(|source| {
+                path.split('.')
+                    .try_fold(source, |value, key| value.get(key))
+                    .filter(|value| !value.is_null())
+            })
+        };
+
+        if self.email_verified && lookup("email_verified") != Some(&Value::Bool(true)) {
+            return Err(PolicyViolation::EmailNotVerified);
+        }
+
+        if !self.email_domains.is_empty() {
+            let domain = lookup("email")
+                .and_then(Value::as_str)
+                .and_then(|email| email.rsplit_once('@'))
+                .map(|(_, domain)| domain);
+            let allowed = domain.is_some_and(|domain| {
+                self.email_domains
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+            });
+            if !allowed {
+                return Err(PolicyViolation::EmailDomain);
+            }
+        }
+
+        let amr = lookup("amr").and_then(Value::as_array);
+        for method in &self.amr {
+            let listed = amr.is_some_and(|amr| amr.iter().any(|value| value == method.as_str()));
+            if !listed {
+                return Err(PolicyViolation::MissingAuthMethod(method.clone()));
+            }
+        }
+
+        if !self.acr.is_empty() {
+            let acr = lookup("acr").and_then(Value::as_str);
+            if !acr.is_some_and(|acr| self.acr.iter().any(|allowed| allowed == acr)) {
+                return Err(PolicyViolation::AuthContext);
+            }
+        }
+
+        for (path, expected) in &self.claims {
+            let matches = match lookup(path) {
+                Some(Value::Array(values)) => values.contains(expected),
+                Some(value) => value == expected,
+                None => false,
+            };
+            if !matches {
+                return Err(PolicyViolation::Claim(path.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The user's claims don't meet the [`ClaimPolicy`]
+#[derive(Debug)]
+pub enum PolicyViolation {
+    /// The user's email address isn't verified
+    EmailNotVerified,
+
+    /// The user's email address doesn't belong to an allowed domain
+    EmailDomain,
+
+    /// The user didn't authenticate using a required method
+    MissingAuthMethod(String),
+
+    /// The user didn't authenticate with an allowed authentication context class
+    AuthContext,
+
+    /// A claim doesn't have the required value
+    Claim(String),
+}
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyViolation::EmailNotVerified => write!(f, "The email address isn't verified"),
+            PolicyViolation::EmailDomain => {
+                write!(f, "The email address' domain isn't allowed")
+            }
+            PolicyViolation::MissingAuthMethod(method) => {
+                write!(f, "The authentication method {method} is required")
+            }
+            PolicyViolation::AuthContext => {
+                write!(f, "The authentication context class isn't allowed")
+            }
+            PolicyViolation::Claim(claim) => {
+                write!(f, "The claim {claim} doesn't have the required value")
+            }
+        }
+    }
+}
+impl std::error::Error for PolicyViolation {}
+impl ResponseError for PolicyViolation {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
@@ -0,0 +1,161 @@
+use actix_session::Session;
+use actix_web::ResponseError;
+use chrono::{DateTime, Utc};
+use openidconnect::core::CoreRequestTokenError;
+use openidconnect::reqwest::HttpClientError;
+use openidconnect::{
+    AdditionalClaims, ClaimsVerificationError, Nonce, OAuth2TokenResponse, SigningError,
+    TokenResponse,
+};
+
+use crate::oidc::{logout, Client, OidcTokenResponse, TokenStoreError, UserData};
+
+/// Time before the actual expiry at which a token is already considered expired
+///
+/// This avoids using a token which expires while the request using it is still in flight.
+pub const EXPIRY_LEEWAY: chrono::Duration = chrono::Duration::seconds(30);
+
+/// Get the user's [`UserData`] from the session and refresh its access token if it has expired
+///
+/// The refreshed token is written back into the session transparently.
+///
+/// - Returns `Ok(None)` if the user isn't logged in.
+/// - Returns `Err(RefreshError::MissingRefreshToken)` if the token expired and can't be refreshed.
+///   The user has to log in again.
+pub async fn refresh<AC: AdditionalClaims + Clone>(
+    client: &Client<AC>,
+    session: &Session,
+) -> Result<Option<UserData<AC>>, RefreshError> {
+    let Some(user_data) = client
+        .get_user_data(session, client.clock.now())
+        .await
+        .map_err(RefreshError::TokenStore)?
+    else {
+        return Ok(None);
+    };
+
+    if !user_data.is_expired(client.clock.now()) {
+        return Ok(Some(user_data));
+    }
+
+    let UserData {
+        token: old_token,
+        claims,
+        user_info,
+        session_id,
+        ..
+    } = user_data;
+    let refresh_token = old_token
+        .refresh_token()
+        .ok_or(RefreshError::MissingRefreshToken)?;
+
+    let mut request = client.exchange_refresh_token(refresh_token);
+    for resource in &client.resources {
+        request = request.add_extra_param("resource", resource.as_str());
+    }
+    for (name, value) in client
+        .client_assertion()
+        .map_err(RefreshError::ClientAssertion)?
+    {
+        request = request.add_extra_param(name, value);
+    }
+    let mut token = request
+        .request_async(|request| client.http_client.request(request))
+        .await
+        .map_err(RefreshError::FailedRequestToken)?;
+
+    // Providers may omit the refresh token, if it didn't change
+    if token.refresh_token().is_none() {
+        token.set_refresh_token(old_token.refresh_token().cloned());
+    }
+
+    // Refresh responses don't contain a nonce, so the id token's nonce can't be checked
+    let (claims, session_id) = match token.id_token() {
+        Some(id_token) => (
+            client
+                .verify(|verifier| {
+                    id_token
+                        .claims(&verifier, |_: Option<&Nonce>| Ok(()))
+                        .cloned()
+                })
+                .await
+                .map_err(RefreshError::InvalidIdToken)?,
+            logout::session_id(id_token).or(session_id),
+        ),
+        None => (claims, session_id),
+    };
+
+    let user_data = UserData {
+        roles: client.roles.roles(&claims, user_info.as_ref()),
+        session_id,
+        expires_at: expires_at(&token, client.clock.now()),
+        token,
+        claims,
+        user_info,
+    };
+    client
+        .insert_user_data(session, &user_data, client.clock.now())
+        .await
+        .map_err(RefreshError::TokenStore)?;
+
+    Ok(Some(user_data))
+}
+
+/// Calculate the point in time a token received at `now` expires at
+pub(crate) fn expires_at<AC: AdditionalClaims>(
+    token: &OidcTokenResponse<AC>,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let expires_in = chrono::Duration::from_std(token.expires_in()?).ok()?;
+    now.checked_add_signed(expires_in)
+}
+
+/// Error returned by [`refresh`]
+#[derive(Debug)]
+pub enum RefreshError {
+    /// The token has expired, but the provider didn't issue a refresh token
+    MissingRefreshToken,
+
+    /// Failed to sign the JWT authenticating your application
+    ClientAssertion(SigningError),
+
+    /// Failed to request a new token from the oidc provider
+    FailedRequestToken(CoreRequestTokenError<HttpClientError>),
+
+    /// Failed to verify the new id token
+    InvalidIdToken(ClaimsVerificationError),
+
+    /// Failed to load or store the user data
+    TokenStore(TokenStoreError),
+}
+impl std::fmt::Display for RefreshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RefreshError::MissingRefreshToken => {
+                write!(f, "Token expired and there is no refresh token")
+            }
+            RefreshError::ClientAssertion(err) => {
+                write!(f, "Failed to sign the client assertion: {err}")
+            }
+            RefreshError::FailedRequestToken(err) => {
+                write!(f, "Failed to refresh token: {err}")
+            }
+            RefreshError::InvalidIdToken(err) => {
+                write!(f, "The ID token didn't pass the verification: {err}")
+            }
+            RefreshError::TokenStore(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl std::error::Error for RefreshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RefreshError::MissingRefreshToken => None,
+            RefreshError::ClientAssertion(err) => Some(err),
+            RefreshError::FailedRequestToken(err) => Some(err),
+            RefreshError::InvalidIdToken(err) => Some(err),
+            RefreshError::TokenStore(err) => Some(err),
+        }
+    }
+}
+impl ResponseError for RefreshError {}
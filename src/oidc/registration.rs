@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use openidconnect::core::{
+    CoreClientRegistrationRequest, CoreGrantType, CoreProviderMetadata,
+    CoreRegisterErrorResponseType, CoreResponseType,
+};
+use openidconnect::registration::{ClientRegistrationError, EmptyAdditionalClientMetadata};
+use openidconnect::reqwest::HttpClientError;
+use openidconnect::{
+    AccessToken, ClientId, ClientName, ClientSecret, DiscoveryError, ResponseTypes,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::oidc::Config;
+
+/// Credentials obtained by [`Config::register`]
+///
+/// Store them and pass them to [`Config::with_registration`] on the next start
+/// instead of registering a new client every time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientRegistration {
+    /// The id the provider assigned to your application
+    pub client_id: ClientId,
+
+    /// The secret the provider issued to your application
+    pub client_secret: Option<ClientSecret>,
+
+    /// Point in time the secret expires at
+    ///
+    /// `None` if it doesn't expire.
+    pub client_secret_expires_at: Option<DateTime<Utc>>,
+}
+
+impl ClientRegistration {
+    /// Check whether the secret has expired and the application has to register again
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.client_secret_expires_at
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+impl Config {
+    /// Register your application at the provider using OpenID Connect Dynamic Client Registration
+    /// (RFC 7591)
+    ///
+    /// The registration contains the [`Config::finish_login_url`], the [`Config::response_types`]
+    /// and the first of the [`Provider::signing_algs`](crate::oidc::Provider::signing_algs).
+    /// The configured [`Provider::client_id`](crate::oidc::Provider::client_id) is ignored.
+    ///
+    /// The registration endpoint is taken from
+    /// [`Provider::registration_url`](crate::oidc::Provider::registration_url) or discovered.
+    ///
+    /// **Parameter**:
+    /// - `client_name`: Name of your application shown to the users
+    /// - `initial_access_token`: Token required by providers which restrict registration
+    pub async fn register(
+        &self,
+        client_name: Option<&str>,
+        initial_access_token: Option<AccessToken>,
+    ) -> Result<ClientRegistration, RegistrationError> {
+        let registration_url = match &self.provider.registration_url {
+            Some(url) => url.clone(),
+            None => self
+                .discovery_retry
+                .run(|| {
+                    CoreProviderMetadata::discover_async(
+                        self.provider.discover_url.clone(),
+                        |request| self.http_client.request(request),
+                    )
+                })
+                .await
+                .map_err(RegistrationError::Discovery)?
+                .registration_endpoint()
+                .cloned()
+                .ok_or(RegistrationError::MissingRegistrationEndpoint)?,
+        };
+
+        let mut response_types = vec![CoreResponseType::Code];
+        for response_type in &self.response_types {
+            if !response_types.contains(response_type) {
+                response_types.push(response_type.clone());
+            }
+        }
+        let mut grant_types = vec![
+            CoreGrantType::AuthorizationCode,
+            CoreGrantType::RefreshToken,
+        ];
+        if self.provider.device_authorization_url.is_some() {
+            grant_types.push(CoreGrantType::DeviceCode);
+        }
+
+        let response = CoreClientRegistrationRequest::new(
+            vec![self.finish_login_url.clone()],
+            EmptyAdditionalClientMetadata {},
+        )
+        .set_response_types(Some(vec![ResponseTypes::new(response_types)]))
+        .set_grant_types(Some(grant_types))
+        .set_id_token_signed_response_alg(self.provider.signing_algs.first().cloned())
+        .set_client_name(client_name.map(|name| ClientName::new(name.to_string()).into()))
+        .set_initial_access_token(initial_access_token)
+        .register_async(&registration_url, |request| {
+            self.http_client.request(request)
+        })
+        .await
+        .map_err(RegistrationError::Register)?;
+
+        Ok(ClientRegistration {
+            client_id: response.client_id().clone(),
+            client_secret: response.client_secret().cloned(),
+            client_secret_expires_at: response.client_secret_expires_at(),
+        })
+    }
+
+    /// Use the credentials obtained by [`Config::register`]
+    pub fn with_registration(mut self, registration: ClientRegistration) -> Self {
+        self.provider.client_id = registration.client_id;
+        self.provider.client_secret = registration.client_secret;
+        self
+    }
+}
+
+/// Error returned by [`Config::register`]
+#[derive(Debug)]
+pub enum RegistrationError {
+    /// Failed to discover the provider's registration endpoint
+    Discovery(DiscoveryError<HttpClientError>),
+
+    /// The provider doesn't support dynamic client registration
+    MissingRegistrationEndpoint,
+
+    /// The provider rejected the registration
+    Register(ClientRegistrationError<CoreRegisterErrorResponseType, HttpClientError>),
+}
+impl std::fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistrationError::Discovery(err) => {
+                write!(f, "Failed to discover the registration endpoint: {err}")
+            }
+            RegistrationError::MissingRegistrationEndpoint => {
+                write!(
+                    f,
+                    "The provider doesn't support dynamic client registration"
+                )
+            }
+            RegistrationError::Register(err) => write!(f, "Failed to register the client: {err}"),
+        }
+    }
+}
+impl std::error::Error for RegistrationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RegistrationError::Discovery(err) => Some(err),
+            RegistrationError::MissingRegistrationEndpoint => None,
+            RegistrationError::Register(err) => Some(err),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::future::{ready, Ready};
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method};
+use actix_web::web::Data;
+use actix_web::{Error, HttpRequest, HttpResponse};
+use futures::future::LocalBoxFuture;
+use openidconnect::url::form_urlencoded;
+use openidconnect::{AdditionalClaims, EmptyAdditionalClaims};
+
+use crate::oidc::extractor::get_user_data;
+use crate::oidc::{Client, UserDataError};
+
+/**
+Middleware only allowing users which are logged in
+
+Browsers navigating to a protected page are redirected to the [`login`](crate::oidc::login)
+handler which returns them to the page after they logged in.
+All other requests (e.g. api calls sending `Accept: application/json`) are answered with
+`401 Unauthorized`.
+
+The login handler is expected where [`configure`](crate::oidc::configure) mounts it,
+use [`RequireLogin::login_url`] if you registered it yourself.
+
+`AC` has to match the [`Client`]'s additional claims.
+
+```no_run
+use actix_toolbox::oidc::RequireLogin;
+use actix_web::web::scope;
+
+let dashboard = scope("/dashboard").wrap(RequireLogin::new());
+```
+*/
+pub struct RequireLogin<AC: AdditionalClaims = EmptyAdditionalClaims> {
+    login_url: Option<Rc<str>>,
+    claims: PhantomData<AC>,
+}
+
+impl RequireLogin {
+    /// Create the middleware
+    pub fn new() -> Self {
+        Self::with_claims()
+    }
+}
+
+impl<AC: AdditionalClaims> RequireLogin<AC> {
+    /// Create the middleware for a [`Client`] with additional claims
+    pub fn with_claims() -> Self {
+        Self {
+            login_url: None,
+            claims: PhantomData,
+        }
+    }
+
+    /// Set the url of the [`login`](crate::oidc::login) handler browsers are redirected to
+    pub fn login_url(mut self, login_url: impl AsRef<str>) -> Self {
+        self.login_url = Some(Rc::from(login_url.as_ref()));
+        self
+    }
+}
+
+impl<AC: AdditionalClaims> Default for RequireLogin<AC> {
+    fn default() -> Self {
+        Self::with_claims()
+    }
+}
+
+impl<AC: AdditionalClaims> Clone for RequireLogin<AC> {
+    fn clone(&self) -> Self {
+        Self {
+            login_url: self.login_url.clone(),
+            claims: PhantomData,
+        }
+    }
+}
+
+impl<S, B, AC> Transform<S, ServiceRequest> for RequireLogin<AC>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+    AC: AdditionalClaims,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireLoginMiddleware<S, AC>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireLoginMiddleware {
+            service: Rc::new(service),
+            login_url: self.login_url.clone(),
+            claims: PhantomData,
+        }))
+    }
+}
+
+/// Service created by [RequireLogin]
+pub struct RequireLoginMiddleware<S, AC> {
+    service: Rc<S>,
+    login_url: Option<Rc<str>>,
+    claims: PhantomData<AC>,
+}
+
+/// Build the url of the login handler returning the user to the requested page
+fn redirect_url<AC: AdditionalClaims>(login_url: Option<&str>, req: &ServiceRequest) -> String {
+    let login_url = match login_url {
+        Some(login_url) => login_url.to_string(),
+        None => match req.app_data::<Data<Client<AC>>>() {
+            Some(client) => format!("{}/login", client.handler_base_path()),
+            None => String::from("/login"),
+        },
+    };
+    let next = req
+        .uri()
+        .path_and_query()
+        .map_or(req.path(), |path| path.as_str());
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("next", next)
+        .finish();
+
+    let separator = if login_url.contains('?') { '&' } else { '?' };
+    format!("{login_url}{separator}{query}")
+}
+
+/// Check whether the request is a browser navigating to a page
+pub(crate) fn is_navigation(req: &HttpRequest) -> bool {
+    let accepts_html = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    accepts_html && (req.method() == Method::GET || req.method() == Method::HEAD)
+}
+
+impl<S, B, AC> Service<ServiceRequest> for RequireLoginMiddleware<S, AC>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+    AC: AdditionalClaims,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let login_url = self.login_url.clone();
+
+        Box::pin(async move {
+            match get_user_data::<AC>(req.request()).await {
+                Ok(Some(_)) => service
+                    .call(req)
+                    .await
+                    .map(ServiceResponse::map_into_left_body),
+                Ok(None) if is_navigation(req.request()) => {
+                    let response = HttpResponse::TemporaryRedirect()
+                        .insert_header((
+                            header::LOCATION,
+                            redirect_url::<AC>(login_url.as_deref(), &req),
+                        ))
+                        .finish()
+                        .map_into_right_body();
+                    Ok(req.into_response(response))
+                }
+                Ok(None) => Err(UserDataError::NotLoggedIn.into()),
+                Err(err) => Err(UserDataError::TokenStore(err).into()),
+            }
+        })
+    }
+}
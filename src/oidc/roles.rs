@@ -0,0 +1,229 @@
+use std::collections::{HashMap, HashSet};
+use std::future::{ready, Ready};
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::StatusCode;
+use actix_web::{Error, ResponseError};
+use futures::future::LocalBoxFuture;
+use openidconnect::{AdditionalClaims, EmptyAdditionalClaims};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::oidc::extractor::get_user_data;
+use crate::oidc::TokenStoreError;
+
+/**
+Mapping from the provider's claims to your application's roles
+
+```yaml
+claims:
+  - groups
+  - realm_access.roles
+mapping:
+  platform-admins: admin
+  platform-users: user
+```
+*/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoleMapping {
+    /// Claims containing the user's groups or roles
+    ///
+    /// Nested claims are addressed using dots, e.g. `realm_access.roles`.
+    /// The claims may either be a string or a list of strings.
+    /// They are looked up in the id token and the UserInfo response.
+    pub claims: Vec<String>,
+
+    /// Maps the claims' values to application roles
+    ///
+    /// If it is empty, the values are used as roles directly.
+    /// Otherwise values without a mapping are ignored.
+    pub mapping: HashMap<String, String>,
+}
+
+impl RoleMapping {
+    /// Collect the application roles from the id token's and UserInfo response's claims
+    pub(crate) fn roles(
+        &self,
+        claims: &impl Serialize,
+        user_info: Option<&impl Serialize>,
+    ) -> HashSet<String> {
+        let mut roles = HashSet::new();
+        if self.claims.is_empty() {
+            return roles;
+        }
+
+        let sources: Vec<Value> = std::iter::once(serde_json::to_value(claims).ok())
+            .chain(user_info.map(|user_info| serde_json::to_value(user_info).ok()))
+            .flatten()
+            .collect();
+        for path in &self.claims {
+            for source in &sources {
+                let claim = path
+                    .split('.')
+                    .try_fold(source, |value, key| value.get(key));
+                let values = match claim {
+                    Some(Value::String(value)) => vec![value.as_str()],
+                    Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+                    _ => continue,
+                };
+                for value in values {
+                    if self.mapping.is_empty() {
+                        roles.insert(value.to_string());
+                    } else if let Some(role) = self.mapping.get(value) {
+                        roles.insert(role.clone());
+                    }
+                }
+            }
+        }
+        roles
+    }
+}
+
+/**
+Middleware only allowing users with a certain role
+
+The roles are read from [`UserData::roles`](crate::oidc::UserData::roles)
+which is populated using [`Config::roles`](crate::oidc::Config::roles).
+
+- Responds with `401 Unauthorized` if the user isn't logged in.
+- Responds with `403 Forbidden` if the user lacks the role.
+
+`AC` has to match the [`Client`](crate::oidc::Client)'s additional claims.
+
+```no_run
+use actix_toolbox::oidc::RequireRole;
+use actix_web::web::scope;
+
+let admin = scope("/admin").wrap(RequireRole::new("admin"));
+```
+*/
+pub struct RequireRole<AC: AdditionalClaims = EmptyAdditionalClaims> {
+    role: Rc<str>,
+    claims: PhantomData<AC>,
+}
+
+impl RequireRole {
+    /// Create the middleware requiring `role`
+    pub fn new(role: impl AsRef<str>) -> Self {
+        Self::with_claims(role)
+    }
+}
+
+impl<AC: AdditionalClaims> RequireRole<AC> {
+    /// Create the middleware requiring `role` for a [`Client`](crate::oidc::Client) with
+    /// additional claims
+    pub fn with_claims(role: impl AsRef<str>) -> Self {
+        Self {
+            role: Rc::from(role.as_ref()),
+            claims: PhantomData,
+        }
+    }
+}
+
+impl<AC: AdditionalClaims> Clone for RequireRole<AC> {
+    fn clone(&self) -> Self {
+        Self {
+            role: self.role.clone(),
+            claims: PhantomData,
+        }
+    }
+}
+
+impl<S, B, AC> Transform<S, ServiceRequest> for RequireRole<AC>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+    AC: AdditionalClaims,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequireRoleMiddleware<S, AC>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireRoleMiddleware {
+            service: Rc::new(service),
+            role: self.role.clone(),
+            claims: PhantomData,
+        }))
+    }
+}
+
+/// Service created by [RequireRole]
+pub struct RequireRoleMiddleware<S, AC> {
+    service: Rc<S>,
+    role: Rc<str>,
+    claims: PhantomData<AC>,
+}
+
+impl<S, B, AC> Service<ServiceRequest> for RequireRoleMiddleware<S, AC>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+    AC: AdditionalClaims,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let role = self.role.clone();
+
+        Box::pin(async move {
+            match get_user_data::<AC>(req.request()).await {
+                Ok(Some(user_data)) if user_data.roles.contains(&*role) => service.call(req).await,
+                Ok(Some(_)) => Err(RoleError::MissingRole.into()),
+                Ok(None) => Err(RoleError::NotLoggedIn.into()),
+                Err(err) => Err(RoleError::TokenStore(err).into()),
+            }
+        })
+    }
+}
+
+/// Error returned by the [`RequireRole`] middleware
+#[derive(Debug)]
+pub enum RoleError {
+    /// The user hasn't logged in
+    NotLoggedIn,
+
+    /// The user lacks the required role
+    MissingRole,
+
+    /// Failed to load the user data
+    TokenStore(TokenStoreError),
+}
+impl std::fmt::Display for RoleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RoleError::NotLoggedIn => write!(f, "The user isn't logged in"),
+            RoleError::MissingRole => write!(f, "The user lacks the required role"),
+            RoleError::TokenStore(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl std::error::Error for RoleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RoleError::NotLoggedIn => None,
+            RoleError::MissingRole => None,
+            RoleError::TokenStore(err) => Some(err),
+        }
+    }
+}
+impl ResponseError for RoleError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RoleError::NotLoggedIn => StatusCode::UNAUTHORIZED,
+            RoleError::MissingRole => StatusCode::FORBIDDEN,
+            RoleError::TokenStore(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
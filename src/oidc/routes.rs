@@ -0,0 +1,42 @@
+use actix_web::web::{get, post, Data, ServiceConfig};
+use openidconnect::AdditionalClaims;
+
+use crate::oidc::{finish_login, front_channel_logout, login, me, Client};
+
+/// Register the oidc handlers and the [`Client`]
+///
+/// The handlers are mounted next to the path of
+/// [`Config::finish_login_url`](crate::oidc::Config::finish_login_url),
+/// so they can't get out of sync with the url registered at the provider.
+/// For `https://example.com/api/v1/auth/finish_login` these routes are added:
+///
+/// - `GET /api/v1/auth/login`: [`login`]
+/// - `GET` and `POST /api/v1/auth/finish_login`: [`finish_login`]
+/// - `GET /api/v1/auth/front_channel_logout`: [`front_channel_logout`]
+/// - `GET /api/v1/auth/me`: [`me`]
+///
+/// ```no_run
+/// use actix_toolbox::oidc;
+/// use actix_web::App;
+///
+/// # async fn build(config: oidc::Config) {
+/// let client = config.discover().await.unwrap();
+/// let app = App::new().configure(|cfg| oidc::configure(cfg, client));
+/// # }
+/// ```
+///
+/// `AC` has to match the [`Client`]'s additional claims.
+pub fn configure<AC: AdditionalClaims + Clone>(cfg: &mut ServiceConfig, client: Data<Client<AC>>) {
+    let finish_login_path = client.finish_login_url.url().path().to_string();
+    let base = client.handler_base_path().to_string();
+
+    cfg.app_data(client)
+        .route(&format!("{base}/login"), get().to(login::<AC>))
+        .route(&finish_login_path, get().to(finish_login::<AC>))
+        .route(&finish_login_path, post().to(finish_login::<AC>))
+        .route(
+            &format!("{base}/front_channel_logout"),
+            get().to(front_channel_logout::<AC>),
+        )
+        .route(&format!("{base}/me"), get().to(me::<AC>));
+}
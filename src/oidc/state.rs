@@ -0,0 +1,192 @@
+use actix_session::{Session, SessionInsertError};
+use actix_web::ResponseError;
+use chrono::{DateTime, Duration, Utc};
+use openidconnect::{CsrfToken, Nonce, PkceCodeVerifier};
+#[cfg(feature = "__session")]
+use rorm::{delete, insert, query, FieldAccess, Model};
+use serde::{Deserialize, Serialize};
+
+/// Time the user has to finish logging in at the provider
+///
+/// Only enforced by [`AuthStateStore::Database`].
+pub const AUTH_STATE_LIFETIME: Duration = Duration::seconds(10 * 60);
+
+/// The data [`finish_login`](super::finish_login) needs to verify the provider's response
+#[derive(Serialize, Deserialize)]
+pub(crate) struct AuthState {
+    pub(crate) csrf_token: CsrfToken,
+    pub(crate) pkce_code_verifier: Option<PkceCodeVerifier>,
+    pub(crate) nonce: Nonce,
+    #[serde(default)]
+    pub(crate) next: Option<String>,
+}
+
+/// Where [`login`](super::login) stores the state of a login in progress
+///
+/// Provides a [Default] which stores it in the user's session.
+#[derive(Clone, Default)]
+pub enum AuthStateStore {
+    /// Store the state in the user's session
+    #[default]
+    Session,
+
+    /// Store the state in the [DBAuthState] table and only its key in the user's session
+    ///
+    /// This keeps cookie based sessions small.
+    /// Entries older than [AUTH_STATE_LIFETIME] are rejected and deleted by later logins.
+    #[cfg(feature = "__session")]
+    Database(rorm::Database),
+}
+
+impl std::fmt::Debug for AuthStateStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthStateStore::Session => write!(f, "AuthStateStore::Session"),
+            #[cfg(feature = "__session")]
+            AuthStateStore::Database(_) => write!(f, "AuthStateStore::Database"),
+        }
+    }
+}
+
+/**
+DB representation of a login in progress.
+*/
+#[cfg(feature = "__session")]
+#[derive(Model, Debug, Clone)]
+pub struct DBAuthState {
+    /// The `state` parameter sent to the provider
+    #[rorm(primary_key)]
+    #[rorm(max_length = 255)]
+    pub state: String,
+
+    /// The json encoded state
+    #[rorm(max_length = 4096)]
+    pub auth_state: String,
+
+    /// DateTime after which the login can't be finished anymore
+    pub expired_after: DateTime<Utc>,
+}
+
+impl AuthStateStore {
+    /// Store the state of a login the user started
+    #[cfg_attr(not(feature = "__session"), allow(unused_variables))]
+    pub(crate) async fn save(
+        &self,
+        session: &Session,
+        key: &str,
+        state: AuthState,
+        now: DateTime<Utc>,
+    ) -> Result<(), AuthStateError> {
+        match self {
+            AuthStateStore::Session => session
+                .insert(key, state)
+                .map_err(AuthStateError::SessionInsert),
+            #[cfg(feature = "__session")]
+            AuthStateStore::Database(db) => {
+                // Remove logins which have been abandoned
+                delete!(db, DBAuthState)
+                    .condition(DBAuthState::F.expired_after.less_than(now))
+                    .await
+                    .map_err(AuthStateError::Database)?;
+
+                let row = DBAuthState {
+                    state: state.csrf_token.secret().clone(),
+                    auth_state: serde_json::to_string(&state)
+                        .map_err(AuthStateError::Serialization)?,
+                    expired_after: now + AUTH_STATE_LIFETIME,
+                };
+                insert!(db, DBAuthState)
+                    .single(&row)
+                    .await
+                    .map_err(AuthStateError::Database)?;
+
+                session
+                    .insert(key, &state.csrf_token)
+                    .map_err(AuthStateError::SessionInsert)
+            }
+        }
+    }
+
+    /// Remove and return the state of the login the user started
+    ///
+    /// Returns `None` if the user hasn't started a login or it expired.
+    #[cfg_attr(not(feature = "__session"), allow(unused_variables))]
+    pub(crate) async fn take(
+        &self,
+        session: &Session,
+        key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<AuthState>, AuthStateError> {
+        match self {
+            AuthStateStore::Session => Ok(session.remove_as(key).and_then(Result::ok)),
+            #[cfg(feature = "__session")]
+            AuthStateStore::Database(db) => {
+                // The session binds the login to the browser it was started in
+                let Some(Ok(csrf_token)) = session.remove_as::<CsrfToken>(key) else {
+                    return Ok(None);
+                };
+
+                let row = query!(db, DBAuthState)
+                    .condition(DBAuthState::F.state.equals(csrf_token.secret().as_str()))
+                    .optional()
+                    .await
+                    .map_err(AuthStateError::Database)?;
+                let Some(row) = row else {
+                    return Ok(None);
+                };
+                delete!(db, DBAuthState)
+                    .condition(DBAuthState::F.state.equals(csrf_token.secret().as_str()))
+                    .await
+                    .map_err(AuthStateError::Database)?;
+
+                if row.expired_after < now {
+                    return Ok(None);
+                }
+                serde_json::from_str(&row.auth_state)
+                    .map(Some)
+                    .map_err(AuthStateError::Serialization)
+            }
+        }
+    }
+}
+
+/// Error while storing or loading the state of a login
+#[derive(Debug)]
+pub enum AuthStateError {
+    /// Error from [`Session::insert`]
+    SessionInsert(SessionInsertError),
+
+    /// Failed to (de)serialize the state
+    Serialization(serde_json::Error),
+
+    /// Error from the database
+    #[cfg(feature = "__session")]
+    Database(rorm::Error),
+}
+impl std::fmt::Display for AuthStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthStateError::SessionInsert(err) => {
+                write!(f, "Failed to store the login state in the session: {err}")
+            }
+            AuthStateError::Serialization(err) => {
+                write!(f, "Failed to (de)serialize the login state: {err}")
+            }
+            #[cfg(feature = "__session")]
+            AuthStateError::Database(err) => {
+                write!(f, "Failed to access the login state in the database: {err}")
+            }
+        }
+    }
+}
+impl std::error::Error for AuthStateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AuthStateError::SessionInsert(err) => Some(err),
+            AuthStateError::Serialization(err) => Some(err),
+            #[cfg(feature = "__session")]
+            AuthStateError::Database(err) => Some(err),
+        }
+    }
+}
+impl ResponseError for AuthStateError {}
@@ -0,0 +1,253 @@
+use actix_web::dev::Payload;
+use actix_web::http::{header, StatusCode};
+use actix_web::web::{Data, Redirect};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Duration, Utc};
+use futures::future::LocalBoxFuture;
+use openidconnect::url::form_urlencoded;
+use openidconnect::{AdditionalClaims, EmptyAdditionalClaims};
+use serde::{Deserialize, Serialize};
+
+use crate::oidc::extractor::get_user_data;
+use crate::oidc::require_login::is_navigation;
+use crate::oidc::{Client, OidcIdTokenClaims, TokenStoreError, UserData};
+
+/**
+Requirements a login has to meet before the user may perform a sensitive action
+
+Register it as app data of the routes using the [`SteppedUp`] extractor.
+Without one, [`StepUpPolicy::default`] is used which requires a login within the last 5 minutes.
+
+```yaml
+max_age: 300
+acr_values:
+  - urn:example:loa:high
+```
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StepUpPolicy {
+    /// Maximum number of seconds since the user authenticated at the provider
+    ///
+    /// `None` accepts logins of any age.
+    pub max_age: Option<u64>,
+
+    /// Values the `acr` claim may have
+    ///
+    /// If it is empty, any value is allowed.
+    /// They are requested from the provider when stepping up.
+    pub acr_values: Vec<String>,
+}
+
+impl Default for StepUpPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: Some(5 * 60),
+            acr_values: Vec::new(),
+        }
+    }
+}
+
+impl StepUpPolicy {
+    /// Check whether the id token's `auth_time` and `acr` claims satisfy the policy
+    ///
+    /// Pass the current time as returned by [`Client::clock`].
+    pub fn is_satisfied_by<AC: AdditionalClaims>(
+        &self,
+        claims: &OidcIdTokenClaims<AC>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if let Some(max_age) = self.max_age {
+            let recent = claims.auth_time().is_some_and(|auth_time| {
+                now - auth_time <= Duration::seconds(max_age.try_into().unwrap_or(i64::MAX))
+            });
+            if !recent {
+                return false;
+            }
+        }
+
+        self.acr_values.is_empty()
+            || claims.auth_context_ref().is_some_and(|acr| {
+                self.acr_values
+                    .iter()
+                    .any(|allowed| allowed == acr.as_str())
+            })
+    }
+
+    /// Build the url of the [`login`](crate::oidc::login) handler forcing the user to
+    /// authenticate again
+    ///
+    /// The provider is asked for a fresh login (`prompt=login` and `max_age=0`)
+    /// with one of the policy's `acr_values`.
+    /// The user is returned to `next` afterwards.
+    pub fn login_url<AC: AdditionalClaims>(&self, client: &Client<AC>, next: &str) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        query
+            .append_pair("next", next)
+            .append_pair("prompt", "login")
+            .append_pair("max_age", "0");
+        if !self.acr_values.is_empty() {
+            query.append_pair("acr_values", &self.acr_values.join(" "));
+        }
+        format!("{}/login?{}", client.handler_base_path(), query.finish())
+    }
+
+    /// Redirect the user through the [`login`](crate::oidc::login) handler to authenticate again
+    ///
+    /// See [`StepUpPolicy::login_url`].
+    ///
+    /// ```no_run
+    /// use actix_toolbox::oidc::{Client, StepUpPolicy};
+    /// use actix_web::web::{Data, Redirect};
+    ///
+    /// async fn confirm_transfer(client: Data<Client>) -> Redirect {
+    ///     StepUpPolicy::default().redirect(&client, "/transfer")
+    /// }
+    /// ```
+    pub fn redirect<AC: AdditionalClaims>(&self, client: &Client<AC>, next: &str) -> Redirect {
+        Redirect::to(self.login_url(client, next)).temporary()
+    }
+}
+
+/**
+Extract the logged-in user if their login satisfies the [`StepUpPolicy`] registered as app data
+
+Otherwise browsers are redirected to authenticate again using [`StepUpPolicy::redirect`]
+and returned to the page afterwards.
+All other requests are answered with `401 Unauthorized` and an
+`insufficient_user_authentication` challenge (RFC 9470).
+
+```no_run
+use actix_toolbox::oidc::{SteppedUp, StepUpPolicy};
+use actix_web::web::resource;
+use actix_web::HttpResponse;
+
+async fn delete_account(user: SteppedUp) -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+let route = resource("/account/delete")
+    .app_data(StepUpPolicy {
+        max_age: Some(60),
+        acr_values: vec![String::from("urn:example:loa:high")],
+    })
+    .post(delete_account);
+```
+*/
+pub struct SteppedUp<AC: AdditionalClaims = EmptyAdditionalClaims>(pub UserData<AC>);
+
+impl<AC: AdditionalClaims> FromRequest for SteppedUp<AC> {
+    type Error = StepUpError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let policy = req.app_data::<StepUpPolicy>().cloned().unwrap_or_default();
+            let client = req.app_data::<Data<Client<AC>>>();
+            // Tolerate the provider's clock being ahead when checking the `auth_time`
+            let now = client.map_or_else(Utc::now, |client| client.clock.now() - client.clock_skew);
+
+            let user_data = get_user_data::<AC>(&req)
+                .await
+                .map_err(StepUpError::TokenStore)?;
+            match user_data {
+                Some(user_data) if policy.is_satisfied_by(&user_data.claims, now) => {
+                    Ok(SteppedUp(user_data))
+                }
+                _ => {
+                    let next = req
+                        .uri()
+                        .path_and_query()
+                        .map_or(req.path(), |path| path.as_str());
+                    Err(StepUpError::Required {
+                        login_url: client.map(|client| policy.login_url(client, next)),
+                        navigation: is_navigation(&req),
+                        policy,
+                    })
+                }
+            }
+        })
+    }
+}
+
+/// Error returned by the [`SteppedUp`] extractor
+#[derive(Debug)]
+pub enum StepUpError {
+    /// The user isn't logged in or their login doesn't satisfy the policy
+    Required {
+        /// The url to authenticate again at
+        ///
+        /// `None` if no [`Client`] is registered as app data.
+        login_url: Option<String>,
+
+        /// Whether the request came from a browser navigating to a page
+        navigation: bool,
+
+        /// The policy which isn't satisfied
+        policy: StepUpPolicy,
+    },
+
+    /// Failed to load the user data
+    TokenStore(TokenStoreError),
+}
+impl std::fmt::Display for StepUpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepUpError::Required { .. } => {
+                write!(f, "The user has to authenticate again")
+            }
+            StepUpError::TokenStore(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl std::error::Error for StepUpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StepUpError::Required { .. } => None,
+            StepUpError::TokenStore(err) => Some(err),
+        }
+    }
+}
+impl ResponseError for StepUpError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            StepUpError::Required {
+                login_url: Some(_),
+                navigation: true,
+                ..
+            } => StatusCode::TEMPORARY_REDIRECT,
+            StepUpError::Required { .. } => StatusCode::UNAUTHORIZED,
+            StepUpError::TokenStore(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        match self {
+            StepUpError::Required {
+                login_url: Some(login_url),
+                navigation: true,
+                ..
+            } => {
+                response.append_header((header::LOCATION, login_url.as_str()));
+            }
+            StepUpError::Required { policy, .. } => {
+                let mut challenge =
+                    String::from(r#"Bearer error="insufficient_user_authentication""#);
+                if let Some(max_age) = policy.max_age {
+                    challenge.push_str(&format!(", max_age={max_age}"));
+                }
+                if !policy.acr_values.is_empty() {
+                    challenge.push_str(&format!(
+                        r#", acr_values="{}""#,
+                        policy.acr_values.join(" ")
+                    ));
+                }
+                response.append_header((header::WWW_AUTHENTICATE, challenge));
+            }
+            StepUpError::TokenStore(_) => {}
+        }
+        response.finish()
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{
+    forward_ready, Extensions, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::error::{ErrorNotFound, ErrorServiceUnavailable};
+use actix_web::web::Data;
+use actix_web::{Error, HttpRequest};
+use futures::future::LocalBoxFuture;
+use futures::lock::Mutex;
+use log::error;
+use openidconnect::{AdditionalClaims, EmptyAdditionalClaims};
+
+use crate::oidc::{Client, Config};
+
+/// Maps requests to the tenant they belong to and the tenant to its provider
+///
+/// Used by [`TenantDiscovery`].
+pub trait TenantResolver: Send + Sync + 'static {
+    /// Identify the tenant a request belongs to, e.g. by its host or a header
+    ///
+    /// Returns `None` if the request doesn't belong to any tenant.
+    fn tenant(&self, request: &HttpRequest) -> Option<String>;
+
+    /// Get the configuration of a tenant's provider and client credentials
+    ///
+    /// This is only called if the tenant's provider hasn't been discovered yet.
+    /// Returns `None` if the tenant is unknown.
+    fn config(&self, tenant: &str) -> LocalBoxFuture<'static, Option<Config>>;
+}
+
+/// Tenants identified by the request's host, e.g. `customer.example.com`
+impl TenantResolver for HashMap<String, Config> {
+    fn tenant(&self, request: &HttpRequest) -> Option<String> {
+        let host = request.connection_info().host().to_string();
+        self.contains_key(&host).then_some(host)
+    }
+
+    fn config(&self, tenant: &str) -> LocalBoxFuture<'static, Option<Config>> {
+        let config = self.get(tenant).cloned();
+        Box::pin(async move { config })
+    }
+}
+
+/**
+Middleware selecting the [`Client`] of the tenant a request belongs to
+
+The tenant is resolved using a [`TenantResolver`] and its provider is discovered
+on the tenant's first request.
+Afterwards the [`Client`] is cached and provided to the handlers as if it had been registered
+using [`App::app_data`](actix_web::App::app_data), so [`login`](crate::oidc::login),
+[`finish_login`](crate::oidc::finish_login) and the extractors work for every tenant.
+
+Requests without a known tenant are answered with `404 Not Found`.
+Until discovery succeeded, requests are answered with `503 Service Unavailable`
+and the next request tries again.
+
+The handlers have to be registered at the same paths for every tenant,
+so [`Config::finish_login_url`] should only differ in its host.
+
+Create it once outside of [`HttpServer::new`](actix_web::HttpServer::new)
+and clone it into every worker, so each provider is only discovered once:
+
+```no_run
+use std::collections::HashMap;
+
+use actix_toolbox::oidc::openidconnect::EmptyAdditionalClaims;
+use actix_toolbox::oidc::{finish_login, login, Config, TenantDiscovery};
+use actix_web::web::get;
+use actix_web::{App, HttpServer};
+
+# async fn run(tenants: HashMap<String, Config>) -> std::io::Result<()> {
+let discovery = TenantDiscovery::new(tenants);
+HttpServer::new(move || {
+    App::new()
+        .route("/login", get().to(login::<EmptyAdditionalClaims>))
+        .route("/finish_login", get().to(finish_login::<EmptyAdditionalClaims>))
+        .wrap(discovery.clone())
+})
+.bind(("127.0.0.1", 8080))?
+.run()
+.await
+# }
+```
+*/
+pub struct TenantDiscovery<AC: AdditionalClaims = EmptyAdditionalClaims> {
+    resolver: Arc<dyn TenantResolver>,
+    clients: Arc<RwLock<HashMap<String, Data<Client<AC>>>>>,
+    discovering: Arc<Mutex<()>>,
+}
+
+impl TenantDiscovery {
+    /// Create the middleware resolving tenants using `resolver`
+    pub fn new(resolver: impl TenantResolver) -> Self {
+        Self::with_claims(resolver)
+    }
+}
+
+impl<AC: AdditionalClaims> TenantDiscovery<AC> {
+    /// Create the middleware for [`Client`]s with additional claims
+    ///
+    /// See [`Config::discover_with_claims`].
+    pub fn with_claims(resolver: impl TenantResolver) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+            clients: Arc::new(RwLock::new(HashMap::new())),
+            discovering: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Get a tenant's client, if its provider has already been discovered
+    pub fn client(&self, tenant: &str) -> Option<Data<Client<AC>>> {
+        self.clients
+            .read()
+            .ok()
+            .and_then(|clients| clients.get(tenant).cloned())
+    }
+
+    /// Drop a tenant's cached client
+    ///
+    /// Its configuration is resolved and its provider discovered again on its next request,
+    /// e.g. after its client credentials changed.
+    pub fn forget(&self, tenant: &str) {
+        if let Ok(mut clients) = self.clients.write() {
+            clients.remove(tenant);
+        }
+    }
+
+    /// Get the tenant's client, discovering its provider if that hasn't been done yet
+    async fn discover(&self, tenant: &str) -> Result<Data<Client<AC>>, Error> {
+        if let Some(client) = self.client(tenant) {
+            return Ok(client);
+        }
+
+        // Only one request discovers a provider, the others wait for it
+        let _guard = self.discovering.lock().await;
+        if let Some(client) = self.client(tenant) {
+            return Ok(client);
+        }
+
+        let config = self
+            .resolver
+            .config(tenant)
+            .await
+            .ok_or_else(|| ErrorNotFound("Unknown tenant"))?;
+        let client = config.discover_with_claims().await.map_err(|err| {
+            error!("Couldn't discover the oidc provider of tenant {tenant}: {err}");
+            ErrorServiceUnavailable("The identity provider is unavailable")
+        })?;
+
+        if let Ok(mut clients) = self.clients.write() {
+            clients.insert(tenant.to_string(), client.clone());
+        }
+        Ok(client)
+    }
+}
+
+impl<AC: AdditionalClaims> Clone for TenantDiscovery<AC> {
+    fn clone(&self) -> Self {
+        Self {
+            resolver: self.resolver.clone(),
+            clients: self.clients.clone(),
+            discovering: self.discovering.clone(),
+        }
+    }
+}
+
+impl<S, B, AC> Transform<S, ServiceRequest> for TenantDiscovery<AC>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+    AC: AdditionalClaims,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TenantDiscoveryMiddleware<S, AC>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TenantDiscoveryMiddleware {
+            service: Rc::new(service),
+            discovery: self.clone(),
+        }))
+    }
+}
+
+/// Service created by [TenantDiscovery]
+pub struct TenantDiscoveryMiddleware<S, AC: AdditionalClaims> {
+    service: Rc<S>,
+    discovery: TenantDiscovery<AC>,
+}
+
+impl<S, B, AC> Service<ServiceRequest> for TenantDiscoveryMiddleware<S, AC>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+    AC: AdditionalClaims,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let discovery = self.discovery.clone();
+
+        Box::pin(async move {
+            let tenant = discovery
+                .resolver
+                .tenant(req.request())
+                .ok_or_else(|| ErrorNotFound("Unknown tenant"))?;
+            let client = discovery.discover(&tenant).await?;
+
+            let mut app_data = Extensions::new();
+            app_data.insert(client);
+            req.add_data_container(Rc::new(app_data));
+            service.call(req).await
+        })
+    }
+}
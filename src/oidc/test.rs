@@ -0,0 +1,615 @@
+//! In-process oidc provider for integration tests
+//!
+//! The [MockServer] serves discovery, signing keys, authorization, token and UserInfo endpoints
+//! for a fixed set of [MockUser]s, so [`login`](super::login) and [`finish_login`](super::finish_login)
+//! can be tested without a real provider.
+//!
+//! The authorization endpoint doesn't show a login page.
+//! It logs in the user selected by the `login_hint` parameter (their subject or email)
+//! or the first user and redirects back immediately.
+//! The id token's `auth_time` is the time it was issued and its `acr` the first of the
+//! requested `acr_values`.
+//!
+//! ```no_run
+//! use actix_toolbox::oidc::openidconnect::RedirectUrl;
+//! use actix_toolbox::oidc::test::{MockProvider, MockUser};
+//!
+//! # async fn test() {
+//! let server = MockProvider::new(vec![MockUser::new("alice").email("alice@example.com")])
+//!     .start()
+//!     .await
+//!     .unwrap();
+//! let client = server
+//!     .config(RedirectUrl::new("http://localhost:8080/finish_login".to_string()).unwrap())
+//!     .discover()
+//!     .await
+//!     .unwrap();
+//!
+//! // Call `login`, pass its redirect to `MockServer::authorize`
+//! // and call `finish_login` with the returned url's query
+//! # }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Mutex;
+
+use actix_web::dev::ServerHandle;
+use actix_web::http::header;
+use actix_web::web::{get, post, Data, Form, Query};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer};
+use chrono::Duration;
+use openidconnect::core::{
+    CoreGenderClaim, CoreJsonWebKeySet, CoreJsonWebKeyType, CoreJweContentEncryptionAlgorithm,
+    CoreJwsSigningAlgorithm, CoreRsaPrivateSigningKey,
+};
+use openidconnect::url::Url;
+use openidconnect::{
+    AccessToken, AdditionalClaims, Audience, AuthenticationContextClass, ClientId, ClientSecret,
+    CsrfToken, EndUserEmail, EndUserName, IdToken, IdTokenClaims, IssuerUrl, JsonWebKeyId, Nonce,
+    PkceCodeChallenge, PkceCodeVerifier, PrivateSigningKey, RedirectUrl, Scope, StandardClaims,
+    SubjectIdentifier,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::clock::{Clock, SharedClock};
+use crate::oidc::config::{default_clock_skew, default_me_claims};
+use crate::oidc::{Config, Provider};
+
+/// The client id the [MockServer] expects
+pub const CLIENT_ID: &str = "mock-client";
+
+/// The client secret the [MockServer] expects
+pub const CLIENT_SECRET: &str = "mock-secret";
+
+/// The audience of the access tokens signed by [`MockServer::access_token`]
+pub const API_AUDIENCE: &str = "mock-api";
+
+/// Seconds the issued tokens are valid for
+pub const TOKEN_LIFETIME: i64 = 3600;
+
+/// The key the [MockServer] signs its id tokens with
+///
+/// It is only meant for tests and must never be trusted outside of them.
+const SIGNING_KEY: &str = include_str!("test_key.pem");
+
+/// A user the [MockServer] can log in
+#[derive(Clone, Debug)]
+pub struct MockUser {
+    /// The user's subject identifier
+    pub subject: String,
+
+    /// The user's email address
+    pub email: Option<String>,
+
+    /// The user's name
+    pub name: Option<String>,
+
+    /// Additional claims, e.g. `groups`
+    pub claims: Map<String, Value>,
+}
+
+impl MockUser {
+    /// Create a user without any claims except its subject
+    pub fn new(subject: impl Into<String>) -> Self {
+        Self {
+            subject: subject.into(),
+            email: None,
+            name: None,
+            claims: Map::new(),
+        }
+    }
+
+    /// Set the user's email address
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.email = Some(email.into());
+        self
+    }
+
+    /// Set the user's name
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Add an additional claim
+    pub fn claim(mut self, name: impl Into<String>, value: impl Serialize) -> Self {
+        self.claims.insert(
+            name.into(),
+            serde_json::to_value(value).expect("The claim should be serializable"),
+        );
+        self
+    }
+
+    fn standard_claims(&self) -> StandardClaims<CoreGenderClaim> {
+        StandardClaims::new(SubjectIdentifier::new(self.subject.clone()))
+            .set_email(self.email.clone().map(EndUserEmail::new))
+            .set_name(self.name.clone().map(|name| EndUserName::new(name).into()))
+    }
+}
+
+/// Configuration of a [MockServer]
+pub struct MockProvider {
+    users: Vec<MockUser>,
+    clock: SharedClock,
+}
+
+impl MockProvider {
+    /// Create a provider for a set of users
+    pub fn new(users: Vec<MockUser>) -> Self {
+        Self {
+            users,
+            clock: SharedClock::default(),
+        }
+    }
+
+    /// Use a different [Clock] to timestamp the issued tokens
+    ///
+    /// Defaults to the [SystemClock](crate::clock::SystemClock).
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Start serving the provider on a random local port
+    ///
+    /// This has to be called within an actix runtime, e.g. in an `#[actix_web::test]`.
+    pub async fn start(self) -> io::Result<MockServer> {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+        let issuer = IssuerUrl::new(format!("http://{}", listener.local_addr()?))
+            .map_err(io::Error::other)?;
+
+        let state = Data::new(MockState {
+            issuer: issuer.clone(),
+            users: self.users,
+            clock: self.clock,
+            key: CoreRsaPrivateSigningKey::from_pem(
+                SIGNING_KEY,
+                Some(JsonWebKeyId::new("mock".to_string())),
+            )
+            .map_err(io::Error::other)?,
+            codes: Mutex::new(HashMap::new()),
+            tokens: Mutex::new(HashMap::new()),
+        });
+
+        let server_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(server_state.clone())
+                .route("/.well-known/openid-configuration", get().to(discovery))
+                .route("/jwks", get().to(jwks))
+                .route("/authorize", get().to(authorize))
+                .route("/token", post().to(token))
+                .route("/userinfo", get().to(user_info))
+        })
+        .workers(1)
+        .listen(listener)?
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        Ok(MockServer {
+            issuer,
+            state,
+            handle,
+        })
+    }
+}
+
+/// A running [MockProvider]
+pub struct MockServer {
+    issuer: IssuerUrl,
+    state: Data<MockState>,
+    handle: ServerHandle,
+}
+
+impl MockServer {
+    /// The provider's issuer and discovery url
+    pub fn issuer(&self) -> &IssuerUrl {
+        &self.issuer
+    }
+
+    /// Create a [Config] for this provider
+    ///
+    /// Redirects to `/` after logging in and requests the `email` and `profile` scopes.
+    /// Tokens from [`MockServer::access_token`] are accepted as bearer tokens.
+    pub fn config(&self, finish_login_url: RedirectUrl) -> Config {
+        Config {
+            finish_login_url,
+            post_auth_url: "/".to_string(),
+            allowed_return_origins: Vec::new(),
+            provider: Provider {
+                client_id: ClientId::new(CLIENT_ID.to_string()),
+                client_secret: Some(ClientSecret::new(CLIENT_SECRET.to_string())),
+                discover_url: self.issuer.clone(),
+                metadata: None,
+                device_authorization_url: None,
+                introspection_url: None,
+                registration_url: None,
+                disable_pkce: false,
+                disable_nonce: false,
+                signing_algs: vec![CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256],
+                client_auth: Default::default(),
+            },
+            scopes: HashSet::from([
+                Scope::new("email".to_string()),
+                Scope::new("profile".to_string()),
+            ]),
+            auth_params: Default::default(),
+            response_types: Vec::new(),
+            response_mode: None,
+            resources: Vec::new(),
+            audience: None,
+            fetch_user_info: false,
+            bearer_audiences: vec![Audience::new(API_AUDIENCE.to_string())],
+            roles: Default::default(),
+            claim_policy: Default::default(),
+            me_claims: default_me_claims(),
+            key_rotation: Default::default(),
+            discovery_retry: Default::default(),
+            session_keys: Default::default(),
+            session_encryption_key: None,
+            renew_session: true,
+            front_channel_logout: Default::default(),
+            clock_skew: default_clock_skew(),
+            clock: self.state.clock.clone(),
+            http_client: Default::default(),
+            error_handler: Default::default(),
+            state_store: Default::default(),
+            token_store: Default::default(),
+            post_login: Default::default(),
+        }
+    }
+
+    /// Handle an authorization request without going through http
+    ///
+    /// `url` is the location [`login`](super::login) redirected to.
+    /// Returns the url the provider redirects back to, i.e. [`finish_login`](super::finish_login)
+    /// with the `code` and `state` parameters.
+    ///
+    /// Returns `None` if the request is invalid or no user matches.
+    pub fn authorize(&self, url: &str) -> Option<Url> {
+        let url = Url::parse(url).ok()?;
+        let params = query_params(&url);
+        self.state.authorize(&params)
+    }
+
+    /// Sign an id token for a user which is valid for [TOKEN_LIFETIME]
+    ///
+    /// Returns `None` if there is no user with this subject.
+    pub fn id_token(&self, subject: &str) -> Option<String> {
+        let user = self
+            .state
+            .users
+            .iter()
+            .find(|user| user.subject == subject)?;
+        self.state.id_token(user, None, None, None)
+    }
+
+    /// Sign a RFC 9068 access token for a user and [API_AUDIENCE] which is valid for [TOKEN_LIFETIME]
+    ///
+    /// Use it to test [`BearerClaims`](super::BearerClaims).
+    ///
+    /// Returns `None` if there is no user with this subject.
+    pub fn access_token(&self, subject: &str) -> Option<String> {
+        let user = self
+            .state
+            .users
+            .iter()
+            .find(|user| user.subject == subject)?;
+        self.state.access_token(user)
+    }
+
+    /// Stop the server
+    pub async fn stop(self) {
+        self.handle.stop(true).await;
+    }
+}
+
+fn query_params(url: &Url) -> HashMap<String, String> {
+    url.query_pairs()
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect()
+}
+
+/// Claims not covered by [StandardClaims]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct MockClaims(Map<String, Value>);
+impl AdditionalClaims for MockClaims {}
+
+struct PendingCode {
+    subject: String,
+    nonce: Option<Nonce>,
+    code_challenge: Option<String>,
+    /// The first of the requested `acr_values`
+    acr: Option<String>,
+}
+
+struct MockState {
+    issuer: IssuerUrl,
+    users: Vec<MockUser>,
+    clock: SharedClock,
+    key: CoreRsaPrivateSigningKey,
+    codes: Mutex<HashMap<String, PendingCode>>,
+    /// Subjects by their access and refresh tokens
+    tokens: Mutex<HashMap<String, String>>,
+}
+
+impl MockState {
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.issuer.as_str().trim_end_matches('/'))
+    }
+
+    fn authorize(&self, params: &HashMap<String, String>) -> Option<Url> {
+        if params.get("client_id").map(String::as_str) != Some(CLIENT_ID) {
+            return None;
+        }
+        let mut redirect = Url::parse(params.get("redirect_uri")?).ok()?;
+
+        let user = match params.get("login_hint") {
+            Some(hint) => self.users.iter().find(|user| {
+                user.subject == *hint || user.email.as_deref() == Some(hint.as_str())
+            })?,
+            None => self.users.first()?,
+        };
+
+        let code = CsrfToken::new_random().secret().clone();
+        self.codes.lock().ok()?.insert(
+            code.clone(),
+            PendingCode {
+                subject: user.subject.clone(),
+                nonce: params.get("nonce").cloned().map(Nonce::new),
+                code_challenge: params.get("code_challenge").cloned(),
+                acr: params
+                    .get("acr_values")
+                    .and_then(|acr_values| acr_values.split_whitespace().next())
+                    .map(String::from),
+            },
+        );
+
+        redirect.query_pairs_mut().append_pair("code", &code);
+        if let Some(state) = params.get("state") {
+            redirect.query_pairs_mut().append_pair("state", state);
+        }
+        Some(redirect)
+    }
+
+    fn id_token(
+        &self,
+        user: &MockUser,
+        nonce: Option<Nonce>,
+        acr: Option<String>,
+        access_token: Option<&AccessToken>,
+    ) -> Option<String> {
+        let now = self.clock.now();
+        let claims = IdTokenClaims::new(
+            self.issuer.clone(),
+            vec![Audience::new(CLIENT_ID.to_string())],
+            now + Duration::seconds(TOKEN_LIFETIME),
+            now,
+            user.standard_claims(),
+            MockClaims(user.claims.clone()),
+        )
+        .set_nonce(nonce)
+        .set_auth_time(Some(now))
+        .set_auth_context_ref(acr.map(AuthenticationContextClass::new));
+        let id_token = IdToken::<
+            MockClaims,
+            CoreGenderClaim,
+            CoreJweContentEncryptionAlgorithm,
+            CoreJwsSigningAlgorithm,
+            CoreJsonWebKeyType,
+        >::new(
+            claims,
+            &self.key,
+            CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+            access_token,
+            None,
+        )
+        .ok()?;
+        Some(id_token.to_string())
+    }
+
+    fn access_token(&self, user: &MockUser) -> Option<String> {
+        let now = self.clock.now().timestamp();
+        let header = json!({ "alg": "RS256", "typ": "at+jwt", "kid": "mock" });
+        let claims = json!({
+            "iss": self.issuer,
+            "sub": user.subject,
+            "aud": API_AUDIENCE,
+            "client_id": CLIENT_ID,
+            "exp": now + TOKEN_LIFETIME,
+            "iat": now,
+            "jti": CsrfToken::new_random().secret(),
+        });
+        let encode = |value: &Value| {
+            serde_json::to_vec(value)
+                .ok()
+                .map(|json| base64::encode_config(json, base64::URL_SAFE_NO_PAD))
+        };
+        let signing_input = format!("{}.{}", encode(&header)?, encode(&claims)?);
+        let signature = self
+            .key
+            .sign(
+                &CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+                signing_input.as_bytes(),
+            )
+            .ok()?;
+        Some(format!(
+            "{signing_input}.{}",
+            base64::encode_config(signature, base64::URL_SAFE_NO_PAD)
+        ))
+    }
+
+    /// Issue a token response for a user
+    fn issue(&self, user: &MockUser, nonce: Option<Nonce>, acr: Option<String>) -> HttpResponse {
+        let access_token = AccessToken::new(CsrfToken::new_random().secret().clone());
+        let refresh_token = CsrfToken::new_random().secret().clone();
+        let Some(id_token) = self.id_token(user, nonce, acr, Some(&access_token)) else {
+            return HttpResponse::InternalServerError().finish();
+        };
+
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.insert(access_token.secret().clone(), user.subject.clone());
+            tokens.insert(refresh_token.clone(), user.subject.clone());
+        }
+
+        HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(json!({
+                "access_token": access_token.secret(),
+                "token_type": "bearer",
+                "expires_in": TOKEN_LIFETIME,
+                "refresh_token": refresh_token,
+                "id_token": id_token,
+            }))
+    }
+
+    fn user_by_token(&self, token: &str) -> Option<&MockUser> {
+        let subject = self.tokens.lock().ok()?.get(token)?.clone();
+        self.users.iter().find(|user| user.subject == subject)
+    }
+}
+
+async fn discovery(state: Data<MockState>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "issuer": state.issuer,
+        "authorization_endpoint": state.url("/authorize"),
+        "token_endpoint": state.url("/token"),
+        "userinfo_endpoint": state.url("/userinfo"),
+        "jwks_uri": state.url("/jwks"),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+        "scopes_supported": ["openid", "email", "profile"],
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+async fn jwks(state: Data<MockState>) -> HttpResponse {
+    HttpResponse::Ok().json(CoreJsonWebKeySet::new(vec![state
+        .key
+        .as_verification_key()]))
+}
+
+async fn authorize(state: Data<MockState>, params: Query<HashMap<String, String>>) -> HttpResponse {
+    match state.authorize(&params) {
+        Some(redirect) => HttpResponse::Found()
+            .insert_header((header::LOCATION, redirect.as_str()))
+            .finish(),
+        None => HttpResponse::BadRequest().body("invalid authorization request"),
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    grant_type: String,
+    code: Option<String>,
+    code_verifier: Option<String>,
+    refresh_token: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+/// Respond with an error as defined by RFC 6749
+fn token_error(error: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({ "error": error }))
+}
+
+/// Check the client's credentials sent either using basic auth or in the body
+fn is_authenticated(request: &HttpRequest, form: &TokenRequest) -> bool {
+    let expected = base64::encode(format!("{CLIENT_ID}:{CLIENT_SECRET}"));
+    let basic = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "));
+    match basic {
+        Some(credentials) => credentials == expected,
+        None => {
+            form.client_id.as_deref() == Some(CLIENT_ID)
+                && form.client_secret.as_deref() == Some(CLIENT_SECRET)
+        }
+    }
+}
+
+async fn token(
+    state: Data<MockState>,
+    request: HttpRequest,
+    form: Form<TokenRequest>,
+) -> HttpResponse {
+    if !is_authenticated(&request, &form) {
+        return HttpResponse::Unauthorized().json(json!({ "error": "invalid_client" }));
+    }
+
+    match form.grant_type.as_str() {
+        "authorization_code" => {
+            let pending = form
+                .code
+                .as_ref()
+                .and_then(|code| state.codes.lock().ok()?.remove(code));
+            let Some(pending) = pending else {
+                return token_error("invalid_grant");
+            };
+
+            if let Some(challenge) = &pending.code_challenge {
+                let verified = form.code_verifier.as_ref().is_some_and(|verifier| {
+                    PkceCodeChallenge::from_code_verifier_sha256(&PkceCodeVerifier::new(
+                        verifier.clone(),
+                    ))
+                    .as_str()
+                        == challenge
+                });
+                if !verified {
+                    return token_error("invalid_grant");
+                }
+            }
+
+            match state
+                .users
+                .iter()
+                .find(|user| user.subject == pending.subject)
+            {
+                Some(user) => state.issue(user, pending.nonce, pending.acr),
+                None => token_error("invalid_grant"),
+            }
+        }
+        "refresh_token" => {
+            let user = form
+                .refresh_token
+                .as_ref()
+                .and_then(|token| state.user_by_token(token));
+            match user {
+                Some(user) => state.issue(user, None, None),
+                None => token_error("invalid_grant"),
+            }
+        }
+        _ => token_error("unsupported_grant_type"),
+    }
+}
+
+async fn user_info(state: Data<MockState>, request: HttpRequest) -> HttpResponse {
+    let user = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|token| state.user_by_token(token));
+    let Some(user) = user else {
+        return HttpResponse::Unauthorized()
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer error=\"invalid_token\""))
+            .finish();
+    };
+
+    let mut claims = user.claims.clone();
+    claims.insert("sub".to_string(), json!(user.subject));
+    if let Some(email) = &user.email {
+        claims.insert("email".to_string(), json!(email));
+    }
+    if let Some(name) = &user.name {
+        claims.insert("name".to_string(), json!(name));
+    }
+    HttpResponse::Ok().json(claims)
+}
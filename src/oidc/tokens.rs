@@ -0,0 +1,293 @@
+use actix_session::{Session, SessionGetError, SessionInsertError};
+use actix_web::ResponseError;
+use chrono::{DateTime, Duration, Utc};
+use log::warn;
+use openidconnect::AdditionalClaims;
+#[cfg(feature = "__session")]
+use rand::distributions::{Alphanumeric, DistString};
+#[cfg(feature = "__session")]
+use rorm::{delete, insert, query, FieldAccess, Model};
+use serde_json::Value;
+
+use crate::oidc::{Client, UserData};
+
+/// Time the [`TokenStore::Database`] keeps a user's tokens
+///
+/// This should be at least the lifetime of your sessions, otherwise users are logged out early.
+pub const TOKEN_STORE_LIFETIME: Duration = Duration::seconds(30 * 24 * 60 * 60);
+
+/// Key in the session's user data referencing the [`DBToken`]
+#[cfg(feature = "__session")]
+const TOKEN_ID: &str = "token_id";
+
+/// Where the [`UserData::token`] is stored
+///
+/// Provides a [Default] which stores it in the user's session.
+#[derive(Clone, Default)]
+pub enum TokenStore {
+    /// Store the tokens in the user's session together with the rest of the [`UserData`]
+    #[default]
+    Session,
+
+    /// Store the tokens in the [DBToken] table and only a reference to them in the user's session
+    ///
+    /// This keeps the sessions small enough for cookie based stores.
+    /// Entries older than [TOKEN_STORE_LIFETIME] are rejected and deleted by later logins.
+    #[cfg(feature = "__session")]
+    Database(rorm::Database),
+}
+
+impl std::fmt::Debug for TokenStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenStore::Session => write!(f, "TokenStore::Session"),
+            #[cfg(feature = "__session")]
+            TokenStore::Database(_) => write!(f, "TokenStore::Database"),
+        }
+    }
+}
+
+/**
+DB representation of a user's tokens.
+*/
+#[cfg(feature = "__session")]
+#[derive(Model, Debug, Clone)]
+pub struct DBToken {
+    /// The random id stored in the user's session
+    #[rorm(primary_key)]
+    #[rorm(max_length = 255)]
+    pub id: String,
+
+    /// The json encoded token response
+    ///
+    /// It is encrypted if [`Config::session_encryption_key`](crate::oidc::Config::session_encryption_key) is set.
+    #[rorm(max_length = 16383)]
+    pub token: String,
+
+    /// DateTime after which the tokens are discarded
+    pub expired_after: DateTime<Utc>,
+}
+
+impl<AC: AdditionalClaims> Client<AC> {
+    /// Read the json stored in the session, decrypting it if
+    /// [`Config::session_encryption_key`](crate::oidc::Config::session_encryption_key) is set
+    ///
+    /// Data which can't be decrypted is ignored, i.e. the user has to log in again.
+    fn load_session_value(&self, session: &Session) -> Result<Option<Value>, TokenStoreError> {
+        let Some(value) = session
+            .get::<Value>(&self.session_keys.data)
+            .map_err(TokenStoreError::SessionGet)?
+        else {
+            return Ok(None);
+        };
+        let Some(key) = &self.session_encryption_key else {
+            return Ok(Some(value));
+        };
+
+        let value = value
+            .as_str()
+            .and_then(|data| key.decrypt(data, self.session_keys.data.as_bytes()))
+            .and_then(|json| serde_json::from_slice(&json).ok());
+        if value.is_none() {
+            warn!("Ignoring user data which couldn't be decrypted");
+        }
+        Ok(value)
+    }
+
+    /// Read the [`UserData`] from the session and the [`TokenStore`]
+    ///
+    /// Returns `None` if the user isn't logged in.
+    #[cfg_attr(not(feature = "__session"), allow(unused_variables))]
+    pub(crate) async fn get_user_data(
+        &self,
+        session: &Session,
+        now: DateTime<Utc>,
+    ) -> Result<Option<UserData<AC>>, TokenStoreError> {
+        #[cfg_attr(not(feature = "__session"), allow(unused_mut))]
+        let Some(mut value) = self.load_session_value(session)?
+        else {
+            return Ok(None);
+        };
+
+        match &self.token_store {
+            TokenStore::Session => {}
+            #[cfg(feature = "__session")]
+            TokenStore::Database(db) => {
+                let Some(id) = value.get(TOKEN_ID).and_then(Value::as_str) else {
+                    return Ok(None);
+                };
+                let row = query!(db, DBToken)
+                    .condition(DBToken::F.id.equals(id))
+                    .optional()
+                    .await
+                    .map_err(TokenStoreError::Database)?;
+                let Some(row) = row.filter(|row| row.expired_after >= now) else {
+                    return Ok(None);
+                };
+
+                let token = match &self.session_encryption_key {
+                    Some(key) => match key.decrypt(&row.token, row.id.as_bytes()) {
+                        Some(token) => token,
+                        None => {
+                            warn!("Ignoring tokens which couldn't be decrypted");
+                            return Ok(None);
+                        }
+                    },
+                    None => row.token.into_bytes(),
+                };
+                let token =
+                    serde_json::from_slice(&token).map_err(TokenStoreError::Serialization)?;
+                if let Value::Object(user_data) = &mut value {
+                    user_data.insert(String::from("token"), token);
+                }
+            }
+        }
+
+        serde_json::from_value(value)
+            .map(Some)
+            .map_err(TokenStoreError::Serialization)
+    }
+
+    /// Store the [`UserData`] in the session and the [`TokenStore`]
+    ///
+    /// Replaces the tokens stored for a previous login of the session.
+    #[cfg_attr(not(feature = "__session"), allow(unused_variables))]
+    pub(crate) async fn insert_user_data(
+        &self,
+        session: &Session,
+        user_data: &UserData<AC>,
+        now: DateTime<Utc>,
+    ) -> Result<(), TokenStoreError> {
+        #[cfg_attr(not(feature = "__session"), allow(unused_mut))]
+        let mut value = serde_json::to_value(user_data).map_err(TokenStoreError::Serialization)?;
+
+        match &self.token_store {
+            TokenStore::Session => {}
+            #[cfg(feature = "__session")]
+            TokenStore::Database(db) => {
+                self.remove_tokens(session).await?;
+                // Remove the tokens of sessions which have been abandoned
+                delete!(db, DBToken)
+                    .condition(DBToken::F.expired_after.less_than(now))
+                    .await
+                    .map_err(TokenStoreError::Database)?;
+
+                let token = serde_json::to_string(&user_data.token)
+                    .map_err(TokenStoreError::Serialization)?;
+                let id = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+                let row = DBToken {
+                    token: match &self.session_encryption_key {
+                        Some(key) => key
+                            .encrypt(token.as_bytes(), id.as_bytes())
+                            .map_err(TokenStoreError::Encryption)?,
+                        None => token,
+                    },
+                    id,
+                    expired_after: now + TOKEN_STORE_LIFETIME,
+                };
+                insert!(db, DBToken)
+                    .single(&row)
+                    .await
+                    .map_err(TokenStoreError::Database)?;
+
+                if let Value::Object(user_data) = &mut value {
+                    user_data.remove("token");
+                    user_data.insert(String::from(TOKEN_ID), Value::String(row.id));
+                }
+            }
+        }
+
+        match &self.session_encryption_key {
+            Some(key) => {
+                let data = key
+                    .encrypt(
+                        value.to_string().as_bytes(),
+                        self.session_keys.data.as_bytes(),
+                    )
+                    .map_err(TokenStoreError::Encryption)?;
+                session.insert(&self.session_keys.data, data)
+            }
+            None => session.insert(&self.session_keys.data, value),
+        }
+        .map_err(TokenStoreError::SessionInsert)
+    }
+
+    /// Remove the tokens referenced by the session from the [`TokenStore`]
+    ///
+    /// The session itself is left untouched.
+    #[cfg_attr(not(feature = "__session"), allow(unused_variables))]
+    pub(crate) async fn remove_tokens(&self, session: &Session) -> Result<(), TokenStoreError> {
+        match &self.token_store {
+            TokenStore::Session => Ok(()),
+            #[cfg(feature = "__session")]
+            TokenStore::Database(db) => {
+                let Some(value) = self.load_session_value(session)? else {
+                    return Ok(());
+                };
+                let Some(id) = value.get(TOKEN_ID).and_then(Value::as_str) else {
+                    return Ok(());
+                };
+                delete!(db, DBToken)
+                    .condition(DBToken::F.id.equals(id))
+                    .await
+                    .map_err(TokenStoreError::Database)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Error while storing or loading the [`UserData`]
+#[derive(Debug)]
+pub enum TokenStoreError {
+    /// Error from [`Session::get`]
+    SessionGet(SessionGetError),
+
+    /// Error from [`Session::insert`]
+    SessionInsert(SessionInsertError),
+
+    /// Failed to (de)serialize the user data
+    Serialization(serde_json::Error),
+
+    /// Failed to encrypt the user data
+    Encryption(aes_gcm::Error),
+
+    /// Error from the database
+    #[cfg(feature = "__session")]
+    Database(rorm::Error),
+}
+impl std::fmt::Display for TokenStoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenStoreError::SessionGet(err) => {
+                write!(f, "Failed to get user data from session: {err}")
+            }
+            TokenStoreError::SessionInsert(err) => {
+                write!(f, "Failed to store user data in session: {err}")
+            }
+            TokenStoreError::Serialization(err) => {
+                write!(f, "Failed to (de)serialize the user data: {err}")
+            }
+            TokenStoreError::Encryption(err) => {
+                write!(f, "Failed to encrypt the user data: {err}")
+            }
+            #[cfg(feature = "__session")]
+            TokenStoreError::Database(err) => {
+                write!(f, "Failed to access the tokens in the database: {err}")
+            }
+        }
+    }
+}
+impl std::error::Error for TokenStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TokenStoreError::SessionGet(err) => Some(err),
+            TokenStoreError::SessionInsert(err) => Some(err),
+            TokenStoreError::Serialization(err) => Some(err),
+            TokenStoreError::Encryption(err) => Some(err),
+            #[cfg(feature = "__session")]
+            TokenStoreError::Database(err) => Some(err),
+        }
+    }
+}
+impl ResponseError for TokenStoreError {}
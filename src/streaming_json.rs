@@ -0,0 +1,318 @@
+//! Extractor for large json arrays which parses them incrementally
+//!
+//! ```no_run
+//! use actix_toolbox::streaming_json::{StreamingJson, StreamingJsonError};
+//! use actix_web::HttpResponse;
+//! use futures::StreamExt;
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct User {
+//!     name: String,
+//! }
+//!
+//! async fn import(mut users: StreamingJson<User>) -> Result<HttpResponse, StreamingJsonError> {
+//!     while let Some(user) = users.next().await {
+//!         let user = user?;
+//!         // Insert the user
+//!     }
+//!     Ok(HttpResponse::Ok().finish())
+//! }
+//! ```
+
+use std::future::{ready, Ready};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_web::dev::Payload;
+use actix_web::error::PayloadError;
+use actix_web::http::StatusCode;
+use actix_web::web::BytesMut;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, ResponseError};
+use futures::Stream;
+use serde::de::DeserializeOwned;
+
+/**
+Configuration for the [StreamingJson] extractor
+
+Register it using [App::app_data](actix_web::App::app_data).
+*/
+#[derive(Clone, Debug)]
+pub struct StreamingJsonConfig {
+    /// Maximum size of a single item in bytes
+    ///
+    /// Only a single item has to be kept in memory at once,
+    /// so this limits the memory used per request.
+    ///
+    /// Defaults to 256 KiB
+    pub item_limit: usize,
+}
+
+impl Default for StreamingJsonConfig {
+    fn default() -> Self {
+        Self {
+            item_limit: 256 * 1024,
+        }
+    }
+}
+
+/**
+Extractor parsing a json array of `T` from the request body while it is being received
+
+The items are yielded as a [Stream], so endpoints like bulk imports don't have to buffer
+the whole payload. Only a single item is kept in memory which is limited by
+[StreamingJsonConfig::item_limit].
+
+The request has to have a json content type.
+*/
+pub struct StreamingJson<T> {
+    payload: Payload,
+    buffer: BytesMut,
+    state: State,
+    eof: bool,
+    item_limit: usize,
+    marker: PhantomData<fn() -> T>,
+}
+
+/// Position of the parser in the array
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum State {
+    /// Before the opening bracket
+    Start,
+    /// After the opening bracket
+    First,
+    /// After a comma
+    Item,
+    /// After an item
+    Next,
+    /// After the closing bracket
+    Done,
+}
+
+impl<T: DeserializeOwned> FromRequest for StreamingJson<T> {
+    type Error = StreamingJsonError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let is_json = req.mime_type().ok().flatten().is_some_and(|mime| {
+            mime.subtype() == "json" || mime.suffix().is_some_and(|suffix| suffix == "json")
+        });
+        if !is_json {
+            return ready(Err(StreamingJsonError::ContentType));
+        }
+
+        let item_limit = req.app_data::<StreamingJsonConfig>().map_or_else(
+            || StreamingJsonConfig::default().item_limit,
+            |c| c.item_limit,
+        );
+
+        ready(Ok(Self {
+            payload: payload.take(),
+            buffer: BytesMut::new(),
+            state: State::Start,
+            eof: false,
+            item_limit,
+            marker: PhantomData,
+        }))
+    }
+}
+
+impl<T: DeserializeOwned> StreamingJson<T> {
+    /// Try to parse the next item from the buffer
+    ///
+    /// Returns `Ok(None)` if more data is required.
+    fn parse_next(&mut self) -> Result<Option<Option<T>>, StreamingJsonError> {
+        let eof = self.eof;
+        loop {
+            let start = skip_whitespace(&self.buffer);
+            let Some(&byte) = self.buffer.get(start) else {
+                let _ = self.buffer.split_to(start);
+                return if eof {
+                    Err(StreamingJsonError::UnexpectedEof)
+                } else {
+                    Ok(None)
+                };
+            };
+
+            match (self.state, byte) {
+                (State::Start, b'[') => {
+                    let _ = self.buffer.split_to(start + 1);
+                    self.state = State::First;
+                }
+                (State::First, b']') | (State::Next, b']') => {
+                    let _ = self.buffer.split_to(start + 1);
+                    self.state = State::Done;
+                    return Ok(Some(None));
+                }
+                (State::Next, b',') => {
+                    let _ = self.buffer.split_to(start + 1);
+                    self.state = State::Item;
+                }
+                (State::First, _) | (State::Item, _) => {
+                    let Some(end) = value_end(&self.buffer[start..], eof) else {
+                        if self.buffer.len() - start > self.item_limit {
+                            return Err(StreamingJsonError::Overflow);
+                        }
+                        return if eof {
+                            Err(StreamingJsonError::UnexpectedEof)
+                        } else {
+                            Ok(None)
+                        };
+                    };
+                    if end == 0 {
+                        return Err(StreamingJsonError::Syntax(byte as char));
+                    }
+                    if end > self.item_limit {
+                        return Err(StreamingJsonError::Overflow);
+                    }
+
+                    let item = self.buffer.split_to(start + end);
+                    self.state = State::Next;
+                    return serde_json::from_slice(&item[start..])
+                        .map(|item| Some(Some(item)))
+                        .map_err(StreamingJsonError::Deserialize);
+                }
+                (_, _) => return Err(StreamingJsonError::Syntax(byte as char)),
+            }
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Stream for StreamingJson<T> {
+    type Item = Result<T, StreamingJsonError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if this.state == State::Done {
+                return Poll::Ready(None);
+            }
+
+            match this.parse_next() {
+                Ok(Some(item)) => return Poll::Ready(item.map(Ok)),
+                Ok(None) => {}
+                Err(err) => {
+                    this.state = State::Done;
+                    return Poll::Ready(Some(Err(err)));
+                }
+            }
+
+            match Pin::new(&mut this.payload).poll_next(cx) {
+                Poll::Ready(Some(Ok(chunk))) => this.buffer.extend_from_slice(&chunk),
+                Poll::Ready(Some(Err(err))) => {
+                    this.state = State::Done;
+                    return Poll::Ready(Some(Err(StreamingJsonError::Payload(err))));
+                }
+                Poll::Ready(None) => this.eof = true,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Get the index of the first non whitespace byte
+fn skip_whitespace(buffer: &[u8]) -> usize {
+    buffer
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(buffer.len())
+}
+
+/// Find the end of the json value at the start of `buffer`
+///
+/// Returns `None` if the value isn't complete yet.
+/// The value itself isn't validated, this is left to serde.
+fn value_end(buffer: &[u8], eof: bool) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, &byte) in buffer.iter().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => {
+                    in_string = false;
+                    if depth == 0 {
+                        return Some(index + 1);
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(index + 1);
+                }
+            }
+            // End of a scalar like a number, bool or null
+            b',' | b']' | b'}' if depth == 0 => return Some(index),
+            _ if depth == 0 && byte.is_ascii_whitespace() => return Some(index),
+            _ => {}
+        }
+    }
+
+    // A scalar may be terminated by the end of the body
+    (eof && depth == 0 && !in_string && !buffer.is_empty()).then_some(buffer.len())
+}
+
+/// Error returned by the [StreamingJson] extractor and stream
+#[derive(Debug)]
+pub enum StreamingJsonError {
+    /// The request doesn't have a json content type
+    ContentType,
+    /// Failed to read the request's body
+    Payload(PayloadError),
+    /// Found an unexpected character outside of an item
+    Syntax(char),
+    /// An item couldn't be deserialized
+    Deserialize(serde_json::Error),
+    /// An item exceeded the [StreamingJsonConfig::item_limit]
+    Overflow,
+    /// The body ended before the array was closed
+    UnexpectedEof,
+}
+
+impl std::fmt::Display for StreamingJsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamingJsonError::ContentType => write!(f, "Content type is not json"),
+            StreamingJsonError::Payload(err) => write!(f, "Failed to read body: {err}"),
+            StreamingJsonError::Syntax(c) => write!(f, "Unexpected character {c:?} in json array"),
+            StreamingJsonError::Deserialize(err) => write!(f, "Invalid item: {err}"),
+            StreamingJsonError::Overflow => write!(f, "Item exceeds the size limit"),
+            StreamingJsonError::UnexpectedEof => {
+                write!(f, "Body ended before the array was closed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StreamingJsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StreamingJsonError::Payload(err) => Some(err),
+            StreamingJsonError::Deserialize(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl ResponseError for StreamingJsonError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            StreamingJsonError::ContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            StreamingJsonError::Overflow => StatusCode::PAYLOAD_TOO_LARGE,
+            StreamingJsonError::Payload(err) => err.status_code(),
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
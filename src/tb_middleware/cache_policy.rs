@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, CACHE_CONTROL, VARY};
+use actix_web::{Error, HttpRequest, HttpResponse, Responder};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+
+/// The `Surrogate-Control` header used by CDNs
+const SURROGATE_CONTROL: HeaderName = HeaderName::from_static("surrogate-control");
+
+/**
+A set of caching headers which should be applied consistently
+
+Provides a default via the [Default] trait, which doesn't set any directive.
+
+Use [Cached] to apply it to a single response or [CachePolicies] to apply named policies by route.
+*/
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct CachePolicy {
+    /// Allow shared caches to store the response
+    pub public: bool,
+    /// Only allow the browser to store the response
+    pub private: bool,
+    /// Forbid storing the response at all
+    pub no_store: bool,
+    /// Require revalidation before using a stored response
+    pub no_cache: bool,
+    /// Seconds a response is considered fresh
+    pub max_age: Option<u32>,
+    /// Seconds a response is considered fresh in shared caches
+    pub s_maxage: Option<u32>,
+    /// Seconds a stale response may be used while it is revalidated in the background
+    pub stale_while_revalidate: Option<u32>,
+    /// Seconds a stale response may be used if revalidating it fails with an error
+    pub stale_if_error: Option<u32>,
+    /// The response will never change
+    pub immutable: bool,
+    /// Request headers the response depends on
+    pub vary: Vec<String>,
+    /// Value of the `Surrogate-Control` header read by CDNs
+    pub surrogate_control: Option<String>,
+}
+
+impl CachePolicy {
+    /// Policy forbidding any caching
+    pub fn no_store() -> Self {
+        Self {
+            no_store: true,
+            ..Default::default()
+        }
+    }
+
+    /// Policy for assets whose url changes with their content
+    pub fn immutable() -> Self {
+        Self {
+            public: true,
+            max_age: Some(365 * 24 * 60 * 60),
+            immutable: true,
+            ..Default::default()
+        }
+    }
+
+    /// Build the value of the `Cache-Control` header
+    pub fn cache_control(&self) -> String {
+        let mut directives = Vec::new();
+        if self.public {
+            directives.push("public".to_string());
+        }
+        if self.private {
+            directives.push("private".to_string());
+        }
+        if self.no_store {
+            directives.push("no-store".to_string());
+        }
+        if self.no_cache {
+            directives.push("no-cache".to_string());
+        }
+        if let Some(max_age) = self.max_age {
+            directives.push(format!("max-age={max_age}"));
+        }
+        if let Some(s_maxage) = self.s_maxage {
+            directives.push(format!("s-maxage={s_maxage}"));
+        }
+        if let Some(stale) = self.stale_while_revalidate {
+            directives.push(format!("stale-while-revalidate={stale}"));
+        }
+        if let Some(stale) = self.stale_if_error {
+            directives.push(format!("stale-if-error={stale}"));
+        }
+        if self.immutable {
+            directives.push("immutable".to_string());
+        }
+        directives.join(", ")
+    }
+
+    /// Set the policy's headers, replacing existing ones
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let cache_control = self.cache_control();
+        if !cache_control.is_empty() {
+            if let Ok(value) = HeaderValue::try_from(cache_control) {
+                headers.insert(CACHE_CONTROL, value);
+            }
+        }
+        if !self.vary.is_empty() {
+            if let Ok(value) = HeaderValue::try_from(self.vary.join(", ")) {
+                headers.insert(VARY, value);
+            }
+        }
+        if let Some(surrogate_control) = &self.surrogate_control {
+            if let Ok(value) = HeaderValue::try_from(surrogate_control.as_str()) {
+                headers.insert(SURROGATE_CONTROL, value);
+            }
+        }
+    }
+}
+
+/**
+Responder applying a [CachePolicy] to the wrapped responder
+
+```no_run
+use actix_toolbox::tb_middleware::{CachePolicy, Cached};
+
+async fn logo() -> Cached<&'static str> {
+    Cached::new("<svg></svg>", CachePolicy::immutable())
+}
+```
+*/
+pub struct Cached<R> {
+    responder: R,
+    policy: CachePolicy,
+}
+
+impl<R> Cached<R> {
+    /// Wrap a responder
+    pub fn new(responder: R, policy: CachePolicy) -> Self {
+        Self { responder, policy }
+    }
+}
+
+impl<R: Responder> Responder for Cached<R> {
+    type Body = R::Body;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let mut response = self.responder.respond_to(req);
+        self.policy.apply(response.headers_mut());
+        response
+    }
+}
+
+/**
+Configuration for the [CachePolicies] middleware.
+
+```yaml
+policies:
+  static:
+    public: true
+    max_age: 86400
+  api:
+    no_store: true
+routes:
+  - ["/static", "static"]
+  - ["/api", "api"]
+```
+*/
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct CachePoliciesConfig {
+    /// Policies by their name
+    pub policies: HashMap<String, CachePolicy>,
+    /// Path prefixes and the name of the policy applied to them
+    ///
+    /// The first matching prefix is used.
+    pub routes: Vec<(String, String)>,
+}
+
+/**
+Middleware applying named [CachePolicy]s by route
+
+Responses which already have a `Cache-Control` header (e.g. set using [Cached]) are left untouched.
+*/
+#[derive(Clone, Debug)]
+pub struct CachePolicies(Rc<CachePoliciesConfig>);
+
+impl CachePolicies {
+    /// Create the middleware from its config
+    pub fn new(config: CachePoliciesConfig) -> Self {
+        Self(Rc::new(config))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CachePolicies
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = CachePoliciesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CachePoliciesMiddleware {
+            service: Rc::new(service),
+            config: self.0.clone(),
+        }))
+    }
+}
+
+/// Service created by [CachePolicies]
+pub struct CachePoliciesMiddleware<S> {
+    service: Rc<S>,
+    config: Rc<CachePoliciesConfig>,
+}
+
+impl<S, B> Service<ServiceRequest> for CachePoliciesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let config = self.config.clone();
+        let path = req.path().to_string();
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if !res.headers().contains_key(CACHE_CONTROL) {
+                let policy = config
+                    .routes
+                    .iter()
+                    .find(|(prefix, _)| path.starts_with(prefix.as_str()))
+                    .and_then(|(_, name)| config.policies.get(name));
+                if let Some(policy) = policy {
+                    policy.apply(res.headers_mut());
+                }
+            }
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,188 @@
+use std::future::{ready, Ready};
+use std::io;
+use std::rc::Rc;
+use std::time::Duration;
+
+use actix_web::body::{BodyStream, BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpResponse};
+use futures::future::LocalBoxFuture;
+use futures::stream;
+use rand::Rng;
+
+/**
+Configuration for the [Chaos] middleware.
+
+Provides a default via the [Default] trait, which doesn't inject any chaos.
+*/
+#[derive(Clone, Debug)]
+pub struct ChaosConfig {
+    /// Whether any chaos should be injected at all. Defaults to `false`
+    ///
+    /// This is meant to be bound to an explicit option, so the middleware
+    /// can stay in the stack without affecting production traffic.
+    pub enabled: bool,
+    /// Fraction of requests (`0.0` to `1.0`) affected by chaos. Defaults to `1.0`
+    pub probability: f64,
+    /// Paths affected by chaos, matched by prefix. Defaults to all paths
+    pub paths: Vec<String>,
+    /// Range of latency added to affected requests. Defaults to none
+    pub latency: Option<(Duration, Duration)>,
+    /// Fraction of affected requests which are answered with [error_status](Self::error_status).
+    /// Defaults to `0.0`
+    pub error_rate: f64,
+    /// Status code of injected errors. Defaults to `503 Service Unavailable`
+    pub error_status: StatusCode,
+    /// Fraction of affected requests whose connection is dropped
+    /// before a response body could be sent. Defaults to `0.0`
+    pub drop_rate: f64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        ChaosConfig {
+            enabled: false,
+            probability: 1.0,
+            paths: Vec::new(),
+            latency: None,
+            error_rate: 0.0,
+            error_status: StatusCode::SERVICE_UNAVAILABLE,
+            drop_rate: 0.0,
+        }
+    }
+}
+
+/**
+Middleware injecting latency, errors and dropped connections for resilience testing.
+
+It does nothing unless [ChaosConfig::enabled] is set.
+
+```no_run
+use std::time::Duration;
+
+use actix_toolbox::tb_middleware::{Chaos, ChaosConfig};
+use actix_web::App;
+
+let app = App::new().wrap(Chaos::new(ChaosConfig {
+    enabled: std::env::var("CHAOS").is_ok(),
+    probability: 0.1,
+    latency: Some((Duration::from_millis(100), Duration::from_secs(2))),
+    error_rate: 0.5,
+    ..Default::default()
+}));
+```
+*/
+#[derive(Clone, Debug)]
+pub struct Chaos(Rc<ChaosConfig>);
+
+impl Chaos {
+    /// Create the middleware from its config
+    pub fn new(config: ChaosConfig) -> Self {
+        Self(Rc::new(config))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Chaos
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ChaosMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ChaosMiddleware {
+            service: Rc::new(service),
+            config: self.0.clone(),
+        }))
+    }
+}
+
+/// Service created by [Chaos]
+pub struct ChaosMiddleware<S> {
+    service: Rc<S>,
+    config: Rc<ChaosConfig>,
+}
+
+/// The kind of chaos chosen for a single request
+enum Outcome {
+    Error,
+    Drop,
+    Pass,
+}
+
+impl<S, B> Service<ServiceRequest> for ChaosMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let config = &self.config;
+        let affected = config.enabled
+            && (config.paths.is_empty()
+                || config
+                    .paths
+                    .iter()
+                    .any(|path| req.path().starts_with(path.as_str())))
+            && rand::thread_rng().gen_bool(config.probability.clamp(0.0, 1.0));
+
+        if !affected {
+            let fut = self.service.call(req);
+            return Box::pin(async move { Ok(fut.await?.map_into_left_body()) });
+        }
+
+        let mut rng = rand::thread_rng();
+        let latency = config.latency.map(|(min, max)| {
+            if min < max {
+                rng.gen_range(min..=max)
+            } else {
+                min
+            }
+        });
+        let outcome = if rng.gen_bool(config.error_rate.clamp(0.0, 1.0)) {
+            Outcome::Error
+        } else if rng.gen_bool(config.drop_rate.clamp(0.0, 1.0)) {
+            Outcome::Drop
+        } else {
+            Outcome::Pass
+        };
+
+        let service = self.service.clone();
+        let error_status = config.error_status;
+        Box::pin(async move {
+            if let Some(latency) = latency {
+                actix_web::rt::time::sleep(latency).await;
+            }
+
+            match outcome {
+                Outcome::Pass => Ok(service.call(req).await?.map_into_left_body()),
+                Outcome::Error => Ok(req
+                    .into_response(HttpResponse::new(error_status))
+                    .map_into_right_body()),
+                Outcome::Drop => {
+                    // A body stream failing immediately makes actix abort the connection
+                    let body = BoxBody::new(BodyStream::new(stream::once(ready(Err::<Bytes, _>(
+                        io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            "Connection dropped by chaos middleware",
+                        ),
+                    )))));
+                    Ok(req
+                        .into_response(HttpResponse::Ok().body(body))
+                        .map_into_right_body())
+                }
+            }
+        })
+    }
+}
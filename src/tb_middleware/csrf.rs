@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_session::{Session, SessionExt, SessionInsertError};
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::{Bytes, Form};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, ResponseError};
+use futures::future::LocalBoxFuture;
+use rand::distributions::{Alphanumeric, DistString};
+use serde::{Deserialize, Serialize};
+
+use crate::tb_middleware::{SessionData, TypedSession};
+
+/// Length of the generated tokens
+const TOKEN_LEN: usize = 64;
+
+/**
+Token protecting a session against cross-site request forgery
+
+It is stored in the session and has to be sent along with every state changing request,
+which other sites can't do as they can't read it.
+The [CsrfProtection] middleware checks it.
+
+Use the extractor to put the token into your pages:
+
+```no_run
+use actix_toolbox::tb_middleware::CsrfToken;
+use actix_web::HttpResponse;
+
+async fn settings(token: CsrfToken) -> HttpResponse {
+    HttpResponse::Ok().body(format!(
+        r#"<form method="post" action="/settings">
+            <input type="hidden" name="csrf_token" value="{token}">
+            <button>Save</button>
+        </form>"#
+    ))
+}
+```
+
+The extractor creates the token if the session doesn't contain one yet.
+*/
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct CsrfToken(String);
+
+impl SessionData for CsrfToken {
+    const KEY: &'static str = "csrf_token";
+}
+
+impl CsrfToken {
+    /// Generate a new random token
+    fn generate() -> Self {
+        Self(Alphanumeric.sample_string(&mut rand::thread_rng(), TOKEN_LEN))
+    }
+
+    /// Get the token's value
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Check whether a token sent by the client matches this one
+    ///
+    /// The comparison takes the same time regardless of where the tokens differ.
+    pub fn matches(&self, token: &str) -> bool {
+        let (expected, token) = (self.0.as_bytes(), token.as_bytes());
+        expected.len() == token.len()
+            && expected
+                .iter()
+                .zip(token)
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl std::fmt::Display for CsrfToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Get the session's CSRF token, storing a new one if it doesn't contain one yet
+pub fn csrf_token(session: &Session) -> Result<CsrfToken, SessionInsertError> {
+    if let Some(token) = session.get_typed::<CsrfToken>().ok().flatten() {
+        return Ok(token);
+    }
+    rotate_csrf_token(session)
+}
+
+/// Replace the session's CSRF token with a new one
+///
+/// Call it when the user logs in, so a token seen before can't be used afterwards.
+pub fn rotate_csrf_token(session: &Session) -> Result<CsrfToken, SessionInsertError> {
+    let token = CsrfToken::generate();
+    session.insert_typed(&token)?;
+    Ok(token)
+}
+
+impl FromRequest for CsrfToken {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(csrf_token(&req.get_session()).map_err(Error::from))
+    }
+}
+
+/**
+Middleware checking the [CsrfToken] of requests using unsafe methods
+
+Requests using any method but `GET`, `HEAD`, `OPTIONS` and `TRACE` are rejected with
+`403 Forbidden`, unless they send the session's token.
+It is taken from the `X-CSRF-Token` header or, for urlencoded forms,
+the `csrf_token` field.
+
+It has to be wrapped before the [SessionMiddleware](crate::tb_middleware::SessionMiddleware),
+so that it runs inside of it:
+
+```no_run
+use actix_session::storage::SessionStore;
+use actix_session::SessionMiddleware;
+use actix_toolbox::tb_middleware::CsrfProtection;
+use actix_web::cookie::Key;
+use actix_web::App;
+
+# fn build(store: impl SessionStore + 'static, key: Key) {
+let app = App::new()
+    .wrap(CsrfProtection::new())
+    .wrap(SessionMiddleware::new(store, key));
+# }
+```
+*/
+#[derive(Clone, Debug)]
+pub struct CsrfProtection {
+    header_name: Rc<str>,
+    field_name: Rc<str>,
+}
+
+impl Default for CsrfProtection {
+    fn default() -> Self {
+        Self {
+            header_name: Rc::from("X-CSRF-Token"),
+            field_name: Rc::from("csrf_token"),
+        }
+    }
+}
+
+impl CsrfProtection {
+    /// Create the middleware
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the name of the header the token is taken from
+    ///
+    /// Defaults to `X-CSRF-Token`
+    pub fn header_name(mut self, header_name: impl AsRef<str>) -> Self {
+        self.header_name = Rc::from(header_name.as_ref());
+        self
+    }
+
+    /// Set the name of the form field the token is taken from
+    ///
+    /// Defaults to `csrf_token`
+    pub fn field_name(mut self, field_name: impl AsRef<str>) -> Self {
+        self.field_name = Rc::from(field_name.as_ref());
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = CsrfProtectionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfProtectionMiddleware {
+            service: Rc::new(service),
+            config: self.clone(),
+        }))
+    }
+}
+
+/// Service created by [CsrfProtection]
+pub struct CsrfProtectionMiddleware<S> {
+    service: Rc<S>,
+    config: CsrfProtection,
+}
+
+/// Check whether a request can't change any state
+fn is_safe(method: &Method) -> bool {
+    [Method::GET, Method::HEAD, Method::OPTIONS, Method::TRACE].contains(method)
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfProtectionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            if is_safe(req.method()) {
+                return service.call(req).await;
+            }
+
+            let mut token = req
+                .headers()
+                .get(config.header_name.as_ref())
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+
+            if token.is_none() && req.content_type() == "application/x-www-form-urlencoded" {
+                // Read the body and put it back for the handler
+                let mut payload = req.take_payload();
+                let body = Bytes::from_request(req.request(), &mut payload).await?;
+                let form = Form::<HashMap<String, String>>::from_request(
+                    req.request(),
+                    &mut body.clone().into(),
+                )
+                .await
+                .ok();
+                req.set_payload(body.into());
+                token = form.and_then(|mut form| form.0.remove(config.field_name.as_ref()));
+            }
+
+            let Some(token) = token else {
+                return Err(CsrfError::Missing.into());
+            };
+            let valid = req
+                .get_session()
+                .get_typed::<CsrfToken>()
+                .ok()
+                .flatten()
+                .is_some_and(|expected| expected.matches(&token));
+            if !valid {
+                return Err(CsrfError::Invalid.into());
+            }
+
+            service.call(req).await
+        })
+    }
+}
+
+/// Error returned by the [CsrfProtection] middleware
+#[derive(Debug)]
+pub enum CsrfError {
+    /// The request didn't send a token
+    Missing,
+
+    /// The token doesn't match the session's one
+    Invalid,
+}
+impl std::fmt::Display for CsrfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsrfError::Missing => write!(f, "The request is missing the CSRF token"),
+            CsrfError::Invalid => write!(f, "The CSRF token is invalid"),
+        }
+    }
+}
+impl std::error::Error for CsrfError {}
+impl ResponseError for CsrfError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
@@ -0,0 +1,274 @@
+use std::fs;
+use std::future::{ready, Ready};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use actix_web::body::{to_bytes, BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{Error, HttpResponse};
+use futures::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/**
+Mode the [Fixtures] middleware operates in
+*/
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FixtureMode {
+    /// Pass requests to the handlers and write each request-response pair to a fixture file
+    Record,
+    /// Answer requests with the recorded responses without calling the handlers
+    ///
+    /// Requests without a fixture are answered with `404 Not Found`.
+    Replay,
+    /// Pass requests to the handlers and compare their responses with the recorded ones
+    ///
+    /// Mismatches are answered with `500 Internal Server Error` describing the difference.
+    Assert,
+}
+
+/**
+Configuration for the [Fixtures] middleware.
+*/
+#[derive(Clone, Debug)]
+pub struct FixtureConfig {
+    /// Directory to store the fixture files in
+    pub directory: PathBuf,
+    /// What to do with the fixtures
+    pub mode: FixtureMode,
+}
+
+/**
+A recorded request-response pair as stored in a fixture file
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Fixture {
+    /// The recorded request
+    pub request: RecordedRequest,
+    /// The recorded response
+    pub response: RecordedResponse,
+}
+
+/**
+Request part of a [Fixture]
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    /// The request's method
+    pub method: String,
+    /// The request's path and query
+    pub uri: String,
+    /// The request's body, lossily converted to utf8
+    pub body: String,
+}
+
+/**
+Response part of a [Fixture]
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedResponse {
+    /// The response's status code
+    pub status: u16,
+    /// The response's headers, sorted by their name
+    ///
+    /// Headers sent several times, like `set-cookie`, keep the order of their values.
+    pub headers: Vec<(String, String)>,
+    /// The response's body, lossily converted to utf8
+    pub body: String,
+}
+
+/**
+Test utility middleware recording request-response pairs to fixture files and replaying them.
+
+Each request is stored in its own json file in [FixtureConfig::directory],
+named after the request's method, the start of its path and query and a digest of the whole request.
+This allows golden-file regression tests:
+record the fixtures once, commit them and run the tests with [FixtureMode::Assert] afterwards.
+
+Fixtures only store bodies as text, so this is intended for json or html apis.
+*/
+#[derive(Clone, Debug)]
+pub struct Fixtures(Rc<FixtureConfig>);
+
+impl Fixtures {
+    /// Create the middleware from its config
+    pub fn new(config: FixtureConfig) -> Self {
+        Self(Rc::new(config))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Fixtures
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = FixturesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(FixturesMiddleware {
+            service: Rc::new(service),
+            config: self.0.clone(),
+        }))
+    }
+}
+
+/// Service created by [Fixtures]
+pub struct FixturesMiddleware<S> {
+    service: Rc<S>,
+    config: Rc<FixtureConfig>,
+}
+
+impl<S, B> Service<ServiceRequest> for FixturesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let body = req.extract::<Bytes>().await?;
+            req.set_payload(body.clone().into());
+
+            let request = RecordedRequest {
+                method: req.method().to_string(),
+                uri: req
+                    .uri()
+                    .path_and_query()
+                    .map_or_else(|| req.path().to_string(), |x| x.to_string()),
+                body: String::from_utf8_lossy(&body).into_owned(),
+            };
+            let path = config.directory.join(fixture_name(&request));
+
+            if config.mode == FixtureMode::Replay {
+                let fixture = read_fixture(&path)?
+                    .ok_or_else(|| ErrorNotFound(format!("No fixture at {}", path.display())))?;
+                let response = build_response(&fixture.response)?;
+                return Ok(req.into_response(response));
+            }
+
+            let response = service.call(req).await?;
+            let (http_request, response) = response.into_parts();
+            let (response, body) = response.into_parts();
+            let body = to_bytes(body).await.map_err(|e| {
+                ErrorInternalServerError(format!("Failed to read response body: {}", e.into()))
+            })?;
+
+            let mut headers: Vec<_> = response
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect();
+            // The header map's order isn't stable, but the one of a header's values is
+            headers.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let recorded = RecordedResponse {
+                status: response.status().as_u16(),
+                headers,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            };
+
+            match config.mode {
+                FixtureMode::Record => {
+                    let fixture = Fixture {
+                        request,
+                        response: recorded,
+                    };
+                    fs::create_dir_all(&config.directory).map_err(ErrorInternalServerError)?;
+                    let json =
+                        serde_json::to_string_pretty(&fixture).map_err(ErrorInternalServerError)?;
+                    fs::write(&path, json).map_err(ErrorInternalServerError)?;
+                }
+                FixtureMode::Assert => {
+                    let fixture = read_fixture(&path)?.ok_or_else(|| {
+                        ErrorInternalServerError(format!("No fixture at {}", path.display()))
+                    })?;
+                    if fixture.response.status != recorded.status
+                        || fixture.response.body != recorded.body
+                    {
+                        return Err(ErrorInternalServerError(format!(
+                            "Response doesn't match fixture {}:\nexpected: {} {}\nactual: {} {}",
+                            path.display(),
+                            fixture.response.status,
+                            fixture.response.body,
+                            recorded.status,
+                            recorded.body,
+                        )));
+                    }
+                }
+                FixtureMode::Replay => unreachable!("Replay returns early"),
+            }
+
+            Ok(ServiceResponse::new(
+                http_request,
+                response.set_body(BoxBody::new(body)),
+            ))
+        })
+    }
+}
+
+/// Maximum number of characters of the request's uri in a fixture's file name
+const MAX_URI_NAME_LEN: usize = 64;
+
+/// Derive a file name from a request
+///
+/// The name ends with a SHA-256 digest of the whole request,
+/// which stays the same across Rust releases, unlike the std [DefaultHasher](std::collections::hash_map::DefaultHasher).
+/// It distinguishes requests whose uri differs only after the truncated part as well.
+fn fixture_name(request: &RecordedRequest) -> String {
+    let mut hasher = Sha256::new();
+    for part in [&request.method, &request.uri, &request.body] {
+        // Prefix each part with its length, so their boundaries can't be shifted
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part.as_bytes());
+    }
+    let digest = format!("{:x}", hasher.finalize());
+
+    let uri: String = request
+        .uri
+        .chars()
+        .take(MAX_URI_NAME_LEN)
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}{}_{}.json", request.method, uri, &digest[..16])
+}
+
+/// Read a fixture file returning `None` if it doesn't exist
+fn read_fixture(path: &Path) -> Result<Option<Fixture>, Error> {
+    match fs::read_to_string(path) {
+        Ok(json) => Ok(Some(
+            serde_json::from_str(&json).map_err(ErrorInternalServerError)?,
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(ErrorInternalServerError(err)),
+    }
+}
+
+/// Rebuild a response from its recording
+fn build_response(recorded: &RecordedResponse) -> Result<HttpResponse, Error> {
+    let status = StatusCode::from_u16(recorded.status).map_err(ErrorInternalServerError)?;
+    let mut response = HttpResponse::build(status);
+    for (name, value) in &recorded.headers {
+        response.append_header((
+            HeaderName::try_from(name.as_str()).map_err(ErrorInternalServerError)?,
+            HeaderValue::try_from(value.as_str()).map_err(ErrorInternalServerError)?,
+        ));
+    }
+    Ok(response.body(recorded.body.clone()))
+}
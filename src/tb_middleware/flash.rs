@@ -0,0 +1,105 @@
+use std::future::{ready, Ready};
+
+use actix_session::{Session, SessionExt, SessionInsertError};
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use serde::{Deserialize, Serialize};
+
+use crate::tb_middleware::{SessionData, TypedSession};
+
+/// Severity of a [FlashMessage]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum FlashLevel {
+    /// E.g. "Your changes have been saved"
+    Info,
+    /// E.g. "Your password expires soon"
+    Warning,
+    /// E.g. "The upload failed"
+    Error,
+}
+
+/// Message shown to the user on the next page they visit
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct FlashMessage {
+    /// Severity of the message
+    pub level: FlashLevel,
+    /// The message's text
+    pub message: String,
+}
+
+/**
+Store a message in the user's session to show it on the next page
+
+This supports the post/redirect/get pattern: the handler processing a form
+flashes a message and redirects, the handler rendering the next page
+takes the message using the [FlashMessages] extractor.
+
+```no_run
+use actix_session::Session;
+use actix_toolbox::tb_middleware::{flash, FlashLevel, FlashMessages};
+use actix_web::web::Redirect;
+use actix_web::HttpResponse;
+
+async fn save(session: Session) -> actix_web::Result<Redirect> {
+    flash(&session, FlashLevel::Info, "Your changes have been saved")?;
+    Ok(Redirect::to("/settings").see_other())
+}
+
+async fn settings(messages: FlashMessages) -> HttpResponse {
+    let text: String = messages
+        .iter()
+        .map(|message| format!("{:?}: {}\n", message.level, message.message))
+        .collect();
+    HttpResponse::Ok().body(text)
+}
+```
+*/
+pub fn flash(
+    session: &Session,
+    level: FlashLevel,
+    message: impl Into<String>,
+) -> Result<(), SessionInsertError> {
+    let mut messages = session
+        .get_typed::<FlashMessages>()
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    messages.0.push(FlashMessage {
+        level,
+        message: message.into(),
+    });
+    session.insert_typed(&messages)
+}
+
+/// Extractor taking the [FlashMessage]s out of the user's session
+///
+/// The messages are removed from the session, so they are only shown once.
+/// It is empty if there are no messages or the request has no session.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(transparent)]
+pub struct FlashMessages(pub Vec<FlashMessage>);
+
+impl SessionData for FlashMessages {
+    const KEY: &'static str = "flash_messages";
+}
+
+impl std::ops::Deref for FlashMessages {
+    type Target = Vec<FlashMessage>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl FromRequest for FlashMessages {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Ok(req
+            .get_session()
+            .remove_typed::<FlashMessages>()
+            .unwrap_or_default()))
+    }
+}
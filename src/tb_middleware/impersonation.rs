@@ -0,0 +1,333 @@
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+use actix_session::{Session, SessionExt, SessionGetError, SessionInsertError};
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError};
+use futures::future::LocalBoxFuture;
+
+use crate::tb_middleware::{set_session_user, SESSION_USER_ID};
+
+/// Key in the session's state [start_impersonation] stores the id of the user impersonating another one under
+///
+/// The impersonated user is stored under [SESSION_USER_ID],
+/// so everything using the session acts on their behalf.
+pub const SESSION_IMPERSONATOR_ID: &str = "session_impersonator_id";
+
+/// A user acting as another one
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Impersonation {
+    /// Id of the user who is impersonating, e.g. an admin
+    pub actual_user_id: String,
+
+    /// Id of the user who is impersonated
+    pub impersonated_user_id: String,
+}
+
+/// Event passed to the [ImpersonationHook]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ImpersonationEvent {
+    /// [start_impersonation] is about to start the impersonation
+    Started(Impersonation),
+
+    /// [stop_impersonation] ended the impersonation
+    Ended(Impersonation),
+}
+
+/// Async function called when an impersonation starts or ends, e.g. to write an audit log
+///
+/// Register it as app data.
+/// Returning an error when the impersonation starts aborts it.
+/// Impersonations are ended regardless of the hook, its error is returned afterwards.
+///
+/// ```no_run
+/// use actix_toolbox::tb_middleware::{ImpersonationEvent, ImpersonationHook};
+/// use actix_web::App;
+///
+/// let hook = ImpersonationHook::new(|_request, event| {
+///     Box::pin(async move {
+///         match event {
+///             ImpersonationEvent::Started(impersonation) => log::info!(
+///                 "{} started impersonating {}",
+///                 impersonation.actual_user_id,
+///                 impersonation.impersonated_user_id
+///             ),
+///             ImpersonationEvent::Ended(impersonation) => log::info!(
+///                 "{} stopped impersonating {}",
+///                 impersonation.actual_user_id,
+///                 impersonation.impersonated_user_id
+///             ),
+///         }
+///         Ok(())
+///     })
+/// });
+/// let app = App::new().app_data(hook);
+/// ```
+#[derive(Clone)]
+pub struct ImpersonationHook(Arc<ImpersonationFn>);
+type ImpersonationFn = dyn Fn(HttpRequest, ImpersonationEvent) -> ImpersonationFuture + Send + Sync;
+type ImpersonationFuture = LocalBoxFuture<'static, Result<(), actix_web::Error>>;
+impl ImpersonationHook {
+    /// Wrap an async function
+    pub fn new(
+        hook: impl Fn(HttpRequest, ImpersonationEvent) -> ImpersonationFuture + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    /// Call the hook registered as app data, if there is one
+    async fn call(
+        request: &HttpRequest,
+        event: ImpersonationEvent,
+    ) -> Result<(), actix_web::Error> {
+        match request.app_data::<ImpersonationHook>() {
+            Some(hook) => (hook.0)(request.clone(), event).await,
+            None => Ok(()),
+        }
+    }
+}
+impl std::fmt::Debug for ImpersonationHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ImpersonationHook").field(&"..").finish()
+    }
+}
+
+/**
+Let the user logged into the request's session act as another user
+
+The session is renewed and the other user is stored under [SESSION_USER_ID],
+while the actual user is kept under [SESSION_IMPERSONATOR_ID].
+Use [SessionUser] to tell them apart and [stop_impersonation] to return to the actual user.
+
+Check that the actual user is allowed to impersonate others before calling it:
+
+```no_run
+use actix_toolbox::tb_middleware::{start_impersonation, SessionUser};
+use actix_web::error::ErrorForbidden;
+use actix_web::web::Path;
+use actix_web::{HttpRequest, HttpResponse};
+
+async fn impersonate(
+    request: HttpRequest,
+    user: SessionUser,
+    user_id: Path<String>,
+) -> actix_web::Result<HttpResponse> {
+    if user.actual_user_id != "admin" {
+        return Err(ErrorForbidden("Only admins may impersonate users"));
+    }
+    start_impersonation(&request, user_id.into_inner()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+```
+*/
+pub async fn start_impersonation(
+    request: &HttpRequest,
+    user_id: impl Into<String>,
+) -> Result<Impersonation, ImpersonationError> {
+    let session = request.get_session();
+    let Some(actual_user_id) = session.get::<String>(SESSION_USER_ID)? else {
+        return Err(ImpersonationError::NotLoggedIn);
+    };
+    if session.get::<String>(SESSION_IMPERSONATOR_ID)?.is_some() {
+        return Err(ImpersonationError::AlreadyImpersonating);
+    }
+
+    let impersonation = Impersonation {
+        actual_user_id,
+        impersonated_user_id: user_id.into(),
+    };
+    ImpersonationHook::call(request, ImpersonationEvent::Started(impersonation.clone()))
+        .await
+        .map_err(ImpersonationError::Rejected)?;
+
+    // Use a new session key for the other user
+    session.renew();
+    session.insert(SESSION_IMPERSONATOR_ID, &impersonation.actual_user_id)?;
+    set_session_user(&session, impersonation.impersonated_user_id.clone())?;
+
+    Ok(impersonation)
+}
+
+/// Return to the actual user of the request's session
+///
+/// Returns the ended impersonation, `None` if the session wasn't impersonating anyone.
+pub async fn stop_impersonation(
+    request: &HttpRequest,
+) -> Result<Option<Impersonation>, ImpersonationError> {
+    let session = request.get_session();
+    let Some(actual_user_id) = session.get::<String>(SESSION_IMPERSONATOR_ID)? else {
+        return Ok(None);
+    };
+    let impersonation = Impersonation {
+        actual_user_id,
+        impersonated_user_id: session.get::<String>(SESSION_USER_ID)?.unwrap_or_default(),
+    };
+
+    session.renew();
+    session.remove(SESSION_IMPERSONATOR_ID);
+    set_session_user(&session, impersonation.actual_user_id.clone())?;
+
+    ImpersonationHook::call(request, ImpersonationEvent::Ended(impersonation.clone()))
+        .await
+        .map_err(ImpersonationError::Rejected)?;
+
+    Ok(Some(impersonation))
+}
+
+/**
+Extractor for the users of the request's session
+
+Responds with `401 Unauthorized` if no user is logged in.
+Use `Option<SessionUser>` if that's allowed.
+
+```no_run
+use actix_toolbox::tb_middleware::SessionUser;
+
+async fn profile(user: SessionUser) -> String {
+    match user.impersonator() {
+        Some(actual) => format!("{actual} viewing {}'s profile", user.effective_user_id),
+        None => format!("Your profile, {}", user.effective_user_id),
+    }
+}
+```
+*/
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SessionUser {
+    /// Id of the user the session acts on behalf of, i.e. the impersonated one
+    pub effective_user_id: String,
+
+    /// Id of the user who logged in, i.e. the one impersonating another
+    ///
+    /// Same as [SessionUser::effective_user_id] if nobody is impersonated.
+    pub actual_user_id: String,
+
+    /// Whether the session stores an impersonator under [SESSION_IMPERSONATOR_ID]
+    ///
+    /// Users may impersonate themselves, so this can't be told from the ids.
+    pub impersonating: bool,
+}
+
+impl SessionUser {
+    /// Get the users of a session
+    fn from_session(session: &Session) -> Result<Self, ImpersonationError> {
+        let Some(effective_user_id) = session.get::<String>(SESSION_USER_ID)? else {
+            return Err(ImpersonationError::NotLoggedIn);
+        };
+        let impersonator = session.get::<String>(SESSION_IMPERSONATOR_ID)?;
+        Ok(Self {
+            impersonating: impersonator.is_some(),
+            actual_user_id: impersonator.unwrap_or_else(|| effective_user_id.clone()),
+            effective_user_id,
+        })
+    }
+
+    /// Check whether the actual user is impersonating another one
+    pub fn is_impersonating(&self) -> bool {
+        self.impersonating
+    }
+
+    /// Get the id of the user impersonating the effective one, if any
+    pub fn impersonator(&self) -> Option<&str> {
+        self.is_impersonating()
+            .then_some(self.actual_user_id.as_str())
+    }
+}
+
+impl FromRequest for SessionUser {
+    type Error = ImpersonationError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(Self::from_session(&req.get_session()))
+    }
+}
+
+/// Error returned by [start_impersonation], [stop_impersonation] and the [SessionUser] extractor
+#[derive(Debug)]
+pub enum ImpersonationError {
+    /// No user is logged into the session
+    NotLoggedIn,
+
+    /// The session is already impersonating a user, stop it first
+    AlreadyImpersonating,
+
+    /// The [ImpersonationHook] returned an error
+    Rejected(actix_web::Error),
+
+    /// The session's state couldn't be read
+    SessionGet(SessionGetError),
+
+    /// The session's state couldn't be written
+    SessionInsert(SessionInsertError),
+}
+impl std::fmt::Display for ImpersonationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImpersonationError::NotLoggedIn => write!(f, "No user is logged in"),
+            ImpersonationError::AlreadyImpersonating => {
+                write!(f, "The session is already impersonating a user")
+            }
+            ImpersonationError::Rejected(err) => write!(f, "The impersonation hook failed: {err}"),
+            ImpersonationError::SessionGet(err) => write!(f, "{err}"),
+            ImpersonationError::SessionInsert(err) => write!(f, "{err}"),
+        }
+    }
+}
+impl std::error::Error for ImpersonationError {}
+impl ResponseError for ImpersonationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ImpersonationError::NotLoggedIn => StatusCode::UNAUTHORIZED,
+            ImpersonationError::AlreadyImpersonating => StatusCode::CONFLICT,
+            ImpersonationError::Rejected(err) => err.as_response_error().status_code(),
+            ImpersonationError::SessionGet(_) | ImpersonationError::SessionInsert(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ImpersonationError::Rejected(err) => err.error_response(),
+            _ => HttpResponse::new(self.status_code()),
+        }
+    }
+}
+impl From<SessionGetError> for ImpersonationError {
+    fn from(value: SessionGetError) -> Self {
+        Self::SessionGet(value)
+    }
+}
+impl From<SessionInsertError> for ImpersonationError {
+    fn from(value: SessionInsertError) -> Self {
+        Self::SessionInsert(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn impersonating_oneself() {
+        let request = TestRequest::default().to_http_request();
+        let session = request.get_session();
+        set_session_user(&session, "alice".to_string()).unwrap();
+
+        let user = SessionUser::from_session(&session).unwrap();
+        assert!(!user.is_impersonating());
+        assert_eq!(user.impersonator(), None);
+
+        start_impersonation(&request, "alice").await.unwrap();
+        let user = SessionUser::from_session(&session).unwrap();
+        assert!(user.is_impersonating());
+        assert_eq!(user.impersonator(), Some("alice"));
+
+        stop_impersonation(&request).await.unwrap();
+        let user = SessionUser::from_session(&session).unwrap();
+        assert!(!user.is_impersonating());
+    }
+}
@@ -1,6 +1,23 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::HeaderValue;
 use actix_web::middleware::Logger;
+use actix_web::{Error, HttpMessage};
+use futures::future::LocalBoxFuture;
 
 use crate::logging::LOG_PATTERN_ACTIX_NGINX_LIKE;
+use crate::tb_middleware::request_id::X_REQUEST_ID;
+use crate::tb_middleware::{request_id, RequestId};
+
+/// Key of the request's id in log4rs' mapped diagnostic context
+///
+/// Use `{X(request_id)}` in a log pattern to include the id of the request a log entry was written for.
+/// See [SetRequestId] for more information.
+pub const REQUEST_ID_MDC_KEY: &str = "request_id";
 
 /**
 Configuration for the Logger middleware.
@@ -10,6 +27,8 @@ Provides a default via the [Default] trait.
 #[derive(Clone, Debug)]
 pub struct LoggingMiddlewareConfig {
     /// Pattern to use in logger. Defaults to [LOG_PATTERN_ACTIX_NGINX_LIKE]
+    ///
+    /// `%{request_id}xi` is replaced by the request's id, see [SetRequestId].
     pub pattern: String,
     /// Logging target. Defaults to "requests"
     pub logging_target: String,
@@ -28,5 +47,119 @@ impl Default for LoggingMiddlewareConfig {
 Sets up a logging middleware with the given config.
 */
 pub fn setup_logging_mw(config: LoggingMiddlewareConfig) -> Logger {
-    Logger::new(&config.pattern).log_target(config.logging_target)
+    Logger::new(&config.pattern)
+        .log_target(config.logging_target)
+        .custom_request_replace(REQUEST_ID_MDC_KEY, |req| {
+            request_id(req.request()).map_or_else(|| "-".to_string(), |id| id.to_string())
+        })
+}
+
+/**
+Middleware assigning an id to every request to correlate its log entries
+
+The id is taken from the `X-Request-Id` or `traceparent` header as described by [request_id].
+If the request has neither, a new one is generated.
+
+The id is
+- stored in the request's extensions, so handlers can use [RequestId] as extractor
+- sent back in the response's `X-Request-Id` header
+- inserted into log4rs' mapped diagnostic context under [REQUEST_ID_MDC_KEY]
+  while the request is handled, so every log entry written for it can include the id
+  using `{X(request_id)}` in its pattern.
+  The [JsonLinesEncoder](crate::logging::JsonLinesEncoder) adds it to the entry's fields.
+
+Wrap it around the [Logger] created by [setup_logging_mw] to let it use the id
+via `%{request_id}xi` in its pattern:
+
+```no_run
+use actix_toolbox::logging::LOG_PATTERN_ACTIX_NGINX_LIKE;
+use actix_toolbox::tb_middleware::{setup_logging_mw, LoggingMiddlewareConfig, SetRequestId};
+use actix_web::App;
+
+let app = App::new()
+    .wrap(setup_logging_mw(LoggingMiddlewareConfig {
+        pattern: format!("[%{{request_id}}xi] {LOG_PATTERN_ACTIX_NGINX_LIKE}"),
+        ..Default::default()
+    }))
+    .wrap(SetRequestId);
+```
+*/
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SetRequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for SetRequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SetRequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SetRequestIdMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// Service created by [SetRequestId]
+pub struct SetRequestIdMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for SetRequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let id = request_id(req.request()).unwrap_or_else(RequestId::generate);
+        req.extensions_mut().insert(id.clone());
+
+        // Services may already do some work when called, before their future is polled
+        let fut = {
+            let _mdc = log_mdc::insert_scoped(REQUEST_ID_MDC_KEY, id.as_str());
+            self.service.call(req)
+        };
+        let fut = WithRequestId {
+            future: fut,
+            id: id.clone(),
+        };
+
+        Box::pin(async move {
+            let mut response = fut.await?;
+            if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+                response.headers_mut().insert(X_REQUEST_ID, value);
+            }
+            Ok(response)
+        })
+    }
+}
+
+/// Future inserting a request's id into the mapped diagnostic context while it is polled
+///
+/// The context is thread local, so it has to be set again whenever the request's handling resumes.
+#[pin_project::pin_project]
+struct WithRequestId<F> {
+    #[pin]
+    future: F,
+    id: RequestId,
+}
+impl<F: Future> Future for WithRequestId<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _mdc = log_mdc::insert_scoped(REQUEST_ID_MDC_KEY, this.id.as_str());
+        this.future.poll(cx)
+    }
 }
@@ -0,0 +1,352 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::clock::{Clock, SharedClock};
+use crate::tb_middleware::session_key::SharedKeyGenerator;
+use crate::tb_middleware::{
+    ExportSessions, ImportSessions, MigratedSession, RandomSessionKey, SessionKeyGenerator,
+};
+
+/// Length of the session keys generated by default
+const SESSION_KEY_LEN: usize = 64;
+
+struct StoredSession {
+    session_state: HashMap<String, String>,
+    expired_after: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct MemorySessions {
+    sessions: HashMap<String, StoredSession>,
+    /// Keys of the sessions ordered by their expiry, the one expiring next first
+    by_expiry: BTreeSet<(DateTime<Utc>, String)>,
+}
+
+impl MemorySessions {
+    fn insert(&mut self, session_key: String, session: StoredSession) {
+        self.remove(&session_key);
+        self.by_expiry
+            .insert((session.expired_after, session_key.clone()));
+        self.sessions.insert(session_key, session);
+    }
+
+    fn remove(&mut self, session_key: &str) {
+        if let Some(session) = self.sessions.remove(session_key) {
+            self.by_expiry
+                .remove(&(session.expired_after, session_key.to_string()));
+        }
+    }
+
+    /// Get a session which hasn't expired at `now`
+    fn get_mut(&mut self, session_key: &str, now: DateTime<Utc>) -> Option<&mut StoredSession> {
+        self.sessions
+            .get_mut(session_key)
+            .filter(|session| session.expired_after >= now)
+    }
+
+    fn set_expiry(&mut self, session_key: &str, expired_after: DateTime<Utc>) {
+        if let Some(session) = self.sessions.get_mut(session_key) {
+            let previous = std::mem::replace(&mut session.expired_after, expired_after);
+            self.by_expiry.remove(&(previous, session_key.to_string()));
+            self.by_expiry
+                .insert((expired_after, session_key.to_string()));
+        }
+    }
+
+    /// Remove the session expiring next
+    ///
+    /// Returns `false` if there was none.
+    fn evict_next(&mut self) -> bool {
+        match self.by_expiry.pop_first() {
+            Some((_, session_key)) => {
+                self.sessions.remove(&session_key);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the sessions which have expired at `now`
+    fn remove_expired(&mut self, now: DateTime<Utc>) {
+        while self
+            .by_expiry
+            .first()
+            .is_some_and(|(expired_after, _)| *expired_after < now)
+        {
+            self.evict_next();
+        }
+    }
+}
+
+/**
+[SessionStore] keeping the sessions in memory
+
+Use it for development, tests and applications running on a single node.
+The sessions are lost when the application restarts.
+
+Expired sessions are never returned and removed from memory when new sessions are stored.
+Use [MemorySessionStore::with_max_entries] to limit the memory used by the store.
+
+Create it once outside of [`HttpServer::new`](actix_web::HttpServer::new)
+and clone it into every worker, so all workers share the same sessions:
+
+```no_run
+use actix_session::SessionMiddleware;
+use actix_toolbox::tb_middleware::MemorySessionStore;
+use actix_web::cookie::Key;
+use actix_web::{App, HttpServer};
+
+# async fn run() -> std::io::Result<()> {
+let store = MemorySessionStore::new().with_max_entries(10_000);
+let key = Key::generate();
+HttpServer::new(move || {
+    App::new().wrap(SessionMiddleware::new(store.clone(), key.clone()))
+})
+.bind(("127.0.0.1", 8080))?
+.run()
+.await
+# }
+```
+*/
+#[derive(Clone)]
+pub struct MemorySessionStore {
+    sessions: Arc<Mutex<MemorySessions>>,
+    max_entries: Option<usize>,
+    clock: SharedClock,
+    key_generator: SharedKeyGenerator,
+}
+
+impl Default for MemorySessionStore {
+    fn default() -> Self {
+        Self {
+            sessions: Default::default(),
+            max_entries: None,
+            clock: SharedClock::default(),
+            key_generator: Arc::new(RandomSessionKey::new(SESSION_KEY_LEN)),
+        }
+    }
+}
+
+impl MemorySessionStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the number of sessions kept in memory
+    ///
+    /// When the limit is reached, the session expiring next is removed to make room for a new one.
+    /// Defaults to no limit
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Use a different [Clock] to calculate the sessions' expiry
+    ///
+    /// Defaults to the [SystemClock](crate::clock::SystemClock).
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Use a different [SessionKeyGenerator] for new sessions
+    ///
+    /// Defaults to 64 random alphanumeric characters.
+    pub fn with_key_generator(mut self, key_generator: impl SessionKeyGenerator) -> Self {
+        self.key_generator = Arc::new(key_generator);
+        self
+    }
+
+    /// Get the number of sessions in memory, including the expired ones not removed yet
+    pub fn len(&self) -> usize {
+        self.lock().sessions.len()
+    }
+
+    /// Check whether there are no sessions in memory
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove all expired sessions from memory
+    pub fn remove_expired(&self) {
+        let now = self.clock.now();
+        self.lock().remove_expired(now);
+    }
+
+    /// Lock the sessions
+    ///
+    /// A panic while holding the lock can't leave the map in an inconsistent state,
+    /// so a poisoned lock is used anyway.
+    fn lock(&self) -> MutexGuard<'_, MemorySessions> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+
+    /// Add the ttl to the current time, saturating at the earliest or latest representable time
+    fn expired_after(&self, ttl: &Duration) -> DateTime<Utc> {
+        i64::try_from(ttl.whole_nanoseconds())
+            .ok()
+            .and_then(|nanos| {
+                self.clock
+                    .now()
+                    .checked_add_signed(chrono::Duration::nanoseconds(nanos))
+            })
+            .unwrap_or(if ttl.is_negative() {
+                DateTime::<Utc>::MIN_UTC
+            } else {
+                DateTime::<Utc>::MAX_UTC
+            })
+    }
+
+    /// Make room for a new session
+    ///
+    /// Removes the expired sessions and evicts the session expiring next, if the store is full.
+    /// The sessions are indexed by their expiry, so this only touches the removed ones.
+    fn make_room(&self, sessions: &mut MemorySessions) {
+        sessions.remove_expired(self.clock.now());
+
+        if let Some(max_entries) = self.max_entries {
+            while sessions.sessions.len() >= max_entries.max(1) && sessions.evict_next() {}
+        }
+    }
+
+    /// Insert a new session under a fresh key
+    fn insert(&self, session_state: HashMap<String, String>, ttl: &Duration) -> String {
+        let session = StoredSession {
+            session_state,
+            expired_after: self.expired_after(ttl),
+        };
+
+        let mut sessions = self.lock();
+        self.make_room(&mut sessions);
+        loop {
+            let session_key = self.key_generator.generate();
+            if !sessions.sessions.contains_key(&session_key) {
+                sessions.insert(session_key.clone(), session);
+                return session_key;
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl SessionStore for MemorySessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        let now = self.clock.now();
+        let mut sessions = self.lock();
+
+        match sessions.sessions.get(session_key.as_ref()) {
+            Some(session) if session.expired_after >= now => {
+                Ok(Some(session.session_state.clone()))
+            }
+            Some(_) => {
+                sessions.remove(session_key.as_ref());
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn save(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let session_key = self.insert(session_state, ttl);
+
+        SessionKey::try_from(session_key).map_err(|e| SaveError::Other(anyhow!(e)))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let now = self.clock.now();
+        let expired_after = self.expired_after(ttl);
+
+        {
+            let mut sessions = self.lock();
+            if let Some(session) = sessions.get_mut(session_key.as_ref(), now) {
+                session.session_state = session_state;
+                sessions.set_expiry(session_key.as_ref(), expired_after);
+                return Ok(session_key);
+            }
+        }
+
+        // The session expired or was evicted in the meantime
+        let session_key = self.insert(session_state, ttl);
+        SessionKey::try_from(session_key).map_err(|e| UpdateError::Other(anyhow!(e)))
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        let now = self.clock.now();
+        let expired_after = self.expired_after(ttl);
+
+        let mut sessions = self.lock();
+        if sessions.get_mut(session_key.as_ref(), now).is_some() {
+            sessions.set_expiry(session_key.as_ref(), expired_after);
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.lock().remove(session_key.as_ref());
+
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl ExportSessions for MemorySessionStore {
+    async fn export_sessions(&self) -> Result<Vec<MigratedSession>, anyhow::Error> {
+        let now = self.clock.now();
+
+        Ok(self
+            .lock()
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.expired_after >= now)
+            .map(|(session_key, session)| MigratedSession {
+                session_key: session_key.clone(),
+                session_state: session.session_state.clone(),
+                ttl: Duration::milliseconds((session.expired_after - now).num_milliseconds()),
+            })
+            .collect())
+    }
+}
+
+#[async_trait(?Send)]
+impl ImportSessions for MemorySessionStore {
+    async fn import_session(&self, session: MigratedSession) -> Result<(), anyhow::Error> {
+        let stored = StoredSession {
+            session_state: session.session_state,
+            expired_after: self.expired_after(&session.ttl),
+        };
+
+        let mut sessions = self.lock();
+        if !sessions.sessions.contains_key(&session.session_key) {
+            self.make_room(&mut sessions);
+        }
+        sessions.insert(session.session_key, stored);
+
+        Ok(())
+    }
+}
@@ -1,9 +1,148 @@
+#[cfg(feature = "cache-policy")]
+pub use cache_policy::*;
+#[cfg(feature = "chaos")]
+pub use chaos::*;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+pub use csrf::*;
+#[cfg(feature = "fixtures")]
+pub use fixtures::*;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+pub use flash::*;
+#[cfg(feature = "__session")]
+pub use impersonation::*;
 #[cfg(feature = "logging")]
 pub use logger::*;
+#[cfg(feature = "memory-session")]
+pub use memory_session::*;
+#[cfg(feature = "preload")]
+pub use preload::*;
+#[cfg(any(feature = "ws", feature = "__session"))]
+pub use real_ip::*;
+#[cfg(feature = "session-redis")]
+pub use redis_session::*;
+#[cfg(feature = "__session")]
+pub use remember_me::*;
+#[cfg(any(feature = "ws", feature = "logging"))]
+pub use request_id::*;
+#[cfg(feature = "tracing")]
+pub use request_tracing::*;
 #[cfg(feature = "__session")]
 pub use session::*;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+pub use session_key::*;
+#[cfg(feature = "__session")]
+pub use session_metadata::*;
+#[cfg(feature = "__session")]
+pub use session_metrics::*;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+pub use session_migration::*;
+#[cfg(feature = "__session")]
+pub use session_model::*;
+#[cfg(feature = "__session")]
+pub use session_presence::*;
+#[cfg(feature = "__session")]
+pub use session_retry::*;
+#[cfg(feature = "__session")]
+pub use session_version::*;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+pub use typed_session::*;
 
+#[cfg(feature = "cache-policy")]
+mod cache_policy;
+#[cfg(feature = "chaos")]
+mod chaos;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+mod csrf;
+#[cfg(feature = "fixtures")]
+mod fixtures;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+mod flash;
+#[cfg(feature = "__session")]
+mod impersonation;
 #[cfg(feature = "logging")]
 mod logger;
+#[cfg(feature = "memory-session")]
+mod memory_session;
+#[cfg(feature = "preload")]
+mod preload;
+#[cfg(any(feature = "ws", feature = "__session"))]
+mod real_ip;
+#[cfg(feature = "session-redis")]
+mod redis_session;
+#[cfg(feature = "__session")]
+mod remember_me;
+#[cfg(any(feature = "ws", feature = "logging"))]
+mod request_id;
+#[cfg(feature = "tracing")]
+mod request_tracing;
 #[cfg(feature = "__session")]
 mod session;
+#[cfg(feature = "__session")]
+mod session_cache;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+mod session_key;
+#[cfg(feature = "__session")]
+mod session_metadata;
+#[cfg(feature = "__session")]
+mod session_metrics;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+mod session_migration;
+#[cfg(feature = "__session")]
+mod session_model;
+#[cfg(feature = "__session")]
+mod session_presence;
+#[cfg(feature = "__session")]
+mod session_retry;
+#[cfg(feature = "__session")]
+mod session_version;
+#[cfg(all(
+    feature = "test-util",
+    any(
+        feature = "__session",
+        feature = "memory-session",
+        feature = "session-redis"
+    )
+))]
+pub mod test;
+#[cfg(any(
+    feature = "__session",
+    feature = "memory-session",
+    feature = "session-redis"
+))]
+mod typed_session;
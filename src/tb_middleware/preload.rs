@@ -0,0 +1,153 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderValue, LINK};
+use actix_web::http::Method;
+use actix_web::Error;
+use futures::future::LocalBoxFuture;
+
+/**
+An asset the browser should start loading before it parses the response
+*/
+#[derive(Clone, Debug)]
+pub struct PreloadAsset {
+    /// Url of the asset
+    pub href: String,
+    /// Kind of the asset, e.g. `script`, `style`, `font` or `image`
+    pub kind: String,
+    /// Whether the asset is fetched using CORS, which is required for fonts
+    pub crossorigin: bool,
+}
+
+impl PreloadAsset {
+    /// Create an asset which isn't fetched using CORS
+    pub fn new(href: impl Into<String>, kind: impl Into<String>) -> Self {
+        Self {
+            href: href.into(),
+            kind: kind.into(),
+            crossorigin: false,
+        }
+    }
+
+    /// Format the asset as a value of the `Link` header
+    fn link(&self) -> String {
+        let mut link = format!("<{}>; rel=preload; as={}", self.href, self.kind);
+        if self.crossorigin {
+            link.push_str("; crossorigin");
+        }
+        link
+    }
+}
+
+/**
+Configuration for the [Preload] middleware.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct PreloadConfig {
+    /// Assets sent with every successful `GET` response
+    pub global: Vec<PreloadAsset>,
+    /// Assets sent with successful `GET` responses whose path starts with the key
+    pub routes: Vec<(String, Vec<PreloadAsset>)>,
+}
+
+/**
+Middleware adding `Link: <...>; rel=preload` headers for configured assets.
+
+Browsers and CDNs use these headers to fetch the assets while the page is still loading.
+Proxies like Cloudflare or nginx can convert them into `103 Early Hints`,
+which actix doesn't support sending itself.
+
+```no_run
+use actix_toolbox::tb_middleware::{Preload, PreloadAsset, PreloadConfig};
+use actix_web::App;
+
+let app = App::new().wrap(Preload::new(PreloadConfig {
+    global: vec![PreloadAsset::new("/assets/main.css", "style")],
+    routes: vec![(
+        "/dashboard".to_string(),
+        vec![PreloadAsset::new("/assets/dashboard.js", "script")],
+    )],
+}));
+```
+*/
+#[derive(Clone, Debug)]
+pub struct Preload(Rc<PreloadConfig>);
+
+impl Preload {
+    /// Create the middleware from its config
+    pub fn new(config: PreloadConfig) -> Self {
+        Self(Rc::new(config))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Preload
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = PreloadMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PreloadMiddleware {
+            service: Rc::new(service),
+            config: self.0.clone(),
+        }))
+    }
+}
+
+/// Service created by [Preload]
+pub struct PreloadMiddleware<S> {
+    service: Rc<S>,
+    config: Rc<PreloadConfig>,
+}
+
+impl<S, B> Service<ServiceRequest> for PreloadMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let links: Vec<String> = if req.method() == Method::GET {
+            let path = req.path();
+            self.config
+                .global
+                .iter()
+                .chain(
+                    self.config
+                        .routes
+                        .iter()
+                        .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+                        .flat_map(|(_, assets)| assets),
+                )
+                .map(PreloadAsset::link)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if res.status().is_success() {
+                for link in links {
+                    if let Ok(value) = HeaderValue::try_from(link) {
+                        res.headers_mut().append(LINK, value);
+                    }
+                }
+            }
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,178 @@
+use std::net::{IpAddr, SocketAddr};
+
+use actix_web::http::header::{HeaderName, FORWARDED};
+use actix_web::HttpRequest;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// List of reverse proxies whose `Forwarded` and `X-Forwarded-For` headers are trusted
+///
+/// Register it using [`App::app_data`](actix_web::App::app_data) to let [`ws::start`](crate::ws::start)
+/// and [`SessionMetadata`](crate::tb_middleware::SessionMetadata) resolve the client's address
+/// behind proxies like nginx.
+///
+/// Without it, only the address of the tcp peer is used.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+impl TrustedProxies {
+    /// Check whether an address belongs to a trusted proxy
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+}
+
+/// Resolve the address of the client which sent a request
+///
+/// If the request's peer is a trusted proxy, the `Forwarded` header (or `X-Forwarded-For` as fallback)
+/// is walked from right to left and the first address not belonging to a trusted proxy is returned.
+/// Headers sent by untrusted peers are ignored, as they could be spoofed.
+/// The walk stops at the first node which isn't an address, e.g. an obfuscated `for=_hidden`,
+/// and the peer's address is used, as the nodes behind it can't be trusted either.
+///
+/// Returns `None` if the peer address is unknown, e.g. in unit tests.
+pub fn real_ip(request: &HttpRequest, trusted: &TrustedProxies) -> Option<IpAddr> {
+    let peer = request.peer_addr()?.ip();
+    if !trusted.contains(&peer) {
+        return Some(peer);
+    }
+
+    let headers = request.headers();
+    // Nodes which can't be parsed are kept as `None` to stop the walk at them
+    let chain: Vec<Option<IpAddr>> = if headers.contains_key(FORWARDED) {
+        headers
+            .get_all(FORWARDED)
+            .map(|value| value.to_str().ok())
+            .flat_map(|value| match value {
+                Some(value) => value.split(',').map(forwarded_for).collect(),
+                None => vec![None],
+            })
+            .collect()
+    } else {
+        headers
+            .get_all(X_FORWARDED_FOR)
+            .map(|value| value.to_str().ok())
+            .flat_map(|value| match value {
+                Some(value) => value
+                    .split(',')
+                    .map(|node| parse_node(node.trim()))
+                    .collect(),
+                None => vec![None],
+            })
+            .collect()
+    };
+
+    for node in chain.iter().rev() {
+        match node {
+            Some(ip) if trusted.contains(ip) => continue,
+            Some(ip) => return Some(*ip),
+            None => return Some(peer),
+        }
+    }
+    // Every node is a trusted proxy
+    Some(chain.first().copied().flatten().unwrap_or(peer))
+}
+
+/// Get the address of an element of the `Forwarded` header, e.g. `for=192.0.2.60;proto=http`
+fn forwarded_for(element: &str) -> Option<IpAddr> {
+    element.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        key.eq_ignore_ascii_case("for")
+            .then(|| parse_node(value.trim_matches('"')))?
+    })
+}
+
+/// Parse a node from a forwarding header which may contain a port, e.g. `"[::1]:1234"`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'))
+                .and_then(|node| node.parse().ok())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    const PROXY: &str = "10.0.0.1";
+    const INNER_PROXY: &str = "10.0.0.2";
+
+    fn trusted() -> TrustedProxies {
+        TrustedProxies(vec![PROXY.parse().unwrap(), INNER_PROXY.parse().unwrap()])
+    }
+
+    fn resolve(peer: &str, headers: &[(&str, &str)]) -> Option<IpAddr> {
+        let mut request =
+            TestRequest::default().peer_addr(SocketAddr::new(peer.parse().unwrap(), 443));
+        for &(name, value) in headers {
+            request = request.append_header((name, value));
+        }
+        real_ip(&request.to_http_request(), &trusted())
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn ignores_headers_of_untrusted_peers() {
+        let headers = [
+            ("x-forwarded-for", "192.0.2.1"),
+            ("forwarded", "for=192.0.2.1"),
+        ];
+        assert_eq!(resolve("198.51.100.7", &headers), ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn skips_trusted_proxies() {
+        let headers = [("x-forwarded-for", "203.0.113.9, 192.0.2.1, 10.0.0.2")];
+        assert_eq!(resolve(PROXY, &headers), ip("192.0.2.1"));
+        assert_eq!(resolve(PROXY, &[]), ip(PROXY));
+    }
+
+    #[test]
+    fn chain_of_trusted_proxies() {
+        let headers = [("x-forwarded-for", "10.0.0.2, 10.0.0.1")];
+        assert_eq!(resolve(PROXY, &headers), ip(INNER_PROXY));
+    }
+
+    #[test]
+    fn stops_at_obfuscated_nodes() {
+        let headers = [("forwarded", "for=192.0.2.1, for=_hidden, for=10.0.0.2")];
+        assert_eq!(resolve(PROXY, &headers), ip(PROXY));
+        let headers = [("x-forwarded-for", "192.0.2.1, unknown")];
+        assert_eq!(resolve(PROXY, &headers), ip(PROXY));
+    }
+
+    #[test]
+    fn parses_ipv6_with_port() {
+        let headers = [("forwarded", r#"for="[2001:db8:cafe::17]:4711";proto=https"#)];
+        assert_eq!(resolve(PROXY, &headers), ip("2001:db8:cafe::17"));
+        let headers = [("x-forwarded-for", "[2001:db8:cafe::17]:4711")];
+        assert_eq!(resolve(PROXY, &headers), ip("2001:db8:cafe::17"));
+    }
+
+    #[test]
+    fn joins_multiple_forwarded_headers() {
+        let headers = [
+            ("forwarded", "for=192.0.2.1, for=198.51.100.7"),
+            ("forwarded", "for=10.0.0.2"),
+        ];
+        assert_eq!(resolve(PROXY, &headers), ip("198.51.100.7"));
+    }
+
+    #[test]
+    fn prefers_forwarded_over_x_forwarded_for() {
+        let headers = [
+            ("x-forwarded-for", "198.51.100.7"),
+            ("forwarded", "for=192.0.2.1"),
+        ];
+        assert_eq!(resolve(PROXY, &headers), ip("192.0.2.1"));
+    }
+}
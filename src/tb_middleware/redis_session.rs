@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_web::cookie::time::Duration;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, Client, RedisError, Value};
+
+use crate::tb_middleware::session_key::{
+    hash_session_key, is_hashed_session_key, SharedKeyGenerator,
+};
+use crate::tb_middleware::{
+    ImportSessions, MigratedSession, RandomSessionKey, SessionKeyGenerator,
+};
+
+/**
+[SessionStore] keeping the sessions in redis
+
+The sessions behave like the ones of [DBSessionStore](crate::tb_middleware::DBSessionStore),
+but expire through redis' own key expiry.
+This keeps the session traffic off your primary database.
+
+Like the [DBSessionStore](crate::tb_middleware::DBSessionStore), only the hashes of the
+sessions' keys are stored, so its sessions can't be migrated to another store.
+Sessions stored under their plain key by earlier versions are renamed when they are loaded.
+
+The connection is shared by all clones of the store and reconnects automatically.
+*/
+#[derive(Clone)]
+pub struct RedisSessionStore {
+    connection: ConnectionManager,
+    key_prefix: String,
+    key_generator: SharedKeyGenerator,
+}
+
+impl RedisSessionStore {
+    /// Create a new RedisSessionStore
+    ///
+    /// **Parameter**:
+    /// - `connection`: Connection to the redis server
+    pub fn new(connection: ConnectionManager) -> Self {
+        Self {
+            connection,
+            key_prefix: String::from("session:"),
+            key_generator: Arc::new(RandomSessionKey::default()),
+        }
+    }
+
+    /// Connect to a redis server
+    ///
+    /// **Parameter**:
+    /// - `url`: Url of the redis server, e.g. `redis://127.0.0.1:6379`
+    pub async fn connect(url: &str) -> Result<Self, RedisError> {
+        let client = Client::open(url)?;
+        Ok(Self::new(ConnectionManager::new(client).await?))
+    }
+
+    /// Set the prefix of the redis keys the sessions are stored under
+    ///
+    /// Defaults to `session:`
+    pub fn with_key_prefix(mut self, key_prefix: impl Into<String>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// Use a different [SessionKeyGenerator] for new sessions
+    ///
+    /// Defaults to 512 random alphanumeric characters.
+    pub fn with_key_generator(mut self, key_generator: impl SessionKeyGenerator) -> Self {
+        self.key_generator = Arc::new(key_generator);
+        self
+    }
+
+    /// The redis key a session is stored under
+    fn redis_key(&self, session_key: &str) -> String {
+        format!("{}{}", self.key_prefix, hash_session_key(session_key))
+    }
+
+    /// Move a session stored under its plain key by an earlier version to its hashed key
+    ///
+    /// Returns the session's state, `None` if there is no such session.
+    async fn rename_session(&self, session_key: &str) -> Result<Option<String>, RedisError> {
+        if is_hashed_session_key(session_key) {
+            return Ok(None);
+        }
+        let mut connection = self.connection.clone();
+        let plain_key = format!("{}{session_key}", self.key_prefix);
+        let state: Option<String> = connection.get(&plain_key).await?;
+        if state.is_some() {
+            // The key keeps its expiry.
+            // Another request may have renamed it already, which is fine.
+            let _: Result<(), RedisError> = connection
+                .rename(&plain_key, self.redis_key(session_key))
+                .await;
+        }
+        Ok(state)
+    }
+}
+
+/// Convert the session's ttl to seconds accepted by redis' `EX` option
+fn ttl_seconds(ttl: &Duration) -> usize {
+    ttl.whole_seconds().max(1) as usize
+}
+
+#[async_trait(?Send)]
+impl SessionStore for RedisSessionStore {
+    async fn load(
+        &self,
+        session_key: &SessionKey,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        let mut state: Option<String> = self
+            .connection
+            .clone()
+            .get(self.redis_key(session_key.as_ref()))
+            .await
+            .map_err(|e| LoadError::Other(anyhow!(e)))?;
+        if state.is_none() {
+            state = self
+                .rename_session(session_key.as_ref())
+                .await
+                .map_err(|e| LoadError::Other(anyhow!(e)))?;
+        }
+
+        state
+            .map(|state| serde_json::from_str(&state))
+            .transpose()
+            .map_err(|e| LoadError::Deserialization(anyhow!(e)))
+    }
+
+    async fn save(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, SaveError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|e| SaveError::Serialization(anyhow!(e)))?;
+        let mut connection = self.connection.clone();
+
+        let mut session_key;
+        loop {
+            session_key = self.key_generator.generate();
+
+            // NX only sets the value if the key isn't taken yet
+            let created: Value = redis::cmd("SET")
+                .arg(self.redis_key(&session_key))
+                .arg(&state)
+                .arg("NX")
+                .arg("EX")
+                .arg(ttl_seconds(ttl))
+                .query_async(&mut connection)
+                .await
+                .map_err(|e| SaveError::Other(anyhow!(e)))?;
+
+            if created != Value::Nil {
+                break;
+            }
+        }
+
+        SessionKey::try_from(session_key).map_err(|e| SaveError::Other(anyhow!(e)))
+    }
+
+    async fn update(
+        &self,
+        session_key: SessionKey,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionKey, UpdateError> {
+        let state = serde_json::to_string(&session_state)
+            .map_err(|e| UpdateError::Serialization(anyhow!(e)))?;
+
+        // XX only updates sessions which still exist
+        redis::cmd("SET")
+            .arg(self.redis_key(session_key.as_ref()))
+            .arg(state)
+            .arg("XX")
+            .arg("EX")
+            .arg(ttl_seconds(ttl))
+            .query_async::<_, Value>(&mut self.connection.clone())
+            .await
+            .map_err(|e| UpdateError::Other(anyhow!(e)))?;
+
+        Ok(session_key)
+    }
+
+    async fn update_ttl(
+        &self,
+        session_key: &SessionKey,
+        ttl: &Duration,
+    ) -> Result<(), anyhow::Error> {
+        self.connection
+            .clone()
+            .expire::<_, ()>(self.redis_key(session_key.as_ref()), ttl_seconds(ttl))
+            .await
+            .map_err(|e| anyhow!(e))?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
+        self.connection
+            .clone()
+            .del::<_, ()>(self.redis_key(session_key.as_ref()))
+            .await
+            .map_err(|e| anyhow!(e))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl ImportSessions for RedisSessionStore {
+    async fn import_session(&self, session: MigratedSession) -> Result<(), anyhow::Error> {
+        let state = serde_json::to_string(&session.session_state)?;
+        self.connection
+            .clone()
+            .set_ex::<_, _, ()>(
+                self.redis_key(&session.session_key),
+                state,
+                ttl_seconds(&session.ttl),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
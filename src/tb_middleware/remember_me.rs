@@ -0,0 +1,436 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_session::SessionExt;
+use actix_web::body::MessageBody;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use chrono::{DateTime, Duration, Utc};
+use futures::future::LocalBoxFuture;
+use log::warn;
+use rand::distributions::{Alphanumeric, DistString};
+use rorm::{and, delete, insert, query, update, FieldAccess, Model};
+use sha2::{Digest, Sha256};
+
+use crate::clock::{Clock, SharedClock};
+use crate::tb_middleware::{set_session_user, SESSION_USER_ID};
+
+/// Length of the random series identifying a remembered login
+const SERIES_LEN: usize = 32;
+
+/// Length of the random token authenticating a remembered login
+const TOKEN_LEN: usize = 64;
+
+/// Seconds the previous token of a series stays valid after it was rotated
+///
+/// Browsers often send multiple requests at once after the session expired,
+/// which would all use the same token.
+const ROTATION_GRACE_SECS: i64 = 60;
+
+/**
+DB representation of a remembered login.
+*/
+#[derive(Model, Debug, Clone)]
+pub struct DBRememberMe {
+    /// Random id of the login, stored in the user's cookie
+    #[rorm(primary_key)]
+    #[rorm(max_length = 255)]
+    pub series: String,
+
+    /// Hex encoded SHA-256 hash of the current token, stored in the user's cookie
+    ///
+    /// The token is replaced every time it is used.
+    #[rorm(max_length = 64)]
+    pub token_hash: String,
+
+    /// Hex encoded SHA-256 hash of the token used before the current one
+    #[rorm(max_length = 64)]
+    pub previous_token_hash: Option<String>,
+
+    /// Id of the user which is logged in again
+    #[rorm(max_length = 255)]
+    #[rorm(index)]
+    pub user_id: String,
+
+    /// DateTime the login was remembered at
+    pub created_at: DateTime<Utc>,
+
+    /// DateTime the token was last used to log the user in
+    pub last_used: DateTime<Utc>,
+
+    /// DateTime after which the login is forgotten
+    pub expired_after: DateTime<Utc>,
+}
+
+/// Hash a token before it is stored in or compared with the [DBRememberMe] table
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// User logged in again by [RememberMeStore::authenticate]
+#[derive(Debug, Clone)]
+pub struct RememberedUser {
+    /// Id of the user
+    pub user_id: String,
+
+    /// Cookie containing the rotated token, which has to be sent to the client
+    ///
+    /// `None` if a concurrent request already rotated the token.
+    pub cookie: Option<Cookie<'static>>,
+}
+
+/**
+Remembers logins for longer than a session lives, the classic "remember me" checkbox
+
+A remembered login is a random series with a token, both stored in a long living cookie.
+Only the hash of the token is stored in the database and it is replaced every time
+it is used, so a stolen cookie is noticed once both the thief and the user have used it.
+All logins of the user are forgotten in that case.
+
+Remember the login after the user logged in and forget it when they log out:
+
+```no_run
+use actix_session::Session;
+use actix_toolbox::tb_middleware::{set_session_user, RememberMeStore};
+use actix_web::error::ErrorInternalServerError;
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse};
+
+async fn login(
+    session: Session,
+    remember_me: Data<RememberMeStore>,
+) -> actix_web::Result<HttpResponse> {
+    // Check the user's credentials
+    let user_id = "42";
+    set_session_user(&session, user_id)?;
+    let cookie = remember_me
+        .issue(user_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().cookie(cookie).finish())
+}
+
+async fn logout(
+    request: HttpRequest,
+    session: Session,
+    remember_me: Data<RememberMeStore>,
+) -> actix_web::Result<HttpResponse> {
+    session.purge();
+    if let Some(cookie) = request.cookie(remember_me.cookie_name()) {
+        remember_me
+            .revoke(cookie.value())
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+    Ok(HttpResponse::Ok()
+        .cookie(remember_me.removal_cookie())
+        .finish())
+}
+```
+
+The [RememberMe] middleware logs the user in again once their session expired.
+*/
+#[derive(Clone)]
+pub struct RememberMeStore {
+    db: rorm::Database,
+    clock: SharedClock,
+    lifetime: Duration,
+    cookie_name: String,
+    secure: bool,
+}
+
+impl RememberMeStore {
+    /// Create a new RememberMeStore
+    ///
+    /// **Parameter**:
+    /// - `db`: Instance of a connected database
+    pub fn new(db: rorm::Database) -> Self {
+        Self {
+            db,
+            clock: SharedClock::default(),
+            lifetime: Duration::days(30),
+            cookie_name: String::from("remember_me"),
+            secure: true,
+        }
+    }
+
+    /// Use a different [Clock] to calculate the logins' expiry
+    ///
+    /// Defaults to the [SystemClock](crate::clock::SystemClock).
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Set the time a login is remembered after it was last used
+    ///
+    /// Defaults to 30 days
+    pub fn with_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Set the name of the cookie
+    ///
+    /// Defaults to `remember_me`
+    pub fn with_cookie_name(mut self, cookie_name: impl Into<String>) -> Self {
+        self.cookie_name = cookie_name.into();
+        self
+    }
+
+    /// Send the cookie over plain http
+    ///
+    /// Only use this for local development.
+    pub fn insecure(mut self) -> Self {
+        self.secure = false;
+        self
+    }
+
+    /// Get the name of the cookie
+    pub fn cookie_name(&self) -> &str {
+        &self.cookie_name
+    }
+
+    /// Build the cookie storing a remembered login
+    fn cookie(&self, series: &str, token: &str) -> Cookie<'static> {
+        Cookie::build(self.cookie_name.clone(), format!("{series}:{token}"))
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+            .max_age(actix_web::cookie::time::Duration::seconds(
+                self.lifetime.num_seconds(),
+            ))
+            .finish()
+    }
+
+    /// Build a cookie removing the remembered login from the client
+    pub fn removal_cookie(&self) -> Cookie<'static> {
+        let mut cookie = self.cookie("", "");
+        cookie.make_removal();
+        cookie
+    }
+
+    /// Remember a user's login
+    ///
+    /// Returns the cookie which has to be sent to the client.
+    pub async fn issue(&self, user_id: impl Into<String>) -> Result<Cookie<'static>, rorm::Error> {
+        let now = self.clock.now();
+
+        // Remove the logins which have been abandoned
+        delete!(&self.db, DBRememberMe)
+            .condition(DBRememberMe::F.expired_after.less_than(now))
+            .await?;
+
+        let mut rng = rand::thread_rng();
+        let series = Alphanumeric.sample_string(&mut rng, SERIES_LEN);
+        let token = Alphanumeric.sample_string(&mut rng, TOKEN_LEN);
+        let row = DBRememberMe {
+            series: series.clone(),
+            token_hash: hash_token(&token),
+            previous_token_hash: None,
+            user_id: user_id.into(),
+            created_at: now,
+            last_used: now,
+            expired_after: now + self.lifetime,
+        };
+        insert!(&self.db, DBRememberMe).single(&row).await?;
+
+        Ok(self.cookie(&series, &token))
+    }
+
+    /// Check the value of a remembered login's cookie and rotate its token
+    ///
+    /// Returns `None` if the login is unknown, expired or its token doesn't match.
+    /// A token which doesn't match is treated as stolen and all logins of the user are forgotten.
+    pub async fn authenticate(
+        &self,
+        cookie_value: &str,
+    ) -> Result<Option<RememberedUser>, rorm::Error> {
+        let now = self.clock.now();
+        let Some((series, token)) = cookie_value.split_once(':') else {
+            return Ok(None);
+        };
+
+        let Some(row) = query!(&self.db, DBRememberMe)
+            .condition(DBRememberMe::F.series.equals(series))
+            .optional()
+            .await?
+        else {
+            return Ok(None);
+        };
+        if row.expired_after < now {
+            self.revoke(cookie_value).await?;
+            return Ok(None);
+        }
+
+        let token_hash = hash_token(token);
+        if token_hash != row.token_hash {
+            if row.previous_token_hash.as_ref() == Some(&token_hash)
+                && now < row.last_used + Duration::seconds(ROTATION_GRACE_SECS)
+            {
+                return Ok(Some(RememberedUser {
+                    user_id: row.user_id,
+                    cookie: None,
+                }));
+            }
+
+            warn!("A stolen remember me token was used, forgetting the user's logins");
+            self.revoke_all_for_user(&row.user_id).await?;
+            return Ok(None);
+        }
+
+        let token = Alphanumeric.sample_string(&mut rand::thread_rng(), TOKEN_LEN);
+        let rotated = update!(&self.db, DBRememberMe)
+            .condition(and!(
+                DBRememberMe::F.series.equals(series),
+                DBRememberMe::F.token_hash.equals(&token_hash),
+            ))
+            .set(DBRememberMe::F.token_hash, hash_token(&token))
+            .set(
+                DBRememberMe::F.previous_token_hash,
+                Some(token_hash.clone()),
+            )
+            .set(DBRememberMe::F.last_used, now)
+            .set(DBRememberMe::F.expired_after, now + self.lifetime)
+            .exec()
+            .await?;
+        if rotated == 0 {
+            // A concurrent request rotated the token first, it sends the new one to the client
+            return Ok(Some(RememberedUser {
+                user_id: row.user_id,
+                cookie: None,
+            }));
+        }
+
+        Ok(Some(RememberedUser {
+            user_id: row.user_id,
+            cookie: Some(self.cookie(series, &token)),
+        }))
+    }
+
+    /// Forget the login of a cookie, e.g. when the user logs out
+    pub async fn revoke(&self, cookie_value: &str) -> Result<(), rorm::Error> {
+        let series = cookie_value
+            .split_once(':')
+            .map_or(cookie_value, |(series, _)| series);
+        delete!(&self.db, DBRememberMe)
+            .condition(DBRememberMe::F.series.equals(series))
+            .await?;
+        Ok(())
+    }
+
+    /// Forget all logins of a user, e.g. when they change their password
+    pub async fn revoke_all_for_user(&self, user_id: &str) -> Result<(), rorm::Error> {
+        delete!(&self.db, DBRememberMe)
+            .condition(DBRememberMe::F.user_id.equals(user_id))
+            .await?;
+        Ok(())
+    }
+}
+
+/**
+Middleware logging users in again using their [RememberMeStore] cookie
+
+If the session isn't associated with a user, but the request has a remembered login,
+the session is renewed and associated with the user using [set_session_user].
+Invalid cookies are removed.
+
+It has to be wrapped before the [SessionMiddleware](crate::tb_middleware::SessionMiddleware),
+so that it runs inside of it:
+
+```no_run
+use actix_toolbox::tb_middleware::{DBSessionStore, RememberMe, RememberMeStore, SessionMiddleware};
+use actix_web::cookie::Key;
+use actix_web::App;
+
+# fn build(store: DBSessionStore, remember_me: RememberMeStore, key: Key) {
+let app = App::new()
+    .wrap(RememberMe::new(remember_me))
+    .wrap(SessionMiddleware::new(store, key));
+# }
+```
+*/
+#[derive(Clone)]
+pub struct RememberMe {
+    store: RememberMeStore,
+}
+
+impl RememberMe {
+    /// Create the middleware
+    pub fn new(store: RememberMeStore) -> Self {
+        Self { store }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RememberMe
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RememberMeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RememberMeMiddleware {
+            service: Rc::new(service),
+            store: self.store.clone(),
+        }))
+    }
+}
+
+/// Service created by [RememberMe]
+pub struct RememberMeMiddleware<S> {
+    service: Rc<S>,
+    store: RememberMeStore,
+}
+
+impl<S, B> Service<ServiceRequest> for RememberMeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let store = self.store.clone();
+
+        Box::pin(async move {
+            let session = req.get_session();
+            let mut set_cookie = None;
+
+            let logged_in = session
+                .get::<String>(SESSION_USER_ID)
+                .ok()
+                .flatten()
+                .is_some();
+            if let Some(cookie) = req.cookie(&store.cookie_name).filter(|_| !logged_in) {
+                match store.authenticate(cookie.value()).await {
+                    Ok(Some(user)) => {
+                        // Use a new session key for the new login
+                        session.renew();
+                        set_session_user(&session, user.user_id)?;
+                        set_cookie = user.cookie;
+                    }
+                    Ok(None) => set_cookie = Some(store.removal_cookie()),
+                    Err(err) => warn!("Couldn't check the remember me cookie: {err}"),
+                }
+            }
+
+            let mut res = service.call(req).await?;
+            if let Some(cookie) = set_cookie {
+                res.response_mut().add_cookie(&cookie)?;
+            }
+            Ok(res)
+        })
+    }
+}
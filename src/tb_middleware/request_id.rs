@@ -0,0 +1,99 @@
+use std::fmt;
+use std::future::{ready, Ready};
+use std::sync::Arc;
+
+use actix_web::dev::Payload;
+use actix_web::http::header::HeaderName;
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+use uuid::Uuid;
+
+pub(crate) const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+
+/// Maximum length of request ids taken from the request's headers
+///
+/// Longer ids are ignored, so clients can't bloat the log with them.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Id of a http request
+///
+/// The [SetRequestId](crate::tb_middleware::SetRequestId) middleware and other middlewares
+/// generating request ids insert it into the request's [extensions](HttpMessage::extensions)
+/// to let [request_id] pick it up.
+///
+/// It can be used as extractor, which generates a new id if the request has none.
+///
+/// Cheap to clone
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RequestId(Arc<str>);
+
+impl RequestId {
+    /// Wrap an id
+    pub fn new(id: impl AsRef<str>) -> Self {
+        Self(Arc::from(id.as_ref()))
+    }
+
+    /// Generate a new random id
+    pub fn generate() -> Self {
+        Self::new(Uuid::new_v4().to_string())
+    }
+
+    /// Get the id as string
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for RequestId {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let id = request_id(req).unwrap_or_else(|| {
+            // Store the generated id, so everything asking for it later gets the same one
+            let id = RequestId::generate();
+            req.extensions_mut().insert(id.clone());
+            id
+        });
+        ready(Ok(id))
+    }
+}
+
+/// Get the id of a request
+///
+/// The id is looked up in this order:
+/// 1. a [RequestId] in the request's extensions
+/// 2. the `X-Request-Id` header
+/// 3. the trace id of the W3C `traceparent` header
+///
+/// Ids from headers longer than 128 characters are ignored.
+///
+/// Returns `None` if neither is present.
+pub fn request_id(request: &HttpRequest) -> Option<RequestId> {
+    if let Some(id) = request.extensions().get::<RequestId>() {
+        return Some(id.clone());
+    }
+
+    let headers = request.headers();
+    if let Some(id) = headers
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+    {
+        return Some(RequestId::new(id));
+    }
+
+    // traceparent: {version}-{trace-id}-{parent-id}-{flags}
+    headers
+        .get(TRACEPARENT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split('-').nth(1))
+        .filter(|trace_id| !trace_id.is_empty() && trace_id.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(RequestId::new)
+}
@@ -0,0 +1,95 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::time::Instant;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::LocalBoxFuture;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+/**
+Middleware creating a `tracing` span for every request
+
+The span is named `request` and has the fields:
+- `method`: the request's method
+- `path`: the request's path
+- `status`: the response's status code
+- `latency_ms`: the milliseconds it took to create the response, without sending its body
+
+Everything the handler logs happens inside of it, so its events can be correlated to the request.
+An event is emitted when the response is created, after `status` and `latency_ms` were recorded.
+
+Set up a subscriber using [setup_tracing](crate::tracing::setup_tracing) to write them.
+
+```no_run
+use actix_toolbox::tb_middleware::RequestTracing;
+use actix_web::App;
+
+let app = App::new().wrap(RequestTracing);
+```
+*/
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// Service created by [RequestTracing]
+pub struct RequestTracingMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let span = tracing::info_span!(
+            "request",
+            method = %req.method(),
+            path = req.path(),
+            status = Empty,
+            latency_ms = Empty,
+        );
+        let start = Instant::now();
+        // Services may already do some work when called, before their future is polled
+        let fut = span.in_scope(|| self.service.call(req));
+
+        Box::pin(async move {
+            let result = fut.instrument(span.clone()).await;
+
+            let status = match &result {
+                Ok(response) => response.status(),
+                Err(err) => err.as_response_error().status_code(),
+            };
+            span.record("status", status.as_u16());
+            span.record("latency_ms", start.elapsed().as_secs_f64() * 1000.0);
+            tracing::info!(parent: &span, "Finished request");
+
+            result
+        })
+    }
+}
@@ -1,76 +1,1826 @@
-use std::collections::HashMap;
-use std::ops::Add;
+use std::collections::{BTreeMap, HashMap};
+use std::future::IntoFuture;
+#[cfg(feature = "session-compression")]
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Instant;
 
 pub use actix_session;
 pub use actix_session::config::PersistentSession;
+use actix_session::config::{BrowserSession, SessionMiddlewareBuilder, TtlExtensionPolicy};
 use actix_session::storage::{LoadError, SaveError, SessionKey, SessionStore, UpdateError};
+use actix_session::SessionInsertError;
 pub use actix_session::{Session, SessionMiddleware};
 use actix_web::cookie::time::Duration;
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::HttpRequest;
 use anyhow::anyhow;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use rand::distributions::{Alphanumeric, DistString};
-use rorm::{delete, insert, query, update, FieldAccess, Model};
+#[cfg(feature = "session-compression")]
+use flate2::read::DeflateDecoder;
+#[cfg(feature = "session-compression")]
+use flate2::write::DeflateEncoder;
+#[cfg(feature = "session-compression")]
+use flate2::Compression;
+use log::warn;
+use rorm::db::transaction::Transaction;
+use rorm::prelude::{ForeignModel, ForeignModelByField};
+use rorm::{delete, insert, query, update, FieldAccess, Model, Patch};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::{Clock, SharedClock};
+pub use crate::encryption::SessionEncryptionKey;
+use crate::tb_middleware::session_cache::SessionCache;
+use crate::tb_middleware::session_key::{
+    hash_session_key, is_hashed_session_key, SharedKeyGenerator,
+};
+use crate::tb_middleware::session_presence::PresenceTracker;
+use crate::tb_middleware::session_version;
+use crate::tb_middleware::{
+    ImportSessions, MigratedSession, RandomSessionKey, SessionDatabaseError, SessionKeyGenerator,
+    SessionMetrics, SessionModel, SessionPresence, SessionRetry, SessionVersions,
+};
+
+/**
+DB representation of a session.
+*/
+#[derive(Model, Debug, Clone)]
+pub struct DBSession {
+    /// Hex encoded SHA-256 hash of the session's key
+    ///
+    /// Only the user's cookie contains the key itself,
+    /// so the sessions can't be hijacked using a leaked copy of this table.
+    ///
+    /// Sessions stored under their plain key by earlier versions are moved
+    /// to the hashed one when they are loaded the next time.
+    #[rorm(primary_key)]
+    #[rorm(max_length = 4096)]
+    pub session_key: String,
+
+    /// State of the session. json encoded HashMap<String, String>
+    ///
+    /// If the store has encryption keys, the json is encrypted and base64 encoded.
+    /// Unset if the store uses a binary [SessionStateFormat], [SessionStateColumn::Binary]
+    /// or [DBSessionEntry] rows.
+    #[rorm(max_length = 16383)]
+    pub session_state: Option<String>,
+
+    /// State of the session in a binary [SessionStateFormat]
+    ///
+    /// The first byte identifies the format, the rest is the encoded and optionally encrypted state.
+    /// Unset if the store uses [SessionStateFormat::Json] with [SessionStateColumn::Text]
+    /// or [DBSessionEntry] rows.
+    pub session_data: Option<Vec<u8>>,
+
+    /// DateTime after the session will be invalid
+    pub expired_after: DateTime<Utc>,
+
+    /// Id of the user the session belongs to
+    ///
+    /// This is set using [set_session_user].
+    #[rorm(max_length = 255)]
+    #[rorm(index)]
+    pub user_id: Option<String>,
+
+    /// DateTime the session was created at
+    pub created_at: DateTime<Utc>,
+
+    /// DateTime the session was last persisted at
+    ///
+    /// The [SessionMiddleware] only persists sessions whose state changed,
+    /// unless its ttl is extended on every request.
+    pub last_accessed: DateTime<Utc>,
+
+    /// Address of the client which used the session last
+    ///
+    /// This is recorded by the [SessionMetadata](crate::tb_middleware::SessionMetadata) middleware.
+    #[rorm(max_length = 255)]
+    pub client_ip: Option<String>,
+
+    /// User agent of the client which used the session last
+    ///
+    /// This is recorded by the [SessionMetadata](crate::tb_middleware::SessionMetadata) middleware.
+    #[rorm(max_length = 1024)]
+    pub user_agent: Option<String>,
+
+    /// Random value replaced on every update of the session's state
+    ///
+    /// Used to detect concurrent updates, see [SessionConflictStrategy].
+    #[rorm(default = 0)]
+    pub version: i64,
+}
+
+/**
+Single entry of a session's state
+
+Used instead of [DBSession::session_state] if the store is configured
+using [DBSessionStore::with_entry_rows].
+The entries are deleted together with their session.
+*/
+#[derive(Model, Debug, Clone)]
+pub struct DBSessionEntry {
+    /// Primary key of the entry
+    #[rorm(id)]
+    pub id: i64,
+
+    /// The session the entry belongs to
+    #[rorm(on_delete = "Cascade")]
+    pub session: ForeignModel<DBSession>,
+
+    /// Key of the entry in the session's state
+    #[rorm(max_length = 255)]
+    pub entry_key: String,
+
+    /// Json encoded value of the entry
+    ///
+    /// If the store has encryption keys, the json is encrypted.
+    pub value: Vec<u8>,
+}
+
+#[derive(Patch)]
+#[rorm(model = "DBSessionEntry")]
+struct NewSessionEntry {
+    session: ForeignModel<DBSession>,
+    entry_key: String,
+    value: Vec<u8>,
+}
+
+/// Key in the session's state [set_session_user] stores the user's id under
+pub const SESSION_USER_ID: &str = "session_user_id";
+
+/// Key in the session's state [SessionMetadata](crate::tb_middleware::SessionMetadata)
+/// stores the client's address under
+pub const SESSION_CLIENT_IP: &str = "session_client_ip";
+
+/// Key in the session's state [SessionMetadata](crate::tb_middleware::SessionMetadata)
+/// stores the client's user agent under
+pub const SESSION_USER_AGENT: &str = "session_user_agent";
+
+/**
+Associate a session with a user
+
+The id is persisted with the session by the [DBSessionStore],
+so the user's sessions can be listed using [DBSessionStore::sessions_for_user]
+and revoked using [DBSessionStore::revoke_all_for_user].
+
+Call it after the user logged in, e.g. from the oidc login hook:
+
+```no_run
+use actix_session::SessionExt;
+use actix_toolbox::oidc::PostLoginHook;
+use actix_toolbox::tb_middleware::set_session_user;
+use actix_web::error::ErrorInternalServerError;
+
+let hook = PostLoginHook::new(|request, claims| {
+    Box::pin(async move {
+        set_session_user(&request.get_session(), claims.subject).map_err(ErrorInternalServerError)
+    })
+});
+```
+*/
+pub fn set_session_user(
+    session: &Session,
+    user_id: impl Into<String>,
+) -> Result<(), SessionInsertError> {
+    session.insert(SESSION_USER_ID, user_id.into())
+}
+
+/// Add a session's ttl to `now`
+///
+/// Fails if the ttl is too large for a [chrono::Duration] or the result overflows.
+fn expiry(now: DateTime<Utc>, ttl: &Duration) -> Result<DateTime<Utc>, anyhow::Error> {
+    i64::try_from(ttl.whole_nanoseconds())
+        .ok()
+        .and_then(|nanos| now.checked_add_signed(chrono::Duration::nanoseconds(nanos)))
+        .ok_or_else(|| anyhow!("The session's ttl of {ttl} is too large"))
+}
+
+/// Get a string stored under `key` from a session's state
+///
+/// Used for the values persisted in their own columns, like the one stored by [set_session_user].
+fn state_string(session_state: &HashMap<String, String>, key: &str) -> Option<String> {
+    session_state
+        .get(key)
+        .and_then(|value| serde_json::from_str(value).ok())
+}
+
+/**
+Format the [DBSessionStore] serializes the sessions' state with
+
+Json is stored as text in [DBSession::session_state], unless [SessionStateColumn::Binary] is used,
+the binary formats are stored in [DBSession::session_data].
+They produce smaller rows and are faster to parse for large sessions.
+
+Sessions are loaded regardless of the format they were stored with,
+so the format can be changed without logging out every user.
+
+In configs it is written as `"json"`, `"message_pack"` or `"cbor"`, if the feature of the format is enabled.
+*/
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStateFormat {
+    /// Json, readable when inspecting the database
+    #[default]
+    Json,
+
+    /// [MessagePack](https://msgpack.org)
+    #[cfg(feature = "session-msgpack")]
+    MessagePack,
+
+    /// [CBOR](https://cbor.io)
+    #[cfg(feature = "session-cbor")]
+    Cbor,
+}
+
+impl SessionStateFormat {
+    /// Tag identifying json in the first byte of [DBSession::session_data]
+    ///
+    /// Json is only stored there if it's compressed or [SessionStateColumn::Binary] is used.
+    const JSON_TAG: u8 = 0;
+
+    /// Tag identifying [SessionStateFormat::MessagePack] in the first byte of [DBSession::session_data]
+    #[cfg(feature = "session-msgpack")]
+    const MESSAGE_PACK_TAG: u8 = 1;
+
+    /// Tag identifying [SessionStateFormat::Cbor] in the first byte of [DBSession::session_data]
+    #[cfg(feature = "session-cbor")]
+    const CBOR_TAG: u8 = 2;
+
+    /// Flag set in the tag if the state is compressed
+    #[cfg(feature = "session-compression")]
+    const COMPRESSED: u8 = 0x80;
+
+    /// Serialize a session's state
+    ///
+    /// Returns the tag identifying the format and the serialized state.
+    fn encode(
+        self,
+        session_state: &HashMap<String, String>,
+    ) -> Result<(u8, Vec<u8>), anyhow::Error> {
+        Ok(match self {
+            SessionStateFormat::Json => (Self::JSON_TAG, serde_json::to_vec(session_state)?),
+            #[cfg(feature = "session-msgpack")]
+            SessionStateFormat::MessagePack => {
+                (Self::MESSAGE_PACK_TAG, rmp_serde::to_vec(session_state)?)
+            }
+            #[cfg(feature = "session-cbor")]
+            SessionStateFormat::Cbor => {
+                let mut data = Vec::new();
+                ciborium::ser::into_writer(session_state, &mut data)?;
+                (Self::CBOR_TAG, data)
+            }
+        })
+    }
+
+    /// Deserialize a session's state serialized by [SessionStateFormat::encode]
+    ///
+    /// Compressed states are decompressed first.
+    fn decode(tag: u8, data: &[u8]) -> Result<HashMap<String, String>, anyhow::Error> {
+        #[cfg(feature = "session-compression")]
+        if tag & Self::COMPRESSED != 0 {
+            let mut decompressed = Vec::new();
+            DeflateDecoder::new(data).read_to_end(&mut decompressed)?;
+            return Self::decode(tag & !Self::COMPRESSED, &decompressed);
+        }
+
+        match tag {
+            Self::JSON_TAG => Ok(serde_json::from_slice(data)?),
+            #[cfg(feature = "session-msgpack")]
+            Self::MESSAGE_PACK_TAG => Ok(rmp_serde::from_slice(data)?),
+            #[cfg(feature = "session-cbor")]
+            Self::CBOR_TAG => Ok(ciborium::de::from_reader(data)?),
+            _ => Err(anyhow!(
+                "The session's state uses the unknown or disabled format {tag}"
+            )),
+        }
+    }
+}
+
+/**
+Column the [DBSessionStore] stores the sessions' state in
+
+[DBSession::session_state] is a text column limited to 16383 characters,
+while [DBSession::session_data] is a binary column without a limit on most databases,
+e.g. `bytea` on Postgres.
+Large sessions, like ones caching a user's permissions, should use the binary column.
+
+Both columns are part of [DBSession] and sessions are loaded from either of them,
+so the column can be changed without logging out every user.
+If your migrations were generated before [DBSession::session_data] existed,
+generate a new one using `rorm-cli make-migrations` and apply it using `rorm-cli migrate`.
+
+In configs it is written as `"text"` or `"binary"`.
+*/
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionStateColumn {
+    /// [DBSession::session_state], used for [SessionStateFormat::Json]
+    ///
+    /// The binary formats are always stored in [DBSession::session_data].
+    #[default]
+    Text,
+
+    /// [DBSession::session_data], used for every [SessionStateFormat]
+    Binary,
+}
+
+/**
+How the [DBSessionStore] handles a session which was changed by another request
+since the request updating it loaded it
+
+This happens if a user sends multiple requests at once, e.g. from multiple tabs.
+The [DBSession::version] is used to detect it.
+The strategies other than [SessionConflictStrategy::LastWriteWins] require
+the [SessionVersions] middleware, which remembers the versions the request loaded.
+
+In configs it is written as `"last_write_wins"`, `"merge"` or `"error"`.
+*/
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionConflictStrategy {
+    /// Overwrite the other request's changes
+    #[default]
+    LastWriteWins,
+
+    /// Add the state of the updating request to the current one and try again
+    ///
+    /// Entries set by either request are kept, the updating request's values win.
+    /// Entries only removed by the updating request are kept as well,
+    /// so don't use it if removing entries matters, e.g. when logging users out.
+    Merge,
+
+    /// Fail the update with a [SessionConflict] error
+    Error,
+}
+
+/// Number of attempts of [SessionConflictStrategy::Merge] before failing with a [SessionConflict]
+const MERGE_ATTEMPTS: u32 = 3;
+
+/// Error updating a session which was changed by another request since it was loaded
+///
+/// It is wrapped in the [UpdateError::Other] returned by the store.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SessionConflict;
+
+impl std::fmt::Display for SessionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The session was changed by another request")
+    }
+}
+impl std::error::Error for SessionConflict {}
+
+/// A session's state as it was loaded from the database
+struct LoadedSession {
+    state: HashMap<String, String>,
+    version: i64,
+    expired_after: DateTime<Utc>,
+    user_id: Option<String>,
+}
+
+/// A session's state as it is stored in the [DBSession]'s columns
+struct StoredState {
+    session_state: Option<String>,
+    session_data: Option<Vec<u8>>,
+}
+
+impl StoredState {
+    /// Number of bytes stored
+    fn size(&self) -> usize {
+        self.session_state.as_ref().map_or(0, String::len)
+            + self.session_data.as_ref().map_or(0, Vec::len)
+    }
+}
+
+/// Default of [DBSessionStore::with_max_state_size] for [SessionStateColumn::Text],
+/// the length of [DBSession::session_state]
+const MAX_STATE_SIZE: usize = 16383;
+
+/// Maximum number of sessions passed to a single [SessionModel::touch]
+const PRESENCE_BATCH_SIZE: usize = 100;
+
+/// Error saving a session whose state exceeds [DBSessionStore::with_max_state_size]
+///
+/// It is wrapped in the [SaveError::Serialization] and [UpdateError::Serialization] returned by the store.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct SessionStateTooLarge {
+    /// Number of bytes the state would have taken
+    pub size: usize,
+    /// Maximum number of bytes allowed
+    pub max_size: usize,
+}
+
+impl std::fmt::Display for SessionStateTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The session's state takes {} bytes, but only {} bytes are allowed",
+            self.size, self.max_size
+        )
+    }
+}
+impl std::error::Error for SessionStateTooLarge {}
+
+/// Associated data binding an encrypted [DBSessionEntry] to its session and key
+///
+/// The hashed key has a fixed length, so the entry key can simply be appended.
+fn entry_aad(hashed_key: &str, entry_key: &str) -> Vec<u8> {
+    [hashed_key.as_bytes(), entry_key.as_bytes()].concat()
+}
+
+/**
+Wrapper for a instance of [rorm::Database].
+
+The sessions are stored in the table of the [SessionModel], which defaults to [DBSession].
+*/
+pub struct DBSessionStore<M: SessionModel = DBSession> {
+    db: rorm::Database,
+    clock: SharedClock,
+    key_generator: SharedKeyGenerator,
+    encryption_keys: Arc<Vec<SessionEncryptionKey>>,
+    state_format: SessionStateFormat,
+    state_column: SessionStateColumn,
+    cache: Option<Arc<SessionCache>>,
+    presence: Option<Arc<PresenceTracker>>,
+    retry: SessionRetry,
+    metrics: SessionMetrics,
+    max_state_size: Option<usize>,
+    #[cfg(feature = "session-compression")]
+    compress_large_states: bool,
+    entry_rows: bool,
+    conflict_strategy: SessionConflictStrategy,
+    model: PhantomData<fn() -> M>,
+}
+
+// Not derived, as that would require the model to implement Clone
+impl<M: SessionModel> Clone for DBSessionStore<M> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            clock: self.clock.clone(),
+            key_generator: self.key_generator.clone(),
+            encryption_keys: self.encryption_keys.clone(),
+            state_format: self.state_format,
+            state_column: self.state_column,
+            cache: self.cache.clone(),
+            presence: self.presence.clone(),
+            retry: self.retry.clone(),
+            metrics: self.metrics.clone(),
+            max_state_size: self.max_state_size,
+            #[cfg(feature = "session-compression")]
+            compress_large_states: self.compress_large_states,
+            entry_rows: self.entry_rows,
+            conflict_strategy: self.conflict_strategy,
+            model: PhantomData,
+        }
+    }
+}
+
+impl DBSessionStore {
+    /// Create a new DBSessionStore
+    ///
+    /// **Parameter**:
+    /// - `db`: Instance of a connected database
+    pub fn new(db: rorm::Database) -> Self {
+        Self::with_model(db)
+    }
+
+    /// Store each entry of the sessions' state in its own [DBSessionEntry] row
+    ///
+    /// Updating a session only writes the entries which changed instead of its whole state.
+    /// The entries aren't limited by the [maximum state size](DBSessionStore::with_max_state_size)
+    /// and their values are always stored as json, regardless of the [SessionStateFormat].
+    ///
+    /// Sessions are loaded regardless of how their state was stored,
+    /// so this can be enabled without logging out every user.
+    ///
+    /// The entries reference the [DBSession] table, so this isn't available for other [SessionModel]s.
+    pub fn with_entry_rows(mut self) -> Self {
+        self.entry_rows = true;
+        self
+    }
+}
+
+impl<M: SessionModel> DBSessionStore<M> {
+    /// Create a new DBSessionStore keeping the sessions in the table of a custom [SessionModel]
+    ///
+    /// **Parameter**:
+    /// - `db`: Instance of a connected database
+    pub fn with_model(db: rorm::Database) -> Self {
+        Self {
+            db,
+            clock: SharedClock::default(),
+            key_generator: Arc::new(RandomSessionKey::default()),
+            encryption_keys: Arc::new(Vec::new()),
+            state_format: SessionStateFormat::default(),
+            state_column: SessionStateColumn::default(),
+            cache: None,
+            presence: None,
+            retry: SessionRetry::default(),
+            metrics: SessionMetrics::default(),
+            max_state_size: None,
+            #[cfg(feature = "session-compression")]
+            compress_large_states: false,
+            entry_rows: false,
+            conflict_strategy: SessionConflictStrategy::default(),
+            model: PhantomData,
+        }
+    }
+
+    /// Use a different [Clock] to calculate the sessions' expiry
+    ///
+    /// Defaults to the [SystemClock](crate::clock::SystemClock).
+    pub fn with_clock(mut self, clock: impl Clock) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Use a different [SessionKeyGenerator] for new sessions
+    ///
+    /// Defaults to 512 random alphanumeric characters.
+    pub fn with_key_generator(mut self, key_generator: impl SessionKeyGenerator) -> Self {
+        self.key_generator = Arc::new(key_generator);
+        self
+    }
+
+    /// Encrypt the sessions' state before storing it
+    ///
+    /// New states are encrypted using the first key,
+    /// while states encrypted using any of the keys can be loaded.
+    /// The values stored in their own columns, like [DBSession::user_id], stay unencrypted.
+    /// A state is bound to its session's key, so it can't be copied into another session's row.
+    /// To rotate the key, prepend the new one and remove the old one once
+    /// all sessions using it have expired.
+    ///
+    /// Sessions which can't be decrypted, including the unencrypted ones stored
+    /// before encryption was enabled and the ones encrypted by versions of this crate
+    /// which didn't bind them to their session yet, are treated as if they didn't exist,
+    /// logging their users out.
+    ///
+    /// Passing no keys disables the encryption, which is the default.
+    pub fn with_encryption_keys(
+        mut self,
+        keys: impl IntoIterator<Item = SessionEncryptionKey>,
+    ) -> Self {
+        self.encryption_keys = Arc::new(keys.into_iter().collect());
+        self
+    }
+
+    /// Serialize the sessions' state using a different [SessionStateFormat]
+    ///
+    /// Defaults to [SessionStateFormat::Json].
+    pub fn with_state_format(mut self, state_format: SessionStateFormat) -> Self {
+        self.state_format = state_format;
+        self
+    }
+
+    /// Store the sessions' state in a different [SessionStateColumn]
+    ///
+    /// Defaults to [SessionStateColumn::Text].
+    pub fn with_state_column(mut self, state_column: SessionStateColumn) -> Self {
+        self.state_column = state_column;
+        self
+    }
+
+    /// Cache the loaded sessions in memory
+    ///
+    /// Saves the database query when loading a session on most requests.
+    /// Changes made through this store are written to the database and the cache.
+    ///
+    /// If multiple nodes share the database, changes made by the other nodes
+    /// (including deleted sessions) go unnoticed until the cached session is older than `ttl`.
+    ///
+    /// **Parameter**:
+    /// - `capacity`: Maximum number of cached sessions, the least recently used one is evicted first
+    /// - `ttl`: Time after which a cached session is loaded from the database again
+    pub fn with_cache(mut self, capacity: usize, ttl: std::time::Duration) -> Self {
+        self.cache = Some(Arc::new(SessionCache::new(capacity, ttl)));
+        self
+    }
+
+    /// Track when the sessions were last seen, i.e. loaded for a request
+    ///
+    /// The times are written to [DBSession::last_accessed] in batches
+    /// and queried using [DBSessionStore::active_sessions_since] and [DBSessionStore::online_user_ids].
+    /// Without it, [DBSession::last_accessed] is only updated when a session's state or ttl changes.
+    ///
+    /// Times which haven't been written yet are lost when the application stops,
+    /// unless [DBSessionStore::flush_presence] is called on shutdown.
+    pub fn with_presence(mut self, presence: SessionPresence) -> Self {
+        self.presence = Some(Arc::new(PresenceTracker::new(presence)));
+        self
+    }
+
+    /// Retry the queries loading and storing sessions if the database can't be reached
+    ///
+    /// The errors returned by the store wrap a [SessionDatabaseError](crate::tb_middleware::SessionDatabaseError),
+    /// telling whether the database couldn't be reached or the query failed for another reason.
+    ///
+    /// Defaults to no retries.
+    pub fn with_retry(mut self, retry: SessionRetry) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Get the counters describing the store's operations
+    ///
+    /// All clones of the store share the same counters.
+    pub fn metrics(&self) -> SessionMetrics {
+        self.metrics.clone()
+    }
+
+    /// Run a query according to the [SessionRetry] and record its latency and failure
+    async fn run<T, F: IntoFuture<Output = Result<T, rorm::Error>>>(
+        &self,
+        query: impl FnMut() -> F,
+    ) -> Result<T, SessionDatabaseError> {
+        let started = Instant::now();
+        let result = self.retry.run(query).await;
+        self.metrics.latency(started.elapsed());
+        if result.is_err() {
+            self.metrics.error();
+        }
+        result
+    }
+
+    /// Limit the number of bytes a session's state takes in the database
+    ///
+    /// Saving a larger state fails with a [SessionStateTooLarge] error,
+    /// instead of an opaque one from the database.
+    ///
+    /// Defaults to 16383 bytes, the maximum length of [DBSession::session_state],
+    /// or no limit for [SessionStateColumn::Binary].
+    pub fn with_max_state_size(mut self, max_state_size: usize) -> Self {
+        self.max_state_size = Some(max_state_size);
+        self
+    }
+
+    /// Get the maximum number of bytes a session's state may take
+    fn max_state_size(&self) -> usize {
+        self.max_state_size.unwrap_or(match self.state_column {
+            SessionStateColumn::Text => MAX_STATE_SIZE,
+            SessionStateColumn::Binary => usize::MAX,
+        })
+    }
+
+    /// Compress states exceeding the [maximum size](DBSessionStore::with_max_state_size)
+    /// instead of rejecting them right away
+    ///
+    /// Compressed states are stored in [DBSession::session_data].
+    #[cfg(feature = "session-compression")]
+    pub fn with_compression(mut self) -> Self {
+        self.compress_large_states = true;
+        self
+    }
+
+    /// Handle sessions changed by another request since they were loaded
+    /// according to a different [SessionConflictStrategy]
+    ///
+    /// Defaults to [SessionConflictStrategy::LastWriteWins].
+    /// The other strategies require the [SessionVersions] middleware.
+    pub fn with_conflict_strategy(mut self, conflict_strategy: SessionConflictStrategy) -> Self {
+        self.conflict_strategy = conflict_strategy;
+        self
+    }
+
+    /// Encode a session's state for the [DBSession]'s columns
+    ///
+    /// Both columns are unset if the state is stored in [DBSessionEntry] rows.
+    fn stored_state(
+        &self,
+        hashed_key: &str,
+        session_state: &HashMap<String, String>,
+    ) -> Result<StoredState, anyhow::Error> {
+        if self.entry_rows {
+            return Ok(StoredState {
+                session_state: None,
+                session_data: None,
+            });
+        }
+        self.encode_state(hashed_key, session_state)
+    }
+
+    /// Serialize a session's state and encrypt it, if the store has an encryption key
+    ///
+    /// Fails if the result exceeds the maximum state size, even after compressing it.
+    fn encode_state(
+        &self,
+        hashed_key: &str,
+        session_state: &HashMap<String, String>,
+    ) -> Result<StoredState, anyhow::Error> {
+        let (tag, data) = self.state_format.encode(session_state)?;
+        let stored = self.seal_state(hashed_key, tag, &data)?;
+        if stored.size() <= self.max_state_size() {
+            return Ok(stored);
+        }
+
+        #[cfg(feature = "session-compression")]
+        let stored = if self.compress_large_states {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&data)?;
+            let stored = self.seal_state(
+                hashed_key,
+                tag | SessionStateFormat::COMPRESSED,
+                &encoder.finish()?,
+            )?;
+            if stored.size() <= self.max_state_size() {
+                return Ok(stored);
+            }
+            stored
+        } else {
+            stored
+        };
+
+        Err(anyhow!(SessionStateTooLarge {
+            size: stored.size(),
+            max_size: self.max_state_size(),
+        }))
+    }
+
+    /// Encrypt a serialized state, if the store has an encryption key, and put it in its column
+    ///
+    /// The state is bound to its session's `hashed_key`, so it can't be moved to another session.
+    fn seal_state(
+        &self,
+        hashed_key: &str,
+        tag: u8,
+        data: &[u8],
+    ) -> Result<StoredState, anyhow::Error> {
+        let key = self.encryption_keys.first();
+
+        // Uncompressed json is stored as text, unless the binary column is used
+        if tag == SessionStateFormat::JSON_TAG && self.state_column == SessionStateColumn::Text {
+            let state = match key {
+                Some(key) => key
+                    .encrypt(data, hashed_key.as_bytes())
+                    .map_err(|_| anyhow!("Couldn't encrypt the session's state"))?,
+                None => String::from_utf8(data.to_vec())?,
+            };
+            return Ok(StoredState {
+                session_state: Some(state),
+                session_data: None,
+            });
+        }
+
+        let mut session_data = vec![tag];
+        match key {
+            Some(key) => session_data.extend(
+                key.seal(data, hashed_key.as_bytes())
+                    .map_err(|_| anyhow!("Couldn't encrypt the session's state"))?,
+            ),
+            None => session_data.extend_from_slice(data),
+        }
+        Ok(StoredState {
+            session_state: None,
+            session_data: Some(session_data),
+        })
+    }
+
+    /// Decrypt a session's state, if the store has encryption keys, and deserialize it
+    ///
+    /// Returns `None` if the session has no state or none of the keys can decrypt it.
+    fn decode_state(
+        &self,
+        session: DBSession,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        if let Some((&tag, data)) = session
+            .session_data
+            .as_deref()
+            .and_then(<[u8]>::split_first)
+        {
+            let aad = session.session_key.as_bytes();
+            let data = if self.encryption_keys.is_empty() {
+                data.to_vec()
+            } else {
+                let Some(data) = self
+                    .encryption_keys
+                    .iter()
+                    .find_map(|key| key.open(data, aad))
+                else {
+                    return Ok(None);
+                };
+                data
+            };
+            return SessionStateFormat::decode(tag, &data)
+                .map(Some)
+                .map_err(LoadError::Deserialization);
+        }
+
+        let Some(state) = session.session_state else {
+            return Ok(None);
+        };
+        if self.encryption_keys.is_empty() {
+            return serde_json::from_str(&state)
+                .map_err(|e| LoadError::Deserialization(anyhow!(e)));
+        }
+
+        let Some(state) = self
+            .encryption_keys
+            .iter()
+            .find_map(|key| key.decrypt(&state, session.session_key.as_bytes()))
+        else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&state).map_err(|e| LoadError::Deserialization(anyhow!(e)))
+    }
+
+    /// Query the [DBSessionEntry] rows of a session
+    async fn query_entries(
+        &self,
+        hashed_key: &String,
+    ) -> Result<Vec<DBSessionEntry>, SessionDatabaseError> {
+        let db = &self.db;
+        self.run(move || {
+            query!(db, DBSessionEntry)
+                .condition(DBSessionEntry::F.session.equals(hashed_key))
+                .all()
+        })
+        .await
+    }
+
+    /// Load a session's state from its [DBSessionEntry] rows
+    ///
+    /// Returns `None` if none of the keys can decrypt one of the entries.
+    async fn load_entries(
+        &self,
+        hashed_key: &String,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        let entries = self
+            .query_entries(hashed_key)
+            .await
+            .map_err(|e| LoadError::Other(anyhow!(e)))?;
+
+        let mut session_state = HashMap::with_capacity(entries.len());
+        for entry in entries {
+            let Some(value) = self.open_entry(hashed_key, &entry.entry_key, entry.value)? else {
+                return Ok(None);
+            };
+            session_state.insert(entry.entry_key, value);
+        }
+        Ok(Some(session_state))
+    }
+
+    /// Encrypt an entry's value, if the store has an encryption key
+    ///
+    /// The value is bound to its session's `hashed_key` and its `entry_key`,
+    /// so it can't be moved to another session or entry.
+    fn seal_entry(
+        &self,
+        hashed_key: &str,
+        entry_key: &str,
+        value: &str,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        match self.encryption_keys.first() {
+            Some(key) => key
+                .seal(value.as_bytes(), &entry_aad(hashed_key, entry_key))
+                .map_err(|_| anyhow!("Couldn't encrypt the session's state")),
+            None => Ok(value.as_bytes().to_vec()),
+        }
+    }
+
+    /// Decrypt an entry's value, if the store has encryption keys
+    ///
+    /// Returns `None` if none of the keys can decrypt it.
+    fn open_entry(
+        &self,
+        hashed_key: &str,
+        entry_key: &str,
+        value: Vec<u8>,
+    ) -> Result<Option<String>, LoadError> {
+        let value = if self.encryption_keys.is_empty() {
+            value
+        } else {
+            let aad = entry_aad(hashed_key, entry_key);
+            let Some(value) = self
+                .encryption_keys
+                .iter()
+                .find_map(|key| key.open(&value, &aad))
+            else {
+                return Ok(None);
+            };
+            value
+        };
+        String::from_utf8(value)
+            .map(Some)
+            .map_err(|e| LoadError::Deserialization(anyhow!(e)))
+    }
+
+    /// Write the entries of a session's state which differ from its `previous` [DBSessionEntry] rows
+    ///
+    /// Entries missing from the state are deleted, changed ones are updated and new ones inserted.
+    async fn write_entries(
+        &self,
+        hashed_key: &str,
+        session_state: &HashMap<String, String>,
+        previous: Vec<DBSessionEntry>,
+    ) -> Result<(), anyhow::Error> {
+        let db = &self.db;
+        let mut remaining: HashMap<&str, &str> = session_state
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+
+        for entry in previous {
+            let id = entry.id;
+            let Some(value) = remaining.remove(entry.entry_key.as_str()) else {
+                self.run(move || {
+                    delete!(db, DBSessionEntry).condition(DBSessionEntry::F.id.equals(id))
+                })
+                .await
+                .map_err(|e| anyhow!(e))?;
+                continue;
+            };
+
+            if self
+                .open_entry(hashed_key, &entry.entry_key, entry.value)
+                .ok()
+                .flatten()
+                .as_deref()
+                == Some(value)
+            {
+                continue;
+            }
+            let sealed = &self.seal_entry(hashed_key, &entry.entry_key, value)?;
+            self.run(move || {
+                update!(db, DBSessionEntry)
+                    .condition(DBSessionEntry::F.id.equals(id))
+                    .set(DBSessionEntry::F.value, sealed.clone())
+                    .exec()
+            })
+            .await
+            .map_err(|e| anyhow!(e))?;
+        }
+
+        let new_entries = self.new_entries(hashed_key, remaining)?;
+        if !new_entries.is_empty() {
+            let new_entries = &new_entries;
+            self.run(move || {
+                insert!(db, NewSessionEntry)
+                    .return_nothing()
+                    .bulk(new_entries)
+            })
+            .await
+            .map_err(|e| anyhow!(e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Build the [DBSessionEntry] rows of new entries of a session's state
+    fn new_entries<'a>(
+        &self,
+        hashed_key: &str,
+        entries: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Vec<NewSessionEntry>, anyhow::Error> {
+        entries
+            .into_iter()
+            .map(|(entry_key, value)| {
+                Ok(NewSessionEntry {
+                    session: ForeignModelByField::Key(hashed_key.to_string()),
+                    entry_key: entry_key.to_string(),
+                    value: self.seal_entry(hashed_key, entry_key, value)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Get all sessions of a user which haven't expired
+    ///
+    /// The sessions are associated with their user using [set_session_user].
+    pub async fn sessions_for_user(&self, user_id: &str) -> Result<Vec<DBSession>, rorm::Error> {
+        let now = self.clock.now();
+
+        let sessions = M::for_user(&self.db, user_id).await?;
+
+        Ok(sessions
+            .into_iter()
+            .filter(|session| session.expired_after >= now)
+            .collect())
+    }
+
+    /// Delete all sessions of a user, logging them out everywhere
+    ///
+    /// The sessions are associated with their user using [set_session_user].
+    pub async fn revoke_all_for_user(&self, user_id: &str) -> Result<(), rorm::Error> {
+        M::delete_for_user(&self.db, user_id).await?;
+
+        if let Some(cache) = &self.cache {
+            cache.remove_user(user_id);
+        }
+
+        Ok(())
+    }
+
+    /// Count the sessions which haven't expired
+    pub async fn count_active(&self) -> Result<u64, rorm::Error> {
+        M::count_active(&self.db, self.clock.now()).await
+    }
+
+    /// Get a page of the sessions which haven't expired, the most recently accessed ones first
+    ///
+    /// **Parameter**:
+    /// - `offset`: Number of sessions to skip
+    /// - `limit`: Maximum number of sessions to return
+    pub async fn list_active(
+        &self,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<DBSession>, rorm::Error> {
+        M::list_active(&self.db, self.clock.now(), offset, limit).await
+    }
+
+    /// Get the sessions which haven't expired and were last seen within `duration`,
+    /// the most recently seen ones first
+    ///
+    /// See [DBSessionStore::with_presence] for how the sessions are seen.
+    pub async fn active_sessions_since(
+        &self,
+        duration: Duration,
+    ) -> Result<Vec<DBSession>, rorm::Error> {
+        self.flush_presence().await;
+
+        let now = self.clock.now();
+        // Durations reaching back further than representable include every session
+        let since = i64::try_from(duration.whole_nanoseconds())
+            .ok()
+            .and_then(|nanos| now.checked_sub_signed(chrono::Duration::nanoseconds(nanos)))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC);
+        M::accessed_since(&self.db, since, now).await
+    }
+
+    /// Get the ids of the users with a session seen within the [SessionPresence::online_window]
+    ///
+    /// The sessions are associated with their user using [set_session_user].
+    /// The ids are sorted and each is only included once.
+    pub async fn online_user_ids(&self) -> Result<Vec<String>, rorm::Error> {
+        let online_window = self.presence.as_ref().map_or_else(
+            || SessionPresence::default().online_window(),
+            |presence| presence.config.online_window(),
+        );
+        self.flush_presence().await;
+
+        let now = self.clock.now();
+        let mut user_ids: Vec<String> = M::accessed_since(&self.db, now - online_window, now)
+            .await?
+            .into_iter()
+            .filter_map(|session| session.user_id)
+            .collect();
+        user_ids.sort_unstable();
+        user_ids.dedup();
+        Ok(user_ids)
+    }
+
+    /// Write the times the sessions were last seen which haven't been written yet
+    ///
+    /// Does nothing unless [DBSessionStore::with_presence] is used.
+    pub async fn flush_presence(&self) {
+        if let Some(presence) = &self.presence {
+            self.write_presence(presence.take(self.clock.now())).await;
+        }
+    }
+
+    /// Write the times sessions were last seen
+    ///
+    /// The sessions seen at the same time are written together,
+    /// so the number of queries depends on the flush interval rather than the number of sessions.
+    ///
+    /// The times are only informational, so failures are logged instead of failing the request.
+    async fn write_presence(&self, seen: HashMap<String, DateTime<Utc>>) {
+        let mut by_time: BTreeMap<DateTime<Utc>, Vec<String>> = BTreeMap::new();
+        for (session_key, last_seen) in seen {
+            by_time.entry(last_seen).or_default().push(session_key);
+        }
+
+        let db = &self.db;
+        for (last_seen, session_keys) in &by_time {
+            for session_keys in session_keys.chunks(PRESENCE_BATCH_SIZE) {
+                if let Err(err) = self
+                    .run(move || M::touch(db, session_keys, *last_seen))
+                    .await
+                {
+                    warn!("Couldn't write when the sessions were last seen: {err}");
+                }
+            }
+        }
+    }
+
+    /// Delete all sessions, logging out every user
+    ///
+    /// Use [DBSessionStore::revoke_all_for_user] to only log out a single user.
+    ///
+    /// If multiple nodes share the database, the sessions stay in their caches
+    /// until the cached sessions are older than the cache's ttl.
+    pub async fn purge_all(&self) -> Result<(), rorm::Error> {
+        M::delete_all(&self.db).await?;
+
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+
+        Ok(())
+    }
+
+    /**
+    Create a session as part of a transaction, e.g. along with the user it belongs to
+
+    The session only exists once the transaction is committed.
+    Unlike [SessionStore::save], the query isn't retried and the session isn't cached.
+
+    The [SessionMiddleware] doesn't know about the session,
+    so send its [cookie](SessionHandle::cookie) to the client yourself:
+
+    ```no_run
+    use std::collections::HashMap;
+
+    use actix_toolbox::tb_middleware::{DBSessionStore, SESSION_USER_ID};
+    use actix_web::cookie::time::Duration;
+    use actix_web::cookie::Key;
+    use actix_web::HttpResponse;
+
+    async fn register(
+        db: &rorm::Database,
+        store: &DBSessionStore,
+        key: &Key,
+    ) -> Result<HttpResponse, anyhow::Error> {
+        let mut tx = db.start_transaction().await?;
+        // Insert the user using `&mut tx`
+        let user_id = "42";
+        let session_state = HashMap::from([(
+            SESSION_USER_ID.to_string(),
+            serde_json::to_string(user_id)?,
+        )]);
+        let session = store
+            .save_in(&mut tx, session_state, &Duration::days(1))
+            .await?;
+        tx.commit().await?;
+
+        Ok(HttpResponse::Ok().cookie(session.cookie(key, "id")).finish())
+    }
+    ```
+    */
+    pub async fn save_in(
+        &self,
+        tx: &mut Transaction,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<SessionHandle<M>, SaveError> {
+        let now = self.clock.now();
+        let expired_after = expiry(now, ttl).map_err(SaveError::Other)?;
+
+        let session_key = self.key_generator.generate();
+        let hashed_key = hash_session_key(&session_key);
+        let state = self
+            .stored_state(&hashed_key, &session_state)
+            .map_err(SaveError::Serialization)?;
+        let session = DBSession {
+            session_key: hashed_key.clone(),
+            session_state: state.session_state,
+            session_data: state.session_data,
+            expired_after,
+            user_id: state_string(&session_state, SESSION_USER_ID),
+            created_at: now,
+            last_accessed: now,
+            client_ip: state_string(&session_state, SESSION_CLIENT_IP),
+            user_agent: state_string(&session_state, SESSION_USER_AGENT),
+            version: rand::random(),
+        };
+
+        // A failed insert aborts the transaction, so a taken key can't be retried
+        M::insert(&mut *tx, &session)
+            .await
+            .map_err(|e| SaveError::Other(anyhow!(e)))?;
+        if self.entry_rows {
+            let entries = self
+                .new_entries(
+                    &hashed_key,
+                    session_state
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.as_str())),
+                )
+                .map_err(SaveError::Serialization)?;
+            if !entries.is_empty() {
+                insert!(&mut *tx, NewSessionEntry)
+                    .return_nothing()
+                    .bulk(&entries)
+                    .await
+                    .map_err(|e| SaveError::Other(anyhow!(e)))?;
+            }
+        }
+        self.metrics.save();
+
+        Ok(SessionHandle {
+            store: self.clone(),
+            session_key,
+        })
+    }
+
+    /// Delete a session as part of a transaction
+    ///
+    /// The session is removed from the cache right away,
+    /// so it is loaded from the database again if the transaction is rolled back.
+    pub async fn delete_in(
+        &self,
+        tx: &mut Transaction,
+        session_key: &SessionKey,
+    ) -> Result<(), rorm::Error> {
+        let hashed_key = hash_session_key(session_key.as_ref());
+        if let Some(cache) = &self.cache {
+            cache.remove(&hashed_key);
+        }
+
+        M::delete(tx, &hashed_key).await?;
+        self.metrics.delete();
+
+        Ok(())
+    }
+
+    /// Delete all sessions of a user as part of a transaction, e.g. along with the user
+    ///
+    /// The sessions are removed from the cache right away,
+    /// so they are loaded from the database again if the transaction is rolled back.
+    pub async fn revoke_all_for_user_in(
+        &self,
+        tx: &mut Transaction,
+        user_id: &str,
+    ) -> Result<(), rorm::Error> {
+        if let Some(cache) = &self.cache {
+            cache.remove_user(user_id);
+        }
+
+        M::delete_for_user(tx, user_id).await
+    }
+
+    async fn load_state(
+        &self,
+        session_key: &str,
+    ) -> Result<Option<HashMap<String, String>>, LoadError> {
+        let now = self.clock.now();
+        let hashed_key = hash_session_key(session_key);
+        self.metrics.load();
+
+        if let Some((state, version)) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get(&hashed_key, now))
+        {
+            self.metrics.cache_hit();
+            self.seen(&hashed_key, now);
+            self.loaded_version(&hashed_key, version);
+            return Ok(Some(state));
+        }
+
+        let loaded = match self.fetch_state(&hashed_key, now).await? {
+            Some(loaded) => loaded,
+            None => match self.rehash_session(session_key, &hashed_key, now).await? {
+                Some(loaded) => loaded,
+                None => return Ok(None),
+            },
+        };
+        self.seen(&hashed_key, now);
+        self.loaded_version(&hashed_key, loaded.version);
+        if let Some(cache) = &self.cache {
+            cache.insert(
+                hashed_key,
+                loaded.state.clone(),
+                loaded.version,
+                loaded.expired_after,
+                loaded.user_id,
+                now,
+            );
+        }
+        Ok(Some(loaded.state))
+    }
+
+    /// Move a session stored under its plain key by an earlier version to its hashed key
+    ///
+    /// Returns `None` if there is no such session.
+    async fn rehash_session(
+        &self,
+        session_key: &str,
+        hashed_key: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Option<LoadedSession>, LoadError> {
+        if is_hashed_session_key(session_key) {
+            return Ok(None);
+        }
+        let db = &self.db;
+        let Some(session) = self
+            .run(move || M::load(db, session_key))
+            .await
+            .map_err(|e| LoadError::Other(anyhow!(e)))?
+        else {
+            return Ok(None);
+        };
+        if session.expired_after.lt(&now) {
+            return Ok(None);
+        }
+
+        let state = if session.session_state.is_none() && session.session_data.is_none() {
+            self.load_entries(&session.session_key).await?
+        } else {
+            self.decode_state(session.clone())?
+        };
+        let Some(state) = state else {
+            return Ok(None);
+        };
+
+        // Encrypted states are bound to their key, so they have to be encrypted again
+        let stored = self
+            .stored_state(hashed_key, &state)
+            .map_err(LoadError::Other)?;
+        let session = DBSession {
+            session_key: hashed_key.to_string(),
+            session_state: stored.session_state,
+            session_data: stored.session_data,
+            ..session
+        };
+        let entries = if self.entry_rows {
+            self.new_entries(
+                hashed_key,
+                state
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            )
+            .map_err(LoadError::Other)?
+        } else {
+            Vec::new()
+        };
+
+        let moved: Result<(), rorm::Error> = async {
+            let mut tx = db.start_transaction().await?;
+            // Deleting the session deletes its entries as well
+            M::delete(&mut tx, session_key).await?;
+            M::insert(&mut tx, &session).await?;
+            if !entries.is_empty() {
+                insert!(&mut tx, NewSessionEntry)
+                    .return_nothing()
+                    .bulk(&entries)
+                    .await?;
+            }
+            tx.commit().await
+        }
+        .await;
+        moved.map_err(|e| LoadError::Other(anyhow!(e)))?;
+
+        Ok(Some(LoadedSession {
+            state,
+            version: session.version,
+            expired_after: session.expired_after,
+            user_id: session.user_id,
+        }))
+    }
+
+    /// Remember the version of a session loaded by the current request,
+    /// if the [SessionConflictStrategy] needs it to detect conflicting updates
+    fn loaded_version(&self, hashed_key: &str, version: i64) {
+        if self.conflict_strategy != SessionConflictStrategy::LastWriteWins {
+            session_version::record_version(hashed_key, version);
+        }
+    }
+
+    /// Record that a session was seen, if the presence is tracked,
+    /// and write the seen sessions once the flush interval has passed
+    ///
+    /// They are written in a spawned task, so the request which happens to trigger it isn't delayed.
+    fn seen(&self, hashed_key: &str, now: DateTime<Utc>) {
+        let Some(presence) = &self.presence else {
+            return;
+        };
+        if let Some(seen) = presence.seen(hashed_key, now) {
+            let store = self.clone();
+            actix_web::rt::spawn(async move { store.write_presence(seen).await });
+        }
+    }
+
+    /// Load a session's state from the database, bypassing the cache
+    async fn fetch_state(
+        &self,
+        hashed_key: &String,
+        now: DateTime<Utc>,
+    ) -> Result<Option<LoadedSession>, LoadError> {
+        let db = &self.db;
+        let session = self
+            .run(move || M::load(db, hashed_key))
+            .await
+            .map_err(|e| LoadError::Other(anyhow!(e)))?;
+
+        let Some(s) = session else {
+            return Ok(None);
+        };
+        if s.expired_after.lt(&now) {
+            self.metrics.expired_on_load();
+            return Ok(None);
+        }
+
+        let expired_after = s.expired_after;
+        let user_id = s.user_id.clone();
+        let version = s.version;
+        let state = if s.session_state.is_none() && s.session_data.is_none() {
+            self.load_entries(hashed_key).await?
+        } else {
+            self.decode_state(s)?
+        };
+        Ok(state.map(|state| LoadedSession {
+            state,
+            version,
+            expired_after,
+            user_id,
+        }))
+    }
+
+    async fn update_state(
+        &self,
+        session_key: &str,
+        mut session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<(), UpdateError> {
+        let now = self.clock.now();
+        let expired_after = expiry(now, ttl).map_err(UpdateError::Other)?;
+        let hashed_key = hash_session_key(session_key);
+
+        let mut expected_version = session_version::take_version(&hashed_key)
+            .filter(|_| self.conflict_strategy != SessionConflictStrategy::LastWriteWins);
+
+        let mut attempt = 1;
+        let session = loop {
+            let state = self
+                .stored_state(&hashed_key, &session_state)
+                .map_err(UpdateError::Serialization)?;
+            let session = DBSession {
+                session_key: hashed_key.clone(),
+                session_state: state.session_state,
+                session_data: state.session_data,
+                expired_after,
+                user_id: state_string(&session_state, SESSION_USER_ID),
+                // Not updated by the model
+                created_at: now,
+                last_accessed: now,
+                client_ip: state_string(&session_state, SESSION_CLIENT_IP),
+                user_agent: state_string(&session_state, SESSION_USER_AGENT),
+                version: rand::random(),
+            };
+
+            let db = &self.db;
+            let updated_session = &session;
+            let updated = self
+                .run(move || M::update(db, updated_session, expected_version))
+                .await
+                .map_err(|e| UpdateError::Other(anyhow!(e)))?;
+            if updated {
+                break session;
+            }
+            if expected_version.is_none() {
+                // The session was deleted by another request, don't write its entries or cache it
+                return Ok(());
+            }
+
+            // The session was deleted or changed by another request since it was loaded
+            let Some(current) = self
+                .fetch_state(&hashed_key, now)
+                .await
+                .map_err(|e| UpdateError::Other(anyhow!(e)))?
+            else {
+                return Ok(());
+            };
+            if self.conflict_strategy == SessionConflictStrategy::Error || attempt >= MERGE_ATTEMPTS
+            {
+                return Err(UpdateError::Other(anyhow!(SessionConflict)));
+            }
+            expected_version = Some(current.version);
+            let mut merged = current.state;
+            merged.extend(session_state);
+            session_state = merged;
+            attempt += 1;
+        };
+
+        if self.entry_rows {
+            let previous = self
+                .query_entries(&session.session_key)
+                .await
+                .map_err(|e| UpdateError::Other(anyhow!(e)))?;
+            self.write_entries(&session.session_key, &session_state, previous)
+                .await
+                .map_err(UpdateError::Other)?;
+        }
+        self.metrics.update();
+
+        if let Some(cache) = &self.cache {
+            cache.insert(
+                session.session_key,
+                session_state,
+                session.version,
+                expired_after,
+                session.user_id,
+                now,
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn delete_session(&self, session_key: &str) -> Result<(), anyhow::Error> {
+        let hashed_key = hash_session_key(session_key);
+
+        // Remove it from the cache first, so a failing delete doesn't leave it in there
+        if let Some(cache) = &self.cache {
+            cache.remove(&hashed_key);
+        }
+
+        let db = &self.db;
+        let key = &hashed_key;
+        self.run(move || M::delete(db, key))
+            .await
+            .map_err(|e| anyhow!(e))?;
+        self.metrics.delete();
+
+        Ok(())
+    }
+}
 
 /**
-DB representation of a session.
+Hardened presets for the session cookie's attributes
+
+All presets set `HttpOnly` and `Path=/`.
+Use [SessionCookiePreset::apply] when building the [SessionMiddleware]:
+
+```no_run
+use actix_toolbox::tb_middleware::{DBSessionStore, SessionCookiePreset, SessionMiddleware};
+use actix_web::cookie::Key;
+
+# fn build(store: DBSessionStore, key: Key) {
+let middleware = SessionCookiePreset::Lax
+    .apply(SessionMiddleware::builder(store, key))
+    .build();
+# }
+```
+
+Select it per environment in your config using `"development"`, `"lax"`, `"strict"` or `"cross_site"`.
 */
-#[derive(Model, Debug, Clone)]
-pub struct DBSession {
-    /// Key of the session
-    #[rorm(primary_key)]
-    #[rorm(max_length = 4096)]
-    pub session_key: String,
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionCookiePreset {
+    /// For local development over plain http: `SameSite=Lax` without `Secure`
+    ///
+    /// A warning is logged when this preset is applied in a release build.
+    Development,
 
-    /// State of the session. json encoded HashMap<String, String>
-    #[rorm(max_length = 16383)]
-    pub session_state: Option<String>,
+    /// `__Host-` prefixed, `Secure` and `SameSite=Lax`
+    ///
+    /// This is the recommended preset.
+    /// The session is kept when the user navigates to your site from another one,
+    /// e.g. when the oidc provider redirects to [`finish_login`](crate::oidc::finish_login).
+    /// Providers posting the response to it using the `form_post`
+    /// [`response_mode`](crate::oidc::Config::response_mode) require [`CrossSite`](Self::CrossSite).
+    Lax,
 
-    /// DateTime after the session will be invalid
-    pub expired_after: DateTime<Utc>,
+    /// `__Host-` prefixed, `Secure` and `SameSite=Strict`
+    ///
+    /// The session cookie isn't sent when navigating to your site from another one,
+    /// so this breaks logins through an oidc provider on a different site.
+    Strict,
+
+    /// `__Host-` prefixed, `Secure` and `SameSite=None`
+    ///
+    /// Required if your site is embedded by other sites, e.g. for
+    /// [`front_channel_logout`](crate::oidc::front_channel_logout).
+    /// Protect state changing requests against CSRF yourself.
+    CrossSite,
+}
+
+impl SessionCookiePreset {
+    /// Name of the session cookie used by the `__Host-` prefixed presets
+    pub const HOST_COOKIE_NAME: &'static str = "__Host-id";
+
+    /// Whether the cookie is only sent over https
+    pub fn is_secure(self) -> bool {
+        self != SessionCookiePreset::Development
+    }
+
+    /// Set the preset's cookie attributes on the builder
+    pub fn apply<S: SessionStore>(
+        self,
+        builder: SessionMiddlewareBuilder<S>,
+    ) -> SessionMiddlewareBuilder<S> {
+        if self == SessionCookiePreset::Development && !cfg!(debug_assertions) {
+            warn!("The session cookie uses the development preset which isn't secure");
+        }
+
+        let builder = builder
+            .cookie_http_only(true)
+            .cookie_path("/".to_string())
+            .cookie_secure(self.is_secure());
+        match self {
+            SessionCookiePreset::Development => builder.cookie_same_site(SameSite::Lax),
+            SessionCookiePreset::Lax => builder
+                // The __Host- prefix requires Secure, Path=/ and no Domain
+                .cookie_name(Self::HOST_COOKIE_NAME.to_string())
+                .cookie_domain(None)
+                .cookie_same_site(SameSite::Lax),
+            SessionCookiePreset::Strict => builder
+                .cookie_name(Self::HOST_COOKIE_NAME.to_string())
+                .cookie_domain(None)
+                .cookie_same_site(SameSite::Strict),
+            SessionCookiePreset::CrossSite => builder
+                .cookie_name(Self::HOST_COOKIE_NAME.to_string())
+                .cookie_domain(None)
+                .cookie_same_site(SameSite::None),
+        }
+    }
 }
 
 /**
-Wrapper for a instance of [rorm::Database].
+Configuration for the [SessionMiddleware] built by [setup_session_mw].
+
+Provides secure defaults via the [Default] trait.
+
+```yaml
+preset: lax
+ttl: 86400
+extend_on_every_request: false
+```
+*/
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// Attributes of the session cookie. Defaults to [SessionCookiePreset::Lax]
+    pub preset: SessionCookiePreset,
+
+    /// Seconds until an unused session expires. Defaults to one day
+    ///
+    /// `None` keeps the session until the browser is closed.
+    pub ttl: Option<u64>,
+
+    /// Extend the session's ttl on every request instead of only when its state changed
+    ///
+    /// This writes to the session store on every request. Defaults to `false`
+    pub extend_on_every_request: bool,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            preset: SessionCookiePreset::Lax,
+            ttl: Some(24 * 60 * 60),
+            extend_on_every_request: false,
+        }
+    }
+}
+
+/**
+Sets up a session middleware with the given config.
+
+```no_run
+use actix_toolbox::tb_middleware::{setup_session_mw, DBSessionStore, SessionConfig};
+use actix_web::cookie::Key;
+use actix_web::App;
+
+# fn build(store: DBSessionStore, key: Key) {
+let app = App::new().wrap(setup_session_mw(store, key, SessionConfig::default()));
+# }
+```
+*/
+pub fn setup_session_mw<S: SessionStore>(
+    store: S,
+    key: Key,
+    config: SessionConfig,
+) -> SessionMiddleware<S> {
+    let builder = config.preset.apply(SessionMiddleware::builder(store, key));
+    match config.ttl {
+        Some(ttl) => {
+            let policy = if config.extend_on_every_request {
+                TtlExtensionPolicy::OnEveryRequest
+            } else {
+                TtlExtensionPolicy::OnStateChanges
+            };
+            builder.session_lifecycle(
+                PersistentSession::default()
+                    .session_ttl(Duration::seconds(ttl as i64))
+                    .session_ttl_extension_policy(policy),
+            )
+        }
+        None => builder.session_lifecycle(BrowserSession::default()),
+    }
+    .build()
+}
+
+/**
+Handle to a user's session which can be used outside of a request.
+
+It is captured from a request (e.g. a websocket upgrade) and accesses the
+session's entry in the [DBSessionStore] directly.
+This allows long living tasks like websocket handlers to read the session
+or to notice when it has been revoked.
+
+Changes made through a [Session] in other requests are visible to the handle
+as soon as they are persisted by the [SessionMiddleware].
 */
 #[derive(Clone)]
-pub struct DBSessionStore(rorm::Database);
+pub struct SessionHandle<M: SessionModel = DBSession> {
+    store: DBSessionStore<M>,
+    session_key: String,
+}
 
-impl DBSessionStore {
-    /// Create a new DBSessionStore
+impl<M: SessionModel> SessionHandle<M> {
+    /// Capture the session of a request
+    ///
+    /// Returns `None` if the request doesn't carry a valid session cookie.
     ///
     /// **Parameter**:
-    /// - `db`: Instance of a connected database
-    pub fn new(db: rorm::Database) -> Self {
-        Self(db)
+    /// - `request`: Request to take the session cookie from
+    /// - `store`: The store used by the [SessionMiddleware]
+    /// - `key`: The key used by the [SessionMiddleware] to encrypt the cookie
+    /// - `cookie_name`: Name of the session cookie. actix-session defaults to `"id"`
+    pub fn from_request(
+        request: &HttpRequest,
+        store: DBSessionStore<M>,
+        key: &Key,
+        cookie_name: &str,
+    ) -> Option<Self> {
+        let cookie = request.cookie(cookie_name)?;
+
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie);
+        let session_key = jar.private(key).get(cookie_name)?.value().to_string();
+
+        Some(Self { store, session_key })
+    }
+
+    /// Load the session's state
+    ///
+    /// Returns `None` if the session has expired or was deleted.
+    pub async fn load(&self) -> Result<Option<HashMap<String, String>>, LoadError> {
+        self.store.load_state(&self.session_key).await
+    }
+
+    /// Build the session cookie the [SessionMiddleware] would send for this session
+    ///
+    /// The cookie is encrypted like the middleware's default, which [SessionHandle::from_request] expects.
+    /// Adjust its attributes to match your middleware's configuration, e.g. its max age.
+    ///
+    /// **Parameter**:
+    /// - `key`: The key used by the [SessionMiddleware] to encrypt the cookie
+    /// - `cookie_name`: Name of the session cookie. actix-session defaults to `"id"`
+    pub fn cookie(&self, key: &Key, cookie_name: &str) -> Cookie<'static> {
+        let mut cookie = Cookie::new(cookie_name.to_string(), self.session_key.clone());
+        cookie.set_path("/");
+        cookie.set_secure(true);
+        cookie.set_http_only(true);
+        cookie.set_same_site(SameSite::Lax);
+
+        let mut jar = CookieJar::new();
+        jar.private_mut(key).add(cookie.clone());
+        jar.get(cookie_name).cloned().unwrap_or(cookie)
+    }
+
+    /// Check whether the session still exists and hasn't expired
+    pub async fn is_valid(&self) -> Result<bool, LoadError> {
+        Ok(self.load().await?.is_some())
+    }
+
+    /// Get a value from the session
+    ///
+    /// This mirrors [Session::get]
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, LoadError> {
+        let Some(state) = self.load().await? else {
+            return Ok(None);
+        };
+        state
+            .get(key)
+            .map(|value| serde_json::from_str(value))
+            .transpose()
+            .map_err(|e| LoadError::Deserialization(anyhow!(e)))
+    }
+
+    /// Overwrite the session's state
+    ///
+    /// **Parameter**:
+    /// - `session_state`: The new state
+    /// - `ttl`: Time until the session expires
+    pub async fn update(
+        &self,
+        session_state: HashMap<String, String>,
+        ttl: &Duration,
+    ) -> Result<(), UpdateError> {
+        self.store
+            .update_state(&self.session_key, session_state, ttl)
+            .await
+    }
+
+    /// Delete the session, logging the user out
+    pub async fn delete(&self) -> Result<(), anyhow::Error> {
+        self.store.delete_session(&self.session_key).await
     }
 }
 
 #[async_trait(?Send)]
-impl SessionStore for DBSessionStore {
+impl<M: SessionModel> SessionStore for DBSessionStore<M> {
     async fn load(
         &self,
         session_key: &SessionKey,
     ) -> Result<Option<HashMap<String, String>>, LoadError> {
-        let now = Utc::now();
-
-        let session = query!(&self.0, DBSession)
-            .condition(DBSession::F.session_key.equals(session_key.as_ref()))
-            .optional()
-            .await
-            .map_err(|e| LoadError::Other(anyhow!(e)))?;
-
-        Ok(if let Some(s) = session {
-            if s.expired_after.lt(&now) {
-                None
-            } else if let Some(state) = s.session_state {
-                serde_json::from_str(&state).map_err(|e| LoadError::Deserialization(anyhow!(e)))?
-            } else {
-                None
-            }
-        } else {
-            None
-        })
+        self.load_state(session_key.as_ref()).await
     }
 
     async fn save(
@@ -78,38 +1828,67 @@ impl SessionStore for DBSessionStore {
         session_state: HashMap<String, String>,
         ttl: &Duration,
     ) -> Result<SessionKey, SaveError> {
-        let expired_after =
-            Utc::now().add(chrono::Duration::nanoseconds(ttl.whole_nanoseconds() as i64));
+        let now = self.clock.now();
+        let expired_after = expiry(now, ttl).map_err(SaveError::Other)?;
 
         let mut session_key;
         loop {
-            session_key = Alphanumeric.sample_string(&mut rand::thread_rng(), 512);
-
-            let res = query!(&self.0, (DBSession::F.session_key,))
-                .condition(DBSession::F.session_key.equals(&session_key))
-                .optional()
-                .await
-                .map_err(|e| SaveError::Other(anyhow!(e)))?;
-
-            if res.is_some() {
-                continue;
-            }
-
-            let state = serde_json::to_string(&session_state)
-                .map_err(|e| SaveError::Serialization(anyhow!(e)))?;
+            session_key = self.key_generator.generate();
+            let hashed_key = hash_session_key(&session_key);
+            let state = self
+                .stored_state(&hashed_key, &session_state)
+                .map_err(SaveError::Serialization)?;
 
             let s = DBSession {
-                session_key: session_key.clone(),
-                session_state: Some(state),
+                session_key: hashed_key.clone(),
+                session_state: state.session_state,
+                session_data: state.session_data,
                 expired_after,
+                user_id: state_string(&session_state, SESSION_USER_ID),
+                created_at: now,
+                last_accessed: now,
+                client_ip: state_string(&session_state, SESSION_CLIENT_IP),
+                user_agent: state_string(&session_state, SESSION_USER_AGENT),
+                version: rand::random(),
             };
 
-            insert!(&self.0, DBSession)
-                .single(&s)
+            // Insert directly and let the primary key reject duplicates,
+            // checking for a taken key beforehand would race with concurrent saves
+            let db = &self.db;
+            let new_session = &s;
+            let err = match self.run(move || M::insert(db, new_session)).await {
+                Ok(_) => {
+                    if self.entry_rows {
+                        self.write_entries(&hashed_key, &session_state, Vec::new())
+                            .await
+                            .map_err(SaveError::Other)?;
+                    }
+                    self.metrics.save();
+                    if let Some(cache) = &self.cache {
+                        cache.insert(
+                            hashed_key,
+                            session_state,
+                            s.version,
+                            expired_after,
+                            s.user_id,
+                            now,
+                        );
+                    }
+                    break;
+                }
+                Err(err) if err.is_transient() => return Err(SaveError::Other(anyhow!(err))),
+                Err(err) => err,
+            };
+
+            // Only retry with a new key if the insert failed because the key is taken
+            let key = &hashed_key;
+            let taken = self
+                .run(move || M::exists(db, key))
                 .await
                 .map_err(|e| SaveError::Other(anyhow!(e)))?;
-
-            break;
+            if !taken {
+                return Err(SaveError::Other(anyhow!(err)));
+            }
         }
 
         Ok(SessionKey::try_from(session_key).map_err(|e| SaveError::Other(anyhow!(e)))?)
@@ -121,19 +1900,8 @@ impl SessionStore for DBSessionStore {
         session_state: HashMap<String, String>,
         ttl: &Duration,
     ) -> Result<SessionKey, UpdateError> {
-        let expired_after =
-            Utc::now().add(chrono::Duration::nanoseconds(ttl.whole_nanoseconds() as i64));
-
-        let state = serde_json::to_string(&session_state)
-            .map_err(|e| UpdateError::Serialization(anyhow!(e)))?;
-
-        update!(&self.0, DBSession)
-            .condition(DBSession::F.session_key.equals(session_key.as_ref()))
-            .set(DBSession::F.session_state, Some(state))
-            .set(DBSession::F.expired_after, expired_after)
-            .exec()
-            .await
-            .map_err(|e| UpdateError::Other(anyhow!(e)))?;
+        self.update_state(session_key.as_ref(), session_state, ttl)
+            .await?;
 
         Ok(session_key)
     }
@@ -143,24 +1911,86 @@ impl SessionStore for DBSessionStore {
         session_key: &SessionKey,
         ttl: &Duration,
     ) -> Result<(), anyhow::Error> {
-        let expired_after =
-            Utc::now().add(chrono::Duration::nanoseconds(ttl.whole_nanoseconds() as i64));
+        let now = self.clock.now();
+        let expired_after = expiry(now, ttl)?;
+
+        let hashed_key = hash_session_key(session_key.as_ref());
 
-        update!(&self.0, DBSession)
-            .condition(DBSession::F.session_key.equals(session_key.as_ref()))
-            .set(DBSession::F.expired_after, expired_after)
-            .exec()
+        let db = &self.db;
+        let key = &hashed_key;
+        self.run(move || M::update_expiry(db, key, expired_after, now))
             .await
             .map_err(|e| anyhow!(e))?;
+        self.metrics.update();
+
+        if let Some(cache) = &self.cache {
+            cache.set_expiry(&hashed_key, expired_after);
+        }
 
         Ok(())
     }
 
     async fn delete(&self, session_key: &SessionKey) -> Result<(), anyhow::Error> {
-        delete!(&self.0, DBSession)
-            .condition(DBSession::F.session_key.equals(session_key.as_ref()))
-            .await
-            .map_err(|e| anyhow!(e))?;
+        self.delete_session(session_key.as_ref()).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<M: SessionModel> ImportSessions for DBSessionStore<M> {
+    async fn import_session(&self, session: MigratedSession) -> Result<(), anyhow::Error> {
+        let now = self.clock.now();
+        let expired_after = expiry(now, &session.ttl)?;
+
+        let hashed_key = hash_session_key(&session.session_key);
+        let state = self.stored_state(&hashed_key, &session.session_state)?;
+        let s = DBSession {
+            session_key: hashed_key,
+            session_state: state.session_state,
+            session_data: state.session_data,
+            expired_after,
+            user_id: state_string(&session.session_state, SESSION_USER_ID),
+            created_at: now,
+            last_accessed: now,
+            client_ip: state_string(&session.session_state, SESSION_CLIENT_IP),
+            user_agent: state_string(&session.session_state, SESSION_USER_AGENT),
+            version: rand::random(),
+        };
+
+        let entries = if self.entry_rows {
+            self.new_entries(
+                &s.session_key,
+                session
+                    .session_state
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            )?
+        } else {
+            Vec::new()
+        };
+
+        // Replace the session with the same key, e.g. if the migration is run twice.
+        // Deleting the session deletes its entries as well.
+        let mut tx = self.db.start_transaction().await?;
+        M::delete(&mut tx, &s.session_key).await?;
+        M::insert(&mut tx, &s).await?;
+        if !entries.is_empty() {
+            insert!(&mut tx, NewSessionEntry)
+                .return_nothing()
+                .bulk(&entries)
+                .await?;
+        }
+        tx.commit().await?;
+
+        if let Some(cache) = &self.cache {
+            cache.insert(
+                s.session_key,
+                session.session_state,
+                s.version,
+                expired_after,
+                s.user_id,
+                now,
+            );
+        }
 
         Ok(())
     }
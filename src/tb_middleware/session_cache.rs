@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+struct CachedSession {
+    session_state: HashMap<String, String>,
+    version: i64,
+    expired_after: DateTime<Utc>,
+    user_id: Option<String>,
+    cached_at: DateTime<Utc>,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CachedSessions {
+    sessions: HashMap<String, CachedSession>,
+    /// Keys of the sessions ordered by their last use, the least recently used one first
+    recency: BTreeMap<u64, String>,
+    /// Counter ordering the accesses to find the least recently used session
+    tick: u64,
+}
+
+impl CachedSessions {
+    /// Advance the counter to mark a use
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Remove a session
+    fn remove(&mut self, session_key: &str) {
+        if let Some(session) = self.sessions.remove(session_key) {
+            self.recency.remove(&session.last_used);
+        }
+    }
+
+    /// Remove the least recently used session
+    ///
+    /// Returns `false` if there was none.
+    fn evict(&mut self) -> bool {
+        match self.recency.pop_first() {
+            Some((_, session_key)) => {
+                self.sessions.remove(&session_key);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Least recently used cache of the sessions loaded by the [DBSessionStore](crate::tb_middleware::DBSessionStore)
+///
+/// The sessions are keyed by their hashed key.
+/// Entries older than `ttl` are reloaded from the database,
+/// which bounds how long changes made by other nodes go unnoticed.
+pub(crate) struct SessionCache {
+    capacity: usize,
+    ttl: Duration,
+    sessions: Mutex<CachedSessions>,
+}
+
+impl SessionCache {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl,
+            sessions: Mutex::new(CachedSessions::default()),
+        }
+    }
+
+    /// Lock the sessions
+    ///
+    /// A panic while holding the lock can't leave the map in an inconsistent state,
+    /// so a poisoned lock is used anyway.
+    fn lock(&self) -> MutexGuard<'_, CachedSessions> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+
+    /// Get a session's state and version, if it is cached, fresh and hasn't expired
+    pub(crate) fn get(
+        &self,
+        session_key: &str,
+        now: DateTime<Utc>,
+    ) -> Option<(HashMap<String, String>, i64)> {
+        let mut sessions = self.lock();
+        let tick = sessions.next_tick();
+
+        let session = sessions.sessions.get_mut(session_key)?;
+        let stale = (now - session.cached_at)
+            .to_std()
+            .is_ok_and(|age| age > self.ttl);
+        if session.expired_after < now || stale {
+            sessions.remove(session_key);
+            return None;
+        }
+        let last_used = std::mem::replace(&mut session.last_used, tick);
+        let cached = (session.session_state.clone(), session.version);
+        sessions.recency.remove(&last_used);
+        sessions.recency.insert(tick, session_key.to_string());
+        Some(cached)
+    }
+
+    /// Cache a session's state, evicting the least recently used session if the cache is full
+    pub(crate) fn insert(
+        &self,
+        session_key: String,
+        session_state: HashMap<String, String>,
+        version: i64,
+        expired_after: DateTime<Utc>,
+        user_id: Option<String>,
+        now: DateTime<Utc>,
+    ) {
+        let mut sessions = self.lock();
+        let tick = sessions.next_tick();
+
+        sessions.remove(&session_key);
+        while sessions.sessions.len() >= self.capacity && sessions.evict() {}
+
+        sessions.recency.insert(tick, session_key.clone());
+        sessions.sessions.insert(
+            session_key,
+            CachedSession {
+                session_state,
+                version,
+                expired_after,
+                user_id,
+                cached_at: now,
+                last_used: tick,
+            },
+        );
+    }
+
+    /// Update the expiry of a cached session
+    pub(crate) fn set_expiry(&self, session_key: &str, expired_after: DateTime<Utc>) {
+        if let Some(session) = self.lock().sessions.get_mut(session_key) {
+            session.expired_after = expired_after;
+        }
+    }
+
+    /// Remove a session from the cache
+    pub(crate) fn remove(&self, session_key: &str) {
+        self.lock().remove(session_key);
+    }
+
+    /// Remove all sessions from the cache
+    pub(crate) fn clear(&self) {
+        let mut sessions = self.lock();
+        sessions.sessions.clear();
+        sessions.recency.clear();
+    }
+
+    /// Remove all sessions of a user from the cache
+    pub(crate) fn remove_user(&self, user_id: &str) {
+        let mut cached = self.lock();
+        let CachedSessions {
+            sessions, recency, ..
+        } = &mut *cached;
+        sessions.retain(|_, session| {
+            let keep = session.user_id.as_deref() != Some(user_id);
+            if !keep {
+                recency.remove(&session.last_used);
+            }
+            keep
+        });
+    }
+}
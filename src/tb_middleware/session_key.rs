@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use rand::distributions::{Alphanumeric, DistString};
+use rand::seq::SliceRandom;
+#[cfg(any(feature = "__session", feature = "session-redis"))]
+use sha2::{Digest, Sha256};
+
+/**
+Generates the keys new sessions are stored under
+
+The key identifies the session, so it has to be unguessable.
+It is encrypted before being stored in the session cookie and mustn't exceed 4064 bytes.
+The stores generate keys until they find an unused one, so don't return the same key forever.
+
+Closures returning a [String] implement this trait,
+e.g. to use deterministic keys in tests:
+
+```
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix_toolbox::tb_middleware::SessionKeyGenerator;
+
+let counter = AtomicUsize::new(0);
+let generator = move || format!("session-{}", counter.fetch_add(1, Ordering::Relaxed));
+assert_eq!(generator.generate(), "session-0");
+```
+*/
+pub trait SessionKeyGenerator: Send + Sync + 'static {
+    /// Generate a new session key
+    fn generate(&self) -> String;
+}
+
+impl<F: Fn() -> String + Send + Sync + 'static> SessionKeyGenerator for F {
+    fn generate(&self) -> String {
+        self()
+    }
+}
+
+/**
+[SessionKeyGenerator] choosing random characters using the thread local rng
+
+Defaults to 512 alphanumeric characters.
+*/
+#[derive(Clone, Debug)]
+pub struct RandomSessionKey {
+    length: usize,
+    alphabet: Option<Vec<char>>,
+}
+
+impl RandomSessionKey {
+    /// Create a generator for alphanumeric keys of the given length
+    pub fn new(length: usize) -> Self {
+        Self {
+            length,
+            alphabet: None,
+        }
+    }
+
+    /// Choose the characters from `alphabet` instead of the alphanumeric ones
+    ///
+    /// An empty alphabet is ignored.
+    pub fn with_alphabet(mut self, alphabet: &str) -> Self {
+        let alphabet: Vec<char> = alphabet.chars().collect();
+        self.alphabet = (!alphabet.is_empty()).then_some(alphabet);
+        self
+    }
+}
+
+impl Default for RandomSessionKey {
+    fn default() -> Self {
+        Self::new(512)
+    }
+}
+
+impl SessionKeyGenerator for RandomSessionKey {
+    fn generate(&self) -> String {
+        let mut rng = rand::thread_rng();
+        match &self.alphabet {
+            Some(alphabet) => (0..self.length)
+                .filter_map(|_| alphabet.choose(&mut rng))
+                .collect(),
+            None => Alphanumeric.sample_string(&mut rng, self.length),
+        }
+    }
+}
+
+/// The [SessionKeyGenerator] shared by all clones of a session store
+pub(crate) type SharedKeyGenerator = Arc<dyn SessionKeyGenerator>;
+
+/// Hash a session key before it is stored in or looked up from a store
+///
+/// Only the user's cookie contains the key itself,
+/// so the sessions can't be hijacked using a leaked copy of the store.
+#[cfg(any(feature = "__session", feature = "session-redis"))]
+pub(crate) fn hash_session_key(session_key: &str) -> String {
+    format!("{:x}", Sha256::digest(session_key.as_bytes()))
+}
+
+/// Check whether a key looks like it was produced by [hash_session_key]
+///
+/// Such keys are never looked up as plain keys, as they could be copied from a leaked store.
+#[cfg(any(feature = "__session", feature = "session-redis"))]
+pub(crate) fn is_hashed_session_key(session_key: &str) -> bool {
+    session_key.len() == 64
+        && session_key
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
@@ -0,0 +1,134 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+
+use actix_session::SessionExt;
+use actix_web::body::MessageBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::USER_AGENT;
+use actix_web::{Error, HttpRequest};
+use futures::future::LocalBoxFuture;
+
+use crate::tb_middleware::{
+    real_ip, Session, TrustedProxies, SESSION_CLIENT_IP, SESSION_USER_AGENT,
+};
+
+/// Maximum number of characters of the user agent stored in the session
+const MAX_USER_AGENT_LEN: usize = 1024;
+
+/**
+Middleware recording the client's address and user agent in its session
+
+The [DBSessionStore](crate::tb_middleware::DBSessionStore) persists them in the
+[DBSession](crate::tb_middleware::DBSession)'s columns, along with the time the session
+was created and last accessed.
+This allows showing users the devices they are logged in on.
+
+Only sessions which already contain some state are touched,
+so anonymous visitors don't get a session just for their metadata.
+The values are only written when they changed.
+
+The client's address is resolved using [real_ip] with the [TrustedProxies] registered as app data,
+so the address of the client is recorded instead of the one of your reverse proxy.
+
+It has to be wrapped before the [SessionMiddleware](crate::tb_middleware::SessionMiddleware),
+so that it runs inside of it:
+
+```no_run
+use actix_toolbox::tb_middleware::{DBSessionStore, SessionMetadata, SessionMiddleware};
+use actix_web::cookie::Key;
+use actix_web::App;
+
+# fn build(store: DBSessionStore, key: Key) {
+let app = App::new()
+    .wrap(SessionMetadata::new())
+    .wrap(SessionMiddleware::new(store, key));
+# }
+```
+*/
+#[derive(Clone, Debug, Default)]
+pub struct SessionMetadata;
+
+impl SessionMetadata {
+    /// Create the middleware
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+/// Resolve the client's address using the registered [TrustedProxies]
+fn client_ip(request: &HttpRequest) -> Option<String> {
+    let ip = match request.app_data::<TrustedProxies>() {
+        Some(trusted) => real_ip(request, trusted),
+        None => real_ip(request, &TrustedProxies::default()),
+    };
+    ip.map(|ip| ip.to_string())
+}
+
+/// Store a value in the session, if it differs from the current one
+fn set_if_changed(session: &Session, key: &str, value: Option<String>) {
+    let Some(value) = value else {
+        return;
+    };
+    if session.get::<String>(key).ok().flatten().as_ref() != Some(&value) {
+        // A string can always be serialized
+        let _ = session.insert(key, value);
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SessionMetadata
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SessionMetadataMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SessionMetadataMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// Service created by [SessionMetadata]
+pub struct SessionMetadataMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for SessionMetadataMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let client_ip = client_ip(req.request());
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LEN).collect());
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+
+            // The handler might have just logged the user in, so check the session afterwards
+            let session = res.request().get_session();
+            if !session.entries().is_empty() {
+                set_if_changed(&session, SESSION_CLIENT_IP, client_ip);
+                set_if_changed(&session, SESSION_USER_AGENT, user_agent);
+            }
+
+            Ok(res)
+        })
+    }
+}
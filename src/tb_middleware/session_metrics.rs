@@ -0,0 +1,198 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Upper bounds of the latency histogram's buckets in milliseconds
+const LATENCY_BUCKETS: [u64; 10] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000];
+
+#[derive(Debug, Default)]
+struct SessionCounters {
+    loads: AtomicU64,
+    cache_hits: AtomicU64,
+    expired_on_load: AtomicU64,
+    saves: AtomicU64,
+    updates: AtomicU64,
+    deletes: AtomicU64,
+    errors: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_micros: AtomicU64,
+}
+
+/**
+Counters describing the operations of a [DBSessionStore](crate::tb_middleware::DBSessionStore)
+
+Get it using [DBSessionStore::metrics](crate::tb_middleware::DBSessionStore::metrics).
+Cloning it is cheap and all clones share the same counters.
+
+Use [SessionMetrics::snapshot] to read the counters
+or [SessionMetrics::prometheus] to expose them to Prometheus:
+
+```no_run
+use actix_toolbox::tb_middleware::SessionMetrics;
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+
+async fn metrics(session_metrics: Data<SessionMetrics>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(session_metrics.prometheus())
+}
+```
+*/
+#[derive(Clone, Debug, Default)]
+pub struct SessionMetrics(Arc<SessionCounters>);
+
+/// Values of the [SessionMetrics] at one point in time
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SessionMetricsSnapshot {
+    /// Number of sessions loaded, including the ones which didn't exist
+    pub loads: u64,
+    /// Number of sessions loaded from the cache instead of the database
+    pub cache_hits: u64,
+    /// Number of sessions which had expired when they were loaded
+    pub expired_on_load: u64,
+    /// Number of new sessions
+    pub saves: u64,
+    /// Number of sessions whose state or expiry was updated
+    pub updates: u64,
+    /// Number of deleted sessions
+    pub deletes: u64,
+    /// Number of failed database queries
+    pub errors: u64,
+    /// Number of database queries
+    pub latency_count: u64,
+    /// Accumulated latency of the database queries
+    pub latency_sum: Duration,
+    /// Upper bounds of the latency buckets in milliseconds and the number of queries
+    /// which took at most as long
+    pub latency_buckets: Vec<(u64, u64)>,
+}
+
+impl SessionMetrics {
+    pub(crate) fn load(&self) {
+        self.0.loads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn cache_hit(&self) {
+        self.0.cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn expired_on_load(&self) {
+        self.0.expired_on_load.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn save(&self) {
+        self.0.saves.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn update(&self) {
+        self.0.updates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn delete(&self) {
+        self.0.deletes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn error(&self) {
+        self.0.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the latency of a database query
+    pub(crate) fn latency(&self, latency: Duration) {
+        let micros = latency.as_micros();
+        for (bucket, upper_bound) in self.0.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            if micros <= upper_bound as u128 * 1000 {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.0.latency_count.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .latency_sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Read the current values of the counters
+    pub fn snapshot(&self) -> SessionMetricsSnapshot {
+        let counters = &self.0;
+        SessionMetricsSnapshot {
+            loads: counters.loads.load(Ordering::Relaxed),
+            cache_hits: counters.cache_hits.load(Ordering::Relaxed),
+            expired_on_load: counters.expired_on_load.load(Ordering::Relaxed),
+            saves: counters.saves.load(Ordering::Relaxed),
+            updates: counters.updates.load(Ordering::Relaxed),
+            deletes: counters.deletes.load(Ordering::Relaxed),
+            errors: counters.errors.load(Ordering::Relaxed),
+            latency_count: counters.latency_count.load(Ordering::Relaxed),
+            latency_sum: Duration::from_micros(counters.latency_sum_micros.load(Ordering::Relaxed)),
+            latency_buckets: LATENCY_BUCKETS
+                .iter()
+                .zip(&counters.latency_buckets)
+                .map(|(upper_bound, bucket)| (*upper_bound, bucket.load(Ordering::Relaxed)))
+                .collect(),
+        }
+    }
+
+    /// Render the counters in Prometheus' text format
+    ///
+    /// The metrics are prefixed with `session_store_`.
+    pub fn prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut out = String::new();
+
+        for (name, help, value) in [
+            ("loads", "Sessions loaded", snapshot.loads),
+            (
+                "cache_hits",
+                "Sessions loaded from the cache",
+                snapshot.cache_hits,
+            ),
+            (
+                "expired_on_load",
+                "Sessions which had expired when they were loaded",
+                snapshot.expired_on_load,
+            ),
+            ("saves", "New sessions", snapshot.saves),
+            ("updates", "Updated sessions", snapshot.updates),
+            ("deletes", "Deleted sessions", snapshot.deletes),
+            ("errors", "Failed database queries", snapshot.errors),
+        ] {
+            let _ = writeln!(out, "# HELP session_store_{name}_total {help}");
+            let _ = writeln!(out, "# TYPE session_store_{name}_total counter");
+            let _ = writeln!(out, "session_store_{name}_total {value}");
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP session_store_query_duration_seconds Latency of the database queries"
+        );
+        let _ = writeln!(out, "# TYPE session_store_query_duration_seconds histogram");
+        for (upper_bound, count) in &snapshot.latency_buckets {
+            let _ = writeln!(
+                out,
+                "session_store_query_duration_seconds_bucket{{le=\"{}\"}} {count}",
+                *upper_bound as f64 / 1000.0
+            );
+        }
+        let _ = writeln!(
+            out,
+            "session_store_query_duration_seconds_bucket{{le=\"+Inf\"}} {}",
+            snapshot.latency_count
+        );
+        let _ = writeln!(
+            out,
+            "session_store_query_duration_seconds_sum {}",
+            snapshot.latency_sum.as_secs_f64()
+        );
+        let _ = writeln!(
+            out,
+            "session_store_query_duration_seconds_count {}",
+            snapshot.latency_count
+        );
+
+        out
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use actix_session::storage::SessionStore;
+use actix_web::cookie::time::Duration;
+use async_trait::async_trait;
+
+/// Session moved from one store to another by [migrate_sessions]
+#[derive(Clone, Debug)]
+pub struct MigratedSession {
+    /// Key of the session, as it is stored in the user's cookie
+    pub session_key: String,
+
+    /// State of the session
+    pub session_state: HashMap<String, String>,
+
+    /// Time until the session expires
+    pub ttl: Duration,
+}
+
+/// [SessionStore] whose sessions can be listed to migrate them to another store
+///
+/// The [DBSessionStore](crate::tb_middleware::DBSessionStore) and the
+/// [RedisSessionStore](crate::tb_middleware::RedisSessionStore) only keep the hashes of
+/// the sessions' keys, so their sessions can't be migrated to another store.
+#[async_trait(?Send)]
+pub trait ExportSessions: SessionStore {
+    /// Get all sessions which haven't expired
+    async fn export_sessions(&self) -> Result<Vec<MigratedSession>, anyhow::Error>;
+}
+
+/// [SessionStore] which can store a session under a given key to migrate it from another store
+#[async_trait(?Send)]
+pub trait ImportSessions: SessionStore {
+    /// Store a session, replacing the session with the same key if there is one
+    async fn import_session(&self, session: MigratedSession) -> Result<(), anyhow::Error>;
+}
+
+/**
+Copy all active sessions from one store to another
+
+The sessions keep their keys, so the users' cookies stay valid and switching
+to the new store doesn't log every user out.
+Changes made to the sessions while they are migrated are lost,
+so run it before the application starts serving requests with the new store.
+
+Returns the number of migrated sessions.
+
+Sessions can be migrated from the [MemorySessionStore](crate::tb_middleware::MemorySessionStore)
+to any of the stores:
+
+```no_run
+use actix_toolbox::tb_middleware::{migrate_sessions, ExportSessions, ImportSessions};
+
+async fn switch_store(
+    old: impl ExportSessions,
+    new: impl ImportSessions,
+) -> Result<(), anyhow::Error> {
+    let migrated = migrate_sessions(&old, &new).await?;
+    println!("Migrated {migrated} sessions");
+    Ok(())
+}
+```
+*/
+pub async fn migrate_sessions(
+    from: &impl ExportSessions,
+    to: &impl ImportSessions,
+) -> Result<usize, anyhow::Error> {
+    let mut migrated = 0;
+    for session in from.export_sessions().await? {
+        if !session.ttl.is_positive() {
+            continue;
+        }
+        to.import_session(session).await?;
+        migrated += 1;
+    }
+    Ok(migrated)
+}
@@ -0,0 +1,431 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rorm::conditions::DynamicCollection;
+use rorm::db::Executor;
+use rorm::{and, delete, insert, query, update, Database, FieldAccess, Model};
+
+use crate::tb_middleware::DBSession;
+
+/**
+Table the [DBSessionStore](crate::tb_middleware::DBSessionStore) keeps the sessions in
+
+It is implemented by [DBSession], which is used by default.
+Implement it for your own model to use an existing table,
+e.g. one with a different name, different column names or a shorter key column.
+The sessions are passed as [DBSession]s, map them to your model's columns.
+
+Sessions are identified by the hex encoded SHA-256 hash of their key, which is 64 characters long.
+The methods writing sessions accept any rorm executor, so they can run in a transaction,
+see [DBSessionStore::save_in](crate::tb_middleware::DBSessionStore::save_in).
+
+```no_run
+use actix_toolbox::tb_middleware::{DBSession, DBSessionStore, SessionModel};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rorm::db::Executor;
+use rorm::{delete, insert, query, update, Database, FieldAccess, Model};
+
+#[derive(Model, Debug, Clone)]
+struct Sessions {
+    #[rorm(primary_key)]
+    #[rorm(max_length = 64)]
+    id: String,
+
+    #[rorm(max_length = 16383)]
+    data: Option<String>,
+
+    expires: DateTime<Utc>,
+}
+
+impl From<Sessions> for DBSession {
+    fn from(session: Sessions) -> Self {
+        DBSession {
+            session_key: session.id,
+            session_state: session.data,
+            session_data: None,
+            expired_after: session.expires,
+            user_id: None,
+            created_at: session.expires,
+            last_accessed: session.expires,
+            client_ip: None,
+            user_agent: None,
+            version: 0,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl SessionModel for Sessions {
+    async fn load(db: &Database, session_key: &str) -> Result<Option<DBSession>, rorm::Error> {
+        Ok(query!(db, Sessions)
+            .condition(Sessions::F.id.equals(session_key))
+            .optional()
+            .await?
+            .map(DBSession::from))
+    }
+
+    async fn exists(db: &Database, session_key: &str) -> Result<bool, rorm::Error> {
+        Ok(query!(db, (Sessions::F.id,))
+            .condition(Sessions::F.id.equals(session_key))
+            .optional()
+            .await?
+            .is_some())
+    }
+
+    async fn insert(executor: impl Executor<'_>, session: &DBSession) -> Result<(), rorm::Error> {
+        let session = Sessions {
+            id: session.session_key.clone(),
+            data: session.session_state.clone(),
+            expires: session.expired_after,
+        };
+        insert!(executor, Sessions).single(&session).await?;
+        Ok(())
+    }
+
+    async fn update(
+        executor: impl Executor<'_>,
+        session: &DBSession,
+        _expected_version: Option<i64>,
+    ) -> Result<bool, rorm::Error> {
+        update!(executor, Sessions)
+            .condition(Sessions::F.id.equals(&session.session_key))
+            .set(Sessions::F.data, session.session_state.clone())
+            .set(Sessions::F.expires, session.expired_after)
+            .exec()
+            .await?;
+        Ok(true)
+    }
+
+    async fn update_expiry(
+        executor: impl Executor<'_>,
+        session_key: &str,
+        expired_after: DateTime<Utc>,
+        _last_accessed: DateTime<Utc>,
+    ) -> Result<(), rorm::Error> {
+        update!(executor, Sessions)
+            .condition(Sessions::F.id.equals(session_key))
+            .set(Sessions::F.expires, expired_after)
+            .exec()
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(executor: impl Executor<'_>, session_key: &str) -> Result<(), rorm::Error> {
+        delete!(executor, Sessions)
+            .condition(Sessions::F.id.equals(session_key))
+            .await?;
+        Ok(())
+    }
+
+    async fn touch(
+        _executor: impl Executor<'_>,
+        _session_keys: &[String],
+        _last_accessed: DateTime<Utc>,
+    ) -> Result<(), rorm::Error> {
+        Ok(())
+    }
+
+    async fn for_user(_db: &Database, _user_id: &str) -> Result<Vec<DBSession>, rorm::Error> {
+        Ok(Vec::new())
+    }
+
+    async fn delete_for_user(_executor: impl Executor<'_>, _user_id: &str) -> Result<(), rorm::Error> {
+        Ok(())
+    }
+
+    async fn count_active(db: &Database, now: DateTime<Utc>) -> Result<u64, rorm::Error> {
+        let (count,) = query!(db, (Sessions::F.id.count(),))
+            .condition(Sessions::F.expires.greater_or_equals(now))
+            .one()
+            .await?;
+        Ok(count as u64)
+    }
+
+    async fn list_active(
+        db: &Database,
+        now: DateTime<Utc>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<DBSession>, rorm::Error> {
+        Ok(query!(db, Sessions)
+            .condition(Sessions::F.expires.greater_or_equals(now))
+            .order_desc(Sessions::F.expires)
+            .limit(limit)
+            .offset(offset)
+            .all()
+            .await?
+            .into_iter()
+            .map(DBSession::from)
+            .collect())
+    }
+
+    async fn accessed_since(
+        _db: &Database,
+        _since: DateTime<Utc>,
+        _now: DateTime<Utc>,
+    ) -> Result<Vec<DBSession>, rorm::Error> {
+        Ok(Vec::new())
+    }
+
+    async fn delete_all(executor: impl Executor<'_>) -> Result<(), rorm::Error> {
+        delete!(executor, Sessions).all().await?;
+        Ok(())
+    }
+}
+
+# fn build(db: Database) {
+let store = DBSessionStore::<Sessions>::with_model(db);
+# }
+```
+
+This table has no binary column, so it only supports
+[SessionStateFormat::Json](crate::tb_middleware::SessionStateFormat::Json) in the
+[text column](crate::tb_middleware::SessionStateColumn::Text),
+and neither associates sessions with users nor tracks when they were accessed.
+Without a version column, concurrent updates can't be detected,
+so every update overwrites the others.
+*/
+#[async_trait(?Send)]
+pub trait SessionModel: 'static {
+    /// Load a session
+    async fn load(db: &Database, session_key: &str) -> Result<Option<DBSession>, rorm::Error>;
+
+    /// Check whether a session with the key exists, regardless of whether it has expired
+    async fn exists(db: &Database, session_key: &str) -> Result<bool, rorm::Error>;
+
+    /// Insert a new session
+    ///
+    /// Fail if a session with the same key exists.
+    async fn insert(executor: impl Executor<'_>, session: &DBSession) -> Result<(), rorm::Error>;
+
+    /// Update all columns of a session except its key and [DBSession::created_at]
+    ///
+    /// If `expected_version` is set, only update the session if its [DBSession::version] matches.
+    /// Returns whether a session was updated.
+    async fn update(
+        executor: impl Executor<'_>,
+        session: &DBSession,
+        expected_version: Option<i64>,
+    ) -> Result<bool, rorm::Error>;
+
+    /// Update a session's [DBSession::expired_after] and [DBSession::last_accessed]
+    async fn update_expiry(
+        executor: impl Executor<'_>,
+        session_key: &str,
+        expired_after: DateTime<Utc>,
+        last_accessed: DateTime<Utc>,
+    ) -> Result<(), rorm::Error>;
+
+    /// Delete a session
+    async fn delete(executor: impl Executor<'_>, session_key: &str) -> Result<(), rorm::Error>;
+
+    /// Update the [DBSession::last_accessed] of several sessions in a single query,
+    /// unless it is more recent already
+    ///
+    /// At most 100 sessions are passed at once.
+    async fn touch(
+        executor: impl Executor<'_>,
+        session_keys: &[String],
+        last_accessed: DateTime<Utc>,
+    ) -> Result<(), rorm::Error>;
+
+    /// Get all sessions with the [DBSession::user_id], including the expired ones
+    async fn for_user(db: &Database, user_id: &str) -> Result<Vec<DBSession>, rorm::Error>;
+
+    /// Delete all sessions with the [DBSession::user_id]
+    async fn delete_for_user(executor: impl Executor<'_>, user_id: &str)
+        -> Result<(), rorm::Error>;
+
+    /// Count the sessions which haven't expired at `now`
+    async fn count_active(db: &Database, now: DateTime<Utc>) -> Result<u64, rorm::Error>;
+
+    /// Get a page of the sessions which haven't expired at `now`,
+    /// the most recently accessed ones first
+    async fn list_active(
+        db: &Database,
+        now: DateTime<Utc>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<DBSession>, rorm::Error>;
+
+    /// Get the sessions which haven't expired at `now` and were accessed since `since`,
+    /// the most recently accessed ones first
+    async fn accessed_since(
+        db: &Database,
+        since: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<DBSession>, rorm::Error>;
+
+    /// Delete all sessions
+    async fn delete_all(executor: impl Executor<'_>) -> Result<(), rorm::Error>;
+}
+
+#[async_trait(?Send)]
+impl SessionModel for DBSession {
+    async fn load(db: &Database, session_key: &str) -> Result<Option<DBSession>, rorm::Error> {
+        query!(db, DBSession)
+            .condition(DBSession::F.session_key.equals(session_key))
+            .optional()
+            .await
+    }
+
+    async fn exists(db: &Database, session_key: &str) -> Result<bool, rorm::Error> {
+        Ok(query!(db, (DBSession::F.session_key,))
+            .condition(DBSession::F.session_key.equals(session_key))
+            .optional()
+            .await?
+            .is_some())
+    }
+
+    async fn insert(executor: impl Executor<'_>, session: &DBSession) -> Result<(), rorm::Error> {
+        insert!(executor, DBSession).single(session).await?;
+        Ok(())
+    }
+
+    async fn update(
+        executor: impl Executor<'_>,
+        session: &DBSession,
+        expected_version: Option<i64>,
+    ) -> Result<bool, rorm::Error> {
+        let updated = match expected_version {
+            Some(version) => {
+                update!(executor, DBSession)
+                    .condition(and!(
+                        DBSession::F.session_key.equals(&session.session_key),
+                        DBSession::F.version.equals(version),
+                    ))
+                    .set(DBSession::F.session_state, session.session_state.clone())
+                    .set(DBSession::F.session_data, session.session_data.clone())
+                    .set(DBSession::F.expired_after, session.expired_after)
+                    .set(DBSession::F.user_id, session.user_id.clone())
+                    .set(DBSession::F.last_accessed, session.last_accessed)
+                    .set(DBSession::F.client_ip, session.client_ip.clone())
+                    .set(DBSession::F.user_agent, session.user_agent.clone())
+                    .set(DBSession::F.version, session.version)
+                    .exec()
+                    .await?
+            }
+            None => {
+                update!(executor, DBSession)
+                    .condition(DBSession::F.session_key.equals(&session.session_key))
+                    .set(DBSession::F.session_state, session.session_state.clone())
+                    .set(DBSession::F.session_data, session.session_data.clone())
+                    .set(DBSession::F.expired_after, session.expired_after)
+                    .set(DBSession::F.user_id, session.user_id.clone())
+                    .set(DBSession::F.last_accessed, session.last_accessed)
+                    .set(DBSession::F.client_ip, session.client_ip.clone())
+                    .set(DBSession::F.user_agent, session.user_agent.clone())
+                    .set(DBSession::F.version, session.version)
+                    .exec()
+                    .await?
+            }
+        };
+        Ok(updated > 0)
+    }
+
+    async fn update_expiry(
+        executor: impl Executor<'_>,
+        session_key: &str,
+        expired_after: DateTime<Utc>,
+        last_accessed: DateTime<Utc>,
+    ) -> Result<(), rorm::Error> {
+        update!(executor, DBSession)
+            .condition(DBSession::F.session_key.equals(session_key))
+            .set(DBSession::F.expired_after, expired_after)
+            .set(DBSession::F.last_accessed, last_accessed)
+            .exec()
+            .await?;
+        Ok(())
+    }
+
+    async fn delete(executor: impl Executor<'_>, session_key: &str) -> Result<(), rorm::Error> {
+        delete!(executor, DBSession)
+            .condition(DBSession::F.session_key.equals(session_key))
+            .await?;
+        Ok(())
+    }
+
+    async fn touch(
+        executor: impl Executor<'_>,
+        session_keys: &[String],
+        last_accessed: DateTime<Utc>,
+    ) -> Result<(), rorm::Error> {
+        if session_keys.is_empty() {
+            return Ok(());
+        }
+        update!(executor, DBSession)
+            .condition(and!(
+                DynamicCollection::or(
+                    session_keys
+                        .iter()
+                        .map(|session_key| DBSession::F.session_key.equals(session_key))
+                        .collect()
+                ),
+                DBSession::F.last_accessed.less_than(last_accessed),
+            ))
+            .set(DBSession::F.last_accessed, last_accessed)
+            .exec()
+            .await?;
+        Ok(())
+    }
+
+    async fn for_user(db: &Database, user_id: &str) -> Result<Vec<DBSession>, rorm::Error> {
+        query!(db, DBSession)
+            .condition(DBSession::F.user_id.equals(user_id))
+            .all()
+            .await
+    }
+
+    async fn delete_for_user(
+        executor: impl Executor<'_>,
+        user_id: &str,
+    ) -> Result<(), rorm::Error> {
+        delete!(executor, DBSession)
+            .condition(DBSession::F.user_id.equals(user_id))
+            .await?;
+        Ok(())
+    }
+
+    async fn count_active(db: &Database, now: DateTime<Utc>) -> Result<u64, rorm::Error> {
+        let (count,) = query!(db, (DBSession::F.session_key.count(),))
+            .condition(DBSession::F.expired_after.greater_or_equals(now))
+            .one()
+            .await?;
+        Ok(count as u64)
+    }
+
+    async fn list_active(
+        db: &Database,
+        now: DateTime<Utc>,
+        offset: u64,
+        limit: u64,
+    ) -> Result<Vec<DBSession>, rorm::Error> {
+        query!(db, DBSession)
+            .condition(DBSession::F.expired_after.greater_or_equals(now))
+            .order_desc(DBSession::F.last_accessed)
+            .limit(limit)
+            .offset(offset)
+            .all()
+            .await
+    }
+
+    async fn accessed_since(
+        db: &Database,
+        since: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<DBSession>, rorm::Error> {
+        query!(db, DBSession)
+            .condition(and!(
+                DBSession::F.expired_after.greater_or_equals(now),
+                DBSession::F.last_accessed.greater_or_equals(since),
+            ))
+            .order_desc(DBSession::F.last_accessed)
+            .all()
+            .await
+    }
+
+    async fn delete_all(executor: impl Executor<'_>) -> Result<(), rorm::Error> {
+        delete!(executor, DBSession).all().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{DateTime, Duration, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Configuration of how the [DBSessionStore](crate::tb_middleware::DBSessionStore)
+/// tracks when its sessions were last seen
+///
+/// Enable it using [DBSessionStore::with_presence](crate::tb_middleware::DBSessionStore::with_presence).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionPresence {
+    /// Seconds between two writes of the sessions' last seen times
+    ///
+    /// The times are collected in memory in the meantime,
+    /// so a session is written at most once per interval, regardless of its number of requests.
+    ///
+    /// Defaults to 60 seconds
+    #[serde(default = "default_flush_interval")]
+    pub flush_interval: u64,
+
+    /// Seconds since a user's session was last seen for them to count as online
+    ///
+    /// Defaults to 5 minutes
+    #[serde(default = "default_online_window")]
+    pub online_window: u64,
+}
+fn default_flush_interval() -> u64 {
+    60
+}
+fn default_online_window() -> u64 {
+    5 * 60
+}
+impl Default for SessionPresence {
+    fn default() -> Self {
+        Self {
+            flush_interval: default_flush_interval(),
+            online_window: default_online_window(),
+        }
+    }
+}
+
+impl SessionPresence {
+    /// Get the [SessionPresence::online_window] as [Duration]
+    pub(crate) fn online_window(&self) -> Duration {
+        Duration::seconds(self.online_window as i64)
+    }
+}
+
+#[derive(Default)]
+struct SeenSessions {
+    /// Time each session was last seen, keyed by their hashed key
+    sessions: HashMap<String, DateTime<Utc>>,
+    /// Time the sessions were last written
+    flushed_at: Option<DateTime<Utc>>,
+}
+
+/// Collects the times the sessions loaded by the [DBSessionStore](crate::tb_middleware::DBSessionStore)
+/// were last seen until they are written in a batch
+pub(crate) struct PresenceTracker {
+    pub(crate) config: SessionPresence,
+    seen: Mutex<SeenSessions>,
+}
+
+impl PresenceTracker {
+    pub(crate) fn new(config: SessionPresence) -> Self {
+        Self {
+            config,
+            seen: Mutex::new(SeenSessions::default()),
+        }
+    }
+
+    /// Lock the seen sessions
+    ///
+    /// A panic while holding the lock can't leave the map in an inconsistent state,
+    /// so a poisoned lock is used anyway.
+    fn lock(&self) -> MutexGuard<'_, SeenSessions> {
+        self.seen
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+
+    /// Record that a session was seen
+    ///
+    /// The time is truncated to whole seconds,
+    /// so the sessions seen within the same second can be written together.
+    ///
+    /// Returns the seen sessions to write if the flush interval has passed.
+    pub(crate) fn seen(
+        &self,
+        session_key: &str,
+        now: DateTime<Utc>,
+    ) -> Option<HashMap<String, DateTime<Utc>>> {
+        let mut seen = self.lock();
+        seen.sessions
+            .insert(session_key.to_string(), now.trunc_subsecs(0));
+
+        let interval = Duration::seconds(self.config.flush_interval as i64);
+        match seen.flushed_at {
+            Some(flushed_at) if now < flushed_at + interval => None,
+            // Start the interval with the first session instead of writing it right away
+            None => {
+                seen.flushed_at = Some(now);
+                None
+            }
+            Some(_) => {
+                seen.flushed_at = Some(now);
+                Some(std::mem::take(&mut seen.sessions))
+            }
+        }
+    }
+
+    /// Take all seen sessions to write them
+    pub(crate) fn take(&self, now: DateTime<Utc>) -> HashMap<String, DateTime<Utc>> {
+        let mut seen = self.lock();
+        seen.flushed_at = Some(now);
+        std::mem::take(&mut seen.sessions)
+    }
+}
@@ -0,0 +1,133 @@
+use std::future::IntoFuture;
+use std::time::Duration;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Configuration of how often the [DBSessionStore](crate::tb_middleware::DBSessionStore)
+/// retries a query if the database can't be reached
+///
+/// Only errors caused by the connection to the database are retried.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRetry {
+    /// Number of attempts before giving up
+    ///
+    /// Defaults to 1, i.e. no retries
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+
+    /// Milliseconds to wait before the first retry
+    ///
+    /// The delay is doubled for every further retry.
+    ///
+    /// Defaults to 50 milliseconds
+    #[serde(default = "default_initial_delay")]
+    pub initial_delay: u64,
+
+    /// Maximum milliseconds to wait between two attempts
+    ///
+    /// Defaults to 1 second
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+}
+fn default_attempts() -> u32 {
+    1
+}
+fn default_initial_delay() -> u64 {
+    50
+}
+fn default_max_delay() -> u64 {
+    1000
+}
+impl Default for SessionRetry {
+    fn default() -> Self {
+        Self {
+            attempts: default_attempts(),
+            initial_delay: default_initial_delay(),
+            max_delay: default_max_delay(),
+        }
+    }
+}
+
+impl SessionRetry {
+    /// Run a query until it succeeds, fails permanently or the attempts are exhausted
+    pub(crate) async fn run<T, F: IntoFuture<Output = Result<T, rorm::Error>>>(
+        &self,
+        mut query: impl FnMut() -> F,
+    ) -> Result<T, SessionDatabaseError> {
+        let mut delay = Duration::from_millis(self.initial_delay);
+        let mut attempt = 1;
+        loop {
+            match query().await {
+                Ok(value) => return Ok(value),
+                Err(err) if is_transient(&err) => {
+                    if attempt >= self.attempts {
+                        return Err(SessionDatabaseError::Transient(err));
+                    }
+                    warn!("Couldn't reach the session database (attempt {attempt}): {err}");
+                    actix_web::rt::time::sleep(delay).await;
+                    delay = (delay * 2).min(Duration::from_millis(self.max_delay));
+                    attempt += 1;
+                }
+                Err(err) => return Err(SessionDatabaseError::Permanent(err)),
+            }
+        }
+    }
+}
+
+/// Check whether retrying a failed query might succeed
+///
+/// The database driver's errors aren't exposed, so this looks for an io error causing it.
+fn is_transient(err: &rorm::Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(err) = source {
+        if err.is::<std::io::Error>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Error of a query run by the [DBSessionStore](crate::tb_middleware::DBSessionStore)
+///
+/// It is wrapped in the [anyhow::Error] of the session store's errors.
+/// Use [anyhow::Error::downcast_ref] to tell both kinds apart, e.g. to respond with
+/// `503 Service Unavailable` instead of `500 Internal Server Error`.
+#[derive(Debug)]
+pub enum SessionDatabaseError {
+    /// The database couldn't be reached, even after retrying according to the [SessionRetry]
+    Transient(rorm::Error),
+
+    /// The query failed for another reason, retrying won't help
+    Permanent(rorm::Error),
+}
+
+impl SessionDatabaseError {
+    /// Check whether the database couldn't be reached
+    pub fn is_transient(&self) -> bool {
+        matches!(self, SessionDatabaseError::Transient(_))
+    }
+}
+
+impl std::fmt::Display for SessionDatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionDatabaseError::Transient(err) => {
+                write!(f, "Couldn't reach the session database: {err}")
+            }
+            SessionDatabaseError::Permanent(err) => {
+                write!(f, "The session database query failed: {err}")
+            }
+        }
+    }
+}
+impl std::error::Error for SessionDatabaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SessionDatabaseError::Transient(err) | SessionDatabaseError::Permanent(err) => {
+                Some(err)
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::LocalBoxFuture;
+
+/// Versions of the sessions loaded by the request, keyed by their hashed key
+type LoadedVersions = Rc<RefCell<HashMap<String, i64>>>;
+
+thread_local! {
+    /// Versions of the request currently being polled on this thread
+    static CURRENT: RefCell<Option<LoadedVersions>> = const { RefCell::new(None) };
+}
+
+/// Remember the version of a session loaded by the current request
+///
+/// Does nothing outside of a request wrapped by [SessionVersions].
+pub(crate) fn record_version(hashed_key: &str, version: i64) {
+    CURRENT.with(|current| {
+        if let Some(versions) = &*current.borrow() {
+            versions
+                .borrow_mut()
+                .insert(hashed_key.to_string(), version);
+        }
+    });
+}
+
+/// Take the version of a session as it was loaded by the current request
+///
+/// Returns `None` if the request didn't load the session
+/// or isn't wrapped by [SessionVersions].
+pub(crate) fn take_version(hashed_key: &str) -> Option<i64> {
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .and_then(|versions| versions.borrow_mut().remove(hashed_key))
+    })
+}
+
+/**
+Middleware remembering the versions of the sessions loaded by a request
+
+The [DBSessionStore](crate::tb_middleware::DBSessionStore) needs them to detect sessions
+changed by another request according to its
+[SessionConflictStrategy](crate::tb_middleware::SessionConflictStrategy).
+They are kept out of the session's state, so handlers never see them.
+Without this middleware, every update overwrites the session like
+[SessionConflictStrategy::LastWriteWins](crate::tb_middleware::SessionConflictStrategy::LastWriteWins).
+
+It has to be wrapped after the [SessionMiddleware](crate::tb_middleware::SessionMiddleware),
+so that the session is loaded and updated inside of it:
+
+```no_run
+use actix_toolbox::tb_middleware::{
+    DBSessionStore, SessionConflictStrategy, SessionMiddleware, SessionVersions,
+};
+use actix_web::cookie::Key;
+use actix_web::App;
+
+# fn build(store: DBSessionStore, key: Key) {
+let store = store.with_conflict_strategy(SessionConflictStrategy::Merge);
+let app = App::new()
+    .wrap(SessionMiddleware::new(store, key))
+    .wrap(SessionVersions);
+# }
+```
+*/
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SessionVersions;
+
+impl<S, B> Transform<S, ServiceRequest> for SessionVersions
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SessionVersionsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SessionVersionsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// Service created by [SessionVersions]
+pub struct SessionVersionsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for SessionVersionsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let versions = LoadedVersions::default();
+
+        // Services may already do some work when called, before their future is polled
+        let future = {
+            let _scope = Scope::enter(&versions);
+            self.service.call(req)
+        };
+
+        Box::pin(WithVersions {
+            future: Box::pin(future),
+            versions,
+        })
+    }
+}
+
+/// Future making a request's versions available to the store while it is polled
+///
+/// Requests are interleaved on a thread, so they have to be set again whenever the request's handling resumes.
+struct WithVersions<F> {
+    future: Pin<Box<F>>,
+    versions: LoadedVersions,
+}
+impl<F: Future> Future for WithVersions<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let _scope = Scope::enter(&self.versions);
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Guard restoring the previous request's versions when dropped
+struct Scope(Option<LoadedVersions>);
+impl Scope {
+    fn enter(versions: &LoadedVersions) -> Self {
+        Self(CURRENT.with(|current| current.replace(Some(versions.clone()))))
+    }
+}
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
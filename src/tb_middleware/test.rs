@@ -0,0 +1,334 @@
+//! Conformance tests for [SessionStore] implementations
+//!
+//! The toolbox' stores behave the same way in the edge cases the
+//! [SessionMiddleware](actix_session::SessionMiddleware) runs into,
+//! e.g. updating a session which was deleted by another request.
+//! Run [check_session_store] and [check_session_expiry] against your own store
+//! to make sure it can replace them.
+//!
+//! The checks panic, naming the case which failed.
+//!
+//! ```no_run
+//! use actix_session::storage::SessionStore;
+//! use actix_toolbox::tb_middleware::test::{check_session_expiry, check_session_store};
+//! use actix_web::rt::time::sleep;
+//!
+//! # async fn test(store: impl SessionStore) {
+//! check_session_store(&store).await;
+//! check_session_expiry(&store, |ttl| sleep(ttl.unsigned_abs())).await;
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+
+use actix_session::storage::{SessionKey, SessionStore};
+use actix_web::cookie::time::Duration;
+use rand::distributions::{Alphanumeric, DistString};
+
+/// Ttl of the sessions saved by [check_session_store]
+const TTL: Duration = Duration::hours(1);
+
+/// Build a session state from its entries
+fn state(entries: &[(&str, &str)]) -> HashMap<String, String> {
+    entries
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Generate a key no store has issued
+fn unknown_key() -> SessionKey {
+    let key = Alphanumeric.sample_string(&mut rand::thread_rng(), 64);
+    SessionKey::try_from(key).expect("64 characters are a valid session key")
+}
+
+/// Load a session, panicking if the store fails
+async fn load(
+    store: &impl SessionStore,
+    case: &str,
+    session_key: &SessionKey,
+) -> Option<HashMap<String, String>> {
+    match store.load(session_key).await {
+        Ok(state) => state,
+        Err(err) => panic!("{case}: loading the session failed: {err}"),
+    }
+}
+
+/// Save a session, panicking if the store fails
+async fn save(
+    store: &impl SessionStore,
+    case: &str,
+    session_state: HashMap<String, String>,
+    ttl: Duration,
+) -> SessionKey {
+    match store.save(session_state, &ttl).await {
+        Ok(session_key) => session_key,
+        Err(err) => panic!("{case}: saving the session failed: {err}"),
+    }
+}
+
+/// Update a session, panicking if the store fails
+async fn update(
+    store: &impl SessionStore,
+    case: &str,
+    session_key: SessionKey,
+    session_state: HashMap<String, String>,
+    ttl: Duration,
+) -> SessionKey {
+    match store.update(session_key, session_state, &ttl).await {
+        Ok(session_key) => session_key,
+        Err(err) => panic!("{case}: updating the session failed: {err}"),
+    }
+}
+
+/// Check that a loaded state contains exactly the expected entries
+///
+/// The removed entries are named in the message if they are still there.
+fn assert_state(
+    case: &str,
+    loaded: Option<HashMap<String, String>>,
+    expected: &HashMap<String, String>,
+    removed: &[&str],
+) {
+    let Some(loaded) = loaded else {
+        panic!("{case}: the session doesn't exist");
+    };
+    for (key, value) in expected {
+        assert_eq!(
+            loaded.get(key),
+            Some(value),
+            "{case}: the session's entry {key:?} differs"
+        );
+    }
+    for key in removed {
+        assert!(
+            !loaded.contains_key(*key),
+            "{case}: the session still contains the removed entry {key:?}"
+        );
+    }
+    for key in loaded.keys() {
+        assert!(
+            expected.contains_key(key),
+            "{case}: the session contains the unexpected entry {key:?}"
+        );
+    }
+}
+
+/**
+Check how a store loads, saves, updates and deletes sessions
+
+The checked cases are:
+- Loading a session which doesn't exist returns `None`
+- Loading a saved session returns its state, including empty and non-ascii values
+- Saved sessions without any entries exist
+- Every saved session gets its own key
+- Updating a session replaces its whole state, without changing other sessions
+- Updating a session's ttl keeps its state
+- Deleting a session removes it, deleting it again succeeds
+- Updating a deleted session or its ttl succeeds without bringing it back under its old key
+- Large values are stored as they are
+
+Their expiry is checked by [check_session_expiry].
+*/
+pub async fn check_session_store(store: &impl SessionStore) {
+    let case = "load unknown";
+    assert!(
+        load(store, case, &unknown_key()).await.is_none(),
+        "{case}: a session which was never saved exists"
+    );
+
+    let case = "save and load";
+    let saved = state(&[
+        ("user", "\"alice\""),
+        ("empty", ""),
+        ("unicode", "\"grüße, 世界 🦀\""),
+        ("nested", r#"{"roles":["admin"],"count":3}"#),
+    ]);
+    let session_key = save(store, case, saved.clone(), TTL).await;
+    assert_state(case, load(store, case, &session_key).await, &saved, &[]);
+
+    let case = "save empty";
+    let empty_key = save(store, case, HashMap::new(), TTL).await;
+    assert_state(
+        case,
+        load(store, case, &empty_key).await,
+        &HashMap::new(),
+        &[],
+    );
+
+    let case = "distinct keys";
+    let other_key = save(store, case, saved.clone(), TTL).await;
+    assert_ne!(
+        session_key.as_ref(),
+        other_key.as_ref(),
+        "{case}: two sessions got the same key"
+    );
+    assert_ne!(
+        empty_key.as_ref(),
+        other_key.as_ref(),
+        "{case}: two sessions got the same key"
+    );
+
+    let case = "update";
+    let updated = state(&[("user", "\"bob\""), ("added", "1")]);
+    let session_key = update(store, case, session_key, updated.clone(), TTL).await;
+    assert_state(
+        case,
+        load(store, case, &session_key).await,
+        &updated,
+        &["empty", "unicode", "nested"],
+    );
+    assert_state(
+        "update keeps other sessions",
+        load(store, case, &other_key).await,
+        &saved,
+        &["added"],
+    );
+
+    let case = "update ttl";
+    if let Err(err) = store.update_ttl(&session_key, &TTL).await {
+        panic!("{case}: updating the session's ttl failed: {err}");
+    }
+    assert_state(case, load(store, case, &session_key).await, &updated, &[]);
+
+    let case = "delete";
+    if let Err(err) = store.delete(&session_key).await {
+        panic!("{case}: deleting the session failed: {err}");
+    }
+    assert!(
+        load(store, case, &session_key).await.is_none(),
+        "{case}: the session still exists"
+    );
+    if let Err(err) = store.delete(&session_key).await {
+        panic!("{case}: deleting the session again failed: {err}");
+    }
+    assert_state(
+        "delete keeps other sessions",
+        load(store, case, &other_key).await,
+        &saved,
+        &[],
+    );
+
+    let case = "update ttl of deleted";
+    if let Err(err) = store.update_ttl(&session_key, &TTL).await {
+        panic!("{case}: updating the session's ttl failed: {err}");
+    }
+    assert!(
+        load(store, case, &session_key).await.is_none(),
+        "{case}: the session exists again"
+    );
+
+    let case = "update deleted";
+    let deleted_key = session_key.as_ref().to_string();
+    let session_key = update(store, case, session_key, updated.clone(), TTL).await;
+    let loaded = load(store, case, &session_key).await;
+    if session_key.as_ref() == deleted_key {
+        assert!(
+            loaded.is_none(),
+            "{case}: the session exists again under its old key"
+        );
+    } else {
+        assert_state(case, loaded, &updated, &[]);
+    }
+
+    let case = "large values";
+    let large = state(&[("large", &"x".repeat(8 * 1024))]);
+    let session_key = save(store, case, large.clone(), TTL).await;
+    assert_state(case, load(store, case, &session_key).await, &large, &[]);
+}
+
+/**
+Check that sessions expire after their ttl and that updating them extends it
+
+`elapse` has to let the passed time elapse for the store.
+Stores using a [ManualClock](crate::clock::ManualClock) can advance it,
+other stores have to sleep, which takes about 4 seconds.
+
+The sessions use ttls of a few seconds, so stores only supporting whole seconds can be checked.
+*/
+pub async fn check_session_expiry<F, Fut>(store: &impl SessionStore, mut elapse: F)
+where
+    F: FnMut(Duration) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let ttl = Duration::seconds(2);
+    let saved = state(&[("user", "\"alice\"")]);
+    let updated = state(&[("user", "\"bob\"")]);
+
+    let (expiring, extended, updating) = (
+        "expiry",
+        "update ttl extends expiry",
+        "update extends expiry",
+    );
+    let expiring_key = save(store, expiring, saved.clone(), ttl).await;
+    let extended_key = save(store, extended, saved.clone(), ttl).await;
+    let updating_key = save(store, updating, saved.clone(), ttl).await;
+
+    elapse(Duration::seconds(1)).await;
+    assert_state(
+        expiring,
+        load(store, expiring, &expiring_key).await,
+        &saved,
+        &[],
+    );
+    if let Err(err) = store.update_ttl(&extended_key, &ttl).await {
+        panic!("{extended}: updating the session's ttl failed: {err}");
+    }
+    let updating_key = update(store, updating, updating_key, updated.clone(), ttl).await;
+
+    elapse(Duration::milliseconds(1500)).await;
+    assert!(
+        load(store, expiring, &expiring_key).await.is_none(),
+        "{expiring}: the session still exists after its ttl"
+    );
+    assert_state(
+        extended,
+        load(store, extended, &extended_key).await,
+        &saved,
+        &[],
+    );
+    assert_state(
+        updating,
+        load(store, updating, &updating_key).await,
+        &updated,
+        &[],
+    );
+
+    elapse(Duration::seconds(1)).await;
+    for (case, session_key) in [(extended, &extended_key), (updating, &updating_key)] {
+        assert!(
+            load(store, case, session_key).await.is_none(),
+            "{case}: the session still exists after its extended ttl"
+        );
+    }
+}
+
+#[cfg(all(test, feature = "memory-session"))]
+mod tests {
+    use std::future::ready;
+
+    use chrono::Utc;
+
+    use super::{check_session_expiry, check_session_store};
+    use crate::clock::ManualClock;
+    use crate::tb_middleware::MemorySessionStore;
+
+    #[actix_web::test]
+    async fn memory_session_store() {
+        check_session_store(&MemorySessionStore::new()).await;
+    }
+
+    #[actix_web::test]
+    async fn memory_session_store_expiry() {
+        let clock = ManualClock::new(Utc::now());
+        let store = MemorySessionStore::new().with_clock(clock.clone());
+        check_session_expiry(&store, |duration| {
+            clock.advance(chrono::Duration::milliseconds(
+                duration.whole_milliseconds() as i64,
+            ));
+            ready(())
+        })
+        .await;
+    }
+}
@@ -0,0 +1,68 @@
+use actix_session::{Session, SessionGetError, SessionInsertError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/**
+Data stored in the session as a whole under a single key
+
+Use it with [TypedSession] to replace scattered string keys with one struct:
+
+```no_run
+use actix_session::Session;
+use actix_toolbox::tb_middleware::{SessionData, TypedSession};
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Default)]
+struct Cart {
+    items: Vec<u64>,
+}
+
+impl SessionData for Cart {
+    const KEY: &'static str = "cart";
+}
+
+async fn add_item(session: Session) -> actix_web::Result<HttpResponse> {
+    let mut cart = session.get_typed::<Cart>()?.unwrap_or_default();
+    cart.items.push(42);
+    session.insert_typed(&cart)?;
+    Ok(HttpResponse::Ok().finish())
+}
+```
+*/
+pub trait SessionData: Serialize + DeserializeOwned {
+    /// Key the data is stored under
+    ///
+    /// It has to be unique among the keys used in the session.
+    const KEY: &'static str;
+}
+
+/// Extension of [Session] to read and write [SessionData]
+pub trait TypedSession {
+    /// Get the data from the session
+    ///
+    /// Returns `None` if the session doesn't contain it.
+    fn get_typed<T: SessionData>(&self) -> Result<Option<T>, SessionGetError>;
+
+    /// Store the data in the session, replacing the previous one
+    fn insert_typed<T: SessionData>(&self, data: &T) -> Result<(), SessionInsertError>;
+
+    /// Remove the data from the session
+    ///
+    /// Returns the removed data, if the session contained it.
+    fn remove_typed<T: SessionData>(&self) -> Option<T>;
+}
+
+impl TypedSession for Session {
+    fn get_typed<T: SessionData>(&self) -> Result<Option<T>, SessionGetError> {
+        self.get(T::KEY)
+    }
+
+    fn insert_typed<T: SessionData>(&self, data: &T) -> Result<(), SessionInsertError> {
+        self.insert(T::KEY, data)
+    }
+
+    fn remove_typed<T: SessionData>(&self) -> Option<T> {
+        self.remove_as(T::KEY).and_then(Result::ok)
+    }
+}
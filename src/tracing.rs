@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/**
+Format of the events written to stdout
+
+Selected in the [TracingConfig] using `"text"` or `"json"`.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TracingFormat {
+    /// Plain text, prefixed by the spans the event happened in
+    ///
+    /// **Example log**:
+    /// ```log
+    /// 2022-11-06T23:54:17.042Z  INFO request{method=GET path=/api/users status=200 latency_ms=1.42}: actix_toolbox::tb_middleware::request_tracing: Finished request
+    /// ```
+    #[default]
+    Text,
+    /// One json object per line, including the fields of the spans the event happened in
+    Json,
+}
+
+/**
+The tracing configuration
+
+Provides a default via the [Default] trait.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct TracingConfig {
+    /// Filter selecting the recorded spans and events by their level and target
+    ///
+    /// It is a comma separated list of a default level and `target=level` pairs,
+    /// e.g. `info,actix_server=warn`.
+    /// See [Targets] for more information.
+    ///
+    /// Defaults to `info`
+    #[serde(default = "default_filter")]
+    pub filter: String,
+    /// Format of the events written to stdout
+    ///
+    /// Defaults to [TracingFormat::Text]
+    #[serde(default)]
+    pub format: TracingFormat,
+}
+fn default_filter() -> String {
+    "info".to_string()
+}
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            filter: default_filter(),
+            format: TracingFormat::default(),
+        }
+    }
+}
+
+/**
+Sets up a global `tracing` subscriber writing to stdout.
+
+This is an alternative to [setup_logging](crate::logging::setup_logging) for applications whose
+dependencies use `tracing`: events are written with the fields of the spans they happened in,
+e.g. the [RequestTracing](crate::tb_middleware::RequestTracing) middleware's request span.
+Records of the `log` crate are converted to events, so only one of both can be set up.
+
+**Parameter**:
+- `config`: [TracingConfig]: Reference to the configuration to use for setup.
+
+```no_run
+use actix_toolbox::tracing::{setup_tracing, TracingConfig};
+
+setup_tracing(&TracingConfig::default()).unwrap();
+```
+*/
+pub fn setup_tracing(config: &TracingConfig) -> Result<(), String> {
+    let filter: Targets = config.filter.parse().map_err(|e| format!("{e}"))?;
+    let registry = tracing_subscriber::registry().with(filter);
+    match config.format {
+        TracingFormat::Text => registry.with(fmt::layer()).try_init(),
+        TracingFormat::Json => registry.with(fmt::layer().json()).try_init(),
+    }
+    .map_err(|e| e.to_string())
+}
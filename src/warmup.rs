@@ -0,0 +1,270 @@
+//! Gate traffic until warmup tasks have completed
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use actix_toolbox::warmup::{readiness, Warmup, WarmupGate};
+//! use actix_web::web::{get, Data};
+//! use actix_web::{App, HttpServer};
+//!
+//! #[actix_web::main]
+//! async fn main() -> std::io::Result<()> {
+//!     let mut warmup = Warmup::new();
+//!     warmup.task("prime cache", Duration::from_secs(30), async {
+//!         // Load the cache
+//!         Ok::<(), std::io::Error>(())
+//!     });
+//!     let status = warmup.status();
+//!     actix_web::rt::spawn(warmup.run());
+//!
+//!     HttpServer::new(move || {
+//!         App::new()
+//!             .app_data(Data::new(status.clone()))
+//!             .wrap(WarmupGate::new(status.clone(), vec!["/health".to_string()]))
+//!             .route("/health/ready", get().to(readiness))
+//!     })
+//!     .bind(("127.0.0.1", 8080))?
+//!     .run()
+//!     .await
+//! }
+//! ```
+
+use std::fmt::Display;
+use std::future::{ready, Future, Ready};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::web::Data;
+use actix_web::{Error, HttpResponse};
+use futures::future::{join_all, LocalBoxFuture};
+use serde::Serialize;
+
+/// State of a single warmup task
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "state", content = "error")]
+pub enum TaskState {
+    /// The task hasn't completed yet
+    Pending,
+    /// The task has completed successfully
+    Done,
+    /// The task has failed
+    Failed(String),
+    /// The task didn't complete within its timeout
+    TimedOut,
+}
+
+/// Report of a single warmup task
+#[derive(Serialize, Clone, Debug)]
+pub struct TaskReport {
+    /// Name of the task
+    pub name: String,
+    /// The task's state
+    #[serde(flatten)]
+    pub state: TaskState,
+    /// Milliseconds the task took to complete or fail
+    pub duration_ms: Option<u128>,
+}
+
+/// Shared view of the warmup's progress
+///
+/// Cloning it is cheap.
+#[derive(Clone, Debug, Default)]
+pub struct WarmupStatus {
+    ready: Arc<AtomicBool>,
+    tasks: Arc<Mutex<Vec<TaskReport>>>,
+}
+
+impl WarmupStatus {
+    /// Whether all warmup tasks have completed successfully
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// Get the report of all tasks
+    pub fn report(&self) -> Vec<TaskReport> {
+        self.tasks
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .clone()
+    }
+
+    fn update(&self, index: usize, state: TaskState, duration: Duration) {
+        let mut tasks = self
+            .tasks
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        tasks[index].state = state;
+        tasks[index].duration_ms = Some(duration.as_millis());
+    }
+}
+
+type Task = LocalBoxFuture<'static, Result<(), String>>;
+
+/// Coordinator running the registered warmup tasks
+#[derive(Default)]
+pub struct Warmup {
+    status: WarmupStatus,
+    tasks: Vec<(Duration, Task)>,
+}
+
+impl Warmup {
+    /// Create a coordinator without any tasks
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a task which has to complete within `timeout`
+    pub fn task<E: Display>(
+        &mut self,
+        name: impl Into<String>,
+        timeout: Duration,
+        task: impl Future<Output = Result<(), E>> + 'static,
+    ) -> &mut Self {
+        self.status
+            .tasks
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .push(TaskReport {
+                name: name.into(),
+                state: TaskState::Pending,
+                duration_ms: None,
+            });
+        self.tasks.push((
+            timeout,
+            Box::pin(async move { task.await.map_err(|err| err.to_string()) }),
+        ));
+        self
+    }
+
+    /// Get the status which is updated while the tasks are running
+    pub fn status(&self) -> WarmupStatus {
+        self.status.clone()
+    }
+
+    /// Run all tasks concurrently
+    ///
+    /// The status becomes ready once all of them have completed successfully.
+    pub async fn run(self) -> WarmupStatus {
+        let Warmup { status, tasks } = self;
+
+        let results = join_all(
+            tasks
+                .into_iter()
+                .enumerate()
+                .map(|(index, (timeout, task))| {
+                    let status = status.clone();
+                    async move {
+                        let start = Instant::now();
+                        let state = match actix_web::rt::time::timeout(timeout, task).await {
+                            Ok(Ok(())) => TaskState::Done,
+                            Ok(Err(err)) => TaskState::Failed(err),
+                            Err(_) => TaskState::TimedOut,
+                        };
+                        let done = state == TaskState::Done;
+                        status.update(index, state, start.elapsed());
+                        done
+                    }
+                }),
+        )
+        .await;
+
+        if results.into_iter().all(|done| done) {
+            status.ready.store(true, Ordering::Release);
+        }
+        status
+    }
+}
+
+/// Readiness handler responding with the [`WarmupStatus`] registered as app data
+///
+/// Responds with `200 Ok` once the warmup has completed and with `503 Service Unavailable` before.
+/// The body contains the report of all tasks.
+pub async fn readiness(status: Data<WarmupStatus>) -> HttpResponse {
+    let report = status.report();
+    if status.is_ready() {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+/**
+Middleware answering requests with `503 Service Unavailable` until the warmup has completed
+
+Requests whose path starts with one of the exempt prefixes (e.g. health checks) are always passed.
+*/
+#[derive(Clone, Debug)]
+pub struct WarmupGate {
+    status: WarmupStatus,
+    exempt: Rc<Vec<String>>,
+}
+
+impl WarmupGate {
+    /// Create the middleware
+    pub fn new(status: WarmupStatus, exempt: Vec<String>) -> Self {
+        Self {
+            status,
+            exempt: Rc::new(exempt),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for WarmupGate
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = WarmupGateMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(WarmupGateMiddleware {
+            service,
+            gate: self.clone(),
+        }))
+    }
+}
+
+/// Service created by [WarmupGate]
+pub struct WarmupGateMiddleware<S> {
+    service: S,
+    gate: WarmupGate,
+}
+
+impl<S, B> Service<ServiceRequest> for WarmupGateMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let exempt = self
+            .gate
+            .exempt
+            .iter()
+            .any(|prefix| req.path().starts_with(prefix.as_str()));
+
+        if exempt || self.gate.status.is_ready() {
+            let fut = self.service.call(req);
+            Box::pin(async move { Ok(fut.await?.map_into_left_body()) })
+        } else {
+            Box::pin(async move {
+                Ok(req
+                    .into_response(HttpResponse::ServiceUnavailable().finish())
+                    .map_into_right_body())
+            })
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use actix_web_actors::ws::{CloseCode, CloseReason};
+
+/// Common reasons for the server to close a websocket
+///
+/// Convert them into a [CloseReason] and pass it to [`Sender::close_with`](super::Sender::close_with)
+/// to let clients react to them consistently.
+///
+/// Application specific codes use the private range `4000..=4999` and mirror the http status codes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum AppCloseCode {
+    /// The client isn't authenticated or its credentials expired (`4401`)
+    Unauthorized,
+
+    /// The client opened another websocket which replaces this one (`4409`)
+    Superseded,
+
+    /// The server is shutting down, the client should reconnect later (`1001`)
+    ServerShutdown,
+
+    /// The client violated the application's policy, e.g. by flooding it (`1008`)
+    PolicyViolation,
+}
+
+impl AppCloseCode {
+    /// Get the code sent in the close frame
+    pub fn code(self) -> CloseCode {
+        match self {
+            AppCloseCode::Unauthorized => CloseCode::Other(4401),
+            AppCloseCode::Superseded => CloseCode::Other(4409),
+            AppCloseCode::ServerShutdown => CloseCode::Away,
+            AppCloseCode::PolicyViolation => CloseCode::Policy,
+        }
+    }
+
+    /// Get the description sent in the close frame
+    pub fn description(self) -> &'static str {
+        match self {
+            AppCloseCode::Unauthorized => "unauthorized",
+            AppCloseCode::Superseded => "superseded",
+            AppCloseCode::ServerShutdown => "server shutdown",
+            AppCloseCode::PolicyViolation => "policy violation",
+        }
+    }
+
+    /// Find the variant sent as `code`
+    pub fn from_code(code: CloseCode) -> Option<Self> {
+        match code {
+            CloseCode::Other(4401) => Some(AppCloseCode::Unauthorized),
+            CloseCode::Other(4409) => Some(AppCloseCode::Superseded),
+            CloseCode::Away => Some(AppCloseCode::ServerShutdown),
+            CloseCode::Policy => Some(AppCloseCode::PolicyViolation),
+            _ => None,
+        }
+    }
+}
+
+impl From<AppCloseCode> for CloseCode {
+    fn from(code: AppCloseCode) -> Self {
+        code.code()
+    }
+}
+
+impl From<AppCloseCode> for CloseReason {
+    fn from(code: AppCloseCode) -> Self {
+        CloseReason {
+            code: code.code(),
+            description: Some(code.description().to_string()),
+        }
+    }
+}
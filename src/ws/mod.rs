@@ -1,19 +1,41 @@
-use std::future::Future;
+use std::collections::VecDeque;
+use std::net::IpAddr;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
 pub use actix::MailboxError;
 use actix::{Actor, ActorContext, ActorFuture, Addr, AsyncContext, Handler, StreamHandler};
+use actix_http::ws::Item;
 use actix_web::error::{Error, PayloadError};
 use actix_web::web::Bytes;
 use actix_web::{HttpRequest, HttpResponse};
-pub use actix_web_actors::ws::{Message, ProtocolError};
+pub use actix_web_actors::ws::{CloseCode, CloseReason, Message, ProtocolError};
 use actix_web_actors::ws::{WebsocketContext, WsResponseBuilder};
+use futures::future::BoxFuture;
 use futures::Stream;
+use log::{debug, trace};
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::OwnedPermit;
+
+pub use self::close::AppCloseCode;
+pub use crate::tb_middleware::{real_ip, request_id, RequestId, TrustedProxies};
+
+mod close;
+#[cfg(feature = "test-util")]
+pub mod test;
 
 /// Perform websocket handshake and produce a [sender](Sender) and [receiver](Receiver) to communicate with the websocket.
 ///
+/// The client's address is resolved using [real_ip] with the [TrustedProxies] registered as app data
+/// and can be retrieved from the sender and receiver.
+///
+/// The upgrading request's id is resolved using [request_id].
+/// It is included in the websocket's log records and can be retrieved from the sender and receiver
+/// to correlate the messages' handling with the originating request.
+///
 /// ```no_run
 /// use actix_web::{HttpRequest, HttpResponse};
 /// use actix_web::web::Payload;
@@ -33,10 +55,44 @@ pub fn start<S>(request: &HttpRequest, stream: S) -> Result<(Sender, Receiver, H
 where
     S: Stream<Item = Result<Bytes, PayloadError>> + 'static,
 {
+    let client_ip = real_ip(
+        request,
+        request
+            .app_data::<TrustedProxies>()
+            .unwrap_or(&TrustedProxies::default()),
+    );
+    let request_id = request_id(request);
     let (sender, receiver) = mpsc::channel(CHANNEL_BUFFER);
-    WsResponseBuilder::new(WebSocketActor { channel: sender }, request, stream)
+    let buffer = Arc::new(BufferOccupancy::default());
+    let close_event = Arc::new(Mutex::new(None));
+    let actor = WebSocketActor {
+        channel: sender,
+        buffer: buffer.clone(),
+        close_event: close_event.clone(),
+        request_id: request_id.clone(),
+        queue: VecDeque::new(),
+        forwarding: false,
+        stream_finished: false,
+    };
+    WsResponseBuilder::new(actor, request, stream)
         .start_with_addr()
-        .map(move |(addr, response)| (Sender { addr }, Receiver { channel: receiver }, response))
+        .map(move |(addr, response)| {
+            (
+                Sender {
+                    addr,
+                    buffer,
+                    client_ip,
+                    request_id: request_id.clone(),
+                },
+                Receiver {
+                    channel: receiver,
+                    client_ip,
+                    request_id,
+                    close_event,
+                },
+                response,
+            )
+        })
 }
 
 /// Receiving part of a websocket
@@ -45,8 +101,21 @@ where
 #[derive(Debug)]
 pub struct Receiver {
     channel: mpsc::Receiver<Result<Message, ProtocolError>>,
+    client_ip: Option<IpAddr>,
+    request_id: Option<RequestId>,
+    close_event: Arc<Mutex<Option<CloseEvent>>>,
 }
 impl Receiver {
+    /// The client's address as resolved by [real_ip]
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    /// The upgrading request's id as resolved by [request_id]
+    pub fn request_id(&self) -> Option<&RequestId> {
+        self.request_id.as_ref()
+    }
+
     /// Listen to websocket messages.
     ///
     /// - Returns `None` if the websocket was closed.
@@ -71,31 +140,148 @@ impl Receiver {
     pub async fn recv(&mut self) -> Option<Result<Message, ProtocolError>> {
         self.channel.recv().await
     }
+
+    /// Get details about how the websocket was closed.
+    ///
+    /// This is available once [`recv`](Self::recv) returned `None`.
+    ///
+    /// ```no_run
+    /// # use actix_toolbox::ws;
+    /// # fn somewhere() -> ! {panic!();}
+    /// let mut receiver: ws::Receiver = somewhere();
+    /// tokio::spawn(async move {
+    ///     while receiver.recv().await.is_some() {}
+    ///     if let Some(event) = receiver.close_event() {
+    ///         println!("Websocket closed by {:?}", event.initiated_by);
+    ///     }
+    /// });
+    /// ```
+    pub fn close_event(&self) -> Option<CloseEvent> {
+        self.close_event.lock().ok()?.clone()
+    }
+}
+
+/// Details about how a websocket was closed
+///
+/// See [Receiver::close_event]
+#[derive(Clone, Debug)]
+pub struct CloseEvent {
+    /// The close code sent with the close frame
+    pub code: Option<CloseCode>,
+
+    /// The description sent with the close frame or the protocol error's message
+    pub reason: Option<String>,
+
+    /// Which side closed the websocket
+    pub initiated_by: CloseInitiator,
+}
+
+/// The origin of a websocket's closing
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CloseInitiator {
+    /// The client sent a close frame
+    Client,
+
+    /// The server closed the websocket using [Sender::close] or [Sender::close_with]
+    Server,
+
+    /// The client sent an invalid frame
+    ProtocolError,
+
+    /// The connection ended without any close frame
+    ConnectionLost,
 }
 
 /// Sending part of a websocket
 ///
 /// Cloneable
-#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+#[derive(Clone, Debug)]
 pub struct Sender {
     addr: Addr<WebSocketActor>,
+    buffer: Arc<BufferOccupancy>,
+    client_ip: Option<IpAddr>,
+    request_id: Option<RequestId>,
+}
+impl PartialEq for Sender {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr == other.addr
+    }
+}
+impl Eq for Sender {}
+impl std::hash::Hash for Sender {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.addr.hash(state);
+    }
 }
 impl Sender {
+    /// The client's address as resolved by [real_ip]
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    /// The upgrading request's id as resolved by [request_id]
+    pub fn request_id(&self) -> Option<&RequestId> {
+        self.request_id.as_ref()
+    }
+
     /// Send a message over the websocket.
     ///
     /// - Returns `Err(...)` if the websocket was closed.
     pub async fn send(&self, msg: Message) -> Result<(), MailboxError> {
-        self.addr.send(WrappedMessage::Send(msg)).await
+        let len = message_len(&msg);
+        self.buffer.add(len);
+        let result = self.addr.send(WrappedMessage::Send(msg)).await;
+        if result.is_err() {
+            self.buffer.remove(len);
+        }
+        result
+    }
+
+    /// Number of messages which have been passed to [`send`](Self::send)
+    /// but haven't been written to the websocket yet.
+    ///
+    /// This can be used to implement custom flow control,
+    /// e.g. pause generating data while a client is lagging behind.
+    pub fn buffered_messages(&self) -> usize {
+        self.buffer.messages.load(Ordering::Relaxed)
+    }
+
+    /// Number of payload bytes which have been passed to [`send`](Self::send)
+    /// but haven't been written to the websocket yet.
+    ///
+    /// See [`buffered_messages`](Self::buffered_messages)
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffer.bytes.load(Ordering::Relaxed)
     }
 
     /// Close the websocket
     ///
     /// - Returns `Err(...)` if the websocket was already closed.
     pub async fn close(&self) -> Result<(), MailboxError> {
-        self.addr.send(WrappedMessage::Close).await
+        self.addr.send(WrappedMessage::Close(None)).await
+    }
+
+    /// Close the websocket sending a close frame with a code and description
+    ///
+    /// Use [AppCloseCode] for common reasons:
+    /// ```no_run
+    /// # use actix_toolbox::ws;
+    /// # async fn somewhere(sender: ws::Sender) {
+    /// sender.close_with(ws::AppCloseCode::Superseded).await.ok();
+    /// # }
+    /// ```
+    ///
+    /// - Returns `Err(...)` if the websocket was already closed.
+    pub async fn close_with(&self, reason: impl Into<CloseReason>) -> Result<(), MailboxError> {
+        self.addr
+            .send(WrappedMessage::Close(Some(reason.into())))
+            .await
     }
 }
 
+/// Target of the websocket's log records
+const LOG_TARGET: &str = "websocket";
+
 /// Buffer size for the "rust -> websocket" channel.
 ///
 /// The other direction uses actix internal mailbox.
@@ -104,10 +290,62 @@ impl Sender {
 /// namely the [Default] impl for [Mailbox](actix::dev::Mailbox) which is used by [ws::start]
 pub const CHANNEL_BUFFER: usize = 16;
 
+/// Counters for messages which are sent but not yet written to the websocket
+#[derive(Debug, Default)]
+struct BufferOccupancy {
+    messages: AtomicUsize,
+    bytes: AtomicUsize,
+}
+impl BufferOccupancy {
+    fn add(&self, len: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(len, Ordering::Relaxed);
+    }
+
+    fn remove(&self, len: usize) {
+        // The counters may have been reset by `clear` in the meantime
+        let sub = |n: usize, x: usize| Some(n.saturating_sub(x));
+        let _ = self
+            .messages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| sub(n, 1));
+        let _ = self
+            .bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| sub(n, len));
+    }
+
+    /// Reset the counters once the websocket is closed
+    ///
+    /// Messages left in the actor's mailbox are never written,
+    /// and their senders may not be around to remove them.
+    fn clear(&self) {
+        self.messages.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Get the length of a message's payload
+fn message_len(msg: &Message) -> usize {
+    match msg {
+        Message::Text(text) => text.len(),
+        Message::Binary(bytes) | Message::Ping(bytes) | Message::Pong(bytes) => bytes.len(),
+        Message::Continuation(item) => match item {
+            Item::FirstText(bytes)
+            | Item::FirstBinary(bytes)
+            | Item::Continue(bytes)
+            | Item::Last(bytes) => bytes.len(),
+        },
+        Message::Close(reason) => reason
+            .as_ref()
+            .and_then(|reason| reason.description.as_ref())
+            .map_or(0, String::len),
+        Message::Nop => 0,
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 enum WrappedMessage {
     Send(Message),
-    Close,
+    Close(Option<CloseReason>),
 }
 impl actix::Message for WrappedMessage {
     type Result = ();
@@ -115,10 +353,43 @@ impl actix::Message for WrappedMessage {
 
 struct WebSocketActor {
     channel: mpsc::Sender<Result<Message, ProtocolError>>,
+    buffer: Arc<BufferOccupancy>,
+    close_event: Arc<Mutex<Option<CloseEvent>>>,
+    request_id: Option<RequestId>,
+    /// Received items which haven't been forwarded to the channel yet
+    queue: VecDeque<Result<Message, ProtocolError>>,
+    /// Whether a [Forward] future has been spawned
+    forwarding: bool,
+    /// Whether the client's stream has ended or failed
+    stream_finished: bool,
+}
+
+impl WebSocketActor {
+    /// The request id used in log records
+    fn log_id(&self) -> &str {
+        self.request_id.as_ref().map_or("-", RequestId::as_str)
+    }
+
+    /// Store the close event unless an earlier one has already been recorded
+    fn set_close_event(&self, event: CloseEvent) {
+        if let Ok(mut close_event) = self.close_event.lock() {
+            close_event.get_or_insert(event);
+        }
+    }
 }
 
 impl Actor for WebSocketActor {
     type Context = WebsocketContext<Self>;
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        debug!(target: LOG_TARGET, "[{}] Websocket stopped", self.log_id());
+        self.buffer.clear();
+        self.set_close_event(CloseEvent {
+            code: None,
+            reason: None,
+            initiated_by: CloseInitiator::ConnectionLost,
+        });
+    }
 }
 
 impl Handler<WrappedMessage> for WebSocketActor {
@@ -126,46 +397,142 @@ impl Handler<WrappedMessage> for WebSocketActor {
 
     fn handle(&mut self, msg: WrappedMessage, ctx: &mut Self::Context) -> Self::Result {
         match msg {
-            WrappedMessage::Send(msg) => ctx.write_raw(msg),
-            WrappedMessage::Close => ctx.stop(),
+            WrappedMessage::Send(msg) => {
+                self.buffer.remove(message_len(&msg));
+                ctx.write_raw(msg);
+            }
+            WrappedMessage::Close(reason) => {
+                debug!(
+                    target: LOG_TARGET,
+                    "[{}] Websocket closed by server: {reason:?}",
+                    self.log_id()
+                );
+                self.set_close_event(CloseEvent {
+                    code: reason.as_ref().map(|reason| reason.code),
+                    reason: reason
+                        .as_ref()
+                        .and_then(|reason| reason.description.clone()),
+                    initiated_by: CloseInitiator::Server,
+                });
+                if reason.is_some() {
+                    ctx.close(reason);
+                }
+                ctx.stop();
+            }
         }
     }
 }
 
 impl StreamHandler<Result<Message, ProtocolError>> for WebSocketActor {
     fn handle(&mut self, item: Result<Message, ProtocolError>, ctx: &mut Self::Context) {
-        let channel = self.channel.clone();
-        let future = async move { channel.send(item).await };
-        ctx.spawn(SendFuture { future });
+        match &item {
+            Ok(Message::Close(reason)) => {
+                debug!(
+                    target: LOG_TARGET,
+                    "[{}] Websocket closed by client: {reason:?}",
+                    self.log_id()
+                );
+                self.set_close_event(CloseEvent {
+                    code: reason.as_ref().map(|reason| reason.code),
+                    reason: reason
+                        .as_ref()
+                        .and_then(|reason| reason.description.clone()),
+                    initiated_by: CloseInitiator::Client,
+                })
+            }
+            Err(error) => {
+                debug!(
+                    target: LOG_TARGET,
+                    "[{}] Invalid websocket frame: {error}",
+                    self.log_id()
+                );
+                self.set_close_event(CloseEvent {
+                    code: None,
+                    reason: Some(error.to_string()),
+                    initiated_by: CloseInitiator::ProtocolError,
+                })
+            }
+            Ok(msg) => trace!(
+                target: LOG_TARGET,
+                "[{}] Received websocket message of {} bytes",
+                self.log_id(),
+                message_len(msg)
+            ),
+        }
+
+        let failed = item.is_err();
+        self.queue.push_back(item);
+        if failed {
+            // The stream yields the same error again when polled,
+            // so stop polling it until everything is forwarded.
+            self.stream_finished = true;
+            ctx.wait(Forward::default());
+        } else if !self.forwarding {
+            self.forwarding = true;
+            ctx.spawn(Forward::default());
+        }
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        // Stopping would drop the items still waiting for room in the channel
+        self.stream_finished = true;
+        if self.queue.is_empty() {
+            ctx.stop();
+        }
     }
 }
 
-#[pin_project::pin_project]
-struct SendFuture<
-    F: Future<Output = Result<(), mpsc::error::SendError<Result<Message, ProtocolError>>>>,
-> {
-    #[pin]
-    future: F,
+/// Future waiting for room in the [WebSocketActor]'s channel
+type PermitFuture =
+    BoxFuture<'static, Result<OwnedPermit<Result<Message, ProtocolError>>, SendError<()>>>;
+
+/// Future forwarding the [WebSocketActor]'s queue to its channel
+///
+/// Items are taken from the queue once there is room in the channel,
+/// so they are forwarded in the order they were received.
+#[derive(Default)]
+struct Forward {
+    permit: Option<PermitFuture>,
 }
-impl<F: Future<Output = Result<(), mpsc::error::SendError<Result<Message, ProtocolError>>>>>
-    ActorFuture<WebSocketActor> for SendFuture<F>
-{
+impl ActorFuture<WebSocketActor> for Forward {
     type Output = ();
 
     fn poll(
-        self: Pin<&mut Self>,
-        _srv: &mut WebSocketActor,
+        mut self: Pin<&mut Self>,
+        srv: &mut WebSocketActor,
         ctx: &mut WebsocketContext<WebSocketActor>,
         task: &mut Context<'_>,
     ) -> Poll<Self::Output> {
-        match self.project().future.poll(task) {
-            Poll::Ready(result) => {
-                if result.is_err() {
+        loop {
+            if srv.queue.is_empty() {
+                srv.forwarding = false;
+                if srv.stream_finished {
+                    ctx.stop();
+                }
+                return Poll::Ready(());
+            }
+
+            let permit = self
+                .permit
+                .get_or_insert_with(|| Box::pin(srv.channel.clone().reserve_owned()));
+            let Poll::Ready(permit) = permit.as_mut().poll(task) else {
+                return Poll::Pending;
+            };
+            self.permit = None;
+
+            match permit {
+                Ok(permit) => {
+                    if let Some(item) = srv.queue.pop_front() {
+                        permit.send(item);
+                    }
+                }
+                Err(_) => {
+                    // The receiver has been dropped
+                    srv.queue.clear();
                     ctx.stop();
+                    return Poll::Ready(());
                 }
-                Poll::Ready(())
             }
-            Poll::Pending => Poll::Pending,
         }
     }
 }
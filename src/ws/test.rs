@@ -0,0 +1,486 @@
+//! Utilities for testing websocket handlers
+//!
+//! ```no_run
+//! use actix_toolbox::ws;
+//!
+//! async fn handle(mut receiver: ws::Receiver) {
+//!     while let Some(_message) = receiver.recv().await {
+//!         // Your handler
+//!     }
+//! }
+//!
+//! # async fn test() {
+//! for case in ws::test::malformed_frames() {
+//!     // The handler must neither panic nor hang
+//!     handle(ws::test::receiver(case.items)).await;
+//! }
+//! # }
+//! ```
+//!
+//! [frame_sequences] generates random sequences to check handlers using [proptest]:
+//!
+//! ```no_run
+//! use actix_toolbox::ws;
+//! use proptest::test_runner::TestRunner;
+//!
+//! # async fn handle(mut receiver: ws::Receiver) {}
+//! TestRunner::default()
+//!     .run(&ws::test::frame_sequences(), |items| {
+//!         actix_web::rt::System::new().block_on(handle(ws::test::receiver(items)));
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use actix_http::ws::{CloseReason, Item, OpCode};
+use actix_web::web::{Bytes, BytesMut};
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::{any, prop_oneof, Just, Strategy};
+use tokio::sync::mpsc;
+
+use super::{CloseCode, CloseEvent, CloseInitiator, Message, ProtocolError, Receiver};
+
+/// Size of the payloads used for oversized frames
+///
+/// This is one byte more than actix' default maximum frame size.
+pub const OVERSIZED_PAYLOAD: usize = 64 * 1024 + 1;
+
+/// A named sequence of items a [Receiver] might yield
+#[derive(Debug)]
+pub struct FrameCase {
+    /// Short description of the case
+    pub name: &'static str,
+
+    /// The items in the order they are received
+    pub items: Vec<Result<Message, ProtocolError>>,
+}
+
+/// Generate sequences of fragmented, oversized and invalid frames
+///
+/// These are the edge cases a [Receiver] may deliver to your handler
+/// when the client misbehaves.
+///
+/// Use [receiver] to turn them into a [Receiver].
+pub fn malformed_frames() -> Vec<FrameCase> {
+    let chunk = || Bytes::from_static(b"chunk");
+    let oversized = || BytesMut::zeroed(OVERSIZED_PAYLOAD).freeze();
+
+    vec![
+        FrameCase {
+            name: "fragmented text",
+            items: vec![
+                Ok(Message::Continuation(Item::FirstText(chunk()))),
+                Ok(Message::Continuation(Item::Continue(chunk()))),
+                Ok(Message::Continuation(Item::Last(chunk()))),
+            ],
+        },
+        FrameCase {
+            name: "fragmented binary interleaved with ping",
+            items: vec![
+                Ok(Message::Continuation(Item::FirstBinary(chunk()))),
+                Ok(Message::Ping(chunk())),
+                Ok(Message::Continuation(Item::Last(chunk()))),
+            ],
+        },
+        FrameCase {
+            name: "fragment never finished",
+            items: vec![
+                Ok(Message::Continuation(Item::FirstText(chunk()))),
+                Ok(Message::Continuation(Item::Continue(chunk()))),
+            ],
+        },
+        FrameCase {
+            name: "continuation without start",
+            items: vec![
+                Ok(Message::Continuation(Item::Continue(chunk()))),
+                Ok(Message::Continuation(Item::Last(chunk()))),
+                Err(ProtocolError::ContinuationNotStarted),
+            ],
+        },
+        FrameCase {
+            name: "continuation started twice",
+            items: vec![
+                Ok(Message::Continuation(Item::FirstText(chunk()))),
+                Err(ProtocolError::ContinuationStarted),
+            ],
+        },
+        FrameCase {
+            name: "data frame inside continuation",
+            items: vec![
+                Ok(Message::Continuation(Item::FirstBinary(chunk()))),
+                Err(ProtocolError::ContinuationFragment(OpCode::Text)),
+            ],
+        },
+        FrameCase {
+            name: "oversized binary",
+            items: vec![Ok(Message::Binary(oversized()))],
+        },
+        FrameCase {
+            name: "oversized fragment",
+            items: vec![
+                Ok(Message::Continuation(Item::FirstBinary(oversized()))),
+                Err(ProtocolError::Overflow),
+            ],
+        },
+        FrameCase {
+            name: "empty messages",
+            items: vec![
+                Ok(Message::Text("".into())),
+                Ok(Message::Binary(Bytes::new())),
+                Ok(Message::Ping(Bytes::new())),
+                Ok(Message::Pong(Bytes::new())),
+                Ok(Message::Nop),
+            ],
+        },
+        FrameCase {
+            name: "unmasked frame",
+            items: vec![Err(ProtocolError::UnmaskedFrame)],
+        },
+        FrameCase {
+            name: "invalid opcode",
+            items: vec![Err(ProtocolError::InvalidOpcode(0xB))],
+        },
+        FrameCase {
+            name: "bad opcode",
+            items: vec![Err(ProtocolError::BadOpCode)],
+        },
+        FrameCase {
+            name: "oversized control frame",
+            items: vec![Err(ProtocolError::InvalidLength(126))],
+        },
+        FrameCase {
+            name: "close with unknown code and oversized reason",
+            items: vec![Ok(Message::Close(Some(CloseReason {
+                code: CloseCode::Other(4999),
+                description: Some("\u{0}".repeat(200)),
+            })))],
+        },
+        FrameCase {
+            name: "messages after close",
+            items: vec![
+                Ok(Message::Close(None)),
+                Ok(Message::Text("after close".into())),
+            ],
+        },
+    ]
+}
+
+/// Generate random sequences of the items a [Receiver] might yield
+///
+/// Unlike [malformed_frames], the sequences freely combine regular, fragmented,
+/// oversized and control frames with protocol errors, e.g. fragments without a start,
+/// messages after a close frame or errors in the middle of a message.
+///
+/// Use [receiver] to turn them into a [Receiver].
+pub fn frame_sequences() -> impl Strategy<Value = Vec<Result<Message, ProtocolError>>> {
+    vec(frame(), 0..16)
+}
+
+/// Generate a single item a [Receiver] might yield
+fn frame() -> impl Strategy<Value = Result<Message, ProtocolError>> {
+    prop_oneof![
+        4 => message().prop_map(Ok),
+        1 => protocol_error().prop_map(Err),
+    ]
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    prop_oneof![
+        any::<String>().prop_map(|text| Message::Text(text.into())),
+        payload().prop_map(Message::Binary),
+        payload().prop_map(Message::Ping),
+        payload().prop_map(Message::Pong),
+        close_reason().prop_map(Message::Close),
+        payload().prop_map(|payload| Message::Continuation(Item::FirstText(payload))),
+        payload().prop_map(|payload| Message::Continuation(Item::FirstBinary(payload))),
+        payload().prop_map(|payload| Message::Continuation(Item::Continue(payload))),
+        payload().prop_map(|payload| Message::Continuation(Item::Last(payload))),
+        Just(()).prop_map(|()| Message::Nop),
+    ]
+}
+
+/// Generate a payload, which is oversized now and then
+fn payload() -> impl Strategy<Value = Bytes> {
+    prop_oneof![
+        8 => vec(any::<u8>(), 0..128).prop_map(Bytes::from),
+        1 => Just(()).prop_map(|()| BytesMut::zeroed(OVERSIZED_PAYLOAD).freeze()),
+    ]
+}
+
+fn close_reason() -> impl Strategy<Value = Option<CloseReason>> {
+    option::of(
+        (any::<u16>(), option::of(any::<String>())).prop_map(|(code, description)| CloseReason {
+            code: code.into(),
+            description,
+        }),
+    )
+}
+
+fn protocol_error() -> impl Strategy<Value = ProtocolError> {
+    prop_oneof![
+        Just(()).prop_map(|()| ProtocolError::UnmaskedFrame),
+        Just(()).prop_map(|()| ProtocolError::MaskedFrame),
+        any::<u8>().prop_map(ProtocolError::InvalidOpcode),
+        any::<usize>().prop_map(ProtocolError::InvalidLength),
+        Just(()).prop_map(|()| ProtocolError::BadOpCode),
+        Just(()).prop_map(|()| ProtocolError::Overflow),
+        Just(()).prop_map(|()| ProtocolError::ContinuationNotStarted),
+        Just(()).prop_map(|()| ProtocolError::ContinuationStarted),
+        prop_oneof![Just(OpCode::Text), Just(OpCode::Binary)]
+            .prop_map(ProtocolError::ContinuationFragment),
+    ]
+}
+
+/// Create a [Receiver] yielding the given items before reporting the websocket as closed
+///
+/// The [close event](Receiver::close_event) is derived from the items.
+pub fn receiver(items: Vec<Result<Message, ProtocolError>>) -> Receiver {
+    let close_event = items.iter().find_map(|item| match item {
+        Ok(Message::Close(reason)) => Some(CloseEvent {
+            code: reason.as_ref().map(|reason| reason.code),
+            reason: reason
+                .as_ref()
+                .and_then(|reason| reason.description.clone()),
+            initiated_by: CloseInitiator::Client,
+        }),
+        Err(error) => Some(CloseEvent {
+            code: None,
+            reason: Some(error.to_string()),
+            initiated_by: CloseInitiator::ProtocolError,
+        }),
+        Ok(_) => None,
+    });
+
+    let (sender, channel) = mpsc::channel(items.len().max(1));
+    for item in items {
+        sender
+            .try_send(item)
+            .expect("The channel's buffer should fit all items");
+    }
+
+    Receiver {
+        channel,
+        client_ip: None,
+        request_id: None,
+        close_event: Arc::new(Mutex::new(Some(close_event.unwrap_or(CloseEvent {
+            code: None,
+            reason: None,
+            initiated_by: CloseInitiator::ConnectionLost,
+        })))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::ready;
+    use std::time::Duration;
+
+    use actix_http::ws::Parser;
+    use actix_web_actors::ws::WebsocketContext;
+    use futures::StreamExt;
+    use proptest::prelude::{prop_assert, prop_assert_eq, proptest, ProptestConfig};
+
+    use super::*;
+    use crate::ws::{BufferOccupancy, Sender, WebSocketActor, CHANNEL_BUFFER};
+
+    /// Describe the items for comparison, as messages can't be cloned
+    fn describe(items: &[Result<Message, ProtocolError>]) -> Vec<String> {
+        items.iter().map(|item| format!("{item:?}")).collect()
+    }
+
+    /// Create the actor of a websocket forwarding the client's messages to `channel`
+    fn actor(
+        channel: mpsc::Sender<Result<Message, ProtocolError>>,
+        buffer: Arc<BufferOccupancy>,
+    ) -> WebSocketActor {
+        WebSocketActor {
+            channel,
+            buffer,
+            close_event: Default::default(),
+            request_id: None,
+            queue: Default::default(),
+            forwarding: false,
+            stream_finished: false,
+        }
+    }
+
+    /// Feed raw bytes sent by a client to the websocket's actor
+    /// and collect the items its receiver yields
+    ///
+    /// Returns `None` if the receiver didn't finish in time.
+    fn run_actor(chunks: Vec<Bytes>) -> Option<Vec<String>> {
+        actix_web::rt::System::new().block_on(async move {
+            let (sender, mut channel) = mpsc::channel(CHANNEL_BUFFER);
+            let output = WebsocketContext::create(
+                actor(sender, Default::default()),
+                futures::stream::iter(chunks.into_iter().map(Ok)),
+            );
+
+            let received = async {
+                let mut items = Vec::new();
+                while let Some(item) = channel.recv().await {
+                    items.push(format!("{item:?}"));
+                }
+                items
+            };
+            let run = async {
+                let (_, items) = futures::join!(output.for_each(|_| ready(())), received);
+                items
+            };
+            actix_web::rt::time::timeout(Duration::from_secs(5), run)
+                .await
+                .ok()
+        })
+    }
+
+    /// Encode a message as a masked frame like a client would send it
+    fn encode(message: &Message, dst: &mut BytesMut) {
+        let (payload, op, fin) = match message {
+            Message::Text(text) => (text.as_bytes(), OpCode::Text, true),
+            Message::Binary(payload) => (payload, OpCode::Binary, true),
+            Message::Ping(payload) => (payload, OpCode::Ping, true),
+            Message::Pong(payload) => (payload, OpCode::Pong, true),
+            Message::Continuation(Item::FirstText(payload)) => (payload, OpCode::Text, false),
+            Message::Continuation(Item::FirstBinary(payload)) => (payload, OpCode::Binary, false),
+            Message::Continuation(Item::Continue(payload)) => (payload, OpCode::Continue, false),
+            Message::Continuation(Item::Last(payload)) => (payload, OpCode::Continue, true),
+            Message::Close(reason) => return Parser::write_close(dst, reason.clone(), true),
+            Message::Nop => return,
+        };
+        Parser::write_message(dst, payload, op, fin, true);
+    }
+
+    /// Generate sequences of well-formed messages, optionally ending with a close frame
+    fn valid_messages() -> impl Strategy<Value = Vec<Message>> {
+        let control = || vec(any::<u8>(), 0..=125).prop_map(Bytes::from);
+        let data = || vec(any::<u8>(), 0..512).prop_map(Bytes::from);
+        let fragmented = (data(), vec(data(), 0..4), data()).prop_map(|(first, middle, last)| {
+            let mut fragments = vec![Message::Continuation(Item::FirstBinary(first))];
+            fragments.extend(
+                middle
+                    .into_iter()
+                    .map(|payload| Message::Continuation(Item::Continue(payload))),
+            );
+            fragments.push(Message::Continuation(Item::Last(last)));
+            fragments
+        });
+        let message = prop_oneof![
+            any::<String>().prop_map(|text| vec![Message::Text(text.into())]),
+            data().prop_map(|payload| vec![Message::Binary(payload)]),
+            control().prop_map(|payload| vec![Message::Ping(payload)]),
+            control().prop_map(|payload| vec![Message::Pong(payload)]),
+            fragmented,
+        ];
+        // Codes reserved by RFC 6455 are rejected by the parser
+        let code = prop_oneof![1000u16..=1003, 1007u16..=1014, 3000u16..=4999];
+        let close = option::of((code, option::of("[a-z ]{1,64}")).prop_map(
+            |(code, description)| {
+                Message::Close(Some(CloseReason {
+                    code: code.into(),
+                    description,
+                }))
+            },
+        ));
+        (vec(message, 0..8), close)
+            .prop_map(|(messages, close)| messages.into_iter().flatten().chain(close).collect())
+    }
+
+    #[test]
+    fn malformed_frames_reach_the_receiver() {
+        actix_web::rt::System::new().block_on(async {
+            for case in malformed_frames() {
+                let expected = describe(&case.items);
+                let mut receiver = receiver(case.items);
+                let mut items = Vec::new();
+                while let Some(item) = receiver.recv().await {
+                    items.push(format!("{item:?}"));
+                }
+                assert_eq!(items, expected, "{}", case.name);
+                assert!(receiver.close_event().is_some(), "{}", case.name);
+            }
+        });
+    }
+
+    #[test]
+    fn disconnect_resets_buffer_occupancy() {
+        actix_web::rt::System::new().block_on(async {
+            let (channel, _receiver) = mpsc::channel(CHANNEL_BUFFER);
+            let buffer = Arc::new(BufferOccupancy::default());
+            let (addr, output) = WebsocketContext::create_with_addr(
+                actor(channel, buffer.clone()),
+                futures::stream::pending(),
+            );
+            let sender = Sender {
+                addr,
+                buffer,
+                client_ip: None,
+                request_id: None,
+            };
+
+            // Messages of abandoned sends stay in the actor's mailbox
+            for _ in 0..3 {
+                let send = sender.send(Message::Text("lost".into()));
+                futures::pin_mut!(send);
+                assert!(futures::poll!(send).is_pending());
+            }
+            assert_eq!(sender.buffered_messages(), 3);
+            assert_eq!(sender.buffered_bytes(), 12);
+
+            // The client disconnects
+            drop(output);
+            assert_eq!(sender.buffered_messages(), 0);
+            assert_eq!(sender.buffered_bytes(), 0);
+        });
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn receiver_yields_every_frame(items in frame_sequences()) {
+            let expected = describe(&items);
+            let initiated_by = items.iter().find_map(|item| match item {
+                Ok(Message::Close(_)) => Some(CloseInitiator::Client),
+                Err(_) => Some(CloseInitiator::ProtocolError),
+                Ok(_) => None,
+            });
+
+            let mut receiver = receiver(items);
+            let items = actix_web::rt::System::new().block_on(async {
+                let mut items = Vec::new();
+                while let Some(item) = receiver.recv().await {
+                    items.push(format!("{item:?}"));
+                }
+                items
+            });
+            prop_assert_eq!(items, expected);
+            prop_assert_eq!(
+                receiver.close_event().map(|event| event.initiated_by),
+                Some(initiated_by.unwrap_or(CloseInitiator::ConnectionLost))
+            );
+        }
+
+        #[test]
+        fn actor_forwards_frames(messages in valid_messages()) {
+            let mut bytes = BytesMut::new();
+            for message in &messages {
+                encode(message, &mut bytes);
+            }
+            let expected: Vec<_> = messages
+                .into_iter()
+                .map(|message| format!("{:?}", Ok::<_, ProtocolError>(message)))
+                .collect();
+
+            prop_assert_eq!(run_actor(vec![bytes.freeze()]), Some(expected));
+        }
+
+        #[test]
+        fn actor_survives_arbitrary_bytes(chunks in vec(vec(any::<u8>(), 0..64), 0..8)) {
+            let chunks = chunks.into_iter().map(Bytes::from).collect();
+            prop_assert!(run_actor(chunks).is_some());
+        }
+    }
+}